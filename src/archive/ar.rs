@@ -0,0 +1,419 @@
+//! `ar` archives, like static libraries and the containers of Debian packages.
+//!
+//! Members are flat files, each after a header of 60 bytes and aligned to 2 bytes. Names longer
+//! than 16 bytes are in a table of names in the GNU variant, and before the data of their member in
+//! the BSD variant. The symbol tables of static libraries are left out. Archives can only be
+//! extracted and listed.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use bstr::ByteSlice;
+use filetime_creation::FileTime;
+use fs_err as fs;
+
+use crate::{
+    archive::{
+        cancel::CancellableReader,
+        events::{Event, EventReader},
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
+    error::FinalError,
+    list::{self, ContentOptions, FileInArchive},
+};
+
+const MAGIC: &[u8; 8] = b"!<arch>\n";
+const HEADER_SIZE: u64 = 60;
+/// Largest table of long names, or long name of the BSD variant, that is read
+const MAX_NAMES_SIZE: u64 = 16 * 1024 * 1024;
+
+fn invalid_archive(reason: &'static str) -> crate::Error {
+    FinalError::with_title("Invalid ar archive").detail(reason).into()
+}
+
+/// A member of an archive, its data is read with `Archive::data`
+pub struct Member {
+    pub name: Vec<u8>,
+    pub size: u64,
+    pub mtime: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    /// Where its header starts
+    pub offset: u64,
+}
+
+impl Member {
+    pub fn path(&self) -> PathBuf {
+        self.name.to_path_lossy().into_owned()
+    }
+}
+
+/// Reader of the members of an archive, one after the other
+pub struct Archive<R> {
+    reader: R,
+    /// Bytes read so far, members start at even offsets
+    offset: u64,
+    /// Bytes of data of the current member that weren't read
+    data_left: u64,
+    /// Table of the long names of the GNU variant
+    long_names: Vec<u8>,
+}
+
+impl<R: Read> Archive<R> {
+    /// Reads the start of the archive in `reader`.
+    pub fn new(mut reader: R) -> crate::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_archive("It doesn't start with the ar magic number"));
+        }
+        Ok(Self {
+            reader,
+            offset: MAGIC.len() as u64,
+            data_left: 0,
+            long_names: vec![],
+        })
+    }
+
+    fn skip(&mut self, count: u64) -> crate::Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(count), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped < count {
+            return Err(invalid_archive("It is truncated"));
+        }
+        Ok(())
+    }
+
+    /// Reads `size` bytes of data of the current member.
+    fn read_data(&mut self, size: u64) -> crate::Result<Vec<u8>> {
+        if size > MAX_NAMES_SIZE || size > self.data_left {
+            return Err(invalid_archive("A member header is corrupted"));
+        }
+        let mut data = vec![0; size as usize];
+        self.data().read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads the header of the next member, after what's left of the current one, returns `None`
+    /// after the last one.
+    pub fn next_member(&mut self) -> crate::Result<Option<Member>> {
+        loop {
+            self.skip(self.data_left)?;
+            self.data_left = 0;
+            // Members are aligned to 2 bytes, the last one may not be padded
+            if self.offset % 2 == 1 {
+                let mut padding = [0];
+                if self.reader.read(&mut padding)? == 0 {
+                    return Ok(None);
+                }
+                self.offset += 1;
+            }
+
+            let offset = self.offset;
+            let mut header = [0; HEADER_SIZE as usize];
+            if self.reader.read(&mut header[..1])? == 0 {
+                return Ok(None);
+            }
+            self.reader
+                .read_exact(&mut header[1..])
+                .map_err(|_| invalid_archive("It is truncated"))?;
+            self.offset += HEADER_SIZE;
+            if &header[58..60] != b"`\n" {
+                return Err(invalid_archive("A member header is corrupted"));
+            }
+
+            let field = |range: std::ops::Range<usize>, radix: u32| {
+                let text = std::str::from_utf8(&header[range]).unwrap_or_default().trim_end();
+                // Empty fields are zeros, like in the symbol tables of some tools
+                if text.is_empty() {
+                    return Ok(0);
+                }
+                u64::from_str_radix(text, radix).map_err(|_| invalid_archive("A member header is corrupted"))
+            };
+            let mut member = Member {
+                name: header[..16].trim_end_with(|c| c == ' ').to_vec(),
+                mtime: field(16..28, 10)?,
+                uid: field(28..34, 10)? as u32,
+                gid: field(34..40, 10)? as u32,
+                mode: field(40..48, 8)? as u32,
+                size: field(48..58, 10)?,
+                offset,
+            };
+            self.data_left = member.size;
+
+            match member.name.as_slice() {
+                // Symbol tables, of the GNU variant, its 64-bit version and the BSD variant
+                b"/" | b"/SYM64/" | b"__.SYMDEF" | b"__.SYMDEF SORTED" => continue,
+                b"//" => {
+                    self.long_names = self.read_data(member.size)?;
+                    continue;
+                }
+                name if name.starts_with(b"#1/") => {
+                    let length = std::str::from_utf8(&name[3..])
+                        .ok()
+                        .and_then(|length| length.parse().ok())
+                        .ok_or_else(|| invalid_archive("A member header is corrupted"))?;
+                    let mut name = self.read_data(length)?;
+                    // The name is padded with null bytes
+                    name.truncate(name.find_byte(0).unwrap_or(name.len()));
+                    member.name = name;
+                    member.size -= length;
+                }
+                name if name.len() > 1 && name[0] == b'/' => {
+                    let position: usize = std::str::from_utf8(&name[1..])
+                        .ok()
+                        .and_then(|position| position.parse().ok())
+                        .filter(|&position| position < self.long_names.len())
+                        .ok_or_else(|| invalid_archive("A long name isn't in the table of names"))?;
+                    let name = &self.long_names[position..];
+                    member.name = name[..name.find(b"/\n").unwrap_or(name.len())].to_vec();
+                }
+                // GNU ar ends names with a slash, so that they can have spaces
+                name => member.name = name.strip_suffix(b"/").unwrap_or(name).to_vec(),
+            }
+
+            if member.name.is_empty() {
+                return Err(invalid_archive("A member has no name"));
+            }
+            return Ok(Some(member));
+        }
+    }
+
+    /// Reader of the data of the current member.
+    pub fn data(&mut self) -> MemberData<'_, R> {
+        MemberData { archive: self }
+    }
+
+    /// The data of the current member, once no other member is needed.
+    pub fn into_data(self) -> io::Take<R> {
+        self.reader.take(self.data_left)
+    }
+}
+
+/// Data of the current member of an `Archive`
+pub struct MemberData<'a, R> {
+    archive: &'a mut Archive<R>,
+}
+
+impl<R: Read> Read for MemberData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = &mut *self.archive;
+        let len = buf.len().min(archive.data_left.try_into().unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = archive.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated ar archive"));
+        }
+        archive.offset += read as u64;
+        archive.data_left -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Unpacks the archive given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    unpack_entries(reader, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries(
+    reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
+    let reader = CancellableReader::new(EventReader::new(reader, events), &unpack_options.cancel);
+    let mut archive = Archive::new(reader)?;
+
+    let mut noticed_stripped_root = false;
+    let mut files_unpacked = 0;
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    while let Some(member) = archive.next_member()? {
+        unpack_options.cancel.check()?;
+        let entry_path = member.path();
+        let filter = &unpack_options.filter;
+        if !filter.matches(Some(EntryKind::File)) || !filter.matches_path(&entry_path) {
+            continue;
+        }
+
+        context.start_entry(&entry_path, Some(member.offset));
+        events.handle(Event::EntryStarted {
+            path: &entry_path,
+            size: member.size,
+        });
+        unpack_options.notice_stripped_root(&entry_path, &mut noticed_stripped_root);
+        let Some(destination) = destinations.destination(&entry_path, false) else {
+            continue;
+        };
+        if destinations.is_extracted(&destination, member.size, Some(member.mtime as i64)) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = fs::File::create(&destination)?;
+        io::copy(&mut archive.data(), &mut file)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(member.mode & 0o7777);
+            unpack_options.lost_metadata.check(
+                MetadataKind::Permissions,
+                &entry_path,
+                file.set_permissions(permissions),
+            )?;
+        }
+        let mtime = FileTime::from_unix_time(member.mtime as i64, 0);
+        let times = filetime_creation::set_file_handle_times(file.file(), None, Some(mtime), None);
+        unpack_options
+            .lost_metadata
+            .check(MetadataKind::ModificationTime, &entry_path, times)?;
+
+        unpack_options.restore_owner(&destination, &entry_path, member.uid, member.gid)?;
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: member.size,
+        });
+        files_unpacked += 1;
+        context.finish_entry();
+    }
+    destinations.report_renames();
+
+    Ok(files_unpacked)
+}
+
+/// List contents of `reader`, returning a vector of archive entries
+pub fn list_archive(
+    reader: impl Read + Send + 'static,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    struct Files(Receiver<crate::Result<FileInArchive>>);
+    impl Iterator for Files {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.recv().ok()
+        }
+    }
+
+    let mut archive = Archive::new(reader)?;
+    let (tx, rx) = mpsc::sync_channel(list::LISTED_AHEAD);
+    thread::spawn(move || loop {
+        let file_in_archive = (|| {
+            let Some(member) = archive.next_member()? else {
+                return Ok(None);
+            };
+            let mut file_in_archive = FileInArchive {
+                path: member.path(),
+                is_dir: false,
+                is_symlink: false,
+                is_executable: member.mode & 0o111 != 0,
+                size: member.size,
+                preview: None,
+                file_type: None,
+            };
+            if contents.is_needed() {
+                list::read_contents(&mut file_in_archive, archive.data(), &contents)?;
+            }
+            Ok(Some(file_in_archive))
+        })();
+        let file_in_archive = match file_in_archive.transpose() {
+            Some(file_in_archive) => file_in_archive,
+            None => break,
+        };
+        let is_err = file_in_archive.is_err();
+        // The listing stopped early, after an error
+        if tx.send(file_in_archive).is_err() || is_err {
+            break;
+        }
+    });
+
+    Ok(Files(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut member = format!(
+            "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            1_700_000_000,
+            0,
+            0,
+            100644,
+            data.len()
+        )
+        .into_bytes();
+        member.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            member.push(b'\n');
+        }
+        member
+    }
+
+    fn read_members(archive: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut archive = Archive::new(archive).unwrap();
+        let mut members = vec![];
+        while let Some(member) = archive.next_member().unwrap() {
+            let mut data = vec![];
+            archive.data().read_to_end(&mut data).unwrap();
+            members.push((member.name, data));
+        }
+        members
+    }
+
+    #[test]
+    fn test_gnu_names() {
+        let mut archive = MAGIC.to_vec();
+        archive.extend(member("/", b"\0\0\0\0"));
+        archive.extend(member("//", b"a_very_long_object_name.o/\n"));
+        archive.extend(member("short.o/", b"odd"));
+        archive.extend(member("/0", b"long"));
+
+        let members = read_members(&archive);
+        assert_eq!(
+            members,
+            [
+                (b"short.o".to_vec(), b"odd".to_vec()),
+                (b"a_very_long_object_name.o".to_vec(), b"long".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bsd_names() {
+        let mut archive = MAGIC.to_vec();
+        archive.extend(member("#1/28", b"a_very_long_object_name.o\0\0\0data"));
+        archive.extend(member("short.o", b"x"));
+
+        let members = read_members(&archive);
+        assert_eq!(
+            members,
+            [
+                (b"a_very_long_object_name.o".to_vec(), b"data".to_vec()),
+                (b"short.o".to_vec(), b"x".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Microsoft cabinet files, like Windows driver bundles and updates, read with the `cab` crate.
+//!
+//! Files are compressed in folders, with MSZIP, LZX or nothing, and their names separate
+//! directories with backslashes. Cabinets can only be extracted and listed.
+
+use std::{
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use filetime_creation::FileTime;
+use fs_err as fs;
+
+use crate::{
+    archive::{
+        cancel::CancellableReader,
+        events::{Event, EventReader},
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
+    list::{self, ContentOptions, FileInArchive},
+};
+
+/// A file of the cabinet
+struct CabFile {
+    /// Its name in the cabinet, which it's read by
+    name: String,
+    path: PathBuf,
+    size: u64,
+    mtime: Option<i64>,
+}
+
+/// The files of `cabinet`, in the order of their data.
+fn files<R: Read + Seek>(cabinet: &cab::Cabinet<R>) -> Vec<CabFile> {
+    cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries())
+        .map(|file| CabFile {
+            name: file.name().to_owned(),
+            path: PathBuf::from(file.name().replace('\\', "/")),
+            size: file.uncompressed_size() as u64,
+            // Usually in local time, which isn't known
+            mtime: file.datetime().map(|time| time.assume_utc().unix_timestamp()),
+        })
+        .collect()
+}
+
+/// Unpacks the cabinet given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    unpack_entries(reader, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
+    let mut cabinet = cab::Cabinet::new(reader)?;
+
+    let mut noticed_stripped_root = false;
+    let mut files_unpacked = 0;
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    for file in files(&cabinet) {
+        unpack_options.cancel.check()?;
+        let filter = &unpack_options.filter;
+        if !filter.matches(Some(EntryKind::File)) || !filter.matches_path(&file.path) {
+            continue;
+        }
+
+        context.start_entry(&file.path, None);
+        events.handle(Event::EntryStarted {
+            path: &file.path,
+            size: file.size,
+        });
+        unpack_options.notice_stripped_root(&file.path, &mut noticed_stripped_root);
+        let Some(destination) = destinations.destination(&file.path, false) else {
+            continue;
+        };
+        if destinations.is_extracted(&destination, file.size, file.mtime) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut output_file = fs::File::create(&destination)?;
+        let data = EventReader::new(cabinet.read_file(&file.name)?, events);
+        io::copy(
+            &mut CancellableReader::new(data, &unpack_options.cancel),
+            &mut output_file,
+        )?;
+        if let Some(mtime) = file.mtime {
+            let mtime = FileTime::from_unix_time(mtime, 0);
+            let times = filetime_creation::set_file_handle_times(output_file.file(), None, Some(mtime), None);
+            unpack_options
+                .lost_metadata
+                .check(MetadataKind::ModificationTime, &file.path, times)?;
+        }
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: file.size,
+        });
+        files_unpacked += 1;
+        context.finish_entry();
+    }
+    destinations.report_renames();
+
+    Ok(files_unpacked)
+}
+
+/// List contents of `reader`, returning a vector of archive entries
+pub fn list_archive<R: Read + Seek + 'static>(
+    reader: R,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    struct Files<R> {
+        cabinet: cab::Cabinet<R>,
+        files: std::vec::IntoIter<CabFile>,
+        contents: ContentOptions,
+    }
+    impl<R: Read + Seek> Iterator for Files<R> {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let file = self.files.next()?;
+            let mut file_in_archive = FileInArchive {
+                path: file.path,
+                is_dir: false,
+                is_symlink: false,
+                is_executable: false,
+                size: file.size,
+                preview: None,
+                file_type: None,
+            };
+            if self.contents.is_needed() {
+                let read = self
+                    .cabinet
+                    .read_file(&file.name)
+                    .and_then(|data| list::read_contents(&mut file_in_archive, data, &self.contents));
+                if let Err(err) = read {
+                    return Some(Err(err.into()));
+                }
+            }
+            Some(Ok(file_in_archive))
+        }
+    }
+
+    let cabinet = cab::Cabinet::new(reader)?;
+    let files = files(&cabinet);
+    Ok(Files {
+        cabinet,
+        files: files.into_iter(),
+        contents,
+    })
+}
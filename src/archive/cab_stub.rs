@@ -0,0 +1,7 @@
+use crate::Error;
+
+pub fn no_support() -> Error {
+    Error::UnsupportedFormat {
+        reason: "CAB support is disabled for this build.".into(),
+    }
+}
@@ -0,0 +1,156 @@
+//! Cooperative cancellation of extractions.
+//!
+//! Extractions check the [`CancellationToken`] of their `UnpackOptions` between entries and
+//! while copying the data of each entry, and stop with [`Error::Cancelled`] once it's cancelled,
+//! which tells how many entries were extracted. Temporary files are removed on the way out,
+//! like for any other error.
+
+use std::{
+    error, fmt,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::Error;
+
+/// Shared flag asking the extractions that hold a clone of it to stop
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every holder of this token to stop, which they do at their next check.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Error::Cancelled`] if the token was cancelled.
+    pub fn check(&self) -> crate::Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled { extracted: None })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Inner error of the `io::Error`s of reads stopped by a cancellation, which are turned back
+/// into [`Error::Cancelled`]
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+/// Reader that fails once its token is cancelled, so that long copies stop in the middle.
+pub struct CancellableReader<R> {
+    inner: R,
+    token: CancellationToken,
+}
+
+impl<R> CancellableReader<R> {
+    pub fn new(inner: R, token: &CancellationToken) -> Self {
+        Self {
+            inner,
+            token: token.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.token.is_cancelled() {
+            return Err(io::Error::other(Cancelled));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for CancellableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::OnceCell;
+
+    use super::*;
+    use crate::{
+        archive::{
+            events::{Event, EventHandler},
+            filter::EntryFilter,
+            metadata::LostMetadata,
+            UnpackOptions,
+        },
+        cli::{CaseCollisionsPolicy, DuplicateEntriesPolicy, LongNamesPolicy, NameNormalization, WindowsLinksPolicy},
+    };
+
+    /// Cancels its token once the first entry is extracted
+    struct CancelAfterFirstEntry(CancellationToken);
+
+    impl EventHandler for CancelAfterFirstEntry {
+        fn handle(&self, event: Event<'_>) {
+            if let Event::EntryFinished { .. } = event {
+                self.0.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancel_tar_extraction() {
+        let mut builder = ::tar::Builder::new(vec![]);
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let mut header = ::tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, &b"data"[..]).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let token = CancellationToken::new();
+        let options = UnpackOptions {
+            absolute_paths: false,
+            events: Box::new(CancelAfterFirstEntry(token.clone())),
+            cancel: token,
+            quiet: true,
+            devices: false,
+            ownership: None,
+            windows_links: WindowsLinksPolicy::Auto,
+            case_collisions: CaseCollisionsPolicy::Overwrite,
+            long_names: LongNamesPolicy::Error,
+            duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+            filter: EntryFilter::default(),
+            password: OnceCell::new(),
+            selinux: false,
+            deb_data_only: false,
+            codec_threads: 1,
+            normalize: NameNormalization::None,
+            zstd_dictionary: None,
+            lost_metadata: LostMetadata::default(),
+            ignore_existing: None,
+        };
+        let output = tempfile::tempdir().unwrap();
+        let result = crate::archive::tar::unpack_archive(Box::new(io::Cursor::new(archive)), output.path(), &options);
+
+        assert!(matches!(result, Err(Error::Cancelled { extracted: Some(1) })));
+        assert!(output.path().join("a.txt").exists());
+        assert!(!output.path().join("b.txt").exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! Compressions stopped at a time budget, see `--max-duration`, and resumed, see `--resume`.
+//!
+//! Once the budget is spent, the tar archive is ended after the entry being written, so it's a
+//! valid archive of part of the files, and a state file, `<archive>.resume`, lists the paths of
+//! the entries in it. Compressing the same files with `--resume <state file>` leaves those
+//! entries out and lists them again in its own state file, so a backup too large for one run is
+//! spread across several archives.
+//!
+//! The state file is made of records separated by null bytes: a header, then the path of each
+//! entry as it was walked.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+use crate::{error::FinalError, utils::EscapedPathDisplay};
+
+/// First record of the state file
+const HEADER: &str = "ouch-resume 1";
+
+/// Where a compression with `--max-duration` or `--resume` is
+pub struct Checkpoint {
+    /// When to stop, if there's a time budget
+    deadline: Option<Instant>,
+    /// Paths of the entries archived by the earlier runs
+    earlier: HashSet<PathBuf>,
+    /// Paths of the entries archived by the earlier runs and by this one, in order
+    done: Vec<PathBuf>,
+    /// Entries archived by this run
+    archived: usize,
+    stopped: bool,
+}
+
+impl Checkpoint {
+    /// Checkpoint of a run stopping after `max_duration`, resuming from the state file `resumed`.
+    pub fn new(max_duration: Option<Duration>, resumed: Option<&Path>) -> crate::Result<Self> {
+        let done = match resumed {
+            Some(path) => read_state(path)?,
+            None => vec![],
+        };
+        Ok(Self {
+            deadline: max_duration.map(|duration| Instant::now() + duration),
+            earlier: done.iter().cloned().collect(),
+            done,
+            archived: 0,
+            stopped: false,
+        })
+    }
+
+    /// Where the state file of `archive_path` goes.
+    pub fn state_path(archive_path: &Path) -> PathBuf {
+        let mut path = archive_path.as_os_str().to_owned();
+        path.push(".resume");
+        PathBuf::from(path)
+    }
+
+    /// Whether the entry walked at `path` is in the archive of an earlier run.
+    pub fn is_done(&self, path: &Path) -> bool {
+        self.earlier.contains(path)
+    }
+
+    /// Whether the time budget is spent, then no more entries are archived. Each run archives at
+    /// least one entry, so that resuming always makes progress.
+    pub fn is_due(&mut self) -> bool {
+        if self.archived > 0 && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    /// Notes that the entry walked at `path` was archived.
+    pub fn record(&mut self, path: PathBuf) {
+        self.done.push(path);
+        self.archived += 1;
+    }
+
+    /// Whether the run was stopped before archiving every entry.
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Number of entries archived by this run.
+    pub fn archived(&self) -> usize {
+        self.archived
+    }
+
+    /// Writes the state file of `archive_path`, to resume from.
+    pub fn write(&self, archive_path: &Path) -> crate::Result<PathBuf> {
+        let mut records = vec![HEADER.as_bytes()];
+        for path in &self.done {
+            let Some(path) = <[u8]>::from_path(path) else {
+                let error = FinalError::with_title("Cannot write the state file to resume from")
+                    .detail(format!("'{}' isn't valid UTF-8", EscapedPathDisplay::new(path)));
+                return Err(error.into());
+            };
+            records.push(path);
+        }
+
+        let mut contents = records.join(&b'\0');
+        contents.push(b'\0');
+        let state_path = Self::state_path(archive_path);
+        fs::write(&state_path, contents)?;
+        Ok(state_path)
+    }
+}
+
+/// The paths listed by the state file at `path`.
+fn read_state(path: &Path) -> crate::Result<Vec<PathBuf>> {
+    let contents = fs::read(path)?;
+    let mut records = contents.split_str("\0").filter(|record| !record.is_empty());
+    if records.next() != Some(HEADER.as_bytes()) {
+        let error = FinalError::with_title(format!("Cannot resume from '{}'", EscapedPathDisplay::new(path)))
+            .detail("It isn't a state file written by '--max-duration'");
+        return Err(error.into());
+    }
+
+    records
+        .map(|record| {
+            record.to_path().map(Path::to_path_buf).map_err(|_| {
+                FinalError::with_title(format!("Cannot resume from '{}'", EscapedPathDisplay::new(path)))
+                    .detail("It lists a path that isn't valid on this platform")
+                    .into()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("backup.tar");
+
+        let mut checkpoint = Checkpoint::new(Some(Duration::ZERO), None).unwrap();
+        // Spent budgets still let one entry through
+        assert!(!checkpoint.is_due());
+        checkpoint.record("src/main.rs".into());
+        assert!(checkpoint.is_due());
+        let state = checkpoint.write(&archive).unwrap();
+        assert_eq!(state, dir.path().join("backup.tar.resume"));
+
+        let mut resumed = Checkpoint::new(None, Some(&state)).unwrap();
+        assert!(resumed.is_done(Path::new("src/main.rs")));
+        assert!(!resumed.is_done(Path::new("src/lib.rs")));
+        resumed.record("src/lib.rs".into());
+        assert!(!resumed.is_due());
+        resumed.write(&archive).unwrap();
+        assert_eq!(
+            read_state(&state).unwrap(),
+            [Path::new("src/main.rs"), Path::new("src/lib.rs")]
+        );
+
+        fs::write(&state, "not a state file").unwrap();
+        assert!(Checkpoint::new(None, Some(&state)).is_err());
+    }
+}
@@ -0,0 +1,782 @@
+//! Cpio archives, like initramfs images and RPM payloads.
+//!
+//! Entries are a header, a name and data, until the one named "TRAILER!!!". The "newc" format of
+//! `cpio -H newc`, with or without checksums, is read and written, the portable "odc" format is
+//! only read. In the newc format, names and data are padded to 4 bytes, and hard links only have
+//! data in one of their entries.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use bstr::{ByteSlice, ByteVec};
+use filetime_creation::FileTime;
+use fs_err as fs;
+use same_file::Handle;
+
+use crate::{
+    archive::{
+        cancel::CancellableReader,
+        directories::DeferredDirectories,
+        events::{Event, EventReader},
+        links::DeferredLinks,
+        metadata::{LostMetadata, MetadataKind},
+        names::EntryDestinations,
+        BuildOptions, ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
+    error::FinalError,
+    extension::CompressionFormat,
+    info,
+    list::{self, ContentOptions, FileInArchive},
+    progress::Tracked,
+    utils::{self, EscapedPathDisplay, SpecialFileKind},
+    warning, BUFFER_CAPACITY,
+};
+
+const NEWC_MAGIC: &[u8; 6] = b"070701";
+/// The newc format, with the sum of the bytes of the data of files in its headers
+const CRC_MAGIC: &[u8; 6] = b"070702";
+const ODC_MAGIC: &[u8; 6] = b"070707";
+/// Name of the entry ending archives
+const TRAILER: &[u8] = b"TRAILER!!!";
+/// Longest name, or symlink target, that is read
+const MAX_NAME_SIZE: u64 = 64 * 1024;
+
+/// Types of files in the mode of entries, like in `stat`
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Header of an entry
+#[derive(Debug, Default)]
+struct Header {
+    /// Device and inode of the file, shared by its hard links
+    dev: u64,
+    ino: u64,
+    /// Type and permissions
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u64,
+    size: u64,
+    /// Major and minor numbers of device nodes
+    rdev: (u32, u32),
+    name: Vec<u8>,
+}
+
+impl Header {
+    fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    fn path(&self) -> PathBuf {
+        self.name.to_path_lossy().into_owned()
+    }
+
+    fn kind(&self) -> Option<EntryKind> {
+        match self.file_type() {
+            S_IFREG => Some(EntryKind::File),
+            S_IFDIR => Some(EntryKind::Directory),
+            S_IFLNK => Some(EntryKind::Symlink),
+            _ => None,
+        }
+    }
+
+    /// Whether it's the directory the archive was made from, like ".", which is the output folder
+    fn is_root(&self) -> bool {
+        self.path()
+            .components()
+            .all(|component| matches!(component, Component::CurDir | Component::RootDir))
+    }
+
+    /// Whether the data of this file may be in another entry, its hard link
+    fn is_hard_link(&self) -> bool {
+        self.nlink > 1 && self.file_type() == S_IFREG
+    }
+}
+
+/// Parses a number written in ASCII digits of `radix`.
+fn parse_number(field: &[u8], radix: u32) -> io::Result<u64> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, radix).ok())
+        .ok_or_else(|| invalid_data("corrupted cpio header"))
+}
+
+/// Reader of the entries of a cpio archive, one after the other
+struct Archive<R> {
+    reader: R,
+    /// Bytes read so far, names and data are padded from the start of the archive
+    offset: u64,
+    /// Whether the current entry is in the newc format, padded
+    padded: bool,
+    /// Bytes of data of the current entry that weren't read
+    data_left: u64,
+}
+
+impl<R: Read> Archive<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            padded: false,
+            data_left: 0,
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(count), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped < count {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated cpio archive"));
+        }
+        Ok(())
+    }
+
+    /// Skips the padding to 4 bytes of the newc format.
+    fn skip_padding(&mut self) -> io::Result<()> {
+        if self.padded {
+            self.skip(self.offset.next_multiple_of(4) - self.offset)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the header of the next entry, after what's left of the current one, returns `None`
+    /// after the last one.
+    fn next_entry(&mut self) -> io::Result<Option<Header>> {
+        self.skip(self.data_left)?;
+        self.data_left = 0;
+        self.skip_padding()?;
+
+        let mut magic = [0; 6];
+        self.read_exact(&mut magic)?;
+        let (mut header, name_size) = match &magic {
+            NEWC_MAGIC | CRC_MAGIC => self.read_newc_header()?,
+            ODC_MAGIC => self.read_odc_header()?,
+            _ => return Err(invalid_data("not a cpio archive, or not in the newc or odc format")),
+        };
+
+        if name_size == 0 || name_size > MAX_NAME_SIZE {
+            return Err(invalid_data("corrupted cpio header"));
+        }
+        let mut name = vec![0; name_size as usize];
+        self.read_exact(&mut name)?;
+        // Names end with a null byte
+        name.pop();
+        header.name = name;
+        self.skip_padding()?;
+
+        if header.name == TRAILER {
+            return Ok(None);
+        }
+        self.data_left = header.size;
+        Ok(Some(header))
+    }
+
+    /// Reads the fields of a newc header, returns it with the size of the name.
+    fn read_newc_header(&mut self) -> io::Result<(Header, u64)> {
+        let mut fields = [0; 13 * 8];
+        self.read_exact(&mut fields)?;
+        let field = |index: usize| parse_number(&fields[index * 8..][..8], 16);
+        self.padded = true;
+
+        let header = Header {
+            ino: field(0)?,
+            mode: field(1)? as u32,
+            uid: field(2)? as u32,
+            gid: field(3)? as u32,
+            nlink: field(4)? as u32,
+            mtime: field(5)?,
+            size: field(6)?,
+            dev: (field(7)? << 32) | field(8)?,
+            rdev: (field(9)? as u32, field(10)? as u32),
+            name: vec![],
+        };
+        Ok((header, field(11)?))
+    }
+
+    /// Reads the fields of an odc header, returns it with the size of the name.
+    fn read_odc_header(&mut self) -> io::Result<(Header, u64)> {
+        let mut fields = [0; 70];
+        self.read_exact(&mut fields)?;
+        let field = |start: usize, len: usize| parse_number(&fields[start..][..len], 8);
+        self.padded = false;
+
+        // The device of device nodes is a single number, of 8 bits of minor
+        let rdev = field(36, 6)?;
+        let header = Header {
+            dev: field(0, 6)?,
+            ino: field(6, 6)?,
+            mode: field(12, 6)? as u32,
+            uid: field(18, 6)? as u32,
+            gid: field(24, 6)? as u32,
+            nlink: field(30, 6)? as u32,
+            rdev: ((rdev >> 8) as u32, (rdev & 0xFF) as u32),
+            mtime: field(42, 11)?,
+            size: field(59, 11)?,
+            name: vec![],
+        };
+        Ok((header, field(53, 6)?))
+    }
+
+    /// Reader of the data of the current entry.
+    fn data(&mut self) -> EntryData<'_, R> {
+        EntryData { archive: self }
+    }
+}
+
+/// Data of the current entry of an `Archive`
+struct EntryData<'a, R> {
+    archive: &'a mut Archive<R>,
+}
+
+impl<R: Read> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = &mut *self.archive;
+        let len = buf.len().min(archive.data_left.try_into().unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = archive.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated cpio archive"));
+        }
+        archive.offset += read as u64;
+        archive.data_left -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Unpacks the archive given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    unpack_entries(reader, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries(
+    reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
+    let reader = CancellableReader::new(EventReader::new(reader, events), &unpack_options.cancel);
+    let mut archive = Archive::new(reader);
+
+    let mut noticed_stripped_root = false;
+    let mut files_unpacked = 0;
+    // Symlinks are created last, so that no entry is written through one of them
+    let mut deferred_links = DeferredLinks::new(unpack_options.windows_links);
+    let mut deferred_directories = DeferredDirectories::default();
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    let mut hard_links = HardLinks::default();
+    loop {
+        let offset = archive.offset;
+        let Some(header) = archive.next_entry()? else {
+            break;
+        };
+        unpack_options.cancel.check()?;
+        let entry_path = header.path();
+        let filter = &unpack_options.filter;
+        if !filter.matches(header.kind()) || !filter.matches_path(&entry_path) || header.is_root() {
+            continue;
+        }
+
+        context.start_entry(&entry_path, Some(offset));
+        events.handle(Event::EntryStarted {
+            path: &entry_path,
+            size: header.size,
+        });
+        unpack_options.notice_stripped_root(&entry_path, &mut noticed_stripped_root);
+        let Some(destination) = destinations.destination(&entry_path, header.file_type() == S_IFDIR) else {
+            continue;
+        };
+        let mtime = Some(header.mtime as i64);
+        if header.kind() == Some(EntryKind::File) && destinations.is_extracted(&destination, header.size, mtime) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mtime = FileTime::from_unix_time(header.mtime as i64, 0);
+        match header.file_type() {
+            S_IFDIR => {
+                fs::create_dir_all(&destination)?;
+                deferred_directories.defer(&destination, &entry_path, Some(header.mode & 0o7777), Some(mtime))?;
+            }
+            S_IFREG if header.is_hard_link() => {
+                hard_links.unpack(&header, archive.data(), &destination, &unpack_options.lost_metadata)?
+            }
+            S_IFREG => unpack_file(&header, archive.data(), &destination, &unpack_options.lost_metadata)?,
+            S_IFLNK => {
+                if header.size > MAX_NAME_SIZE {
+                    return Err(invalid_data("corrupted cpio header").into());
+                }
+                let mut target = vec![];
+                archive.data().read_to_end(&mut target)?;
+                deferred_links.defer(destination.clone(), target.to_path_lossy().into_owned());
+            }
+            #[cfg(unix)]
+            S_IFIFO => utils::create_fifo(&destination, header.mode & 0o7777)?,
+            #[cfg(unix)]
+            S_IFCHR | S_IFBLK if unpack_options.devices => {
+                let kind = if header.file_type() == S_IFCHR {
+                    SpecialFileKind::CharDevice
+                } else {
+                    SpecialFileKind::BlockDevice
+                };
+                let (major, minor) = header.rdev;
+                utils::create_device(&destination, kind, header.mode, major, minor)?;
+            }
+            S_IFCHR | S_IFBLK if !unpack_options.devices => {
+                events.handle(Event::Warning(format!(
+                    "Skipping device node '{}', use '--devices' to restore it.",
+                    EscapedPathDisplay::new(&entry_path)
+                )));
+                continue;
+            }
+            _ => {
+                events.handle(Event::Warning(format!(
+                    "Skipping '{}', files of its type can't be extracted here.",
+                    EscapedPathDisplay::new(&entry_path)
+                )));
+                continue;
+            }
+        }
+
+        unpack_options.restore_owner(&destination, &entry_path, header.uid, header.gid)?;
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: header.size,
+        });
+        files_unpacked += 1;
+        context.finish_entry();
+    }
+    deferred_links.create_all(output_folder, unpack_options.quiet)?;
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
+
+    Ok(files_unpacked)
+}
+
+/// Writes a regular file, with the permissions and modification time of its header, the ones that
+/// can't be restored go to `lost_metadata`.
+fn unpack_file(
+    header: &Header,
+    mut data: impl Read,
+    destination: &Path,
+    lost_metadata: &LostMetadata,
+) -> crate::Result<()> {
+    let mut file = fs::File::create(destination)?;
+    io::copy(&mut data, &mut file)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(header.mode & 0o7777);
+        lost_metadata.check(
+            MetadataKind::Permissions,
+            &header.path(),
+            file.set_permissions(permissions),
+        )?;
+    }
+    let mtime = FileTime::from_unix_time(header.mtime as i64, 0);
+    let times = filetime_creation::set_file_handle_times(file.file(), None, Some(mtime), None);
+    lost_metadata.check(MetadataKind::ModificationTime, &header.path(), times)
+}
+
+/// Files with hard links, whose data is in one of their entries, the last one in the newc format
+#[derive(Default)]
+struct HardLinks {
+    /// Where the file with the data of each inode was extracted
+    extracted: HashMap<(u64, u64), PathBuf>,
+    /// Empty files of the entries of each inode found before its data
+    waiting: HashMap<(u64, u64), Vec<PathBuf>>,
+}
+
+impl HardLinks {
+    fn unpack(
+        &mut self,
+        header: &Header,
+        data: impl Read,
+        destination: &Path,
+        lost_metadata: &LostMetadata,
+    ) -> crate::Result<()> {
+        let inode = (header.dev, header.ino);
+        match self.extracted.get(&inode) {
+            Some(original) if header.size == 0 => {
+                fs::hard_link(original, destination)?;
+            }
+            _ if header.size == 0 => {
+                unpack_file(header, data, destination, lost_metadata)?;
+                self.waiting.entry(inode).or_default().push(destination.to_path_buf());
+            }
+            _ => {
+                unpack_file(header, data, destination, lost_metadata)?;
+                for link in self.waiting.remove(&inode).unwrap_or_default() {
+                    fs::remove_file(&link)?;
+                    fs::hard_link(destination, &link)?;
+                }
+                self.extracted.insert(inode, destination.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// List contents of `reader`, returning a vector of archive entries
+pub fn list_archive(
+    reader: impl Read + Send + 'static,
+    contents: ContentOptions,
+) -> impl Iterator<Item = crate::Result<FileInArchive>> {
+    struct Files(Receiver<crate::Result<FileInArchive>>);
+    impl Iterator for Files {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.recv().ok()
+        }
+    }
+
+    let (tx, rx) = mpsc::sync_channel(list::LISTED_AHEAD);
+    thread::spawn(move || {
+        let mut archive = Archive::new(reader);
+        loop {
+            let file_in_archive = (|| {
+                let Some(header) = archive.next_entry()? else {
+                    return Ok(None);
+                };
+                let is_dir = header.file_type() == S_IFDIR;
+                let is_file = header.file_type() == S_IFREG;
+                let mut file_in_archive = FileInArchive {
+                    path: header.path(),
+                    is_dir,
+                    is_symlink: header.file_type() == S_IFLNK,
+                    is_executable: !is_dir && header.mode & 0o111 != 0,
+                    size: if is_file { header.size } else { 0 },
+                    preview: None,
+                    file_type: None,
+                };
+                if contents.is_needed() && is_file {
+                    list::read_contents(&mut file_in_archive, archive.data(), &contents)?;
+                }
+                Ok(Some((file_in_archive, header.is_root())))
+            })();
+            let file_in_archive = match file_in_archive {
+                Ok(None) => break,
+                Ok(Some((_, true))) => continue,
+                Ok(Some((file_in_archive, false))) => Ok(file_in_archive),
+                Err(err) => Err(err),
+            };
+            let is_err = file_in_archive.is_err();
+            // The listing stopped early, after an error
+            if tx.send(file_in_archive).is_err() || is_err {
+                break;
+            }
+        }
+    });
+
+    Files(rx)
+}
+
+/// Writer of newc archives
+struct Builder<W: Write> {
+    writer: W,
+    offset: u64,
+    /// Inode given to the next entry, they only need to be different
+    next_ino: u64,
+}
+
+impl<W: Write> Builder<W> {
+    fn write_padded(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        self.write_padding()
+    }
+
+    fn write_padding(&mut self) -> io::Result<()> {
+        let padding = self.offset.next_multiple_of(4) - self.offset;
+        self.writer.write_all(&[0; 3][..padding as usize])?;
+        self.offset += padding;
+        Ok(())
+    }
+
+    /// Appends an entry, with `header.size` bytes of `data`.
+    fn append(&mut self, header: &Header, data: impl Read) -> crate::Result<()> {
+        let size = u32::try_from(header.size).map_err(|_| {
+            FinalError::with_title(format!(
+                "Could not add '{}' to the cpio archive",
+                EscapedPathDisplay::new(&header.path())
+            ))
+            .detail("Files of cpio archives can't be larger than 4 GiB")
+            .hint("Compress to a tar archive instead, which stores files of any size")
+        })?;
+        let ino = self.next_ino;
+        self.next_ino += 1;
+
+        let fields = [
+            ino as u32,
+            header.mode,
+            header.uid,
+            header.gid,
+            header.nlink,
+            header.mtime.min(u32::MAX.into()) as u32,
+            size,
+            0,
+            0,
+            header.rdev.0,
+            header.rdev.1,
+            header.name.len() as u32 + 1,
+            0,
+        ];
+        let mut header_bytes = NEWC_MAGIC.to_vec();
+        for field in fields {
+            header_bytes.extend_from_slice(format!("{field:08x}").as_bytes());
+        }
+        header_bytes.extend_from_slice(&header.name);
+        header_bytes.push(0);
+        self.write_padded(&header_bytes)?;
+
+        let copied = io::copy(&mut data.take(header.size), &mut self.writer)?;
+        self.offset += copied;
+        if copied != header.size {
+            let error = FinalError::with_title(format!(
+                "Could not add '{}' to the cpio archive",
+                EscapedPathDisplay::new(&header.path())
+            ))
+            .detail("The file got shorter while it was read");
+            return Err(error.into());
+        }
+        self.write_padding()?;
+        Ok(())
+    }
+
+    /// Appends the trailer, returns the writer.
+    fn finish(mut self) -> crate::Result<W> {
+        let trailer = Header {
+            nlink: 1,
+            name: TRAILER.to_vec(),
+            ..Header::default()
+        };
+        self.append(&trailer, io::empty())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The header of an entry for a file with the given `metadata`, named `name`.
+fn entry_header(name: &Path, metadata: &std::fs::Metadata, build_options: &BuildOptions) -> Header {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .map(|time| build_options.clamp_time(time))
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    let mut header = Header {
+        nlink: if metadata.is_dir() { 2 } else { 1 },
+        mtime,
+        size: if metadata.is_file() { metadata.len() } else { 0 },
+        name: <Vec<u8> as ByteVec>::from_path_lossy(name).into_owned(),
+        ..Header::default()
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        header.mode = metadata.mode();
+        header.uid = metadata.uid();
+        header.gid = metadata.gid();
+        if matches!(header.file_type(), S_IFCHR | S_IFBLK) {
+            header.rdev = utils::device_numbers(metadata.rdev());
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        header.mode = if metadata.is_dir() {
+            S_IFDIR | 0o755
+        } else {
+            S_IFREG | 0o644
+        };
+    }
+
+    header
+}
+
+/// Compresses the files given by `input_filenames` into a newc archive written to `writer`.
+pub fn build_archive_from_paths<W>(
+    input_filenames: &[PathBuf],
+    output_path: &Path,
+    writer: W,
+    build_options: &BuildOptions,
+) -> crate::Result<W>
+where
+    W: Write,
+{
+    let mut builder = Builder {
+        writer: BufWriter::with_capacity(BUFFER_CAPACITY, writer),
+        offset: 0,
+        next_ino: 1,
+    };
+    let output_handle = Handle::from_path(output_path);
+
+    for filename in input_filenames {
+        let (previous_location, filename) = utils::cd_into_base_dir(filename, build_options.base_directory())?;
+
+        for entry in build_options.file_visibility_policy.build_walker(filename) {
+            let entry = entry?;
+            let path = entry.path();
+
+            // This is printed for every file in `input_filenames` and has
+            // little importance for most users, but would generate lots of
+            // spoken text for users using screen readers, braille displays
+            // and so on
+            if !build_options.quiet {
+                info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
+            }
+
+            let metadata = match path.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::NotFound && utils::is_symlink(path) {
+                        // This path is for a broken symlink
+                        // We just ignore it
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            };
+
+            if let Some(kind) = SpecialFileKind::from_metadata(&metadata) {
+                if !utils::should_store_special_file(path, kind, build_options.special_files, CompressionFormat::Cpio)?
+                {
+                    continue;
+                }
+            } else if let Ok(handle) = &output_handle {
+                // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
+                //
+                // This must come after the special files check, opening a FIFO would block
+                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    warning!(
+                        "The output file and the input file are the same: `{}`, skipping...",
+                        output_path.display()
+                    );
+                    continue;
+                }
+            }
+
+            let header = entry_header(&build_options.entry_name(path), &metadata, build_options);
+            if metadata.is_file() {
+                builder.append(&header, Tracked::new(fs::File::open(path)?))?;
+            } else {
+                builder.append(&header, io::empty())?;
+            }
+        }
+        env::set_current_dir(previous_location)?;
+    }
+
+    let writer = builder.finish()?;
+    Ok(writer.into_inner().map_err(|err| err.into_error())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_entries(archive: &[u8]) -> Vec<(Header, Vec<u8>)> {
+        let mut archive = Archive::new(archive);
+        let mut entries = vec![];
+        while let Some(header) = archive.next_entry().unwrap() {
+            let mut data = vec![];
+            archive.data().read_to_end(&mut data).unwrap();
+            entries.push((header, data));
+        }
+        entries
+    }
+
+    #[test]
+    fn test_newc_roundtrip() {
+        let mut builder = Builder {
+            writer: vec![],
+            offset: 0,
+            next_ino: 1,
+        };
+        for (name, mode, data) in [
+            ("dir", S_IFDIR | 0o755, &b""[..]),
+            ("dir/file.txt", S_IFREG | 0o644, b"hello"),
+            ("dir/link", S_IFLNK | 0o777, b"file.txt"),
+        ] {
+            let header = Header {
+                mode,
+                nlink: 1,
+                mtime: 1_700_000_000,
+                size: data.len() as u64,
+                name: name.as_bytes().to_vec(),
+                ..Header::default()
+            };
+            builder.append(&header, data).unwrap();
+        }
+        let archive = builder.finish().unwrap();
+        assert_eq!(archive.len() % 4, 0);
+
+        let entries = read_entries(&archive);
+        let names: Vec<&[u8]> = entries.iter().map(|(header, _)| header.name.as_slice()).collect();
+        assert_eq!(names, [&b"dir"[..], b"dir/file.txt", b"dir/link"]);
+        assert_eq!(entries[1].0.mode, S_IFREG | 0o644);
+        assert_eq!(entries[1].0.mtime, 1_700_000_000);
+        assert_eq!(entries[1].1, b"hello");
+        assert_eq!(entries[2].1, b"file.txt");
+
+        let truncated = &archive[..archive.len() - 40];
+        let mut archive = Archive::new(truncated);
+        assert!(std::iter::from_fn(|| archive.next_entry().transpose()).any(|entry| entry.is_err()));
+    }
+
+    #[test]
+    fn test_odc_header() {
+        // As written by `bsdtar --format odc`
+        let mut archive =
+            b"0707071770000000011006440000000000000000010000001452477040000000500000000005file\0data\n".to_vec();
+        archive.extend_from_slice(
+            b"0707070000000000000000000000000000000000010000000000000000000001300000000000TRAILER!!!\0",
+        );
+
+        let entries = read_entries(&archive);
+        assert_eq!(entries.len(), 1);
+        let (header, data) = &entries[0];
+        assert_eq!(header.name, b"file");
+        assert_eq!(header.mode, S_IFREG | 0o644);
+        assert_eq!(header.mtime, 1_700_000_000);
+        assert_eq!(data, b"data\n");
+    }
+}
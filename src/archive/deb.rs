@@ -0,0 +1,135 @@
+//! Debian packages, `ar` archives of a `debian-binary` version file, a `control.tar.*` tarball of
+//! the package metadata and a `data.tar.*` tarball of the files it installs.
+//!
+//! The data is extracted like a tar archive, and the control files into a `DEBIAN` folder next to
+//! it, like `dpkg-deb --raw-extract` does. Packages can only be extracted and listed.
+
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::{
+        ar::{Archive, Member},
+        payload::{PayloadCompression, PayloadDecoder},
+        tar, UnpackOptions,
+    },
+    error::{Error, FinalError},
+    list::{ContentOptions, FileInArchive},
+};
+
+/// Folder the control files are extracted into
+const CONTROL_FOLDER: &str = "DEBIAN";
+/// Largest control tarball, which is kept in memory until the data is extracted
+const MAX_CONTROL_SIZE: u64 = 64 * 1024 * 1024;
+
+fn invalid_package(reason: &'static str) -> crate::Error {
+    FinalError::with_title("Invalid Debian package").detail(reason).into()
+}
+
+fn is_control(member: &Member) -> bool {
+    member.name.starts_with(b"control.tar")
+}
+
+fn is_data(member: &Member) -> bool {
+    member.name.starts_with(b"data.tar")
+}
+
+/// Decoder of the tarball `name` of the package, compressed as its name tells
+fn decoder<R: Read>(name: &[u8], data: R) -> crate::Result<PayloadDecoder<R>> {
+    let name = String::from_utf8_lossy(name);
+    let compression = match name.rsplit_once(".tar").map_or("", |(_, compression)| compression) {
+        "" => PayloadCompression::None,
+        ".gz" => PayloadCompression::Gzip,
+        ".bz2" => PayloadCompression::Bzip2,
+        ".xz" => PayloadCompression::Xz,
+        ".lzma" => PayloadCompression::Lzma,
+        ".zst" => PayloadCompression::Zstd,
+        _ => {
+            return Err(Error::UnsupportedFormat {
+                reason: format!("The '{name}' member of the Debian package is compressed in an unknown format"),
+            })
+        }
+    };
+    Ok(PayloadDecoder::new(compression, data)?)
+}
+
+/// Reads the control tarball of the package into memory.
+fn read_control(archive: &mut Archive<impl Read>, member: &Member) -> crate::Result<Vec<u8>> {
+    if member.size > MAX_CONTROL_SIZE {
+        return Err(invalid_package("Its control files are too large"));
+    }
+    let mut control = vec![];
+    archive.data().read_to_end(&mut control)?;
+    Ok(control)
+}
+
+/// Unpacks the package given by `reader` into the folder given by `output_folder`, the control
+/// files too unless `--deb-data-only` was given.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut archive = Archive::new(reader)?;
+
+    // The control tarball comes first, it's extracted last because `tar::unpack_archive` only
+    // extracts into empty folders
+    let mut control = None;
+    let mut files_unpacked = None;
+    while let Some(member) = archive.next_member()? {
+        if is_control(&member) && !unpack_options.deb_data_only {
+            control = Some((read_control(&mut archive, &member)?, member.name));
+        } else if is_data(&member) {
+            let decoder = decoder(&member.name, archive.data())?;
+            files_unpacked = Some(tar::unpack_archive(Box::new(decoder), output_folder, unpack_options)?);
+        }
+    }
+    let Some(mut files_unpacked) = files_unpacked else {
+        return Err(invalid_package("It has no data.tar member"));
+    };
+
+    if let Some((control, name)) = control {
+        let control_folder = output_folder.join(CONTROL_FOLDER);
+        fs::create_dir(&control_folder)?;
+        let decoder = decoder(&name, Cursor::new(control))?;
+        files_unpacked += tar::unpack_archive(Box::new(decoder), &control_folder, unpack_options)?;
+    }
+
+    Ok(files_unpacked)
+}
+
+/// List contents of `reader`, the control files first, in the `DEBIAN` folder
+pub fn list_archive(
+    reader: impl Read + Send + 'static,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    let mut archive = Archive::new(reader)?;
+
+    let mut control_files = vec![];
+    while let Some(member) = archive.next_member()? {
+        if is_control(&member) {
+            let decoder = decoder(&member.name, Cursor::new(read_control(&mut archive, &member)?))?;
+            control_files = tar::list_archive(::tar::Archive::new(decoder), contents)
+                .map(|file| {
+                    file.map(|mut file| {
+                        // Without the trailing slash of "DEBIAN/", from "./"
+                        let path = file.path.strip_prefix(".").unwrap_or(&file.path);
+                        file.path = Path::new(CONTROL_FOLDER).join(path).components().collect();
+                        file
+                    })
+                })
+                .collect();
+        } else if is_data(&member) {
+            // The data is the last member that matters, it's listed while it's read
+            let decoder = decoder(&member.name, archive.into_data())?;
+            let data_files = tar::list_archive(::tar::Archive::new(decoder), contents);
+            return Ok(control_files.into_iter().chain(data_files));
+        }
+    }
+    Err(invalid_package("It has no data.tar member"))
+}
@@ -0,0 +1,84 @@
+//! Permissions and modification times of extracted directories.
+//!
+//! Like GNU tar, they are applied after every other entry of the archive, otherwise read-only
+//! directories couldn't receive their own contents, and extracting those contents would change
+//! the modification times just restored.
+
+use std::path::{Path, PathBuf};
+
+use filetime_creation::{set_file_mtime, FileTime};
+use fs_err as fs;
+
+use crate::archive::metadata::{LostMetadata, MetadataKind};
+
+/// A directory whose attributes are waiting for the rest of the archive to be extracted
+struct DeferredDirectory {
+    path: PathBuf,
+    /// Name of its entry, for the attributes that can't be applied
+    entry_path: PathBuf,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    mode: Option<u32>,
+    mtime: Option<FileTime>,
+}
+
+/// Directories waiting for the rest of the archive to be extracted
+#[derive(Default)]
+pub struct DeferredDirectories {
+    directories: Vec<DeferredDirectory>,
+}
+
+impl DeferredDirectories {
+    /// Remembers the attributes of the directory at `entry_path`, extracted at `path`, which is left
+    /// writable until they're applied.
+    pub fn defer(
+        &mut self,
+        path: &Path,
+        entry_path: &Path,
+        mode: Option<u32>,
+        mtime: Option<FileTime>,
+    ) -> crate::Result<()> {
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+            // If the filesystem can't store them, that's reported once they're applied
+            let _ = fs::set_permissions(path, Permissions::from_mode(mode & 0o7777 | 0o700));
+        }
+
+        self.directories.push(DeferredDirectory {
+            path: path.to_path_buf(),
+            entry_path: entry_path.to_path_buf(),
+            mode,
+            mtime,
+        });
+        Ok(())
+    }
+
+    /// Applies the deferred attributes, starting with the deepest directories, whose parents
+    /// could otherwise become inaccessible first, the ones that can't be go to `lost_metadata`.
+    pub fn apply_all(mut self, lost_metadata: &LostMetadata) -> crate::Result<()> {
+        self.directories
+            .sort_by_key(|directory| std::cmp::Reverse(directory.path.components().count()));
+
+        for directory in self.directories {
+            // Later entries may have replaced it, never follow a symlink out of the output folder
+            let is_dir = fs::symlink_metadata(&directory.path).is_ok_and(|metadata| metadata.is_dir());
+            if !is_dir {
+                continue;
+            }
+
+            if let Some(mtime) = directory.mtime {
+                let times = set_file_mtime(&directory.path, mtime);
+                lost_metadata.check(MetadataKind::ModificationTime, &directory.entry_path, times)?;
+            }
+            #[cfg(unix)]
+            if let Some(mode) = directory.mode {
+                use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+                let permissions = fs::set_permissions(&directory.path, Permissions::from_mode(mode & 0o7777));
+                lost_metadata.check(MetadataKind::Permissions, &directory.entry_path, permissions)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Events reported while extracting archives.
+//!
+//! The code unpacking archives doesn't display anything about the entries it writes, it reports
+//! what happens to the [`EventHandler`] of its `UnpackOptions` instead. The command line interface
+//! is just one of those handlers, see `progress::CliEvents`, so anything it shows is available to
+//! other handlers too.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Something that happened while extracting an archive
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The entry at `path` of the archive is being extracted, `size` is its uncompressed size
+    EntryStarted { path: &'a Path, size: u64 },
+    /// `bytes` more bytes of the current entry were written
+    BytesWritten(u64),
+    /// An entry of `size` bytes was extracted to `destination`
+    EntryFinished { destination: &'a Path, size: u64 },
+    /// The entry at `path` couldn't be extracted under its own name, because of `reason`, it was
+    /// renamed to `renamed_to`, or skipped if that's `None`
+    Conflict {
+        path: &'a Path,
+        reason: &'static str,
+        renamed_to: Option<&'a Path>,
+    },
+    /// Something went wrong without stopping the extraction
+    Warning(String),
+}
+
+/// Receives the events of extractions, which may run in parallel.
+pub trait EventHandler: Send + Sync {
+    fn handle(&self, event: Event<'_>);
+}
+
+/// Reader that reports the bytes read through it as [`Event::BytesWritten`].
+pub struct EventReader<'a, R> {
+    inner: R,
+    events: &'a dyn EventHandler,
+}
+
+impl<'a, R: Read> EventReader<'a, R> {
+    pub fn new(inner: R, events: &'a dyn EventHandler) -> Self {
+        Self { inner, events }
+    }
+}
+
+impl<R: Read> Read for EventReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.events.handle(Event::BytesWritten(read as u64));
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for EventReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use once_cell::sync::OnceCell;
+
+    use super::*;
+    use crate::{
+        archive::{cancel::CancellationToken, filter::EntryFilter, metadata::LostMetadata, UnpackOptions},
+        cli::{CaseCollisionsPolicy, DuplicateEntriesPolicy, LongNamesPolicy, NameNormalization, WindowsLinksPolicy},
+    };
+
+    /// Keeps a description of every event, without the byte counts
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl EventHandler for &'static Recorder {
+        fn handle(&self, event: Event<'_>) {
+            let description = match event {
+                Event::EntryStarted { path, size } => format!("started {} {size}", path.display()),
+                Event::BytesWritten(_) => return,
+                Event::EntryFinished { destination, size } => {
+                    format!("finished {} {size}", destination.file_name().unwrap().to_string_lossy())
+                }
+                Event::Conflict { path, renamed_to, .. } => format!("conflict {} {renamed_to:?}", path.display()),
+                Event::Warning(message) => format!("warning {message}"),
+            };
+            self.0.lock().unwrap().push(description);
+        }
+    }
+
+    #[test]
+    fn test_tar_extraction_events() {
+        let mut builder = ::tar::Builder::new(vec![]);
+        for (name, content) in [("a.txt", &b"first"[..]), ("../outside", b"x"), ("b.txt", b"second")] {
+            let mut header = ::tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            // `append_data` refuses paths leaving the archive
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, content).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let recorder: &'static Recorder = Box::leak(Box::default());
+        let options = UnpackOptions {
+            absolute_paths: false,
+            events: Box::new(recorder),
+            cancel: CancellationToken::new(),
+            quiet: true,
+            devices: false,
+            ownership: None,
+            windows_links: WindowsLinksPolicy::Auto,
+            case_collisions: CaseCollisionsPolicy::Overwrite,
+            long_names: LongNamesPolicy::Error,
+            duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+            filter: EntryFilter::default(),
+            password: OnceCell::new(),
+            selinux: false,
+            deb_data_only: false,
+            codec_threads: 1,
+            normalize: NameNormalization::None,
+            zstd_dictionary: None,
+            lost_metadata: LostMetadata::default(),
+            ignore_existing: None,
+        };
+        let output = tempfile::tempdir().unwrap();
+        let unpacked =
+            crate::archive::tar::unpack_archive(Box::new(io::Cursor::new(archive)), output.path(), &options).unwrap();
+
+        assert_eq!(unpacked, 2);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "started a.txt 5",
+                "finished a.txt 5",
+                "started ../outside 1",
+                "conflict ../outside None",
+                "started b.txt 6",
+                "finished b.txt 6",
+            ]
+            .map(String::from)
+        );
+    }
+}
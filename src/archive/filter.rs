@@ -0,0 +1,206 @@
+//! Which entries of an archive are extracted, listed or copied, see `--type`, `--only` and
+//! `--only-from`.
+
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::{cli::EntryKind, error::FinalError};
+
+/// Entries to keep, every entry is kept by default
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    /// Types of the entries to keep, any type if empty
+    types: Vec<EntryKind>,
+    /// Patterns the paths of the entries to keep match one of, any path if `None`
+    only: Option<GlobSet>,
+    /// The patterns of `only`, in the same order
+    patterns: Vec<String>,
+    /// Indices of the patterns that some entries matched, shared by the clones of the filter
+    matched_patterns: Arc<Mutex<HashSet<usize>>>,
+    /// Paths of the entries to keep, with the entries inside of them, any path if `None`
+    paths: Option<HashSet<PathBuf>>,
+    /// The ones of `paths` that some entries matched, shared by the clones of the filter
+    matched_paths: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+/// `path` without its "./" components, nor its trailing slash
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+impl EntryFilter {
+    pub fn new(types: Vec<EntryKind>) -> Self {
+        Self {
+            types,
+            ..Self::default()
+        }
+    }
+
+    /// Only keeps the entries whose paths match one of `patterns`, where `*` stays within a
+    /// directory and `**` goes through any number of them, ignoring case if `ignore_case`.
+    pub fn only(mut self, patterns: &[String], ignore_case: bool) -> crate::Result<Self> {
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|err| {
+                    FinalError::with_title(format!("Invalid pattern '{pattern}'")).detail(err.to_string())
+                })?;
+            builder.add(glob);
+        }
+        let only = builder
+            .build()
+            .map_err(|err| FinalError::with_title("Invalid patterns").detail(err.to_string()))?;
+        self.only = Some(only);
+        self.patterns = patterns.to_vec();
+        Ok(self)
+    }
+
+    /// Only keeps the entries at `paths` and the ones inside of them, like the paths printed by
+    /// `ouch list --null`.
+    pub fn only_paths(mut self, paths: &[PathBuf]) -> Self {
+        self.paths = Some(paths.iter().map(|path| normalize(path)).collect());
+        self
+    }
+
+    /// Whether some entries may be left out.
+    pub fn is_active(&self) -> bool {
+        self.filters_types() || self.only.is_some() || self.paths.is_some()
+    }
+
+    /// Whether some entries may be left out because of their types.
+    pub fn filters_types(&self) -> bool {
+        !self.types.is_empty()
+    }
+
+    /// Whether to keep an entry of type `kind`, `None` for the types that `--type` can't name,
+    /// like device nodes, which are only kept without filter.
+    pub fn matches(&self, kind: Option<EntryKind>) -> bool {
+        match kind {
+            _ if self.types.is_empty() => true,
+            Some(kind) => self.types.contains(&kind),
+            None => false,
+        }
+    }
+
+    /// Whether to keep the entry at `path`, leading "./" are ignored.
+    pub fn matches_path(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        if let Some(only) = &self.only {
+            let matches = only.matches(&path);
+            if matches.is_empty() {
+                return false;
+            }
+            self.matched_patterns.lock().expect("not poisoned").extend(matches);
+        }
+        let Some(paths) = &self.paths else {
+            return true;
+        };
+        match path.ancestors().find(|ancestor| paths.contains(*ancestor)) {
+            Some(listed_path) => {
+                let mut matched_paths = self.matched_paths.lock().expect("not poisoned");
+                if !matched_paths.contains(listed_path) {
+                    matched_paths.insert(listed_path.to_path_buf());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The paths given to `only_paths` that no entry matched so far, sorted.
+    pub fn unmatched_paths(&self) -> Vec<PathBuf> {
+        let Some(paths) = &self.paths else {
+            return vec![];
+        };
+        let matched_paths = self.matched_paths.lock().expect("not poisoned");
+        let mut unmatched_paths: Vec<PathBuf> = paths.difference(&matched_paths).cloned().collect();
+        unmatched_paths.sort();
+        unmatched_paths
+    }
+
+    /// The patterns given to `only` that no entry matched so far, in their order.
+    pub fn unmatched_patterns(&self) -> Vec<&str> {
+        let matched_patterns = self.matched_patterns.lock().expect("not poisoned");
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !matched_patterns.contains(index))
+            .map(|(_, pattern)| pattern.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_filter() {
+        let filter = EntryFilter::default();
+        assert!(!filter.is_active());
+        assert!(filter.matches(Some(EntryKind::Symlink)));
+        assert!(filter.matches(None));
+
+        let filter = EntryFilter::new(vec![EntryKind::File, EntryKind::Directory]);
+        assert!(filter.is_active());
+        assert!(filter.matches(Some(EntryKind::File)));
+        assert!(!filter.matches(Some(EntryKind::Symlink)));
+        assert!(!filter.matches(None));
+    }
+
+    #[test]
+    fn test_entry_filter_only() {
+        let filter = EntryFilter::default()
+            .only(&["assets/**".to_string(), "*.txt".to_string()], false)
+            .unwrap();
+        assert!(filter.is_active());
+        assert!(filter.matches_path(Path::new("assets/images/logo.png")));
+        assert!(filter.matches_path(Path::new("./assets/style.css")));
+        assert!(filter.matches_path(Path::new("notes.txt")));
+        assert!(!filter.matches_path(Path::new("docs/notes.txt")));
+        assert!(!filter.matches_path(Path::new("src/main.rs")));
+        assert!(!filter.matches_path(Path::new("NOTES.TXT")));
+        assert_eq!(filter.unmatched_patterns(), Vec::<&str>::new());
+
+        let filter = EntryFilter::default().only(&["readme.txt".to_string()], true).unwrap();
+        assert!(filter.matches_path(Path::new("README.TXT")));
+        assert!(filter.matches_path(Path::new("readme.txt")));
+        assert!(!filter.matches_path(Path::new("docs/README.TXT")));
+
+        let filter = EntryFilter::default()
+            .only(&["docs/**".to_string(), "missing.txt".to_string()], false)
+            .unwrap();
+        assert!(filter.clone().matches_path(Path::new("docs/guide.md")));
+        assert_eq!(filter.unmatched_patterns(), ["missing.txt"]);
+    }
+
+    #[test]
+    fn test_entry_filter_only_paths() {
+        let filter = EntryFilter::default().only_paths(&[PathBuf::from("assets/"), PathBuf::from("./notes.txt")]);
+        assert!(filter.is_active());
+        assert!(!filter.filters_types());
+        assert!(filter.matches_path(Path::new("assets")));
+        assert!(filter.matches_path(Path::new("./assets/images/logo.png")));
+        assert!(filter.matches_path(Path::new("notes.txt")));
+        assert!(!filter.matches_path(Path::new("assets.txt")));
+        assert!(!filter.matches_path(Path::new("docs/notes.txt")));
+        assert_eq!(filter.unmatched_paths(), Vec::<PathBuf>::new());
+
+        let filter = EntryFilter::default().only_paths(&[PathBuf::from("assets"), PathBuf::from("missing")]);
+        assert!(filter.clone().matches_path(Path::new("assets/style.css")));
+        assert_eq!(filter.unmatched_paths(), [PathBuf::from("missing")]);
+    }
+}
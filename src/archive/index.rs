@@ -0,0 +1,647 @@
+//! Seek index of tar.zst archives, see `--index`.
+//!
+//! Archives compressed with `--index` are split into zstd frames of at most [`FRAME_SIZE`] bytes
+//! of the tar stream, which any zstd decoder reads as a single stream, and get a sidecar file,
+//! `<archive>.idx`, telling where each frame and each entry starts. An entry can be read by
+//! decoding from the start of its frame instead of from the start of the archive, and the
+//! entries can be listed without decoding anything.
+//!
+//! The sidecar is made of records separated by null bytes: a header with the size, modification
+//! time and a checksum of the end of the archive, which tell a stale sidecar apart, then a record
+//! per frame and a record per entry.
+//!
+//! Files compressed with `--seekable` carry the same frames in themselves instead, in the seek
+//! table of the zstd seekable format: a skippable frame at the end of the file, with the
+//! compressed and uncompressed size of each frame, which other zstd decoders ignore.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+use crate::{archive::filter::EntryFilter, cli::EntryKind, list::FileInArchive};
+
+/// Uncompressed bytes of the tar stream in each frame, the most that is decoded for nothing
+/// before reaching an entry
+pub const FRAME_SIZE: u64 = 1024 * 1024;
+
+/// First record of the sidecar, followed by the identity of the archive
+const HEADER: &str = "ouch-index 2";
+
+/// Bytes at the end of the archive covered by the checksum of the header, the last frame of
+/// tar.zst archives and the trailer of the other formats, which has a checksum of the whole stream
+const TAIL_SIZE: u64 = 64 * 1024;
+
+/// Magic number of the skippable frame holding the seek table
+const SEEK_TABLE_FRAME_MAGIC: u32 = 0x184D_2A5E;
+/// Magic number ending the seek table
+const SEEK_TABLE_FOOTER_MAGIC: u32 = 0x8F92_EAB1;
+/// Number of frames, descriptor and magic number
+const SEEK_TABLE_FOOTER_SIZE: u64 = 9;
+
+/// Where a frame starts, in the archive and in the tar stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub compressed_offset: u64,
+    pub uncompressed_offset: u64,
+}
+
+/// An entry and where its header starts in the tar stream
+#[derive(Debug, Clone)]
+pub struct IndexedEntry {
+    pub offset: u64,
+    pub file: FileInArchive,
+}
+
+/// The part of the tar stream holding the entries kept by a filter, see [`SeekIndex::range_of`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryRange {
+    /// The frame to start decoding from
+    pub frame: Frame,
+    /// Where the first entry starts in the tar stream
+    pub start: u64,
+    /// Where the entry after the last one starts, `None` if the last one ends the archive
+    pub end: Option<u64>,
+}
+
+/// What tells an archive apart from another one written at the same path since its sidecar was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArchiveIdentity {
+    size: u64,
+    /// Nanoseconds since the Unix epoch
+    mtime: u128,
+    /// CRC32 of the last [`TAIL_SIZE`] bytes
+    tail_checksum: u32,
+}
+
+impl ArchiveIdentity {
+    fn of(archive_path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(archive_path)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let mut file = fs::File::open(archive_path)?;
+        file.seek(SeekFrom::Start(metadata.len().saturating_sub(TAIL_SIZE)))?;
+        let mut tail = vec![];
+        file.read_to_end(&mut tail)?;
+
+        Ok(Self {
+            size: metadata.len(),
+            mtime: mtime.as_nanos(),
+            tail_checksum: crc32fast::hash(&tail),
+        })
+    }
+
+    fn parse(fields: &str) -> Option<Self> {
+        let mut fields = fields.split_whitespace();
+        let identity = Self {
+            size: fields.next()?.parse().ok()?,
+            mtime: fields.next()?.parse().ok()?,
+            tail_checksum: u32::from_str_radix(fields.next()?, 16).ok()?,
+        };
+        fields.next().is_none().then_some(identity)
+    }
+}
+
+/// The contents of a sidecar
+pub struct SeekIndex {
+    frames: Vec<Frame>,
+    entries: Vec<IndexedEntry>,
+}
+
+impl SeekIndex {
+    pub fn new(frames: Vec<Frame>, entries: Vec<IndexedEntry>) -> Self {
+        Self { frames, entries }
+    }
+
+    /// Where the sidecar of `archive_path` goes.
+    pub fn sidecar_path(archive_path: &Path) -> PathBuf {
+        let mut path = archive_path.as_os_str().to_owned();
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    /// Writes the sidecar of `archive_path`, once the archive is written.
+    pub fn write(&self, archive_path: &Path) -> crate::Result<()> {
+        let ArchiveIdentity {
+            size,
+            mtime,
+            tail_checksum,
+        } = ArchiveIdentity::of(archive_path)?;
+        let mut records = vec![format!("{HEADER} {size} {mtime} {tail_checksum:08x}").into_bytes()];
+        for frame in &self.frames {
+            records.push(format!("F {} {}", frame.compressed_offset, frame.uncompressed_offset).into_bytes());
+        }
+        for entry in &self.entries {
+            let FileInArchive {
+                path,
+                is_dir,
+                is_symlink,
+                is_executable,
+                size,
+                ..
+            } = &entry.file;
+            let kind = match (is_dir, is_symlink, is_executable) {
+                (true, _, _) => 'd',
+                (false, true, _) => 'l',
+                (false, false, true) => 'x',
+                (false, false, false) => 'f',
+            };
+            let Some(path) = <[u8]>::from_path(path) else {
+                // No sidecar is better than one missing entries
+                return Ok(());
+            };
+            records.push([format!("E {} {size} {kind} ", entry.offset).as_bytes(), path].concat());
+        }
+
+        let mut contents = records.join(&b'\0');
+        contents.push(b'\0');
+        fs::write(Self::sidecar_path(archive_path), contents)?;
+        Ok(())
+    }
+
+    /// Reads the sidecar of `archive_path`, if it has one that's up to date.
+    pub fn read_for(archive_path: &Path) -> Option<Self> {
+        let contents = fs::read(Self::sidecar_path(archive_path)).ok()?;
+        let mut records = contents.split_str("\0").filter(|record| !record.is_empty());
+
+        let header = records.next()?.to_str().ok()?;
+        let identity = ArchiveIdentity::parse(header.strip_prefix(HEADER)?)?;
+        if ArchiveIdentity::of(archive_path).ok()? != identity {
+            return None;
+        }
+
+        let mut frames = vec![];
+        let mut entries = vec![];
+        for record in records {
+            let mut fields = record.splitn_str(5, " ");
+            let kind = fields.next()?;
+            let mut number = || fields.next()?.to_str().ok()?.parse::<u64>().ok();
+            match kind {
+                b"F" => frames.push(Frame {
+                    compressed_offset: number()?,
+                    uncompressed_offset: number()?,
+                }),
+                b"E" => {
+                    let (offset, size) = (number()?, number()?);
+                    let kind = fields.next()?;
+                    let path = fields.next()?.to_path().ok()?.to_path_buf();
+                    entries.push(IndexedEntry {
+                        offset,
+                        file: FileInArchive {
+                            path,
+                            is_dir: kind == b"d",
+                            is_symlink: kind == b"l",
+                            is_executable: kind == b"x",
+                            size,
+                            preview: None,
+                            file_type: None,
+                        },
+                    });
+                }
+                _ => return None,
+            }
+        }
+
+        Some(Self::new(frames, entries))
+    }
+
+    /// The part of the tar stream holding the entries that `filter` keeps, which are the only
+    /// ones that need to be decoded. It's empty if the filter keeps none.
+    pub fn range_of(&self, filter: &EntryFilter) -> EntryRange {
+        let kept = |entry: &IndexedEntry| {
+            // Hard links and special files are indexed as files, they're kept here when they
+            // could be, the filter is applied again while unpacking
+            let kind = match (entry.file.is_dir, entry.file.is_symlink) {
+                (true, _) => EntryKind::Directory,
+                (false, true) => EntryKind::Symlink,
+                (false, false) => EntryKind::File,
+            };
+            filter.matches(Some(kind)) && filter.matches_path(&entry.file.path)
+        };
+        let first = self.entries.iter().position(kept);
+        let last = self.entries.iter().rposition(kept);
+
+        let (start, end) = match (first, last) {
+            (Some(first), Some(last)) => (
+                self.entries[first].offset,
+                self.entries.get(last + 1).map(|next| next.offset),
+            ),
+            _ => (0, Some(0)),
+        };
+        let frame = self
+            .frames
+            .iter()
+            .rev()
+            .find(|frame| frame.uncompressed_offset <= start)
+            .copied()
+            .unwrap_or(Frame {
+                compressed_offset: 0,
+                uncompressed_offset: 0,
+            });
+        EntryRange { frame, start, end }
+    }
+
+    /// The entries of the archive, in order.
+    pub fn into_files(self) -> impl Iterator<Item = crate::Result<FileInArchive>> {
+        self.entries.into_iter().map(|entry| Ok(entry.file))
+    }
+}
+
+/// Writer keeping track of how many bytes went through it
+pub struct OffsetWriter<W> {
+    inner: W,
+    offset: u64,
+}
+
+impl<W> OffsetWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, offset: 0 }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for OffsetWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Zstd encoder starting a new frame every [`FRAME_SIZE`] bytes, recording where each one starts
+pub struct FramedEncoder<W: Write> {
+    /// `None` between frames
+    encoder: Option<zstd::stream::write::Encoder<'static, OffsetWriter<W>>>,
+    /// The writer of the encoder, between frames
+    writer: Option<OffsetWriter<W>>,
+    level: i32,
+    threads: u32,
+    /// Uncompressed bytes written so far
+    uncompressed: u64,
+    frames: Vec<Frame>,
+    /// Whether the frames are followed by a seek table
+    seek_table: bool,
+}
+
+impl<W: Write> FramedEncoder<W> {
+    pub fn new(writer: W, level: i32, threads: u32) -> Self {
+        Self {
+            encoder: None,
+            writer: Some(OffsetWriter::new(writer)),
+            level,
+            threads,
+            uncompressed: 0,
+            frames: vec![],
+            seek_table: false,
+        }
+    }
+
+    /// Ends the output with a seek table, making it a file of the zstd seekable format.
+    pub fn with_seek_table(mut self) -> Self {
+        self.seek_table = true;
+        self
+    }
+
+    fn start_frame(&mut self) -> io::Result<()> {
+        let writer = self.writer.take().expect("the writer is kept between frames");
+        self.frames.push(Frame {
+            compressed_offset: writer.offset(),
+            uncompressed_offset: self.uncompressed,
+        });
+
+        let mut encoder = zstd::stream::write::Encoder::new(writer, self.level)?;
+        if self.threads > 1 {
+            encoder.multithread(self.threads)?;
+        }
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            self.writer = Some(encoder.finish()?);
+        }
+        Ok(())
+    }
+
+    /// Ends the last frame and writes the seek table if asked to, returns the writer, the size of
+    /// the compressed output and the frames.
+    pub fn finish(mut self) -> io::Result<(W, u64, Vec<Frame>)> {
+        self.end_frame()?;
+        let mut writer = self.writer.take().expect("the last frame was ended");
+        if self.seek_table {
+            write_seek_table(&mut writer, &self.frames, self.uncompressed)?;
+        }
+        let size = writer.offset();
+        Ok((writer.into_inner(), size, self.frames))
+    }
+}
+
+/// Writes the seek table of `frames`, at the end of the `uncompressed` bytes they hold.
+fn write_seek_table(writer: &mut OffsetWriter<impl Write>, frames: &[Frame], uncompressed: u64) -> io::Result<()> {
+    let end = Frame {
+        compressed_offset: writer.offset(),
+        uncompressed_offset: uncompressed,
+    };
+    let too_large = || io::Error::other("zstd frame too large for the seek table");
+
+    let mut table = vec![];
+    table.extend(SEEK_TABLE_FRAME_MAGIC.to_le_bytes());
+    table.extend((frames.len() as u32 * 8 + SEEK_TABLE_FOOTER_SIZE as u32).to_le_bytes());
+    for (frame, next) in frames.iter().zip(frames.iter().skip(1).chain([&end])) {
+        let compressed = u32::try_from(next.compressed_offset - frame.compressed_offset).map_err(|_| too_large())?;
+        let uncompressed =
+            u32::try_from(next.uncompressed_offset - frame.uncompressed_offset).map_err(|_| too_large())?;
+        table.extend(compressed.to_le_bytes());
+        table.extend(uncompressed.to_le_bytes());
+    }
+    table.extend((frames.len() as u32).to_le_bytes());
+    // No checksums
+    table.push(0);
+    table.extend(SEEK_TABLE_FOOTER_MAGIC.to_le_bytes());
+    writer.write_all(&table)
+}
+
+/// The frames of a file of the zstd seekable format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekTable {
+    frames: Vec<Frame>,
+    /// Where the frames end, in the file and in the decompressed data
+    end: Frame,
+}
+
+impl SeekTable {
+    /// Reads the seek table at the end of `reader`, if it has one.
+    pub fn read(reader: &mut (impl Read + Seek)) -> io::Result<Option<Self>> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        if file_size < SEEK_TABLE_FOOTER_SIZE + 8 {
+            return Ok(None);
+        }
+
+        let mut footer = [0; SEEK_TABLE_FOOTER_SIZE as usize];
+        reader.seek(SeekFrom::End(-(SEEK_TABLE_FOOTER_SIZE as i64)))?;
+        reader.read_exact(&mut footer)?;
+        let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let (frame_count, descriptor) = (u32_at(&footer, 0) as u64, footer[4]);
+        // The other bits of the descriptor are reserved
+        if u32_at(&footer, 5) != SEEK_TABLE_FOOTER_MAGIC || descriptor & 0x7f != 0 {
+            return Ok(None);
+        }
+
+        let entry_size = if descriptor & 0x80 != 0 { 12 } else { 8 };
+        let table_size = 8 + frame_count * entry_size + SEEK_TABLE_FOOTER_SIZE;
+        if table_size > file_size {
+            return Ok(None);
+        }
+        let mut table = vec![0; table_size as usize];
+        reader.seek(SeekFrom::End(-(table_size as i64)))?;
+        reader.read_exact(&mut table)?;
+        if u32_at(&table, 0) != SEEK_TABLE_FRAME_MAGIC || u32_at(&table, 4) as u64 != table_size - 8 {
+            return Ok(None);
+        }
+
+        let mut frames = vec![];
+        let mut end = Frame {
+            compressed_offset: 0,
+            uncompressed_offset: 0,
+        };
+        for entry in table[8..].chunks_exact(entry_size as usize).take(frame_count as usize) {
+            frames.push(end);
+            end.compressed_offset += u32_at(entry, 0) as u64;
+            end.uncompressed_offset += u32_at(entry, 4) as u64;
+        }
+        // The frames must fill the file up to the table
+        if end.compressed_offset != file_size - table_size {
+            return Ok(None);
+        }
+        Ok(Some(Self { frames, end }))
+    }
+
+    /// The frame holding the decompressed byte at `position`.
+    fn frame_of(&self, position: u64) -> Frame {
+        let index = self
+            .frames
+            .partition_point(|frame| frame.uncompressed_offset <= position)
+            .saturating_sub(1);
+        self.frames.get(index).copied().unwrap_or(self.end)
+    }
+}
+
+/// Encoder of the zstd seekable format, finishing the output when dropped, like the `auto_finish`
+/// encoders of the codecs
+pub struct SeekableEncoder<W: Write>(Option<FramedEncoder<W>>);
+
+impl<W: Write> SeekableEncoder<W> {
+    pub fn new(writer: W, level: i32, threads: u32) -> Self {
+        Self(Some(FramedEncoder::new(writer, level, threads).with_seek_table()))
+    }
+}
+
+impl<W: Write> Write for SeekableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.as_mut().expect("only taken when dropped").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.as_mut().expect("only taken when dropped").flush()
+    }
+}
+
+impl<W: Write> Drop for SeekableEncoder<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.0.take() {
+            let _ = encoder.finish();
+        }
+    }
+}
+
+/// Decoder of the zstd seekable format, which seeks by decoding from the start of the frame
+/// holding the target instead of from the start of the file
+pub struct SeekableDecoder<R: Read + Seek> {
+    table: SeekTable,
+    /// Decoder from the current position to the end of the frames, `None` once it ran into an
+    /// error
+    decoder: Option<zstd::stream::read::Decoder<'static, io::BufReader<io::Take<R>>>>,
+    /// Position in the decompressed data
+    position: u64,
+}
+
+impl<R: Read + Seek> SeekableDecoder<R> {
+    /// Decoder of `reader`, if it has a seek table.
+    pub fn new(mut reader: R) -> io::Result<Option<Self>> {
+        let Some(table) = SeekTable::read(&mut reader)? else {
+            return Ok(None);
+        };
+        let mut decoder = Self {
+            table,
+            decoder: None,
+            position: 0,
+        };
+        decoder.decode_from(reader, 0)?;
+        Ok(Some(decoder))
+    }
+
+    /// Starts decoding `reader` from the start of the frame holding `position`, then skips to it.
+    fn decode_from(&mut self, mut reader: R, position: u64) -> io::Result<()> {
+        let frame = self.table.frame_of(position);
+        reader.seek(SeekFrom::Start(frame.compressed_offset))?;
+        let frames = reader.take(self.table.end.compressed_offset - frame.compressed_offset);
+        let mut decoder = zstd::stream::read::Decoder::new(frames)?;
+        let skip = position.min(self.table.end.uncompressed_offset) - frame.uncompressed_offset;
+        io::copy(&mut (&mut decoder).take(skip), &mut io::sink())?;
+        self.decoder = Some(decoder);
+        self.position = frame.uncompressed_offset + skip;
+        Ok(())
+    }
+
+    fn decoder(&mut self) -> io::Result<&mut zstd::stream::read::Decoder<'static, io::BufReader<io::Take<R>>>> {
+        self.decoder
+            .as_mut()
+            .ok_or_else(|| io::Error::other("the zstd seekable file couldn't be decoded"))
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.decoder()?.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.table.end.uncompressed_offset.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"))?;
+
+        let current_frame = self.table.frame_of(self.position);
+        if target < self.position || self.table.frame_of(target) != current_frame {
+            // Decoding starts over from the frame of the target
+            let decoder = self.decoder.take();
+            let reader = decoder
+                .ok_or_else(|| io::Error::other("the zstd seekable file couldn't be decoded"))?
+                .finish()
+                .into_inner()
+                .into_inner();
+            self.decode_from(reader, target)?;
+        } else {
+            let skip = target - self.position;
+            let skipped = io::copy(&mut self.decoder()?.take(skip), &mut io::sink())?;
+            self.position += skipped;
+        }
+        Ok(self.position)
+    }
+}
+
+impl<W: Write> Write for FramedEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.encoder.is_none() {
+            self.start_frame()?;
+        }
+
+        let frame_start = self.frames.last().expect("a frame was started").uncompressed_offset;
+        let left_in_frame = FRAME_SIZE - (self.uncompressed - frame_start);
+        let len = buf.len().min(left_in_frame as usize);
+        let written = self.encoder.as_mut().expect("a frame was started").write(&buf[..len])?;
+        self.uncompressed += written as u64;
+
+        if self.uncompressed - frame_start == FRAME_SIZE {
+            self.end_frame()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match (&mut self.encoder, &mut self.writer) {
+            (Some(encoder), _) => encoder.flush(),
+            (None, Some(writer)) => writer.flush(),
+            (None, None) => unreachable!("either the encoder or its writer is kept"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_frames_decode_as_one_stream() {
+        let data: Vec<u8> = (0..FRAME_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+
+        let mut encoder = FramedEncoder::new(vec![], 3, 1);
+        encoder.write_all(&data).unwrap();
+        let (compressed, size, frames) = encoder.finish().unwrap();
+        assert_eq!(size, compressed.len() as u64);
+
+        let starts: Vec<u64> = frames.iter().map(|frame| frame.uncompressed_offset).collect();
+        assert_eq!(starts, [0, FRAME_SIZE, FRAME_SIZE * 2]);
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data);
+
+        // Decoding can start at any frame
+        let last = frames[2];
+        let mut rest = vec![];
+        zstd::stream::read::Decoder::new(&compressed[last.compressed_offset as usize..])
+            .unwrap()
+            .read_to_end(&mut rest)
+            .unwrap();
+        assert_eq!(rest, data[last.uncompressed_offset as usize..]);
+    }
+
+    #[test]
+    fn test_seekable_decoder() {
+        let data: Vec<u8> = (0..FRAME_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+
+        let mut compressed = vec![];
+        let mut encoder = SeekableEncoder::new(&mut compressed, 3, 1);
+        encoder.write_all(&data).unwrap();
+        drop(encoder);
+        // Other decoders skip the seek table
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data);
+
+        let mut reader = io::Cursor::new(&compressed);
+        let table = SeekTable::read(&mut reader).unwrap().unwrap();
+        let starts: Vec<u64> = table.frames.iter().map(|frame| frame.uncompressed_offset).collect();
+        assert_eq!(starts, [0, FRAME_SIZE, FRAME_SIZE * 2]);
+        assert_eq!(table.end.uncompressed_offset, data.len() as u64);
+
+        let mut decoder = SeekableDecoder::new(reader).unwrap().unwrap();
+        let mut read_at = |position: u64| {
+            assert_eq!(decoder.seek(SeekFrom::Start(position)).unwrap(), position);
+            let mut buf = [0; 16];
+            decoder.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[position as usize..position as usize + 16]);
+        };
+        // Forward within a frame, to later frames and back
+        for position in [10, 1000, FRAME_SIZE * 2 + 5, FRAME_SIZE - 8, 0] {
+            read_at(position);
+        }
+
+        // Plain zstd files have no seek table
+        let plain = zstd::encode_all(&data[..], 3).unwrap();
+        assert!(SeekableDecoder::new(io::Cursor::new(plain)).unwrap().is_none());
+    }
+}
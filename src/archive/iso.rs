@@ -0,0 +1,892 @@
+//! ISO 9660 images, like the ones of CDs and installers, read without mounting them.
+//!
+//! After 16 sectors of system area, volume descriptors point to the root directory, whose records
+//! point to the extents of files and other directories. The Rock Ridge extensions, in the system
+//! use area of records, give POSIX names, permissions, timestamps and symlinks. Otherwise the
+//! Joliet volume, with Unicode names, is read if there is one. Images can only be extracted and
+//! listed.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use filetime_creation::FileTime;
+use fs_err as fs;
+
+#[cfg(unix)]
+use crate::utils::{self, SpecialFileKind};
+use crate::{
+    archive::{
+        cancel::CancellableReader,
+        directories::DeferredDirectories,
+        events::{Event, EventReader},
+        links::DeferredLinks,
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
+    error::FinalError,
+    list::{self, ContentOptions, FileInArchive},
+    utils::EscapedPathDisplay,
+};
+
+const SECTOR_SIZE: u64 = 2048;
+/// Sector of the first volume descriptor, after the system area
+const FIRST_DESCRIPTOR: u64 = 16;
+/// Most volume descriptors that are read before giving up on the terminator
+const MAX_DESCRIPTORS: u64 = 64;
+const DESCRIPTOR_MAGIC: &[u8; 5] = b"CD001";
+const PRIMARY_DESCRIPTOR: u8 = 1;
+const SUPPLEMENTARY_DESCRIPTOR: u8 = 2;
+const TERMINATOR_DESCRIPTOR: u8 = 255;
+/// Escape sequences of the UCS-2 levels of Joliet volumes
+const JOLIET_ESCAPES: [&[u8; 3]; 3] = [b"%/@", b"%/C", b"%/E"];
+/// Largest directory, which is read into memory
+const MAX_DIRECTORY_SIZE: u64 = 64 * 1024 * 1024;
+/// Most continuation areas of the system use of a record
+const MAX_CONTINUATIONS: usize = 32;
+
+/// Flags of directory records
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_ASSOCIATED: u8 = 0x04;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// Types of files in the modes of Rock Ridge, like in `stat`
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+fn invalid_image(reason: &'static str) -> crate::Error {
+    FinalError::with_title("Invalid ISO 9660 image").detail(reason).into()
+}
+
+/// The first record of the directory `data`, the "." one, which is at least as long as a record
+/// without name.
+fn first_record(data: &[u8]) -> crate::Result<&[u8]> {
+    data.first()
+        .filter(|&&len| len >= 34)
+        .and_then(|&len| data.get(..len as usize))
+        .ok_or_else(|| invalid_image("A directory is corrupted"))
+}
+
+/// The little-endian half of a "both-endian" number, or a little-endian one
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+/// A time in the 7 bytes of directory records, in seconds since the Unix epoch
+fn record_time(bytes: &[u8]) -> Option<i64> {
+    let date =
+        time::Date::from_calendar_date(1900 + bytes[0] as i32, time::Month::try_from(bytes[1]).ok()?, bytes[2]).ok()?;
+    let offset = time::UtcOffset::from_whole_seconds(bytes[6] as i8 as i32 * 15 * 60).ok()?;
+    Some(
+        date.with_hms(bytes[3], bytes[4], bytes[5])
+            .ok()?
+            .assume_offset(offset)
+            .unix_timestamp(),
+    )
+}
+
+/// A time in the 17 bytes of volume descriptors, digits of the date and time and an offset
+fn long_time(bytes: &[u8]) -> Option<i64> {
+    let digits = std::str::from_utf8(&bytes[..16]).ok()?;
+    let number = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u16>().ok();
+    let date = time::Date::from_calendar_date(
+        number(0..4)? as i32,
+        time::Month::try_from(number(4..6)? as u8).ok()?,
+        number(6..8)? as u8,
+    )
+    .ok()?;
+    let offset = time::UtcOffset::from_whole_seconds(bytes[16] as i8 as i32 * 15 * 60).ok()?;
+    let time = date.with_hms(number(8..10)? as u8, number(10..12)? as u8, number(12..14)? as u8);
+    Some(time.ok()?.assume_offset(offset).unix_timestamp())
+}
+
+/// A directory record
+#[derive(Clone)]
+struct Record {
+    /// Where its data starts, in bytes
+    start: u64,
+    size: u64,
+    flags: u8,
+    mtime: Option<i64>,
+    name: Vec<u8>,
+    system_use: Vec<u8>,
+}
+
+impl Record {
+    /// Parses the record at the start of `bytes`, which is as long as its first byte says.
+    fn parse(bytes: &[u8], block_size: u64) -> crate::Result<Self> {
+        let name_len = *bytes
+            .get(32)
+            .ok_or_else(|| invalid_image("A directory record is corrupted"))? as usize;
+        // The name is padded to an even length
+        let system_use_start = 33 + name_len + (name_len + 1) % 2;
+        if bytes.len() < 33 + name_len {
+            return Err(invalid_image("A directory record is corrupted"));
+        }
+        Ok(Self {
+            start: le32(bytes, 2) as u64 * block_size,
+            size: le32(bytes, 10) as u64,
+            flags: bytes[25],
+            mtime: record_time(&bytes[18..25]),
+            name: bytes[33..33 + name_len].to_vec(),
+            system_use: bytes.get(system_use_start..).unwrap_or_default().to_vec(),
+        })
+    }
+
+    /// Whether it's the record of the directory itself, ".", or of its parent, "..".
+    fn is_special(&self) -> bool {
+        matches!(self.name.as_slice(), [0] | [1])
+    }
+
+    fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+}
+
+/// What the Rock Ridge entries of a record say
+#[derive(Default)]
+struct RockRidge {
+    name: Option<Vec<u8>>,
+    mode: Option<u32>,
+    uid: u32,
+    gid: u32,
+    mtime: Option<i64>,
+    /// Device numbers of device nodes
+    rdev: Option<(u32, u32)>,
+    target: Option<Vec<u8>>,
+    /// Where a directory that was moved because it was too deep really starts, in blocks
+    child_link: Option<u64>,
+    /// Whether this directory was moved there because it was too deep, it's read from `child_link`
+    relocated: bool,
+}
+
+impl RockRidge {
+    /// Reads the entries of `area`, a system use area or a continuation of one, returns the
+    /// continuation area that follows it, if any, as its start and size in bytes.
+    fn parse(&mut self, area: &[u8], block_size: u64, link_continues: &mut bool) -> Option<(u64, u64)> {
+        let mut continuation = None;
+        let mut rest = area;
+        while rest.len() >= 4 {
+            let len = rest[2] as usize;
+            if len < 4 || len > rest.len() {
+                break;
+            }
+            let (entry, data) = (&rest[..2], &rest[4..len]);
+            rest = &rest[len..];
+            match entry {
+                b"PX" if data.len() >= 32 => {
+                    self.mode = Some(le32(data, 0));
+                    self.uid = le32(data, 16);
+                    self.gid = le32(data, 24);
+                }
+                b"NM" if !data.is_empty() => {
+                    let name = self.name.get_or_insert_with(Vec::new);
+                    match data[0] & 0x06 {
+                        0x02 => name.push(b'.'),
+                        0x04 => name.extend_from_slice(b".."),
+                        _ => name.extend_from_slice(&data[1..]),
+                    }
+                }
+                b"SL" if !data.is_empty() => {
+                    let target = self.target.get_or_insert_with(Vec::new);
+                    let mut components = &data[1..];
+                    while components.len() >= 2 {
+                        let (flags, len) = (components[0], components[1] as usize);
+                        let Some(content) = components.get(2..2 + len) else {
+                            break;
+                        };
+                        components = &components[2 + len..];
+                        if flags & 0x08 != 0 {
+                            target.clear();
+                            target.push(b'/');
+                            *link_continues = false;
+                            continue;
+                        }
+                        if !*link_continues && !target.is_empty() && !target.ends_with(b"/") {
+                            target.push(b'/');
+                        }
+                        match flags & 0x06 {
+                            0x02 => target.push(b'.'),
+                            0x04 => target.extend_from_slice(b".."),
+                            _ => target.extend_from_slice(content),
+                        }
+                        *link_continues = flags & 0x01 != 0;
+                    }
+                }
+                b"TF" if !data.is_empty() => {
+                    let flags = data[0];
+                    let size = if flags & 0x80 != 0 { 17 } else { 7 };
+                    // The creation time comes before the modification time
+                    if flags & 0x02 != 0 {
+                        let start = 1 + if flags & 0x01 != 0 { size } else { 0 };
+                        self.mtime = match data.get(start..start + size) {
+                            Some(time) if size == 17 => long_time(time),
+                            Some(time) => record_time(time),
+                            None => None,
+                        };
+                    }
+                }
+                b"PN" if data.len() >= 16 => {
+                    let (high, low) = (le32(data, 0), le32(data, 8));
+                    #[cfg(unix)]
+                    let rdev = if high == 0 {
+                        utils::device_numbers(low as u64)
+                    } else {
+                        (high, low)
+                    };
+                    #[cfg(not(unix))]
+                    let rdev = (high, low);
+                    self.rdev = Some(rdev);
+                }
+                b"CL" if data.len() >= 4 => self.child_link = Some(le32(data, 0) as u64),
+                b"RE" => self.relocated = true,
+                b"CE" if data.len() >= 24 => {
+                    continuation = Some((
+                        le32(data, 0) as u64 * block_size + le32(data, 8) as u64,
+                        le32(data, 16) as u64,
+                    ));
+                }
+                b"ST" => break,
+                _ => {}
+            }
+        }
+        continuation
+    }
+}
+
+/// An entry of the image, a file, a directory, a symlink or a special file
+struct Entry {
+    path: PathBuf,
+    /// Type and permissions, the permissions are only known with Rock Ridge
+    mode: u32,
+    has_permissions: bool,
+    uid: u32,
+    gid: u32,
+    mtime: Option<i64>,
+    rdev: (u32, u32),
+    target: Option<PathBuf>,
+    /// The extents of its data, as their start and size in bytes, files larger than 4 GiB have more
+    /// than one
+    extents: Vec<(u64, u64)>,
+}
+
+impl Entry {
+    fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    fn kind(&self) -> Option<EntryKind> {
+        match self.file_type() {
+            S_IFREG => Some(EntryKind::File),
+            S_IFDIR => Some(EntryKind::Directory),
+            S_IFLNK => Some(EntryKind::Symlink),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.file_type() {
+            S_IFREG => self.extents.iter().map(|(_, size)| size).sum(),
+            _ => 0,
+        }
+    }
+}
+
+/// The volume whose directories are read
+struct Volume {
+    root: Record,
+    block_size: u64,
+    joliet: bool,
+    /// Whether records have Rock Ridge entries, and how many bytes come before them
+    rock_ridge: Option<usize>,
+}
+
+/// Reader of the directories and files of an image
+struct Image<R> {
+    reader: R,
+    volume: Volume,
+}
+
+impl<R: Read + Seek> Image<R> {
+    /// Reads the volume descriptors of the image in `reader`.
+    fn new(mut reader: R) -> crate::Result<Self> {
+        let mut primary = None;
+        let mut joliet = None;
+        for index in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+            let mut descriptor = [0; SECTOR_SIZE as usize];
+            reader.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+            reader
+                .read_exact(&mut descriptor)
+                .map_err(|_| invalid_image("It is truncated"))?;
+            if &descriptor[1..6] != DESCRIPTOR_MAGIC {
+                return Err(invalid_image("A volume descriptor is corrupted"));
+            }
+
+            let volume = || -> crate::Result<Volume> {
+                let block_size = u16::from_le_bytes([descriptor[128], descriptor[129]]) as u64;
+                if !block_size.is_power_of_two() || block_size < 512 {
+                    return Err(invalid_image("A volume descriptor is corrupted"));
+                }
+                let joliet = JOLIET_ESCAPES
+                    .iter()
+                    .any(|escape| descriptor[88..120].contains_str(escape));
+                let root = Record::parse(&descriptor[156..190], block_size)?;
+                Ok(Volume {
+                    root,
+                    block_size,
+                    joliet,
+                    rock_ridge: None,
+                })
+            };
+            match descriptor[0] {
+                PRIMARY_DESCRIPTOR if primary.is_none() => primary = Some(volume()?),
+                SUPPLEMENTARY_DESCRIPTOR if joliet.is_none() => joliet = Some(volume()?).filter(|volume| volume.joliet),
+                TERMINATOR_DESCRIPTOR => break,
+                _ => {}
+            }
+        }
+        let Some(primary) = primary else {
+            return Err(invalid_image("It has no primary volume descriptor"));
+        };
+
+        let mut image = Self {
+            reader,
+            volume: primary,
+        };
+        // The "." record of the root directory tells whether the volume has Rock Ridge entries
+        let root_directory = image.read_directory(&image.volume.root.clone())?;
+        let dot = Record::parse(first_record(&root_directory)?, image.volume.block_size)?;
+        let sharing_protocol = match dot.system_use.get(..7) {
+            Some([b'S', b'P', _, _, 0xBE, 0xEF, skip]) => Some(*skip as usize),
+            _ => None,
+        };
+        if let Some(skip) = sharing_protocol {
+            image.volume.rock_ridge = Some(skip);
+        } else if let Some(joliet) = joliet {
+            image.volume = joliet;
+        }
+        Ok(image)
+    }
+
+    /// Reads `size` bytes from `start`.
+    fn read_at(&mut self, start: u64, size: u64) -> crate::Result<Vec<u8>> {
+        if size > MAX_DIRECTORY_SIZE {
+            return Err(invalid_image("A directory is too large"));
+        }
+        let mut data = vec![0; size as usize];
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.reader
+            .read_exact(&mut data)
+            .map_err(|_| invalid_image("It is truncated"))?;
+        Ok(data)
+    }
+
+    /// Reads the records of `directory`, which start with "." and "..".
+    fn read_directory(&mut self, directory: &Record) -> crate::Result<Vec<u8>> {
+        let data = self.read_at(directory.start, directory.size)?;
+        first_record(&data)?;
+        Ok(data)
+    }
+
+    fn rock_ridge(&mut self, record: &Record) -> crate::Result<RockRidge> {
+        let mut rock_ridge = RockRidge::default();
+        let Some(skip) = self.volume.rock_ridge else {
+            return Ok(rock_ridge);
+        };
+        let block_size = self.volume.block_size;
+        let mut link_continues = false;
+        let area = record.system_use.get(skip..).unwrap_or_default();
+        let mut continuation = rock_ridge.parse(area, block_size, &mut link_continues);
+        for _ in 0..MAX_CONTINUATIONS {
+            let Some((start, size)) = continuation else {
+                break;
+            };
+            let area = self.read_at(start, size)?;
+            continuation = rock_ridge.parse(&area, block_size, &mut link_continues);
+        }
+        Ok(rock_ridge)
+    }
+
+    /// The name of `record` in the volume, without the version of ISO 9660 names, like ";1".
+    fn name(&self, record: &Record) -> Vec<u8> {
+        let name = if self.volume.joliet {
+            let units = record
+                .name
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]));
+            char::decode_utf16(units)
+                .map(|char| char.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect::<String>()
+                .into_bytes()
+        } else {
+            record.name.clone()
+        };
+        let mut name = match name.rfind_byte(b';') {
+            Some(version) => name[..version].to_vec(),
+            None => name,
+        };
+        // Files without extensions end with a dot in ISO 9660 names
+        if !self.volume.joliet && name.ends_with(b".") && !record.is_dir() {
+            name.pop();
+        }
+        name
+    }
+
+    /// Reads every directory, returns the entries, each directory before the entries inside of it.
+    fn entries(&mut self) -> crate::Result<Vec<Entry>> {
+        let block_size = self.volume.block_size;
+        let root = self.volume.root.clone();
+        let mut entries: Vec<Entry> = vec![];
+        let mut visited = HashSet::from([root.start]);
+        let mut directories = vec![(root, PathBuf::new())];
+        while let Some((directory, directory_path)) = directories.pop() {
+            let data = self.read_directory(&directory)?;
+            let mut subdirectories = vec![];
+            // Files larger than 4 GiB are in records of the same name, every one but the last with
+            // the multi-extent flag
+            let mut extents_continue = false;
+            let mut position = 0;
+            while position < data.len() {
+                let len = data[position] as usize;
+                // Records don't cross sectors, the rest of a sector is padded with zeros
+                if len == 0 {
+                    position = (position as u64 / SECTOR_SIZE + 1) as usize * SECTOR_SIZE as usize;
+                    continue;
+                }
+                let Some(bytes) = data.get(position..position + len) else {
+                    return Err(invalid_image("A directory record is corrupted"));
+                };
+                position += len;
+                let mut record = Record::parse(bytes, block_size)?;
+                if record.is_special() || record.flags & FLAG_ASSOCIATED != 0 {
+                    continue;
+                }
+
+                let rock_ridge = self.rock_ridge(&record)?;
+                if rock_ridge.relocated {
+                    continue;
+                }
+                let name = rock_ridge.name.clone().unwrap_or_else(|| self.name(&record));
+                if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0) {
+                    return Err(invalid_image("A file name is invalid"));
+                }
+                let path = directory_path.join(name.to_path_lossy());
+
+                if let Some(child_link) = rock_ridge.child_link {
+                    // The directory's own "." record has its size
+                    let data = self.read_at(child_link * block_size, SECTOR_SIZE)?;
+                    let dot = Record::parse(first_record(&data)?, block_size)?;
+                    record = Record {
+                        start: dot.start,
+                        size: dot.size,
+                        flags: FLAG_DIRECTORY,
+                        ..record
+                    };
+                }
+
+                let is_dir = record.is_dir() || rock_ridge.child_link.is_some();
+                let default_type = if is_dir { S_IFDIR } else { S_IFREG };
+                let mode = rock_ridge.mode.unwrap_or(default_type);
+                let continued = extents_continue;
+                extents_continue = record.flags & FLAG_MULTI_EXTENT != 0 && !is_dir;
+                if continued {
+                    match entries.last_mut() {
+                        Some(previous) if previous.path == path => {
+                            previous.extents.push((record.start, record.size));
+                            continue;
+                        }
+                        _ => return Err(invalid_image("The extents of a file are corrupted")),
+                    }
+                }
+
+                if is_dir {
+                    if !visited.insert(record.start) {
+                        return Err(invalid_image("A directory is inside of itself"));
+                    }
+                    subdirectories.push((
+                        Record {
+                            name: vec![],
+                            system_use: vec![],
+                            ..record
+                        },
+                        path.clone(),
+                    ));
+                }
+                entries.push(Entry {
+                    path,
+                    mode,
+                    has_permissions: rock_ridge.mode.is_some(),
+                    uid: rock_ridge.uid,
+                    gid: rock_ridge.gid,
+                    mtime: rock_ridge.mtime.or(record.mtime),
+                    rdev: rock_ridge.rdev.unwrap_or_default(),
+                    target: rock_ridge.target.map(|target| target.to_path_lossy().into_owned()),
+                    extents: if is_dir {
+                        vec![]
+                    } else {
+                        vec![(record.start, record.size)]
+                    },
+                });
+            }
+            directories.extend(subdirectories.into_iter().rev());
+        }
+        Ok(entries)
+    }
+
+    /// Reader of the data of `entry`.
+    fn data<'a>(&'a mut self, entry: &'a Entry) -> EntryData<'a, R> {
+        EntryData {
+            reader: &mut self.reader,
+            extents: entry.extents.iter(),
+            left: 0,
+        }
+    }
+}
+
+/// Data of an entry of an `Image`, in one or more extents
+struct EntryData<'a, R> {
+    reader: &'a mut R,
+    extents: std::slice::Iter<'a, (u64, u64)>,
+    /// Bytes of the current extent that weren't read
+    left: u64,
+}
+
+impl<R: Read + Seek> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.left == 0 {
+            let Some(&(start, size)) = self.extents.next() else {
+                return Ok(0);
+            };
+            self.reader.seek(SeekFrom::Start(start))?;
+            self.left = size;
+        }
+        let len = buf.len().min(self.left.try_into().unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated ISO 9660 image"));
+        }
+        self.left -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Unpacks the image given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    unpack_entries(reader, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
+    let mut image = Image::new(reader)?;
+
+    let mut files_unpacked = 0;
+    // Symlinks are created last, so that no entry is written through one of them
+    let mut deferred_links = DeferredLinks::new(unpack_options.windows_links);
+    let mut deferred_directories = DeferredDirectories::default();
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    for entry in image.entries()? {
+        unpack_options.cancel.check()?;
+        let filter = &unpack_options.filter;
+        if !filter.matches(entry.kind()) || !filter.matches_path(&entry.path) {
+            continue;
+        }
+
+        let offset = entry.extents.first().map(|&(start, _)| start);
+        context.start_entry(&entry.path, offset);
+        events.handle(Event::EntryStarted {
+            path: &entry.path,
+            size: entry.size(),
+        });
+        let Some(destination) = destinations.destination(&entry.path, entry.file_type() == S_IFDIR) else {
+            continue;
+        };
+        if entry.kind() == Some(EntryKind::File) && destinations.is_extracted(&destination, entry.size(), entry.mtime) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mode = entry.has_permissions.then_some(entry.mode & 0o7777);
+        let mtime = entry.mtime.map(|mtime| FileTime::from_unix_time(mtime, 0));
+        match entry.file_type() {
+            S_IFDIR => {
+                fs::create_dir_all(&destination)?;
+                deferred_directories.defer(&destination, &entry.path, mode, mtime)?;
+            }
+            S_IFREG => {
+                let mut file = fs::File::create(&destination)?;
+                let data = EventReader::new(image.data(&entry), events);
+                io::copy(&mut CancellableReader::new(data, &unpack_options.cancel), &mut file)?;
+                #[cfg(unix)]
+                if let Some(mode) = mode {
+                    use std::os::unix::fs::PermissionsExt;
+                    let permissions = std::fs::Permissions::from_mode(mode);
+                    unpack_options.lost_metadata.check(
+                        MetadataKind::Permissions,
+                        &entry.path,
+                        file.set_permissions(permissions),
+                    )?;
+                }
+                if let Some(mtime) = mtime {
+                    let times = filetime_creation::set_file_handle_times(file.file(), None, Some(mtime), None);
+                    unpack_options
+                        .lost_metadata
+                        .check(MetadataKind::ModificationTime, &entry.path, times)?;
+                }
+            }
+            S_IFLNK => {
+                let Some(target) = entry.target.clone() else {
+                    return Err(invalid_image("A symlink has no target"));
+                };
+                deferred_links.defer(destination.clone(), target);
+            }
+            #[cfg(unix)]
+            S_IFIFO => utils::create_fifo(&destination, entry.mode & 0o7777)?,
+            #[cfg(unix)]
+            S_IFCHR | S_IFBLK if unpack_options.devices => {
+                let kind = if entry.file_type() == S_IFCHR {
+                    SpecialFileKind::CharDevice
+                } else {
+                    SpecialFileKind::BlockDevice
+                };
+                let (major, minor) = entry.rdev;
+                utils::create_device(&destination, kind, entry.mode, major, minor)?;
+            }
+            S_IFCHR | S_IFBLK if !unpack_options.devices => {
+                events.handle(Event::Warning(format!(
+                    "Skipping device node '{}', use '--devices' to restore it.",
+                    EscapedPathDisplay::new(&entry.path)
+                )));
+                continue;
+            }
+            _ => {
+                events.handle(Event::Warning(format!(
+                    "Skipping '{}', files of its type can't be extracted here.",
+                    EscapedPathDisplay::new(&entry.path)
+                )));
+                continue;
+            }
+        }
+
+        if entry.has_permissions {
+            unpack_options.restore_owner(&destination, &entry.path, entry.uid, entry.gid)?;
+        }
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: entry.size(),
+        });
+        files_unpacked += 1;
+        context.finish_entry();
+    }
+    deferred_links.create_all(output_folder, unpack_options.quiet)?;
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
+
+    Ok(files_unpacked)
+}
+
+/// List contents of `reader`, returning a vector of archive entries
+pub fn list_archive<R: Read + Seek + 'static>(
+    reader: R,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    struct Files<R> {
+        image: Image<R>,
+        entries: std::vec::IntoIter<Entry>,
+        contents: ContentOptions,
+    }
+    impl<R: Read + Seek> Iterator for Files<R> {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let entry = self.entries.next()?;
+            let mut file_in_archive = FileInArchive {
+                path: entry.path.clone(),
+                is_dir: entry.file_type() == S_IFDIR,
+                is_symlink: entry.file_type() == S_IFLNK,
+                is_executable: entry.file_type() == S_IFREG && entry.has_permissions && entry.mode & 0o111 != 0,
+                size: entry.size(),
+                preview: None,
+                file_type: None,
+            };
+            if self.contents.is_needed() && entry.file_type() == S_IFREG {
+                if let Err(err) = list::read_contents(&mut file_in_archive, self.image.data(&entry), &self.contents) {
+                    return Some(Err(err.into()));
+                }
+            }
+            Some(Ok(file_in_archive))
+        }
+    }
+
+    let mut image = Image::new(reader)?;
+    let entries = image.entries()?;
+    Ok(Files {
+        image,
+        entries: entries.into_iter(),
+        contents,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A directory record, recorded at 2020-01-02 03:04:05 UTC
+    fn record(extent: u32, size: u32, flags: u8, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[18..25].copy_from_slice(&[120, 1, 2, 3, 4, 5, 0]);
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        record.extend_from_slice(system_use);
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn susp_entry(signature: &[u8; 2], data: &[u8]) -> Vec<u8> {
+        let mut entry = signature.to_vec();
+        entry.extend_from_slice(&[4 + data.len() as u8, 1]);
+        entry.extend_from_slice(data);
+        entry
+    }
+
+    fn px(mode: u32) -> Vec<u8> {
+        let mut data = vec![];
+        for number in [mode, 1, 1000, 1000] {
+            data.extend_from_slice(&number.to_le_bytes());
+            data.extend_from_slice(&number.to_be_bytes());
+        }
+        susp_entry(b"PX", &data)
+    }
+
+    fn nm(name: &str) -> Vec<u8> {
+        susp_entry(b"NM", &[&[0], name.as_bytes()].concat())
+    }
+
+    /// An image with Rock Ridge entries: a file, a symlink and a file in two extents
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; 22 * SECTOR_SIZE as usize];
+        let sector = |index: usize| index * SECTOR_SIZE as usize;
+
+        let primary = &mut image[sector(16)..sector(17)];
+        primary[..7].copy_from_slice(&[PRIMARY_DESCRIPTOR, b'C', b'D', b'0', b'0', b'1', 1]);
+        primary[128..132].copy_from_slice(&[0x00, 0x08, 0x08, 0x00]);
+        primary[156..190].copy_from_slice(&record(18, 2048, FLAG_DIRECTORY, &[0], &[]));
+        image[sector(17)..sector(17) + 7].copy_from_slice(&[TERMINATOR_DESCRIPTOR, b'C', b'D', b'0', b'0', b'1', 1]);
+
+        let sp = susp_entry(b"SP", &[0xBE, 0xEF, 0]);
+        let link = [
+            px(0o120777),
+            nm("link"),
+            susp_entry(b"SL", &[0, 0, 2, b'.', b'.', 0, 4, b'n', b'o', b't', b'e']),
+        ]
+        .concat();
+        let directory = [
+            record(18, 2048, FLAG_DIRECTORY, &[0], &[sp, px(0o040755)].concat()),
+            record(18, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(19, 3, FLAG_MULTI_EXTENT, b"BIG.;1", &[px(0o100644), nm("big")].concat()),
+            record(20, 4, 0, b"BIG.;1", &[px(0o100644), nm("big")].concat()),
+            record(0, 0, 0, b"LINK.;1", &link),
+            record(21, 5, 0, b"NOTES.TXT;1", &[px(0o100750), nm("notes.txt")].concat()),
+        ]
+        .concat();
+        image[sector(18)..sector(18) + directory.len()].copy_from_slice(&directory);
+        image[sector(19)..sector(19) + 3].copy_from_slice(b"abc");
+        image[sector(20)..sector(20) + 4].copy_from_slice(b"defg");
+        image[sector(21)..sector(21) + 5].copy_from_slice(b"notes");
+        image
+    }
+
+    #[test]
+    fn test_rock_ridge_entries() {
+        let mut image = Image::new(Cursor::new(image())).unwrap();
+        let entries = image.entries().unwrap();
+        let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("big"), Path::new("link"), Path::new("notes.txt")]);
+
+        let (big, link, notes) = (&entries[0], &entries[1], &entries[2]);
+        assert_eq!(big.size(), 7);
+        let mut data = vec![];
+        image.data(big).read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdefg");
+
+        assert_eq!(link.file_type(), S_IFLNK);
+        assert_eq!(link.target.as_deref(), Some(Path::new("../note")));
+
+        assert_eq!(notes.mode, 0o100750);
+        assert_eq!((notes.uid, notes.gid), (1000, 1000));
+        assert_eq!(notes.mtime, Some(1_577_934_245));
+    }
+
+    #[test]
+    fn test_plain_names() {
+        let mut image = image();
+        // Without the "SP" entry, the Rock Ridge entries are ignored
+        let root = 18 * SECTOR_SIZE as usize;
+        image[root + 34..root + 36].copy_from_slice(b"XX");
+
+        let mut image = Image::new(Cursor::new(image)).unwrap();
+        let paths: Vec<PathBuf> = image.entries().unwrap().into_iter().map(|entry| entry.path).collect();
+        assert_eq!(paths, [Path::new("BIG"), Path::new("LINK"), Path::new("NOTES.TXT")]);
+    }
+
+    #[test]
+    fn test_malformed_images() {
+        let is_invalid = |image: Vec<u8>| match Image::new(Cursor::new(image)).and_then(|mut image| image.entries()) {
+            Err(crate::Error::Custom { reason }) => reason.to_string().contains("Invalid ISO 9660 image"),
+            _ => false,
+        };
+        let root = 18 * SECTOR_SIZE as usize;
+
+        // The "." record is longer than the root directory
+        let mut bytes = image();
+        bytes[16 * SECTOR_SIZE as usize + 156 + 10..][..4].copy_from_slice(&34u32.to_le_bytes());
+        bytes[root] = 200;
+        assert!(is_invalid(bytes));
+
+        // The "." record is shorter than a record
+        let mut bytes = image();
+        bytes[root] = 20;
+        assert!(is_invalid(bytes));
+
+        // The "." record ends inside of the "SP" entry, before the length of the entries it skips
+        let mut bytes = image();
+        bytes[root] = 40;
+        let image = Image::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(image.volume.rock_ridge, None);
+    }
+}
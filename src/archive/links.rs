@@ -0,0 +1,205 @@
+//! Symlinks extracted on Windows, where creating them requires a privilege or developer mode.
+//!
+//! They are created after every other entry of the archive, so that the junctions and copies
+//! replacing them, which need their targets to exist, can be made.
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{cli::WindowsLinksPolicy, error::FinalError, info, utils::EscapedPathDisplay, warning};
+
+/// Windows' `ERROR_PRIVILEGE_NOT_HELD`, returned when symlinks can't be created
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+/// Symlinks waiting for the rest of the archive to be extracted
+pub struct DeferredLinks {
+    policy: WindowsLinksPolicy,
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl DeferredLinks {
+    pub fn new(policy: WindowsLinksPolicy) -> Self {
+        Self { policy, links: vec![] }
+    }
+
+    /// Remembers that a symlink to `target` must be created at `destination`.
+    pub fn defer(&mut self, destination: PathBuf, target: PathBuf) {
+        self.links.push((destination, target));
+    }
+
+    /// Creates the deferred links inside of `output_folder`, following the policy.
+    ///
+    /// With `WindowsLinksPolicy::Auto`, the first symlink that can't be created switches to
+    /// junctions and copies for it and every following link.
+    pub fn create_all(self, output_folder: &Path, quiet: bool) -> crate::Result<()> {
+        let mut policy = self.policy;
+
+        for (destination, target) in self.links {
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if policy == WindowsLinksPolicy::Auto {
+                match create_symlink(&target, &destination) {
+                    Ok(()) => {
+                        if !quiet {
+                            info!(
+                                inaccessible,
+                                "Created symlink '{}'.",
+                                EscapedPathDisplay::new(&destination)
+                            );
+                        }
+                        continue;
+                    }
+                    Err(err) if err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) => {
+                        info!(
+                            accessible,
+                            "Symlinks can't be created without a privilege or developer mode, creating junctions and copies instead."
+                        );
+                        policy = WindowsLinksPolicy::Junction;
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            create_link(policy, output_folder, &destination, &target, quiet)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the link at `destination` following `policy`, which isn't `WindowsLinksPolicy::Auto`.
+fn create_link(
+    policy: WindowsLinksPolicy,
+    output_folder: &Path,
+    destination: &Path,
+    target: &Path,
+    quiet: bool,
+) -> crate::Result<()> {
+    let skip = |reason: &str| {
+        warning!("Skipping symlink '{}', {reason}.", EscapedPathDisplay::new(destination));
+        Ok(())
+    };
+
+    match policy {
+        WindowsLinksPolicy::Skip => return skip("as asked by '--windows-links skip'"),
+        WindowsLinksPolicy::Auto | WindowsLinksPolicy::Symlink => {
+            create_symlink(target, destination).map_err(|err| {
+                let error = FinalError::with_title(format!(
+                    "Could not create symlink '{}'",
+                    EscapedPathDisplay::new(destination)
+                ))
+                .detail(format!("Error: {err}."));
+                if err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) {
+                    error
+                        .hint("Enable developer mode, or run as administrator, to create symlinks.")
+                        .hint("Use '--windows-links junction' or '--windows-links copy' to extract them anyway.")
+                } else {
+                    error
+                }
+            })?;
+        }
+        WindowsLinksPolicy::Junction | WindowsLinksPolicy::Copy => {
+            // Junctions and copies don't stay relative, they only point to the extracted files
+            let Some(resolved) = resolve_target(output_folder, destination, target) else {
+                return skip("it points outside of the output folder");
+            };
+            let Ok(metadata) = fs::metadata(&resolved) else {
+                return skip("it points to a file that doesn't exist");
+            };
+
+            if policy == WindowsLinksPolicy::Junction && metadata.is_dir() {
+                create_junction(&resolved, destination)?;
+            } else {
+                copy_recursively(&resolved, destination)?;
+            }
+        }
+    }
+
+    if !quiet {
+        info!(inaccessible, "Created '{}'.", EscapedPathDisplay::new(destination));
+    }
+    Ok(())
+}
+
+/// Where the symlink to `target` at `destination` points to, if it's inside of `output_folder`.
+fn resolve_target(output_folder: &Path, destination: &Path, target: &Path) -> Option<PathBuf> {
+    let link_dir = destination.parent()?.strip_prefix(output_folder).ok()?;
+
+    let mut parts: Vec<_> = link_dir.components().collect();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => parts.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::Prefix(_) | Component::RootDir => return None,
+        }
+    }
+
+    Some(output_folder.join(parts.iter().collect::<PathBuf>()))
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    if !fs::metadata(from)?.is_dir() {
+        return fs::copy(from, to).map(drop);
+    }
+
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, destination: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    // Windows symlinks are either to files or to directories, unknown targets are taken as files
+    let is_dir = destination.parent().is_some_and(|parent| parent.join(target).is_dir());
+    if is_dir {
+        symlink_dir(target, destination)
+    } else {
+        symlink_file(target, destination)
+    }
+}
+
+#[cfg(not(windows))]
+fn create_symlink(target: &Path, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, destination)
+}
+
+#[cfg(windows)]
+fn create_junction(target: &Path, destination: &Path) -> io::Result<()> {
+    junction::create(target, destination)
+}
+
+#[cfg(not(windows))]
+fn create_junction(_target: &Path, _destination: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_target() {
+        let output_folder = Path::new("out");
+        let resolve =
+            |destination: &str, target: &str| resolve_target(output_folder, Path::new(destination), Path::new(target));
+
+        assert_eq!(resolve("out/a/link", "b"), Some(PathBuf::from("out/a/b")));
+        assert_eq!(resolve("out/a/link", "../b/c"), Some(PathBuf::from("out/b/c")));
+        assert_eq!(resolve("out/a/link", "./b"), Some(PathBuf::from("out/a/b")));
+        assert_eq!(resolve("out/a/link", "../../b"), None);
+        assert_eq!(resolve("out/link", "/etc/passwd"), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Metadata of extracted entries that couldn't be restored, like permissions on FAT filesystems or
+//! owners without root.
+//!
+//! Their entries are extracted anyway, and what was lost is reported once the extraction ends, a
+//! warning for each kind of metadata. With `--strict-metadata`, the first loss fails the
+//! extraction instead.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    archive::events::{Event, EventHandler},
+    error::FinalError,
+    utils::EscapedPathDisplay,
+};
+
+/// Kind of metadata of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetadataKind {
+    Permissions,
+    ModificationTime,
+    Owner,
+    ExtendedAttributes,
+}
+
+impl fmt::Display for MetadataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Permissions => "permissions",
+            Self::ModificationTime => "modification time",
+            Self::Owner => "owner",
+            Self::ExtendedAttributes => "extended attributes",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The first entry that lost a kind of metadata, and how many did
+struct Loss {
+    path: PathBuf,
+    reason: String,
+    count: usize,
+}
+
+/// Metadata lost by the extractions of a run, which may run in parallel
+#[derive(Default)]
+pub struct LostMetadata {
+    /// Fail on the first loss, see `--strict-metadata`
+    strict: bool,
+    losses: Mutex<BTreeMap<MetadataKind, Loss>>,
+}
+
+impl LostMetadata {
+    pub fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            losses: Mutex::default(),
+        }
+    }
+
+    /// Notes that the `kind` of the entry extracted to `path` couldn't be restored, because of
+    /// `reason`, fails if strict.
+    pub fn record(&self, kind: MetadataKind, path: &Path, reason: impl fmt::Display) -> crate::Result<()> {
+        if self.strict {
+            let error = FinalError::with_title(format!(
+                "Could not restore the {kind} of '{}'",
+                EscapedPathDisplay::new(path)
+            ))
+            .detail(reason.to_string())
+            .hint("Extract without '--strict-metadata' to be warned about it instead");
+            return Err(error.into());
+        }
+
+        let mut losses = self.losses.lock().expect("not poisoned");
+        let loss = losses.entry(kind).or_insert_with(|| Loss {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+            count: 0,
+        });
+        loss.count += 1;
+        Ok(())
+    }
+
+    /// Records the failure of restoring the `kind` of `path`, if `result` is one.
+    pub fn check<E: fmt::Display>(&self, kind: MetadataKind, path: &Path, result: Result<(), E>) -> crate::Result<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => self.record(kind, path, err),
+        }
+    }
+
+    /// Reports what was lost to `events`, a warning for each kind of metadata.
+    pub fn report(&self, events: &dyn EventHandler) {
+        let losses = self.losses.lock().expect("not poisoned");
+        for (kind, loss) in losses.iter() {
+            let path = EscapedPathDisplay::new(&loss.path);
+            let message = match loss.count {
+                1 => format!("Could not restore the {kind} of '{path}': {}", loss.reason),
+                count => format!(
+                    "Could not restore the {kind} of {count} entries, like '{path}': {}",
+                    loss.reason
+                ),
+            };
+            events.handle(Event::Warning(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lost_metadata() {
+        let lost = LostMetadata::new(false);
+        lost.record(MetadataKind::Owner, Path::new("a"), "not root").unwrap();
+        lost.record(MetadataKind::Owner, Path::new("b"), "not root").unwrap();
+        lost.check(MetadataKind::Permissions, Path::new("c"), Ok::<_, String>(()))
+            .unwrap();
+        lost.check(MetadataKind::Permissions, Path::new("d"), Err("read-only"))
+            .unwrap();
+
+        let losses = lost.losses.lock().unwrap();
+        assert_eq!(losses[&MetadataKind::Owner].count, 2);
+        assert_eq!(losses[&MetadataKind::Owner].path, Path::new("a"));
+        assert_eq!(losses[&MetadataKind::Permissions].count, 1);
+        assert_eq!(losses[&MetadataKind::Permissions].path, Path::new("d"));
+
+        let strict = LostMetadata::new(true);
+        assert!(strict.record(MetadataKind::Owner, Path::new("a"), "not root").is_err());
+    }
+}
@@ -1,9 +1,376 @@
 //! Archive compression algorithms
 
+use std::{
+    borrow::Cow,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fs_err as fs;
+use once_cell::sync::OnceCell;
+
+use self::{
+    cancel::CancellationToken,
+    events::EventHandler,
+    filter::EntryFilter,
+    metadata::{LostMetadata, MetadataKind},
+};
+use crate::{
+    cli::{
+        CaseCollisionsPolicy, DuplicateEntriesPolicy, EntryKind, IgnoreExisting, LongNamesPolicy, NameNormalization,
+        SpecialFilesPolicy, WindowsLinksPolicy, ZipEncryption,
+    },
+    extension::{
+        self,
+        CompressionFormat::{self, *},
+    },
+    info,
+    privileges::OwnershipHelper,
+    progress::Tracked,
+    utils::{self, EscapedPathDisplay, FileVisibilityPolicy, LzipDecoder, LzopDecoder, LzwDecoder, SpillBuffer},
+    BUFFER_CAPACITY,
+};
+
+pub mod ar;
+#[cfg(feature = "cab")]
+pub mod cab;
+#[cfg(not(feature = "cab"))]
+pub mod cab_stub;
+pub mod cancel;
+pub mod checkpoint;
+pub mod cpio;
+pub mod deb;
+pub mod directories;
+pub mod events;
+pub mod filter;
+pub mod index;
+pub mod iso;
+pub mod links;
+pub mod metadata;
+pub mod names;
+pub mod nested;
+pub mod payload;
 #[cfg(feature = "unrar")]
 pub mod rar;
 #[cfg(not(feature = "unrar"))]
 pub mod rar_stub;
+pub mod rpm;
 pub mod sevenz;
+pub mod squashfs;
 pub mod tar;
 pub mod zip;
+
+/// Options shared by the functions that build archives out of files on disk
+pub struct BuildOptions {
+    /// Which files should be read or ignored when walking directories
+    pub file_visibility_policy: FileVisibilityPolicy,
+    /// What to do with FIFOs, sockets and device nodes found while walking directories
+    pub special_files: SpecialFilesPolicy,
+    /// Store inputs relative to this directory, instead of relative to their parent directories
+    pub base_directory: Option<PathBuf>,
+    /// Leading directory prepended to the name of every entry
+    pub prefix: Option<PathBuf>,
+    /// Store the absolute path of inputs, instead of relative ones
+    pub absolute_paths: bool,
+    /// Alignment of the data of each file in zip archives
+    pub zip_align: Option<u16>,
+    /// Password the files of zip archives are encrypted with
+    pub password: Option<String>,
+    /// How the files are encrypted with `password`
+    pub encryption: ZipEncryption,
+    /// Latest modification time that can be stored, in seconds since the Unix epoch
+    pub source_date_epoch: Option<u64>,
+    /// Store the SELinux contexts of the files, in tar archives
+    pub selinux: bool,
+    /// Silences the per-file output
+    pub quiet: bool,
+    /// Add the entries of tar and zip archives given as inputs, instead of the archives
+    pub expand_archives: bool,
+    /// Unicode normalization form of the entry names
+    pub normalize: NameNormalization,
+}
+
+impl BuildOptions {
+    /// The directory that inputs are stored relative to, if not their parent directories
+    pub fn base_directory(&self) -> Option<&Path> {
+        if self.absolute_paths {
+            Some(Path::new("/"))
+        } else {
+            self.base_directory.as_deref()
+        }
+    }
+
+    /// The formats of the input at `path`, if its entries are added instead of itself, see
+    /// `expand_archives`.
+    pub fn expanded_formats(&self, path: &Path) -> Option<Vec<CompressionFormat>> {
+        if !self.expand_archives || !path.is_file() {
+            return None;
+        }
+        let formats = extension::flatten_compression_formats(&extension::extensions_from_path(path));
+        matches!(formats.first(), Some(Tar | Zip)).then_some(formats)
+    }
+
+    /// Adds the entries of the archive at `path`, of `formats`, with `append`, under the prefix.
+    pub fn expand_archive(
+        &self,
+        path: &Path,
+        formats: &[CompressionFormat],
+        mut append: impl FnMut(CopiedEntry) -> crate::Result<()>,
+    ) -> crate::Result<usize> {
+        if !self.quiet {
+            info!(
+                inaccessible,
+                "Compressing the entries of '{}'.",
+                EscapedPathDisplay::new(path)
+            );
+        }
+        copy_entries_from(path, formats, &EntryFilter::default(), |mut entry| {
+            if let Some(prefix) = &self.prefix {
+                entry.path = prefix.join(&entry.path);
+            }
+            if let Cow::Owned(path) = utils::normalize_path(&entry.path, self.normalize) {
+                entry.path = path;
+            }
+            append(entry)
+        })
+    }
+
+    /// Clamps `time` to `source_date_epoch`, if it was set
+    pub fn clamp_time(&self, time: SystemTime) -> SystemTime {
+        match self.source_date_epoch {
+            Some(epoch) => time.min(UNIX_EPOCH + Duration::from_secs(epoch)),
+            None => time,
+        }
+    }
+
+    /// The name that the file at `path` gets inside of the archive
+    pub fn entry_name<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let name = match &self.prefix {
+            Some(prefix) => Cow::Owned(prefix.join(path)),
+            None => Cow::Borrowed(path),
+        };
+        let name = match utils::normalize_path(&name, self.normalize) {
+            Cow::Owned(normalized) => Cow::Owned(normalized),
+            Cow::Borrowed(_) => name,
+        };
+
+        if self.absolute_paths {
+            Cow::Owned(Path::new("/").join(name))
+        } else {
+            name
+        }
+    }
+}
+
+/// An entry read from an archive to be written into another one, see `ouch copy`
+pub struct CopiedEntry<'a> {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Permission bits, if the archive has them
+    pub mode: Option<u32>,
+    /// Modification time, in seconds since the Unix epoch
+    pub mtime: u64,
+    /// Target of symlinks
+    pub link_target: Option<PathBuf>,
+    /// Size of `data`, zero for directories and symlinks
+    pub size: u64,
+    pub data: &'a mut dyn Read,
+}
+
+/// Reads the entries of the tar or zip archive at `source` that `filter` keeps, giving them to
+/// `append`, returns how many there were.
+///
+/// Formats are in compression order, like `[Tar, Zstd]`. Compressed zip archives are buffered
+/// first, they can't be read as a stream.
+pub fn copy_entries_from(
+    source: &Path,
+    formats: &[CompressionFormat],
+    filter: &EntryFilter,
+    append: impl FnMut(CopiedEntry) -> crate::Result<()>,
+) -> crate::Result<usize> {
+    if let [Zip] = formats {
+        let zip_archive = ::zip::ZipArchive::new(Tracked::new(fs::File::open(source)?))?;
+        return zip::copy_entries(zip_archive, filter, append);
+    }
+
+    let source_file = Tracked::new(fs::File::open(source)?);
+    let mut reader: Box<dyn Read> = Box::new(BufReader::with_capacity(BUFFER_CAPACITY, source_file));
+    for format in formats.iter().skip(1).rev() {
+        reader = match format {
+            Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Bzip => Box::new(bzip2::read::BzDecoder::new(reader)),
+            Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            Lzma => Box::new(liblzma::read::XzDecoder::new(reader)),
+            Snappy => Box::new(snap::read::FrameDecoder::new(reader)),
+            Brotli => Box::new(brotli::Decompressor::new(reader, BUFFER_CAPACITY)),
+            Lzip => Box::new(LzipDecoder::new(BufReader::new(reader))),
+            Lzw => Box::new(LzwDecoder::new(reader)),
+            Lzop => Box::new(LzopDecoder::new(reader)),
+            Zstd => Box::new(utils::zstd_decoder(reader, None)?),
+            Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
+        };
+    }
+
+    match formats[0] {
+        Tar => tar::copy_entries(::tar::Archive::new(reader), filter, append),
+        Zip => {
+            let zip_archive = ::zip::ZipArchive::new(SpillBuffer::from_reader(&mut reader)?)?;
+            zip::copy_entries(zip_archive, filter, append)
+        }
+        _ => unreachable!("only tar and zip archives can be copied"),
+    }
+}
+
+/// Options shared by the functions that unpack archives to disk
+pub struct UnpackOptions {
+    /// Extract entries with absolute paths to that path, instead of inside of the output folder
+    pub absolute_paths: bool,
+    /// Receives what happens during the extraction
+    pub events: Box<dyn EventHandler>,
+    /// Stops the extraction once cancelled
+    pub cancel: CancellationToken,
+    /// Silences the per-file output
+    pub quiet: bool,
+    /// Recreate device nodes, they are skipped otherwise
+    pub devices: bool,
+    /// Restores the owners of the entries, when extracting as another user with `--run-as`
+    pub ownership: Option<OwnershipHelper>,
+    /// How symlinks are extracted on Windows
+    pub windows_links: WindowsLinksPolicy,
+    /// What to do with entries whose names only differ by case, on case-insensitive filesystems
+    pub case_collisions: CaseCollisionsPolicy,
+    /// What to do with entries whose names are too long for the filesystem
+    pub long_names: LongNamesPolicy,
+    /// What to do with file entries that have the same path as an earlier one
+    pub duplicate_entries: DuplicateEntriesPolicy,
+    /// Entries to extract, the others are left out silently
+    pub filter: EntryFilter,
+    /// Password of encrypted zip archives, from `--password` or asked for by the first one
+    pub password: OnceCell<String>,
+    /// Restore the SELinux contexts stored in tar archives
+    pub selinux: bool,
+    /// Only extract the files installed by Debian packages, without their control files
+    pub deb_data_only: bool,
+    /// Number of threads the decoders that support it may use, at least 1
+    pub codec_threads: usize,
+    /// Unicode normalization form the entry names are converted to
+    pub normalize: NameNormalization,
+    /// Dictionary the zstd compression was made with, see `--dict`
+    pub zstd_dictionary: Option<Vec<u8>>,
+    /// Metadata of the entries that couldn't be restored, reported at the end
+    pub lost_metadata: LostMetadata,
+    /// Extract in place, skipping the files already extracted, see `--ignore-existing`
+    pub ignore_existing: Option<IgnoreExisting>,
+}
+
+/// Where an extraction is at, added to its error so that damaged archives can be located
+#[derive(Default)]
+pub struct ExtractionContext {
+    /// The entry being extracted, `None` between entries
+    entry: Option<PathBuf>,
+    /// Offset of that entry in the archive, when the format has a meaningful one
+    offset: Option<u64>,
+    /// Entries fully extracted so far
+    extracted: usize,
+}
+
+impl ExtractionContext {
+    pub fn start_entry(&mut self, entry: &Path, offset: Option<u64>) {
+        self.entry = Some(entry.to_path_buf());
+        self.offset = offset;
+    }
+
+    pub fn finish_entry(&mut self) {
+        self.entry = None;
+        self.extracted += 1;
+    }
+
+    /// Adds what was being extracted when `err` happened, a cancellation only gets the count of
+    /// extracted entries.
+    pub fn add_to(&self, err: crate::Error) -> crate::Error {
+        if let crate::Error::Cancelled { .. } = err {
+            return crate::Error::Cancelled {
+                extracted: Some(self.extracted),
+            };
+        }
+        let mut error = err.to_final_error();
+        if let Some(entry) = &self.entry {
+            error = error.detail(format!("While extracting '{}'", EscapedPathDisplay::new(entry)));
+        }
+        if let Some(offset) = self.offset.filter(|_| self.entry.is_some()) {
+            error = error.detail(format!("The entry starts at byte {offset} of the uncompressed archive"));
+        }
+        error
+            .detail(format!("{} entries were extracted before the error", self.extracted))
+            .into()
+    }
+}
+
+impl UnpackOptions {
+    /// Where the entry at `entry_path` should be extracted to.
+    ///
+    /// Returns `None` if it would end up outside of `output_folder`, note that absolute paths
+    /// are kept only if `absolute_paths` is set, otherwise their root is stripped.
+    pub fn entry_destination(&self, output_folder: &Path, entry_path: &Path) -> Option<PathBuf> {
+        if self.absolute_paths && entry_path.has_root() {
+            Some(entry_path.to_path_buf())
+        } else {
+            utils::entry_destination(output_folder, entry_path)
+        }
+    }
+
+    /// Whether the file entry extracted to `destination`, of `size` bytes and modified at `mtime`,
+    /// in seconds since the Unix epoch, is already there from an earlier extraction, see
+    /// `--ignore-existing`.
+    pub fn is_extracted(&self, destination: &Path, size: u64, mtime: Option<i64>) -> bool {
+        let Some(check) = self.ignore_existing else {
+            return false;
+        };
+        let Ok(metadata) = fs::symlink_metadata(destination) else {
+            return false;
+        };
+        let modified = || {
+            let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+            i64::try_from(modified.as_secs()).ok()
+        };
+        metadata.is_file()
+            && metadata.len() == size
+            && match check {
+                IgnoreExisting::Size => true,
+                IgnoreExisting::Mtime => mtime.is_some() && modified() == mtime,
+            }
+    }
+
+    /// Tells the user, once per archive, that absolute entry paths are being made relative.
+    pub fn notice_stripped_root(&self, entry_path: &Path, already_noticed: &mut bool) {
+        if !self.absolute_paths && entry_path.has_root() && !*already_noticed {
+            info!(
+                accessible,
+                "Removing leading '/' from entry names, use '--absolute-paths' to keep them."
+            );
+            *already_noticed = true;
+        }
+    }
+
+    /// Restores the owner of the entry at `entry_path`, extracted to `destination`, with
+    /// `--run-as`, without it only root could, so a different owner is reported as lost.
+    pub fn restore_owner(&self, destination: &Path, entry_path: &Path, uid: u32, gid: u32) -> crate::Result<()> {
+        if let Some(ownership) = &self.ownership {
+            return ownership.record(destination, uid, gid);
+        }
+
+        #[cfg(unix)]
+        {
+            // Safety: `geteuid` and `getegid` have no preconditions
+            let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+            if euid != 0 && (uid, gid) != (euid, egid) {
+                let reason = "only root can give files to other users";
+                self.lost_metadata.record(MetadataKind::Owner, entry_path, reason)?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (entry_path, uid, gid);
+        Ok(())
+    }
+}
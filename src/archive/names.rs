@@ -0,0 +1,305 @@
+//! Where the entries of an archive are extracted to, renaming the ones whose names can't be used
+//! as they are.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{events::Event, UnpackOptions},
+    cli::{CaseCollisionsPolicy, DuplicateEntriesPolicy, LongNamesPolicy},
+    info,
+    utils::{self, EscapedPathDisplay},
+};
+
+const OUTSIDE_OUTPUT_FOLDER: &str = "it would be extracted outside of the output folder";
+const NAME_TOO_LONG: &str = "its name is too long for this filesystem";
+const CASE_COLLISION: &str = "its name only differs by case from another entry";
+const DUPLICATE: &str = "an earlier entry has the same path";
+
+/// The destinations of the entries of a single archive
+pub struct EntryDestinations<'a> {
+    output_folder: &'a Path,
+    options: &'a UnpackOptions,
+    /// Whether names differing only by case are the same file, they only need to be told apart then
+    case_insensitive: bool,
+    /// The longest name, in bytes, that can be created in `output_folder`
+    max_name_length: usize,
+    /// The paths given to entries and their parents, relative to `output_folder`
+    mapped: HashMap<PathBuf, PathBuf>,
+    /// Lowercased paths already given to an entry
+    taken: HashSet<String>,
+    /// Paths of the file entries so far, relative to `output_folder`, to find the duplicates
+    files: HashSet<PathBuf>,
+    /// Entries that were renamed, their new paths, relative to `output_folder`, and why
+    renames: Vec<(PathBuf, PathBuf, &'static str)>,
+    /// File entries skipped because they were already extracted, see `--ignore-existing`
+    extracted: usize,
+}
+
+impl<'a> EntryDestinations<'a> {
+    /// Expects `output_folder` to exist.
+    pub fn new(output_folder: &'a Path, options: &'a UnpackOptions) -> crate::Result<Self> {
+        let case_insensitive =
+            options.case_collisions != CaseCollisionsPolicy::Overwrite && utils::is_case_insensitive(output_folder)?;
+
+        Ok(Self {
+            output_folder,
+            options,
+            case_insensitive,
+            max_name_length: utils::max_name_length(output_folder),
+            mapped: HashMap::new(),
+            taken: HashSet::new(),
+            files: HashSet::new(),
+            renames: vec![],
+            extracted: 0,
+        })
+    }
+
+    /// Where the entry at `entry_path` should be extracted to, `None` if it must be skipped.
+    ///
+    /// Directories can be repeated, later file entries with the same path as an earlier one are
+    /// handled according to `--duplicate-entries`.
+    pub fn destination(&mut self, entry_path: &Path, is_dir: bool) -> Option<PathBuf> {
+        let Some(destination) = self.options.entry_destination(self.output_folder, entry_path) else {
+            self.skip(entry_path, OUTSIDE_OUTPUT_FOLDER);
+            return None;
+        };
+
+        let keeps_absolute_path = self.options.absolute_paths && entry_path.has_root();
+        if keeps_absolute_path {
+            return Some(destination);
+        }
+
+        let relative = destination
+            .strip_prefix(self.output_folder)
+            .expect("entry destinations are inside of the output folder");
+        let relative = utils::normalize_path(relative, self.options.normalize);
+        let relative = if is_dir {
+            relative.to_path_buf()
+        } else {
+            self.deduplicate(entry_path, &relative)?
+        };
+        let relative = relative.as_path();
+        let has_long_name = || relative.components().any(|name| self.is_too_long(name.as_os_str()));
+        if !self.case_insensitive && (self.options.long_names == LongNamesPolicy::Error || !has_long_name()) {
+            return Some(self.output_folder.join(relative));
+        }
+
+        // Every parent is mapped too, so the children of a renamed directory follow it
+        let mut original = PathBuf::new();
+        let mut mapped = PathBuf::new();
+        for component in relative.components() {
+            original.push(component);
+            if let Some(known) = self.mapped.get(&original) {
+                mapped = known.clone();
+                continue;
+            }
+
+            let mut name = component.as_os_str().to_os_string();
+            let mut reason = CASE_COLLISION;
+            if self.is_too_long(&name) {
+                match self.options.long_names {
+                    LongNamesPolicy::Error => {}
+                    LongNamesPolicy::Truncate => {
+                        name = truncate_name(&name, self.max_name_length);
+                        reason = NAME_TOO_LONG;
+                    }
+                    LongNamesPolicy::Skip => {
+                        self.skip(entry_path, NAME_TOO_LONG);
+                        return None;
+                    }
+                }
+            }
+
+            let mut candidate = mapped.join(&name);
+            let mut variant = 1;
+            while self.case_insensitive && self.taken.contains(&case_key(&candidate)) {
+                variant += 1;
+                let mut variant_name = numbered_variant(&name, "case", variant);
+                if self.is_too_long(&variant_name) && self.options.long_names == LongNamesPolicy::Truncate {
+                    variant_name = truncate_name(&variant_name, self.max_name_length);
+                }
+                candidate = mapped.join(variant_name);
+            }
+
+            if variant > 1 && self.options.case_collisions == CaseCollisionsPolicy::Skip {
+                self.skip(entry_path, CASE_COLLISION);
+                return None;
+            }
+            if candidate.file_name() != Some(component.as_os_str()) {
+                self.renames.push((original.clone(), candidate.clone(), reason));
+            }
+
+            if self.case_insensitive {
+                self.taken.insert(case_key(&candidate));
+            }
+            self.mapped.insert(original.clone(), candidate.clone());
+            mapped = candidate;
+        }
+
+        Some(self.output_folder.join(mapped))
+    }
+
+    /// The path the file entry at `relative` gets given the file entries before it, `None` if it
+    /// must be skipped.
+    fn deduplicate(&mut self, entry_path: &Path, relative: &Path) -> Option<PathBuf> {
+        if self.files.insert(relative.to_path_buf()) {
+            return Some(relative.to_path_buf());
+        }
+
+        match self.options.duplicate_entries {
+            DuplicateEntriesPolicy::KeepLast => {
+                self.options.events.handle(Event::Warning(format!(
+                    "'{}' is in the archive more than once, the last one is kept",
+                    utils::EscapedPathDisplay::new(entry_path)
+                )));
+                Some(relative.to_path_buf())
+            }
+            DuplicateEntriesPolicy::KeepFirst => {
+                self.skip(entry_path, DUPLICATE);
+                None
+            }
+            DuplicateEntriesPolicy::VersionSuffix => {
+                let name = relative.file_name().expect("file entries have a name");
+                let versioned = (2..)
+                    .map(|version| relative.with_file_name(numbered_variant(name, "version", version)))
+                    .find(|versioned| !self.files.contains(versioned))
+                    .expect("some version is free");
+                self.files.insert(versioned.clone());
+                self.renames
+                    .push((relative.to_path_buf(), versioned.clone(), DUPLICATE));
+                Some(versioned)
+            }
+        }
+    }
+
+    fn is_too_long(&self, name: &OsStr) -> bool {
+        name.len() > self.max_name_length
+    }
+
+    fn skip(&self, entry_path: &Path, reason: &'static str) {
+        self.options.events.handle(Event::Conflict {
+            path: entry_path,
+            reason,
+            renamed_to: None,
+        });
+    }
+
+    /// Whether the file entry extracted to `destination`, of `size` bytes and modified at `mtime`,
+    /// is already there, it's skipped then, see `UnpackOptions::is_extracted`.
+    pub fn is_extracted(&mut self, destination: &Path, size: u64, mtime: Option<i64>) -> bool {
+        let extracted = self.options.is_extracted(destination, size, mtime);
+        self.extracted += usize::from(extracted);
+        extracted
+    }
+
+    /// Reports every entry that was renamed, and how many were already extracted, once the
+    /// extraction is done.
+    pub fn report_renames(&self) {
+        report_extracted(self.output_folder, self.extracted);
+        for (original, renamed, reason) in &self.renames {
+            self.options.events.handle(Event::Conflict {
+                path: original,
+                reason,
+                renamed_to: Some(renamed),
+            });
+        }
+    }
+}
+
+/// Tells the user that `count` file entries were already extracted to `output_folder`, and skipped.
+pub fn report_extracted(output_folder: &Path, count: usize) {
+    if count > 0 {
+        info!(
+            accessible,
+            "Skipped {count} files already extracted to '{}'.",
+            EscapedPathDisplay::new(output_folder)
+        );
+    }
+}
+
+/// The path as case-insensitive filesystems compare it, approximately
+fn case_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// `file.txt` becomes `file (case 2).txt` for the variant 2 of kind "case".
+fn numbered_variant(name: &OsStr, kind: &str, variant: usize) -> OsString {
+    let name = Path::new(name);
+    let mut renamed = name.file_stem().unwrap_or(name.as_os_str()).to_os_string();
+    renamed.push(format!(" ({kind} {variant})"));
+    if let Some(extension) = name.extension() {
+        renamed.push(".");
+        renamed.push(extension);
+    }
+    renamed
+}
+
+/// Shortens `name` to at most `max_length` bytes, keeping its extension and replacing the end of
+/// its stem with a hash of the full name, so that names starting alike stay apart.
+fn truncate_name(name: &OsStr, max_length: usize) -> OsString {
+    let full_name = name.to_string_lossy();
+    let hash = format!("~{:08x}", fnv1a(full_name.as_bytes()));
+
+    let path = Path::new(name);
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .filter(|extension| extension.len() + hash.len() < max_length)
+        .unwrap_or_default();
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let stem = if extension.is_empty() { full_name.clone() } else { stem };
+
+    let mut stem_length = max_length.saturating_sub(hash.len() + extension.len());
+    while !stem.is_char_boundary(stem_length.min(stem.len())) {
+        stem_length -= 1;
+    }
+    let stem = &stem[..stem_length.min(stem.len())];
+
+    format!("{stem}{hash}{extension}").into()
+}
+
+/// The 32-bit FNV-1a hash, short and stable across versions
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_variant() {
+        let variant = |name: &str| numbered_variant(name.as_ref(), "case", 2);
+
+        assert_eq!(variant("file.txt"), "file (case 2).txt");
+        assert_eq!(variant("README"), "README (case 2)");
+        assert_eq!(variant(".bashrc"), ".bashrc (case 2)");
+        assert_eq!(
+            numbered_variant("file.txt".as_ref(), "version", 3),
+            "file (version 3).txt"
+        );
+    }
+
+    #[test]
+    fn test_truncate_name() {
+        let long_name = format!("{}.tar.gz", "a".repeat(300));
+        let truncated = truncate_name(long_name.as_ref(), 255);
+        assert_eq!(truncated.len(), 255);
+        assert!(truncated.to_str().unwrap().ends_with(".gz"));
+
+        // Names only differing after the cut stay apart
+        let other = truncate_name(format!("{}b.tar.gz", "a".repeat(300)).as_ref(), 255);
+        assert_ne!(truncated, other);
+
+        // Multi-byte characters aren't cut in half
+        let truncated = truncate_name("é".repeat(200).as_ref(), 255);
+        assert!(truncated.len() <= 255);
+        assert!(truncated.to_str().unwrap().starts_with('é'));
+    }
+}
@@ -0,0 +1,92 @@
+//! Archives and compressed files inside of archives, tested as they're read by `ouch test
+//! --recursive`, without extracting them to disk.
+//!
+//! Only the formats that can be read as streams are tested: tar and zip archives and the
+//! compression formats. The entries of other archives are decompressed, but not read.
+
+use std::{
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    archive,
+    extension::{
+        self,
+        CompressionFormat::{self, *},
+    },
+    utils::{EscapedPathDisplay, LzipDecoder, LzopDecoder, LzwDecoder},
+    BUFFER_CAPACITY,
+};
+
+/// Reads `reader`, the contents of the entry at `path`, to its end, so that its checksums are
+/// checked. With `recursive`, it's tested too if it's an archive or a compressed file.
+pub fn test_entry(path: &Path, mut reader: impl Read, recursive: bool) -> io::Result<()> {
+    let result = match recursive {
+        true => test_stream(path, &mut reader),
+        false => Ok(()),
+    };
+    result
+        .and_then(|()| io::copy(&mut reader, &mut io::sink()))
+        .map(drop)
+        .map_err(|err| io::Error::new(err.kind(), format!("'{}': {err}", EscapedPathDisplay::new(path))))
+}
+
+/// Tests `reader` as the archive or compressed file its name, `path`, tells it is.
+fn test_stream(path: &Path, reader: &mut dyn Read) -> io::Result<()> {
+    let formats = extension::flatten_compression_formats(&extension::extensions_from_path(path));
+    test_formats(&formats, reader)
+}
+
+/// Tests `reader` as a stream of `formats`, in decompression order, reading it to its end.
+pub fn test_formats(formats: &[CompressionFormat], reader: &mut dyn Read) -> io::Result<()> {
+    let Some(first) = formats.first() else {
+        return Ok(());
+    };
+    let compressions = match first.is_archive_format() {
+        true => &formats[1..],
+        false => formats,
+    };
+
+    let mut decoder: Box<dyn Read + '_> = Box::new(reader);
+    for format in compressions.iter().rev() {
+        decoder = chain_decoder(format, decoder)?;
+    }
+
+    match first {
+        Tar => {
+            let mut archive = tar::Archive::new(&mut decoder);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let path = entry.path()?.into_owned();
+                    test_entry(&path, entry, true)?;
+                }
+            }
+        }
+        Zip => archive::zip::test_stream(&mut decoder, |path, file| test_entry(path, file, true))?,
+        _ => {}
+    }
+    // The rest of the stream, like the end of the archive and the checksum of its compression
+    io::copy(&mut decoder, &mut io::sink())?;
+    Ok(())
+}
+
+/// Wraps `decoder` in a decoder of `format`.
+pub fn chain_decoder<'a>(format: &CompressionFormat, decoder: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+    let decoder: Box<dyn Read + 'a> = match format {
+        Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
+        Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
+        Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
+        Lzma => Box::new(liblzma::read::XzDecoder::new(decoder)),
+        Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
+        Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
+        Lzip => Box::new(LzipDecoder::new(BufReader::new(decoder))),
+        Lzw => Box::new(LzwDecoder::new(decoder)),
+        Lzop => Box::new(LzopDecoder::new(decoder)),
+        // No dictionary can be given for the files inside of archives
+        Zstd => Box::new(zstd::stream::read::Decoder::new(decoder)?),
+        Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
+    };
+    Ok(decoder)
+}
@@ -0,0 +1,54 @@
+//! Decoders of the archives embedded in packages, like the tarballs of Debian packages and the cpio
+//! archive of RPM packages, which are compressed with a format given by the package.
+
+use std::io::{self, BufReader, Read};
+
+/// Compression of an embedded archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadCompression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    /// The legacy format of LZMA Utils
+    Lzma,
+    Zstd,
+}
+
+/// Decoder of an embedded archive
+pub enum PayloadDecoder<R: Read> {
+    Plain(R),
+    Gzip(flate2::read::GzDecoder<R>),
+    Bzip(bzip2::read::BzDecoder<R>),
+    Lzma(liblzma::read::XzDecoder<R>),
+    Zstd(zstd::stream::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> PayloadDecoder<R> {
+    pub fn new(compression: PayloadCompression, data: R) -> io::Result<Self> {
+        let decoder = match compression {
+            PayloadCompression::None => Self::Plain(data),
+            PayloadCompression::Gzip => Self::Gzip(flate2::read::GzDecoder::new(data)),
+            PayloadCompression::Bzip2 => Self::Bzip(bzip2::read::BzDecoder::new(data)),
+            PayloadCompression::Xz => Self::Lzma(liblzma::read::XzDecoder::new(data)),
+            PayloadCompression::Lzma => Self::Lzma(liblzma::read::XzDecoder::new_stream(
+                data,
+                liblzma::stream::Stream::new_lzma_decoder(u64::MAX)?,
+            )),
+            PayloadCompression::Zstd => Self::Zstd(zstd::stream::Decoder::new(data)?),
+        };
+        Ok(decoder)
+    }
+}
+
+impl<R: Read> Read for PayloadDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read(buf),
+            Self::Gzip(reader) => reader.read(buf),
+            Self::Bzip(reader) => reader.read(buf),
+            Self::Lzma(reader) => reader.read(buf),
+            Self::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
@@ -1,37 +1,68 @@
 //! Contains RAR-specific building and unpacking functions
 
-use std::path::Path;
+use std::{iter, path::Path};
 
-use unrar::{self, Archive};
+use unrar::{self, Archive, FileHeader};
 
-use crate::{error::Error, info, list::FileInArchive};
+use crate::{
+    archive::{events::Event, names, ExtractionContext, UnpackOptions},
+    cli::EntryKind,
+    error::Error,
+    list::{self, ContentOptions, FileInArchive},
+};
 
 /// Unpacks the archive given by `archive_path` into the folder given by `output_folder`.
-/// Assumes that output_folder is empty
-pub fn unpack_archive(archive_path: &Path, output_folder: &Path, quiet: bool) -> crate::Result<usize> {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    archive_path: &Path,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
 
     let mut archive = Archive::new(archive_path).open_for_processing()?;
     let mut unpacked = 0;
+    let mut extracted = 0;
+    let mut context = ExtractionContext::default();
 
-    while let Some(header) = archive.read_header()? {
+    while let Some(header) = archive.read_header().map_err(|err| context.add_to(err.into()))? {
+        // The library writes each entry at once, it can only stop between entries
+        unpack_options.cancel.check().map_err(|err| context.add_to(err))?;
         let entry = header.entry();
-        archive = if entry.is_file() {
-            if !quiet {
-                info!(
-                    inaccessible,
-                    "{} extracted. ({})",
-                    entry.filename.display(),
-                    entry.unpacked_size
-                );
-            }
-            unpacked += 1;
-            header.extract_with_base(output_folder)?
-        } else {
-            header.skip()?
-        };
+        context.start_entry(&entry.filename, None);
+        // Directories are only created for the files inside of them
+        let filter = &unpack_options.filter;
+        if !entry.is_file() || !filter.matches(Some(EntryKind::File)) || !filter.matches_path(&entry.filename) {
+            archive = header.skip().map_err(|err| context.add_to(err.into()))?;
+            continue;
+        }
+
+        let destination = output_folder.join(&entry.filename);
+        let size = entry.unpacked_size;
+        // Its time is in the local time zone of whoever made the archive, so only sizes can match
+        if unpack_options.is_extracted(&destination, size, None) {
+            extracted += 1;
+            archive = header.skip().map_err(|err| context.add_to(err.into()))?;
+            continue;
+        }
+        unpack_options.events.handle(Event::EntryStarted {
+            path: &entry.filename,
+            size,
+        });
+        archive = header
+            .extract_with_base(output_folder)
+            .map_err(|err| context.add_to(err.into()))?;
+        // The library writes the whole entry at once
+        unpack_options.events.handle(Event::BytesWritten(size));
+        unpack_options.events.handle(Event::EntryFinished {
+            destination: &destination,
+            size,
+        });
+        unpacked += 1;
+        context.finish_entry();
     }
 
+    names::report_extracted(output_folder, extracted);
     Ok(unpacked)
 }
 
@@ -40,13 +71,61 @@ pub fn list_archive(archive_path: &Path) -> impl Iterator<Item = crate::Result<F
     Archive::new(archive_path)
         .open_for_listing()
         .expect("cannot open archive")
-        .map(|item| {
-            let item = item?;
-            let is_dir = item.is_directory();
-            let path = item.filename;
+        .map(|item| Ok(file_in_archive(&item?)))
+}
+
+/// Reads every entry of `archive_path`, which checks them, and what `contents` asks for out of
+/// them, see `ouch test`.
+///
+/// The library only reads whole entries, into memory.
+pub fn test_archive(
+    archive_path: &Path,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    let mut archive = Some(Archive::new(archive_path).open_for_processing()?);
+    Ok(iter::from_fn(move || {
+        let header = match archive.take()?.read_header() {
+            Ok(header) => header?,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let mut file = file_in_archive(header.entry());
+        if !header.entry().is_file() {
+            return Some(
+                header
+                    .skip()
+                    .map(|next| {
+                        archive = Some(next);
+                        file
+                    })
+                    .map_err(Into::into),
+            );
+        }
+        let data = match header.read() {
+            Ok((data, next)) => {
+                archive = Some(next);
+                data
+            }
+            Err(err) => return Some(Err(err.into())),
+        };
+        Some(
+            list::read_contents(&mut file, data.as_slice(), &contents)
+                .map(|()| file)
+                .map_err(Into::into),
+        )
+    }))
+}
 
-            Ok(FileInArchive { path, is_dir })
-        })
+fn file_in_archive(header: &FileHeader) -> FileInArchive {
+    // RAR doesn't reliably store Unix permissions
+    FileInArchive {
+        path: header.filename.clone(),
+        is_dir: header.is_directory(),
+        is_symlink: false,
+        is_executable: false,
+        size: header.unpacked_size,
+        preview: None,
+        file_type: None,
+    }
 }
 
 pub fn no_compression() -> Error {
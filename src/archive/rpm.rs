@@ -0,0 +1,154 @@
+//! RPM packages, a lead, a signature and a header of the package metadata, followed by the payload,
+//! a compressed cpio archive of the files it installs.
+//!
+//! Like `rpm2cpio | cpio -id`, the files of the payload are extracted and the metadata is left out.
+//! Packages can only be extracted and listed.
+
+use std::{io::Read, path::Path};
+
+use crate::{
+    archive::{
+        cpio,
+        payload::{PayloadCompression, PayloadDecoder},
+        UnpackOptions,
+    },
+    error::{Error, FinalError},
+    list::{ContentOptions, FileInArchive},
+};
+
+const LEAD_MAGIC: &[u8; 4] = &[0xED, 0xAB, 0xEE, 0xDB];
+const LEAD_SIZE: usize = 96;
+const HEADER_MAGIC: &[u8; 3] = &[0x8E, 0xAD, 0xE8];
+/// Largest headers, rpm's own limits
+const MAX_INDEX_ENTRIES: u32 = 0xFFFF;
+const MAX_DATA_SIZE: u32 = 256 * 1024 * 1024;
+
+const TAG_PAYLOAD_FORMAT: u32 = 1124;
+const TAG_PAYLOAD_COMPRESSOR: u32 = 1125;
+const TYPE_STRING: u32 = 6;
+
+fn invalid_package(reason: &'static str) -> crate::Error {
+    FinalError::with_title("Invalid RPM package").detail(reason).into()
+}
+
+/// An entry of the index of a header, whose value is in the data of the header
+struct IndexEntry {
+    tag: u32,
+    kind: u32,
+    offset: u32,
+}
+
+/// The signature or the header of the package
+struct Header {
+    index: Vec<IndexEntry>,
+    data: Vec<u8>,
+}
+
+impl Header {
+    /// Reads a header, `padded` to 8 bytes like the signature is.
+    fn read(reader: &mut impl Read, padded: bool) -> crate::Result<Self> {
+        let mut intro = [0; 16];
+        reader.read_exact(&mut intro)?;
+        if !intro.starts_with(HEADER_MAGIC) {
+            return Err(invalid_package("A header is corrupted"));
+        }
+        let number = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+        let (entries, data_size) = (number(&intro[8..12]), number(&intro[12..16]));
+        if entries > MAX_INDEX_ENTRIES || data_size > MAX_DATA_SIZE {
+            return Err(invalid_package("A header is too large"));
+        }
+
+        let mut index_bytes = vec![0; entries as usize * 16];
+        reader.read_exact(&mut index_bytes)?;
+        let index = index_bytes
+            .chunks_exact(16)
+            .map(|entry| IndexEntry {
+                tag: number(&entry[0..4]),
+                kind: number(&entry[4..8]),
+                offset: number(&entry[8..12]),
+            })
+            .collect();
+
+        let mut data = vec![0; data_size as usize];
+        reader.read_exact(&mut data)?;
+        if padded {
+            let padding = (8 - data.len() % 8) % 8;
+            reader.read_exact(&mut [0; 8][..padding])?;
+        }
+
+        Ok(Self { index, data })
+    }
+
+    /// The string value of `tag`, if the header has it.
+    fn string(&self, tag: u32) -> crate::Result<Option<&str>> {
+        let Some(entry) = self.index.iter().find(|entry| entry.tag == tag) else {
+            return Ok(None);
+        };
+        let value = self
+            .data
+            .get(entry.offset as usize..)
+            .filter(|_| entry.kind == TYPE_STRING)
+            .and_then(|value| value.split(|&byte| byte == 0).next())
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .ok_or_else(|| invalid_package("A header is corrupted"))?;
+        Ok(Some(value))
+    }
+}
+
+/// Reads everything before the payload, returns how it's compressed.
+fn read_package(reader: &mut impl Read) -> crate::Result<PayloadCompression> {
+    let mut lead = [0; LEAD_SIZE];
+    reader.read_exact(&mut lead)?;
+    if !lead.starts_with(LEAD_MAGIC) {
+        return Err(invalid_package("It doesn't start with the RPM magic number"));
+    }
+    Header::read(reader, true)?;
+    let header = Header::read(reader, false)?;
+
+    let format = header.string(TAG_PAYLOAD_FORMAT)?.unwrap_or("cpio");
+    if format != "cpio" {
+        return Err(Error::UnsupportedFormat {
+            reason: format!("The payload of the RPM package is a '{format}' archive, only cpio is supported"),
+        });
+    }
+
+    // Without the tag, the payload is compressed with gzip
+    let compression = match header.string(TAG_PAYLOAD_COMPRESSOR)?.unwrap_or("gzip") {
+        "identity" => PayloadCompression::None,
+        "gzip" => PayloadCompression::Gzip,
+        "bzip2" => PayloadCompression::Bzip2,
+        "xz" => PayloadCompression::Xz,
+        "lzma" => PayloadCompression::Lzma,
+        "zstd" => PayloadCompression::Zstd,
+        compressor => {
+            return Err(Error::UnsupportedFormat {
+                reason: format!(
+                    "The payload of the RPM package is compressed with '{compressor}', which isn't supported"
+                ),
+            })
+        }
+    };
+    Ok(compression)
+}
+
+/// Unpacks the files of the package given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    mut reader: Box<dyn Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let compression = read_package(&mut reader)?;
+    let payload = PayloadDecoder::new(compression, reader)?;
+    cpio::unpack_archive(Box::new(payload), output_folder, unpack_options)
+}
+
+/// List the files of the package given by `reader`
+pub fn list_archive(
+    mut reader: impl Read + Send + 'static,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    let compression = read_package(&mut reader)?;
+    let payload = PayloadDecoder::new(compression, reader)?;
+    Ok(cpio::list_archive(payload, contents))
+}
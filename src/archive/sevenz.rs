@@ -4,15 +4,28 @@ use std::{
     env, io,
     io::{Read, Seek, Write},
     path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
 };
 
 use fs_err as fs;
 use same_file::Handle;
 
 use crate::{
+    archive::{
+        cancel::CancellableReader,
+        events::{Event, EventReader},
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        BuildOptions, ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
     error::FinalError,
+    extension::CompressionFormat,
     info,
-    utils::{self, cd_into_same_dir_as, Bytes, EscapedPathDisplay, FileVisibilityPolicy},
+    list::{self, ContentOptions, FileInArchive},
+    progress::Tracked,
+    utils::{self, EscapedPathDisplay, SpecialFileKind},
     warning,
 };
 
@@ -20,8 +33,7 @@ pub fn compress_sevenz<W>(
     files: &[PathBuf],
     output_path: &Path,
     writer: W,
-    file_visibility_policy: FileVisibilityPolicy,
-    quiet: bool,
+    build_options: &BuildOptions,
 ) -> crate::Result<W>
 where
     W: Write + Seek,
@@ -30,32 +42,17 @@ where
     let output_handle = Handle::from_path(output_path);
 
     for filename in files {
-        let previous_location = cd_into_same_dir_as(filename)?;
+        let (previous_location, filename) = utils::cd_into_base_dir(filename, build_options.base_directory())?;
 
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
-
-        for entry in file_visibility_policy.build_walker(filename) {
+        for entry in build_options.file_visibility_policy.build_walker(filename) {
             let entry = entry?;
             let path = entry.path();
 
-            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning!(
-                        "The output file and the input file are the same: `{}`, skipping...",
-                        output_path.display()
-                    );
-                    continue;
-                }
-            }
-
             // This is printed for every file in `input_filenames` and has
             // little importance for most users, but would generate lots of
             // spoken text for users using screen readers, braille displays
             // and so on
-            if !quiet {
+            if !build_options.quiet {
                 info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
             }
 
@@ -71,19 +68,48 @@ where
                 }
             };
 
-            let entry_name = path.to_str().ok_or_else(|| {
+            if let Some(kind) = SpecialFileKind::from_metadata(&metadata) {
+                utils::should_store_special_file(path, kind, build_options.special_files, CompressionFormat::SevenZip)?;
+                continue;
+            }
+
+            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
+            //
+            // This must come after the special files check, opening a FIFO would block
+            if let Ok(handle) = &output_handle {
+                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    warning!(
+                        "The output file and the input file are the same: `{}`, skipping...",
+                        output_path.display()
+                    );
+                    continue;
+                }
+            }
+
+            if utils::is_symlink(path) {
+                warning!(
+                    "'{}' is a symlink, which 7z archives can't store, storing what it points to... tar and zip archives can store it",
+                    EscapedPathDisplay::new(path)
+                );
+            }
+
+            let entry_name = build_options.entry_name(path);
+            let entry_name = entry_name.to_str().ok_or_else(|| {
                 FinalError::with_title("7z requires that all entry names are valid UTF-8")
                     .detail(format!("File at '{path:?}' has a non-UTF-8 name"))
             })?;
 
-            let entry = sevenz_rust::SevenZArchiveEntry::from_path(path, entry_name.to_owned());
+            let mut entry = sevenz_rust::SevenZArchiveEntry::from_path(path, entry_name.to_owned());
+            if build_options.source_date_epoch.is_some() {
+                clamp_entry_times(&mut entry, build_options);
+            }
             let entry_data = if metadata.is_dir() {
                 None
             } else {
-                Some(fs::File::open(path)?)
+                Some(Tracked::new(fs::File::open(path)?))
             };
 
-            writer.push_archive_entry::<fs::File>(entry, entry_data)?;
+            writer.push_archive_entry(entry, entry_data)?;
         }
 
         env::set_current_dir(previous_location)?;
@@ -93,63 +119,169 @@ where
     Ok(bytes)
 }
 
-pub fn decompress_sevenz<R>(reader: R, output_path: &Path, quiet: bool) -> crate::Result<usize>
+/// Clamps the timestamps of `entry` to `SOURCE_DATE_EPOCH`
+fn clamp_entry_times(entry: &mut sevenz_rust::SevenZArchiveEntry, build_options: &BuildOptions) {
+    for time in [
+        &mut entry.creation_date,
+        &mut entry.last_modified_date,
+        &mut entry.access_date,
+    ] {
+        if let Ok(clamped) = build_options.clamp_time((*time).into()).try_into() {
+            *time = clamped;
+        }
+    }
+}
+
+/// The unix mode of `entry`, if it was stored
+fn unix_mode(entry: &sevenz_rust::SevenZArchiveEntry) -> Option<u32> {
+    // Unix permissions are stored in the high bits of the attributes, if this flag is set
+    const UNIX_EXTENSION: u32 = 0x8000;
+    (entry.has_windows_attributes && entry.windows_attributes & UNIX_EXTENSION != 0)
+        .then_some(entry.windows_attributes >> 16)
+}
+
+fn entry_kind(entry: &sevenz_rust::SevenZArchiveEntry) -> EntryKind {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+    if entry.is_directory() {
+        EntryKind::Directory
+    } else if unix_mode(entry).is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    }
+}
+
+/// List contents of the archive at `archive_path`, as they're read by another thread
+pub fn list_archive(
+    archive_path: PathBuf,
+    contents: ContentOptions,
+) -> impl Iterator<Item = crate::Result<FileInArchive>> {
+    struct Files(mpsc::Receiver<crate::Result<FileInArchive>>);
+    impl Iterator for Files {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.0.recv().ok()
+        }
+    }
+
+    let (tx, rx) = mpsc::sync_channel(list::LISTED_AHEAD);
+    thread::spawn(move || {
+        let result = sevenz_rust::decompress_file_with_extract_fn(&archive_path, ".", |entry, reader, _| {
+            let is_executable = unix_mode(entry).is_some_and(|mode| mode & 0o111 != 0);
+            let mut file_in_archive = FileInArchive {
+                path: entry.name().into(),
+                is_dir: entry.is_directory(),
+                is_symlink: entry_kind(entry) == EntryKind::Symlink,
+                is_executable: is_executable && !entry.is_directory(),
+                size: entry.size(),
+                preview: None,
+                file_type: None,
+            };
+            if contents.is_needed() && entry_kind(entry) == EntryKind::File {
+                // Sent as is, the library would wrap it into an error of its own
+                if let Err(err) = list::read_contents(&mut file_in_archive, reader, &contents) {
+                    let _ = tx.send(Err(err.into()));
+                    return Ok(false);
+                }
+            }
+            // Stops reading when the listing stopped early, after an error
+            Ok(tx.send(Ok(file_in_archive)).is_ok())
+        });
+        if let Err(err) = result {
+            let _ = tx.send(Err(err.into()));
+        }
+    });
+
+    Files(rx)
+}
+
+pub fn decompress_sevenz<R>(reader: R, output_path: &Path, unpack_options: &UnpackOptions) -> crate::Result<usize>
 where
     R: Read + Seek,
 {
+    let events = unpack_options.events.as_ref();
+    let mut noticed_stripped_root = false;
     let mut count: usize = 0;
-    sevenz_rust::decompress_with_extract_fn(reader, output_path, |entry, reader, path| {
-        count += 1;
+    let mut destinations = EntryDestinations::new(output_path, unpack_options)?;
+    let mut context = ExtractionContext::default();
+    // Set when `--strict-metadata` stops the extraction, the library's errors can't carry it
+    let mut metadata_error = None;
+    let result = sevenz_rust::decompress_with_extract_fn(reader, output_path, |entry, reader, _| {
         // Manually handle writing all files from 7z archive, due to library exluding empty files
         use std::io::BufWriter;
 
         use filetime_creation as ft;
 
-        let file_path = output_path.join(entry.name());
+        if unpack_options.cancel.is_cancelled() {
+            return Ok(false);
+        }
+        let entry_path = Path::new(entry.name());
+        let filter = &unpack_options.filter;
+        if !filter.matches(Some(entry_kind(entry))) || !filter.matches_path(entry_path) {
+            return Ok(true);
+        }
+        // Entries are in compressed blocks, their offsets would be meaningless
+        context.start_entry(entry_path, None);
+        unpack_options.notice_stripped_root(entry_path, &mut noticed_stripped_root);
+        let Some(file_path) = destinations.destination(entry_path, entry.is_directory()) else {
+            return Ok(true);
+        };
+        let mtime = ft::FileTime::from_system_time(entry.last_modified_date().into()).unix_seconds();
+        if entry_kind(entry) == EntryKind::File && destinations.is_extracted(&file_path, entry.size(), Some(mtime)) {
+            return Ok(true);
+        }
+        let path = file_path.as_path();
+        count += 1;
 
         if entry.is_directory() {
-            if !quiet {
-                info!(
-                    inaccessible,
-                    "File {} extracted to \"{}\"",
-                    entry.name(),
-                    file_path.display()
-                );
-            }
             if !path.exists() {
                 fs::create_dir_all(path)?;
             }
         } else {
-            if !quiet {
-                info!(
-                    inaccessible,
-                    "{:?} extracted. ({})",
-                    file_path.display(),
-                    Bytes::new(entry.size()),
-                );
-            }
-
             if let Some(parent) = path.parent() {
                 if !parent.exists() {
                     fs::create_dir_all(parent)?;
                 }
             }
 
+            events.handle(Event::EntryStarted {
+                path: entry_path,
+                size: entry.size(),
+            });
             let file = fs::File::create(path)?;
             let mut writer = BufWriter::new(file);
-            io::copy(reader, &mut writer)?;
+            let mut reader = CancellableReader::new(EventReader::new(reader, events), &unpack_options.cancel);
+            io::copy(&mut reader, &mut writer)?;
 
-            ft::set_file_handle_times(
+            let times = ft::set_file_handle_times(
                 writer.get_ref().file(),
                 Some(ft::FileTime::from_system_time(entry.access_date().into())),
                 Some(ft::FileTime::from_system_time(entry.last_modified_date().into())),
                 Some(ft::FileTime::from_system_time(entry.creation_date().into())),
-            )
-            .unwrap_or_default();
+            );
+            let lost_metadata = &unpack_options.lost_metadata;
+            if let Err(err) = lost_metadata.check(MetadataKind::ModificationTime, entry_path, times) {
+                metadata_error = Some(err);
+                return Ok(false);
+            }
         }
 
+        events.handle(Event::EntryFinished {
+            destination: path,
+            size: entry.size(),
+        });
+        context.finish_entry();
         Ok(true)
-    })?;
+    });
+    // Cancellations stop the library without an error, or with an error of its own
+    unpack_options.cancel.check().map_err(|err| context.add_to(err))?;
+    if let Some(err) = metadata_error {
+        return Err(context.add_to(err));
+    }
+    result.map_err(|err| context.add_to(err.into()))?;
+    destinations.report_renames();
 
     Ok(count)
 }
@@ -0,0 +1,960 @@
+//! SquashFS images, the compressed read-only filesystems of firmwares, live systems and AppImages,
+//! read without mounting them.
+//!
+//! A superblock points to tables of metadata blocks: the inodes, the directory listings, the
+//! fragments that pack the tails of small files together and the ids of owners and groups. The
+//! data of files is in blocks compressed one by one, all with the same compressor. Only SquashFS
+//! 4.0 images are read, and they can only be extracted and listed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use filetime_creation::FileTime;
+use fs_err as fs;
+
+#[cfg(unix)]
+use crate::utils::{self, SpecialFileKind};
+use crate::{
+    archive::{
+        cancel::CancellableReader,
+        directories::DeferredDirectories,
+        events::{Event, EventReader},
+        links::DeferredLinks,
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
+    error::{Error, FinalError},
+    list::{self, ContentOptions, FileInArchive},
+    utils::{decompress_lzo1x, EscapedPathDisplay},
+};
+
+const MAGIC: &[u8; 4] = b"hsqs";
+const SUPERBLOCK_SIZE: usize = 96;
+/// Largest metadata block, once decompressed
+const METADATA_SIZE: usize = 8192;
+/// Bit of the headers of metadata blocks that are stored uncompressed
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+/// Bit of the sizes of data blocks and fragments that are stored uncompressed
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+/// Fragment index of files whose tail isn't in a fragment
+const NO_FRAGMENT: u32 = 0xFFFF_FFFF;
+const FRAGMENT_ENTRY_SIZE: usize = 16;
+const ID_ENTRY_SIZE: usize = 4;
+/// Most entries after a header of a directory listing
+const MAX_HEADER_ENTRIES: usize = 256;
+/// Longest symlink target
+const MAX_TARGET_SIZE: usize = 64 * 1024;
+
+/// Types of files in modes, like in `stat`
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+fn invalid_image(reason: &'static str) -> crate::Error {
+    FinalError::with_title("Invalid SquashFS image").detail(reason).into()
+}
+
+fn corrupted_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupted SquashFS image")
+}
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().expect("2 bytes"))
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn le64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+/// What the blocks of an image are compressed with
+#[derive(Clone, Copy)]
+enum Compressor {
+    Gzip,
+    Lzma,
+    Lzo,
+    Xz,
+    Lz4,
+    Zstd,
+}
+
+impl Compressor {
+    fn from_id(id: u16) -> crate::Result<Self> {
+        Ok(match id {
+            1 => Self::Gzip,
+            2 => Self::Lzma,
+            3 => Self::Lzo,
+            4 => Self::Xz,
+            5 => Self::Lz4,
+            6 => Self::Zstd,
+            _ => {
+                return Err(Error::UnsupportedFormat {
+                    reason: format!("The SquashFS image is compressed with an unknown compressor, number {id}"),
+                })
+            }
+        })
+    }
+
+    /// Decompresses a block into at most `max_size` bytes.
+    fn decompress(self, data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        let read_limited = |mut decoder: Box<dyn Read + '_>| {
+            let mut output = Vec::with_capacity(max_size);
+            decoder.by_ref().take(max_size as u64 + 1).read_to_end(&mut output)?;
+            if output.len() > max_size {
+                return Err(corrupted_data());
+            }
+            Ok(output)
+        };
+        match self {
+            Self::Gzip => read_limited(Box::new(flate2::read::ZlibDecoder::new(data))),
+            Self::Lzma => {
+                let stream = liblzma::stream::Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::other)?;
+                read_limited(Box::new(liblzma::read::XzDecoder::new_stream(data, stream)))
+            }
+            Self::Lzo => decompress_lzo1x(data, max_size),
+            Self::Xz => read_limited(Box::new(liblzma::read::XzDecoder::new(data))),
+            Self::Lz4 => lz4_flex::block::decompress(data, max_size).map_err(|_| corrupted_data()),
+            Self::Zstd => zstd::bulk::decompress(data, max_size),
+        }
+    }
+}
+
+/// A position in a table of metadata blocks, the start of a block and an offset in its data
+#[derive(Clone, Copy)]
+struct MetadataPosition {
+    block: u64,
+    offset: usize,
+}
+
+/// Where the listing of a directory is in the directory table
+#[derive(Clone, Copy)]
+struct Listing {
+    position: MetadataPosition,
+    /// Size of the listing, plus 3 for the "." and ".." entries that aren't in it
+    size: u32,
+}
+
+/// The data blocks of a file
+#[derive(Default)]
+struct Blocks {
+    /// Where the first block starts, the others follow it
+    start: u64,
+    /// Sizes of the blocks, 0 for the blocks of holes
+    sizes: Vec<u32>,
+    /// Fragment with the tail of the file, and where the tail is in it
+    fragment: Option<(u32, usize)>,
+}
+
+/// An entry of the image, a file, a directory, a symlink or a special file
+#[derive(Default)]
+struct Entry {
+    path: PathBuf,
+    /// Type and permissions
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u32,
+    /// Number of the inode, shared by the hard links of a file
+    inode: u32,
+    links: u32,
+    size: u64,
+    /// Device number of device nodes, in the encoding of Linux
+    device: u32,
+    target: Option<PathBuf>,
+    listing: Option<Listing>,
+    blocks: Blocks,
+}
+
+impl Entry {
+    fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    fn kind(&self) -> Option<EntryKind> {
+        match self.file_type() {
+            S_IFREG => Some(EntryKind::File),
+            S_IFDIR => Some(EntryKind::Directory),
+            S_IFLNK => Some(EntryKind::Symlink),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.file_type() {
+            S_IFREG => self.size,
+            _ => 0,
+        }
+    }
+}
+
+/// Reader of the directories and files of an image
+struct Image<R> {
+    reader: R,
+    compressor: Compressor,
+    block_size: u32,
+    bytes_used: u64,
+    root_inode: u64,
+    inode_table: u64,
+    directory_table: u64,
+    /// Start and size of each fragment
+    fragments: Vec<(u64, u32)>,
+    ids: Vec<u32>,
+    /// Metadata blocks that were read, with where the next block starts
+    metadata: HashMap<u64, (Vec<u8>, u64)>,
+    /// The last fragment that was read, small files often share one
+    fragment: Option<(u32, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> Image<R> {
+    /// Reads the superblock of the image in `reader`, and its tables of fragments and ids.
+    fn new(mut reader: R) -> crate::Result<Self> {
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader
+            .read_exact(&mut superblock)
+            .map_err(|_| invalid_image("It is truncated"))?;
+        if !superblock.starts_with(MAGIC) {
+            return Err(invalid_image("It doesn't start with the SquashFS magic number"));
+        }
+        let (major, minor) = (le16(&superblock, 28), le16(&superblock, 30));
+        if (major, minor) != (4, 0) {
+            return Err(Error::UnsupportedFormat {
+                reason: format!("The image is a SquashFS {major}.{minor} image, only 4.0 is supported"),
+            });
+        }
+        let block_size = le32(&superblock, 12);
+        let block_log = le16(&superblock, 22);
+        if !(4096..=1024 * 1024).contains(&block_size) || 1u32.checked_shl(block_log.into()) != Some(block_size) {
+            return Err(invalid_image("The block size is invalid"));
+        }
+
+        let mut image = Self {
+            reader,
+            compressor: Compressor::from_id(le16(&superblock, 20))?,
+            block_size,
+            bytes_used: le64(&superblock, 40),
+            root_inode: le64(&superblock, 32),
+            inode_table: le64(&superblock, 64),
+            directory_table: le64(&superblock, 72),
+            fragments: vec![],
+            ids: vec![],
+            metadata: HashMap::new(),
+            fragment: None,
+        };
+        let fragments = image.read_table(le64(&superblock, 80), le32(&superblock, 16), FRAGMENT_ENTRY_SIZE)?;
+        image.fragments = fragments
+            .chunks_exact(FRAGMENT_ENTRY_SIZE)
+            .map(|entry| (le64(entry, 0), le32(entry, 8)))
+            .collect();
+        let ids = image.read_table(le64(&superblock, 48), le16(&superblock, 26) as u32, ID_ENTRY_SIZE)?;
+        image.ids = ids.chunks_exact(ID_ENTRY_SIZE).map(|id| le32(id, 0)).collect();
+        Ok(image)
+    }
+
+    /// Reads `len` bytes from `start`.
+    fn read_at(&mut self, start: u64, len: usize) -> crate::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.reader
+            .read_exact(&mut data)
+            .map_err(|_| invalid_image("It is truncated"))?;
+        Ok(data)
+    }
+
+    /// The data of the metadata block that starts at `start`, and where the next block starts.
+    fn metadata_block(&mut self, start: u64) -> crate::Result<&(Vec<u8>, u64)> {
+        if !self.metadata.contains_key(&start) {
+            let corrupted = || invalid_image("A metadata block is corrupted");
+            let header = le16(&self.read_at(start, 2)?, 0);
+            let size = (header & !METADATA_UNCOMPRESSED) as usize;
+            if size == 0 || size > METADATA_SIZE {
+                return Err(corrupted());
+            }
+            let data_start = start.checked_add(2).ok_or_else(corrupted)?;
+            let mut data = self.read_at(data_start, size)?;
+            if header & METADATA_UNCOMPRESSED == 0 {
+                data = self
+                    .compressor
+                    .decompress(&data, METADATA_SIZE)
+                    .map_err(|_| corrupted())?;
+            }
+            self.metadata.insert(start, (data, data_start + size as u64));
+        }
+        Ok(&self.metadata[&start])
+    }
+
+    /// Reads `len` bytes of metadata at `position`, and moves it past them.
+    fn read_metadata(&mut self, position: &mut MetadataPosition, len: usize) -> crate::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        while bytes.len() < len {
+            let (data, next) = self.metadata_block(position.block)?;
+            if position.offset >= data.len() {
+                if position.offset > data.len() {
+                    return Err(invalid_image("A metadata reference is corrupted"));
+                }
+                *position = MetadataPosition {
+                    block: *next,
+                    offset: 0,
+                };
+                continue;
+            }
+            let count = (len - bytes.len()).min(data.len() - position.offset);
+            bytes.extend_from_slice(&data[position.offset..position.offset + count]);
+            position.offset += count;
+        }
+        Ok(bytes)
+    }
+
+    /// Reads `count` entries of `size` bytes of a table, whose metadata blocks are listed at `start`.
+    fn read_table(&mut self, start: u64, count: u32, size: usize) -> crate::Result<Vec<u8>> {
+        let len = count as usize * size;
+        let blocks = len.div_ceil(METADATA_SIZE);
+        if blocks as u64 * 8 > self.bytes_used {
+            return Err(invalid_image("A table is corrupted"));
+        }
+        let locations = self.read_at(start, blocks * 8)?;
+        let mut table = vec![];
+        for location in locations.chunks_exact(8) {
+            let mut position = MetadataPosition {
+                block: le64(location, 0),
+                offset: 0,
+            };
+            let block_len = (len - table.len()).min(METADATA_SIZE);
+            table.extend(self.read_metadata(&mut position, block_len)?);
+        }
+        Ok(table)
+    }
+
+    fn id(&self, index: u16) -> crate::Result<u32> {
+        let id = self.ids.get(index as usize);
+        id.copied().ok_or_else(|| invalid_image("An inode is corrupted"))
+    }
+
+    /// Reads the inode at `reference`, the start of its metadata block in the inode table in the
+    /// upper bits and its offset in the block in the lower 16 bits.
+    fn read_inode(&mut self, reference: u64, path: PathBuf) -> crate::Result<Entry> {
+        let block = self.inode_table.checked_add(reference >> 16);
+        let mut position = MetadataPosition {
+            block: block.ok_or_else(|| invalid_image("An inode reference is corrupted"))?,
+            offset: (reference & 0xFFFF) as usize,
+        };
+        let header = self.read_metadata(&mut position, 16)?;
+        let kind = le16(&header, 0);
+        let file_type = match kind {
+            1 | 8 => S_IFDIR,
+            2 | 9 => S_IFREG,
+            3 | 10 => S_IFLNK,
+            4 | 11 => S_IFBLK,
+            5 | 12 => S_IFCHR,
+            6 | 13 => S_IFIFO,
+            7 | 14 => S_IFSOCK,
+            _ => return Err(invalid_image("An inode is corrupted")),
+        };
+        let mut entry = Entry {
+            path,
+            mode: file_type | (le16(&header, 2) as u32 & 0o7777),
+            uid: self.id(le16(&header, 4))?,
+            gid: self.id(le16(&header, 6))?,
+            mtime: le32(&header, 8),
+            inode: le32(&header, 12),
+            ..Entry::default()
+        };
+
+        match kind {
+            1 => {
+                let inode = self.read_metadata(&mut position, 16)?;
+                entry.links = le32(&inode, 4);
+                entry.listing = Some(self.listing(le32(&inode, 0), le16(&inode, 10), le16(&inode, 8) as u32)?);
+            }
+            8 => {
+                // Followed by an index of the listing, which isn't needed to read it whole
+                let inode = self.read_metadata(&mut position, 24)?;
+                entry.links = le32(&inode, 0);
+                entry.listing = Some(self.listing(le32(&inode, 8), le16(&inode, 18), le32(&inode, 4))?);
+            }
+            2 | 9 => {
+                let (start, size, fragment, fragment_offset) = if kind == 2 {
+                    let inode = self.read_metadata(&mut position, 16)?;
+                    entry.links = 1;
+                    let size = le32(&inode, 12) as u64;
+                    (le32(&inode, 0) as u64, size, le32(&inode, 4), le32(&inode, 8))
+                } else {
+                    let inode = self.read_metadata(&mut position, 40)?;
+                    entry.links = le32(&inode, 24);
+                    (le64(&inode, 0), le64(&inode, 8), le32(&inode, 28), le32(&inode, 32))
+                };
+                let block_size = self.block_size as u64;
+                let count = if fragment == NO_FRAGMENT {
+                    size.div_ceil(block_size)
+                } else {
+                    size / block_size
+                };
+                // The sizes of the blocks are in the image, even the ones of holes
+                if count * 4 > self.bytes_used {
+                    return Err(invalid_image("An inode is corrupted"));
+                }
+                let sizes = self.read_metadata(&mut position, count as usize * 4)?;
+                entry.size = size;
+                entry.blocks = Blocks {
+                    start,
+                    sizes: sizes.chunks_exact(4).map(|size| le32(size, 0)).collect(),
+                    fragment: (fragment != NO_FRAGMENT).then_some((fragment, fragment_offset as usize)),
+                };
+            }
+            3 | 10 => {
+                let inode = self.read_metadata(&mut position, 8)?;
+                entry.links = le32(&inode, 0);
+                let target_size = le32(&inode, 4) as usize;
+                if target_size == 0 || target_size > MAX_TARGET_SIZE {
+                    return Err(invalid_image("A symlink target is corrupted"));
+                }
+                let target = self.read_metadata(&mut position, target_size)?;
+                entry.target = Some(target.to_path_lossy().into_owned());
+            }
+            4 | 5 | 11 | 12 => {
+                let inode = self.read_metadata(&mut position, 8)?;
+                entry.links = le32(&inode, 0);
+                entry.device = le32(&inode, 4);
+            }
+            _ => {
+                let inode = self.read_metadata(&mut position, 4)?;
+                entry.links = le32(&inode, 0);
+            }
+        }
+        Ok(entry)
+    }
+
+    fn listing(&self, block: u32, offset: u16, size: u32) -> crate::Result<Listing> {
+        let block = self.directory_table.checked_add(block as u64);
+        Ok(Listing {
+            position: MetadataPosition {
+                block: block.ok_or_else(|| invalid_image("A directory is corrupted"))?,
+                offset: offset as usize,
+            },
+            size,
+        })
+    }
+
+    /// Reads the listing of a directory, returns the names of its entries and their inodes.
+    fn read_directory(&mut self, listing: Listing) -> crate::Result<Vec<(Vec<u8>, u64)>> {
+        let corrupted = || invalid_image("A directory is corrupted");
+        let mut position = listing.position;
+        let mut left = listing.size.saturating_sub(3) as usize;
+        let mut children = vec![];
+        // Entries come after headers with the metadata block of their inodes
+        while left > 0 {
+            left = left.checked_sub(12).ok_or_else(corrupted)?;
+            let header = self.read_metadata(&mut position, 12)?;
+            let count = le32(&header, 0) as usize + 1;
+            let block = le32(&header, 4) as u64;
+            if count > MAX_HEADER_ENTRIES {
+                return Err(corrupted());
+            }
+            for _ in 0..count {
+                left = left.checked_sub(8).ok_or_else(corrupted)?;
+                let entry = self.read_metadata(&mut position, 8)?;
+                let name_size = le16(&entry, 6) as usize + 1;
+                left = left.checked_sub(name_size).ok_or_else(corrupted)?;
+                let name = self.read_metadata(&mut position, name_size)?;
+                children.push((name, block << 16 | le16(&entry, 0) as u64));
+            }
+        }
+        Ok(children)
+    }
+
+    /// Reads every directory, returns the entries, each directory before the entries inside of it.
+    fn entries(&mut self) -> crate::Result<Vec<Entry>> {
+        let root = self.read_inode(self.root_inode, PathBuf::new())?;
+        let Some(listing) = root.listing else {
+            return Err(invalid_image("The root inode isn't a directory"));
+        };
+        let mut entries = vec![];
+        let mut visited = HashSet::from([self.root_inode]);
+        let mut directories = vec![(listing, PathBuf::new())];
+        while let Some((listing, directory_path)) = directories.pop() {
+            let mut subdirectories = vec![];
+            for (name, reference) in self.read_directory(listing)? {
+                if name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0) {
+                    return Err(invalid_image("A file name is invalid"));
+                }
+                let entry = self.read_inode(reference, directory_path.join(name.to_path_lossy()))?;
+                if let Some(listing) = entry.listing {
+                    if !visited.insert(reference) {
+                        return Err(invalid_image("A directory is inside of itself"));
+                    }
+                    subdirectories.push((listing, entry.path.clone()));
+                }
+                entries.push(entry);
+            }
+            directories.extend(subdirectories.into_iter().rev());
+        }
+        Ok(entries)
+    }
+
+    /// Reader of the data of `entry`.
+    fn data<'a>(&'a mut self, entry: &'a Entry) -> EntryData<'a, R> {
+        EntryData {
+            position: entry.blocks.start,
+            image: self,
+            entry,
+            next_block: 0,
+            loaded: 0,
+            buffer: vec![],
+            read: 0,
+        }
+    }
+
+    /// Reads and decompresses a data block or a fragment of `size` bytes at `start`.
+    fn read_block(&mut self, start: u64, size: u32) -> io::Result<Vec<u8>> {
+        let stored_size = size & !DATA_UNCOMPRESSED;
+        if stored_size > self.block_size {
+            return Err(corrupted_data());
+        }
+        let mut data = vec![0; stored_size as usize];
+        self.reader.seek(SeekFrom::Start(start))?;
+        self.reader.read_exact(&mut data)?;
+        if size & DATA_UNCOMPRESSED != 0 {
+            Ok(data)
+        } else {
+            self.compressor.decompress(&data, self.block_size as usize)
+        }
+    }
+
+    /// The data of the fragment number `index`.
+    fn fragment(&mut self, index: u32) -> io::Result<&[u8]> {
+        if self.fragment.as_ref().is_none_or(|(cached, _)| *cached != index) {
+            let &(start, size) = self.fragments.get(index as usize).ok_or_else(corrupted_data)?;
+            self.fragment = Some((index, self.read_block(start, size)?));
+        }
+        Ok(&self.fragment.as_ref().expect("fragment was read").1)
+    }
+}
+
+/// Data of a file of an `Image`, decompressed one block at a time
+struct EntryData<'a, R> {
+    image: &'a mut Image<R>,
+    entry: &'a Entry,
+    /// Index of the next block, the fragment comes after the blocks
+    next_block: usize,
+    /// Where the next block starts
+    position: u64,
+    /// Bytes of the file that were decompressed
+    loaded: u64,
+    buffer: Vec<u8>,
+    /// Bytes of the buffer that were read
+    read: usize,
+}
+
+impl<R: Read + Seek> EntryData<'_, R> {
+    /// Decompresses the next block of the file into the buffer.
+    fn load_block(&mut self) -> io::Result<()> {
+        let entry = self.entry;
+        let left = entry.size - self.loaded;
+        let len = left.min(self.image.block_size as u64) as usize;
+        let blocks = &entry.blocks;
+        self.buffer = if let Some(&size) = blocks.sizes.get(self.next_block) {
+            self.next_block += 1;
+            if size == 0 {
+                vec![0; len]
+            } else {
+                let block = self.image.read_block(self.position, size)?;
+                let next = self.position.checked_add((size & !DATA_UNCOMPRESSED) as u64);
+                self.position = next.ok_or_else(corrupted_data)?;
+                block
+            }
+        } else if let Some((index, offset)) = blocks.fragment {
+            let fragment = self.image.fragment(index)?;
+            fragment.get(offset..offset + len).ok_or_else(corrupted_data)?.to_vec()
+        } else {
+            return Err(corrupted_data());
+        };
+        if self.buffer.len() != len {
+            return Err(corrupted_data());
+        }
+        self.loaded += len as u64;
+        self.read = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for EntryData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.buffer.len() {
+            if self.loaded == self.entry.size {
+                return Ok(0);
+            }
+            self.load_block()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.read);
+        buf[..len].copy_from_slice(&self.buffer[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+/// Unpacks the image given by `reader` into the folder given by `output_folder`.
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    unpack_entries(reader, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
+    let mut image = Image::new(reader)?;
+
+    let mut files_unpacked = 0;
+    // Symlinks are created last, so that no entry is written through one of them
+    let mut deferred_links = DeferredLinks::new(unpack_options.windows_links);
+    let mut deferred_directories = DeferredDirectories::default();
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    // Where the first entry of each file with hard links was extracted
+    let mut hard_links: HashMap<u32, PathBuf> = HashMap::new();
+    for entry in image.entries()? {
+        unpack_options.cancel.check()?;
+        let filter = &unpack_options.filter;
+        if !filter.matches(entry.kind()) || !filter.matches_path(&entry.path) {
+            continue;
+        }
+
+        context.start_entry(&entry.path, Some(entry.blocks.start).filter(|&start| start != 0));
+        events.handle(Event::EntryStarted {
+            path: &entry.path,
+            size: entry.size(),
+        });
+        let Some(destination) = destinations.destination(&entry.path, entry.file_type() == S_IFDIR) else {
+            continue;
+        };
+        let mtime = Some(entry.mtime as i64);
+        if entry.kind() == Some(EntryKind::File) && destinations.is_extracted(&destination, entry.size(), mtime) {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mode = entry.mode & 0o7777;
+        let mtime = FileTime::from_unix_time(entry.mtime as i64, 0);
+        match entry.file_type() {
+            S_IFDIR => {
+                fs::create_dir_all(&destination)?;
+                deferred_directories.defer(&destination, &entry.path, Some(mode), Some(mtime))?;
+            }
+            S_IFREG if hard_links.contains_key(&entry.inode) => {
+                fs::hard_link(&hard_links[&entry.inode], &destination)?;
+            }
+            S_IFREG => {
+                let mut file = fs::File::create(&destination)?;
+                let data = EventReader::new(image.data(&entry), events);
+                io::copy(&mut CancellableReader::new(data, &unpack_options.cancel), &mut file)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let permissions = std::fs::Permissions::from_mode(mode);
+                    unpack_options.lost_metadata.check(
+                        MetadataKind::Permissions,
+                        &entry.path,
+                        file.set_permissions(permissions),
+                    )?;
+                }
+                let times = filetime_creation::set_file_handle_times(file.file(), None, Some(mtime), None);
+                unpack_options
+                    .lost_metadata
+                    .check(MetadataKind::ModificationTime, &entry.path, times)?;
+                if entry.links > 1 {
+                    hard_links.insert(entry.inode, destination.clone());
+                }
+            }
+            S_IFLNK => {
+                let Some(target) = entry.target.clone() else {
+                    return Err(invalid_image("A symlink has no target"));
+                };
+                deferred_links.defer(destination.clone(), target);
+            }
+            #[cfg(unix)]
+            S_IFIFO => utils::create_fifo(&destination, mode)?,
+            #[cfg(unix)]
+            S_IFCHR | S_IFBLK if unpack_options.devices => {
+                let kind = if entry.file_type() == S_IFCHR {
+                    SpecialFileKind::CharDevice
+                } else {
+                    SpecialFileKind::BlockDevice
+                };
+                let (major, minor) = utils::device_numbers(entry.device as u64);
+                utils::create_device(&destination, kind, entry.mode, major, minor)?;
+            }
+            S_IFCHR | S_IFBLK if !unpack_options.devices => {
+                events.handle(Event::Warning(format!(
+                    "Skipping device node '{}', use '--devices' to restore it.",
+                    EscapedPathDisplay::new(&entry.path)
+                )));
+                continue;
+            }
+            _ => {
+                events.handle(Event::Warning(format!(
+                    "Skipping '{}', files of its type can't be extracted here.",
+                    EscapedPathDisplay::new(&entry.path)
+                )));
+                continue;
+            }
+        }
+
+        unpack_options.restore_owner(&destination, &entry.path, entry.uid, entry.gid)?;
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: entry.size(),
+        });
+        files_unpacked += 1;
+        context.finish_entry();
+    }
+    deferred_links.create_all(output_folder, unpack_options.quiet)?;
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
+
+    Ok(files_unpacked)
+}
+
+/// List contents of `reader`, returning a vector of archive entries
+pub fn list_archive<R: Read + Seek + 'static>(
+    reader: R,
+    contents: ContentOptions,
+) -> crate::Result<impl Iterator<Item = crate::Result<FileInArchive>>> {
+    struct Files<R> {
+        image: Image<R>,
+        entries: std::vec::IntoIter<Entry>,
+        contents: ContentOptions,
+    }
+    impl<R: Read + Seek> Iterator for Files<R> {
+        type Item = crate::Result<FileInArchive>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let entry = self.entries.next()?;
+            let mut file_in_archive = FileInArchive {
+                path: entry.path.clone(),
+                is_dir: entry.file_type() == S_IFDIR,
+                is_symlink: entry.file_type() == S_IFLNK,
+                is_executable: entry.file_type() == S_IFREG && entry.mode & 0o111 != 0,
+                size: entry.size(),
+                preview: None,
+                file_type: None,
+            };
+            if self.contents.is_needed() && entry.file_type() == S_IFREG {
+                if let Err(err) = list::read_contents(&mut file_in_archive, self.image.data(&entry), &self.contents) {
+                    return Some(Err(err.into()));
+                }
+            }
+            Some(Ok(file_in_archive))
+        }
+    }
+
+    let mut image = Image::new(reader)?;
+    let entries = image.entries()?;
+    Ok(Files {
+        image,
+        entries: entries.into_iter(),
+        contents,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    fn inode_header(kind: u16, mode: u16, number: u32) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&kind.to_le_bytes());
+        header.extend_from_slice(&mode.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&1_577_934_245u32.to_le_bytes());
+        header.extend_from_slice(&number.to_le_bytes());
+        header
+    }
+
+    fn numbers(numbers: &[u32]) -> Vec<u8> {
+        numbers.iter().flat_map(|number| number.to_le_bytes()).collect()
+    }
+
+    /// A listing of the inodes at `entries`, all in the first metadata block of the inode table
+    fn listing(entries: &[(&str, u16)]) -> Vec<u8> {
+        let mut listing = numbers(&[entries.len() as u32 - 1, 0, 1]);
+        for (name, offset) in entries {
+            listing.extend_from_slice(&offset.to_le_bytes());
+            listing.extend_from_slice(&[0; 4]);
+            listing.extend_from_slice(&(name.len() as u16 - 1).to_le_bytes());
+            listing.extend_from_slice(name.as_bytes());
+        }
+        listing
+    }
+
+    /// An image with a file in a block and a fragment and a hard link to it, a symlink, and a
+    /// directory with a file in the same fragment, its metadata compressed with zlib if `compressed`
+    fn image(compressed: bool) -> Vec<u8> {
+        let metadata = |data: &[u8]| {
+            let (header, data) = if compressed {
+                let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                let data = encoder.finish().unwrap();
+                (data.len() as u16, data)
+            } else {
+                (data.len() as u16 | METADATA_UNCOMPRESSED, data.to_vec())
+            };
+            [header.to_le_bytes().as_slice(), &data].concat()
+        };
+
+        let mut image = vec![0; SUPERBLOCK_SIZE];
+        image.extend_from_slice(&[b'a'; 4096]);
+        let fragment_start = image.len() as u64;
+        image.extend_from_slice(b"xyznotes");
+
+        // "big" at 0, "link" at 60, "notes.txt" at 87, "sub" at 119 and the root at 151
+        let inodes = [
+            inode_header(9, 0o644, 2),
+            numbers(&[SUPERBLOCK_SIZE as u32, 0, 4099, 0, 0, 0, 2, 0, 0, u32::MAX]),
+            numbers(&[4096 | DATA_UNCOMPRESSED]),
+            inode_header(3, 0o777, 3),
+            numbers(&[1, 3]),
+            b"big".to_vec(),
+            inode_header(2, 0o755, 4),
+            numbers(&[0, 0, 3, 5]),
+            inode_header(1, 0o750, 5),
+            numbers(&[0, 2, 32, 1]),
+            inode_header(1, 0o755, 1),
+            numbers(&[0, 3, 61 | 29 << 16, 1]),
+        ]
+        .concat();
+        let inode_table = image.len() as u64;
+        image.extend(metadata(&inodes));
+
+        let directories = [
+            listing(&[("notes.txt", 87)]),
+            listing(&[("big", 0), ("hard", 0), ("link", 60), ("sub", 119)]),
+        ]
+        .concat();
+        let directory_table = image.len() as u64;
+        image.extend(metadata(&directories));
+
+        let fragments = image.len() as u64;
+        image.extend(metadata(
+            &[
+                &fragment_start.to_le_bytes(),
+                numbers(&[8 | DATA_UNCOMPRESSED, 0]).as_slice(),
+            ]
+            .concat(),
+        ));
+        let fragment_table = image.len() as u64;
+        image.extend_from_slice(&fragments.to_le_bytes());
+        let ids = image.len() as u64;
+        image.extend(metadata(&numbers(&[1000])));
+        let id_table = image.len() as u64;
+        image.extend_from_slice(&ids.to_le_bytes());
+
+        let bytes_used = image.len() as u64;
+        let superblock = &mut image[..SUPERBLOCK_SIZE];
+        superblock[..4].copy_from_slice(MAGIC);
+        superblock[4..8].copy_from_slice(&5u32.to_le_bytes());
+        superblock[12..16].copy_from_slice(&4096u32.to_le_bytes());
+        superblock[16..20].copy_from_slice(&1u32.to_le_bytes());
+        superblock[20..22].copy_from_slice(&1u16.to_le_bytes());
+        superblock[22..24].copy_from_slice(&12u16.to_le_bytes());
+        superblock[26..28].copy_from_slice(&1u16.to_le_bytes());
+        superblock[28..30].copy_from_slice(&4u16.to_le_bytes());
+        for (offset, value) in [
+            (32, 151),
+            (40, bytes_used),
+            (48, id_table),
+            (56, u64::MAX),
+            (64, inode_table),
+            (72, directory_table),
+            (80, fragment_table),
+            (88, u64::MAX),
+        ] {
+            superblock[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn test_entries() {
+        for compressed in [false, true] {
+            let mut image = Image::new(Cursor::new(image(compressed))).unwrap();
+            let entries = image.entries().unwrap();
+            let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+            assert_eq!(paths, ["big", "hard", "link", "sub", "sub/notes.txt"].map(Path::new));
+
+            let (big, hard, link, notes) = (&entries[0], &entries[1], &entries[2], &entries[4]);
+            let mut data = vec![];
+            image.data(big).read_to_end(&mut data).unwrap();
+            assert_eq!(data, [[b'a'; 4096].as_slice(), b"xyz"].concat());
+            assert_eq!((big.inode, big.links), (hard.inode, 2));
+
+            assert_eq!(link.file_type(), S_IFLNK);
+            assert_eq!(link.target.as_deref(), Some(Path::new("big")));
+
+            let mut data = vec![];
+            image.data(notes).read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"notes");
+            assert_eq!(notes.mode, 0o100755);
+            assert_eq!((notes.uid, notes.gid, notes.mtime), (1000, 1000, 1_577_934_245));
+        }
+    }
+
+    #[test]
+    fn test_corrupted_listing() {
+        let mut image = image(false);
+        // The root directory says its listing is longer than the directory table
+        let root = image
+            .windows(4)
+            .rposition(|bytes| bytes == (61u32 | 29 << 16).to_le_bytes())
+            .unwrap();
+        image[root..root + 2].copy_from_slice(&200u16.to_le_bytes());
+
+        let mut image = Image::new(Cursor::new(image)).unwrap();
+        assert!(image.entries().is_err());
+    }
+
+    #[test]
+    fn test_invalid_block_log() {
+        // 1 << 44 overflows, and wraps to the block size of 4096
+        let mut image = image(false);
+        image[22..24].copy_from_slice(&44u16.to_le_bytes());
+        assert!(Image::new(Cursor::new(image)).is_err());
+    }
+
+    #[test]
+    fn test_overflowing_inode_reference() {
+        // The metadata block of the root inode is past the end of the address space
+        let mut image = image(false);
+        image[32..40].copy_from_slice(&(1u64 << 16).to_le_bytes());
+        image[64..72].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut image = Image::new(Cursor::new(image)).unwrap();
+        assert!(image.entries().is_err());
+    }
+}
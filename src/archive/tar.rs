@@ -1,58 +1,357 @@
 //! Contains Tar-specific building and unpacking functions
 
 use std::{
+    borrow::Cow,
     env,
-    io::prelude::*,
+    io::{self, prelude::*, BufWriter, Cursor},
     path::{Path, PathBuf},
     sync::mpsc::{self, Receiver},
     thread,
 };
 
+use filetime_creation::FileTime;
 use fs_err as fs;
+use rayon::prelude::{ParallelDrainRange, ParallelIterator};
 use same_file::Handle;
+use tar::EntryType;
 
 use crate::{
+    archive::{
+        cancel::CancellableReader,
+        checkpoint::Checkpoint,
+        directories::DeferredDirectories,
+        events::{Event, EventHandler, EventReader},
+        filter::EntryFilter,
+        index::{IndexedEntry, OffsetWriter},
+        links::DeferredLinks,
+        metadata::{LostMetadata, MetadataKind},
+        names::EntryDestinations,
+        BuildOptions, CopiedEntry, ExtractionContext, UnpackOptions,
+    },
+    cli::EntryKind,
     error::FinalError,
+    extension::CompressionFormat,
     info,
-    list::FileInArchive,
-    utils::{self, Bytes, EscapedPathDisplay, FileVisibilityPolicy},
+    list::{self, ContentOptions, FileInArchive},
+    progress::{self, Tracked},
+    selinux,
+    utils::{self, EscapedPathDisplay, SpecialFileKind},
     warning,
 };
 
 /// Unpacks the archive given by `archive` into the folder given by `into`.
-/// Assumes that output_folder is empty
-pub fn unpack_archive(reader: Box<dyn Read>, output_folder: &Path, quiet: bool) -> crate::Result<usize> {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
-    let mut archive = tar::Archive::new(reader);
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive(
+    reader: Box<dyn Read + '_>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    let mut archive = tar::Archive::new(wrap_reader(reader, unpack_options));
+    archive
+        .entries()
+        .map_err(Into::into)
+        .and_then(|entries| unpack_entries(entries, output_folder, unpack_options, &mut context))
+        .map_err(|err| context.add_to(err))
+}
+
+/// Like [`unpack_archive`], but seeks over the contents of the entries left out by the filter
+/// instead of reading them, see `index::SeekableDecoder`.
+pub fn unpack_seekable_archive(
+    reader: impl Read + Seek,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    let mut archive = tar::Archive::new(wrap_reader(reader, unpack_options));
+    archive
+        .entries_with_seek()
+        .map_err(Into::into)
+        .and_then(|entries| unpack_entries(entries, output_folder, unpack_options, &mut context))
+        .map_err(|err| context.add_to(err))
+}
+
+/// `reader`, reporting what is read and stopping once cancelled
+fn wrap_reader<'a, R: Read>(reader: R, unpack_options: &'a UnpackOptions) -> CancellableReader<EventReader<'a, R>> {
+    CancellableReader::new(
+        EventReader::new(reader, unpack_options.events.as_ref()),
+        &unpack_options.cancel,
+    )
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries<R: Read>(
+    entries: tar::Entries<'_, R>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    let events = unpack_options.events.as_ref();
 
+    let mut noticed_stripped_root = false;
     let mut files_unpacked = 0;
-    for file in archive.entries()? {
+    let mut deferred_links = DeferredLinks::new(unpack_options.windows_links);
+    let mut deferred_directories = DeferredDirectories::default();
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    for file in entries {
+        unpack_options.cancel.check()?;
         let mut file = file?;
+        let entry_path = file.path()?.into_owned();
+        let filter = &unpack_options.filter;
+        if !filter.matches(entry_kind(file.header().entry_type())) || !filter.matches_path(&entry_path) {
+            continue;
+        }
 
-        file.unpack_in(output_folder)?;
-
-        // This is printed for every file in the archive and has little
-        // importance for most users, but would generate lots of
-        // spoken text for users using screen readers, braille displays
-        // and so on
-        if !quiet {
-            info!(
-                inaccessible,
-                "{:?} extracted. ({})",
-                utils::strip_cur_dir(&output_folder.join(file.path()?)),
-                Bytes::new(file.size()),
-            );
+        context.start_entry(&entry_path, Some(file.raw_header_position()));
+        events.handle(Event::EntryStarted {
+            path: &entry_path,
+            size: file.size(),
+        });
+        unpack_options.notice_stripped_root(&entry_path, &mut noticed_stripped_root);
+        let Some(destination) = destinations.destination(&entry_path, file.header().entry_type().is_dir()) else {
+            continue;
+        };
+        let mtime = file.header().mtime().ok().map(|mtime| mtime as i64);
+        if file.header().entry_type().is_file() && destinations.is_extracted(&destination, file.size(), mtime) {
+            continue;
+        }
+        let selinux_context = if unpack_options.selinux {
+            selinux_context(&mut file)?
+        } else {
+            None
+        };
+        let extended_attributes = extended_attributes(&mut file)?;
+        let lost_metadata = &unpack_options.lost_metadata;
+
+        // Entries with absolute paths are allowed outside of the output folder
+        let bound = (!unpack_options.absolute_paths || !entry_path.has_root()).then_some(output_folder);
 
-            files_unpacked += 1;
+        match file.header().entry_type() {
+            EntryType::Fifo => {
+                if !unpack_fifo(&file, bound, &destination, events)? {
+                    continue;
+                }
+            }
+            EntryType::Char | EntryType::Block => {
+                if !unpack_options.devices {
+                    events.handle(Event::Warning(format!(
+                        "Skipping device node '{}', use '--devices' to restore it.",
+                        EscapedPathDisplay::new(&entry_path)
+                    )));
+                    continue;
+                }
+                if !unpack_device(&file, bound, &destination, events)? {
+                    continue;
+                }
+            }
+            EntryType::Symlink if cfg!(windows) => {
+                if let Some(target) = file.link_name()? {
+                    deferred_links.defer(destination, target.into_owned());
+                }
+                continue;
+            }
+            _ if unpack_options.absolute_paths && entry_path.has_root() => {
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                record_lost_metadata(file.unpack(&destination), &entry_path, lost_metadata)?;
+            }
+            _ if Some(&destination) != utils::entry_destination(output_folder, &entry_path).as_ref() => {
+                unpack_renamed(&mut file, output_folder, &destination, &entry_path, lost_metadata)?;
+            }
+            _ => {
+                record_lost_metadata(file.unpack_in(output_folder), &entry_path, lost_metadata)?;
+            }
+        }
+        restore_extended_attributes(&destination, &entry_path, &extended_attributes, lost_metadata)?;
+
+        if file.header().entry_type() == EntryType::Directory {
+            let header = file.header();
+            let mtime = FileTime::from_unix_time(header.mtime()? as i64, 0);
+            deferred_directories.defer(&destination, &entry_path, Some(header.mode()?), Some(mtime))?;
+        }
+
+        // Some tools leave them blank, those entries have no owner to restore
+        let header = file.header();
+        if let (Ok(uid), Ok(gid)) = (header.uid(), header.gid()) {
+            unpack_options.restore_owner(&destination, &entry_path, uid as u32, gid as u32)?;
         }
+        if let Some(context) = selinux_context {
+            selinux::set_file_context(&destination, &context)?;
+        }
+
+        events.handle(Event::EntryFinished {
+            destination: &destination,
+            size: file.size(),
+        });
+        files_unpacked += 1;
+        context.finish_entry();
     }
+    deferred_links.create_all(output_folder, unpack_options.quiet)?;
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
 
     Ok(files_unpacked)
 }
 
+/// The type of entries of type `entry_type` for `--type`, hard links are regular files
+fn entry_kind(entry_type: EntryType) -> Option<EntryKind> {
+    match entry_type {
+        EntryType::Regular | EntryType::Link | EntryType::Continuous | EntryType::GNUSparse => Some(EntryKind::File),
+        EntryType::Directory => Some(EntryKind::Directory),
+        EntryType::Symlink => Some(EntryKind::Symlink),
+        _ => None,
+    }
+}
+
+/// Unpacks an entry that was given another name than its own, which `unpack_in` can't do.
+///
+/// Like `unpack_in`, refuses to write through symlinks extracted earlier that lead out of
+/// `output_folder`.
+fn unpack_renamed(
+    file: &mut tar::Entry<impl Read>,
+    output_folder: &Path,
+    destination: &Path,
+    entry_path: &Path,
+    lost_metadata: &LostMetadata,
+) -> crate::Result<()> {
+    create_parent_inside(output_folder, destination)?;
+    record_lost_metadata(file.unpack(destination), entry_path, lost_metadata)
+}
+
+/// Creates the parent directory of `destination`, failing if it resolves outside of
+/// `output_folder` through a symlink extracted earlier.
+fn create_parent_inside(output_folder: &Path, destination: &Path) -> crate::Result<()> {
+    let parent = destination
+        .parent()
+        .expect("destinations are inside of the output folder");
+    fs::create_dir_all(parent)?;
+
+    if !fs::canonicalize(parent)?.starts_with(fs::canonicalize(output_folder)?) {
+        let error = FinalError::with_title(format!("Could not extract '{}'", EscapedPathDisplay::new(destination)))
+            .detail("Its parent directory is outside of the output folder");
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Creates the parent directory of a special file, inside of `bound` if it's given.
+#[cfg(unix)]
+fn create_special_parent(bound: Option<&Path>, destination: &Path) -> crate::Result<()> {
+    match (bound, destination.parent()) {
+        (Some(output_folder), _) => create_parent_inside(output_folder, destination),
+        (None, Some(parent)) => Ok(fs::create_dir_all(parent)?),
+        (None, None) => Ok(()),
+    }
+}
+
+/// The `tar` crate fails entries whose permissions or modification time can't be set, once their
+/// data is written, those failures of `entry_path` go to `lost_metadata` instead.
+fn record_lost_metadata<T>(
+    result: io::Result<T>,
+    entry_path: &Path,
+    lost_metadata: &LostMetadata,
+) -> crate::Result<()> {
+    let Err(err) = result else {
+        return Ok(());
+    };
+    let causes = std::iter::successors(Some(&err as &dyn std::error::Error), |cause| cause.source());
+    let kind = causes.clone().find_map(|cause| {
+        let message = cause.to_string();
+        if message.starts_with("failed to set permissions") {
+            Some(MetadataKind::Permissions)
+        } else if message.starts_with("failed to set mtime") {
+            Some(MetadataKind::ModificationTime)
+        } else {
+            None
+        }
+    });
+
+    match kind {
+        Some(kind) => lost_metadata.record(kind, entry_path, causes.last().expect("has the error itself")),
+        None => Err(err.into()),
+    }
+}
+
+/// The `tar` crate writes FIFO entries as empty regular files, so we create them ourselves.
+///
+/// Returns `Ok(false)` if the entry was skipped.
+#[cfg(unix)]
+fn unpack_fifo(
+    file: &tar::Entry<impl Read>,
+    bound: Option<&Path>,
+    destination: &Path,
+    _events: &dyn EventHandler,
+) -> crate::Result<bool> {
+    create_special_parent(bound, destination)?;
+    utils::create_fifo(destination, file.header().mode()? & 0o7777)?;
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn unpack_fifo(
+    file: &tar::Entry<impl Read>,
+    _bound: Option<&Path>,
+    _destination: &Path,
+    events: &dyn EventHandler,
+) -> crate::Result<bool> {
+    events.handle(Event::Warning(format!(
+        "Skipping FIFO '{}', named pipes are only supported on Unix.",
+        EscapedPathDisplay::new(&file.path()?)
+    )));
+    Ok(false)
+}
+
+/// The `tar` crate doesn't create device nodes, so we create them ourselves.
+///
+/// Returns `Ok(false)` if the entry was skipped.
+#[cfg(unix)]
+fn unpack_device(
+    file: &tar::Entry<impl Read>,
+    bound: Option<&Path>,
+    destination: &Path,
+    events: &dyn EventHandler,
+) -> crate::Result<bool> {
+    let header = file.header();
+    let (Some(major), Some(minor)) = (header.device_major()?, header.device_minor()?) else {
+        events.handle(Event::Warning(format!(
+            "Skipping device node '{}', its device numbers are missing.",
+            EscapedPathDisplay::new(&file.path()?)
+        )));
+        return Ok(false);
+    };
+    let kind = if header.entry_type() == EntryType::Char {
+        SpecialFileKind::CharDevice
+    } else {
+        SpecialFileKind::BlockDevice
+    };
+
+    create_special_parent(bound, destination)?;
+    utils::create_device(destination, kind, header.mode()?, major, minor)?;
+
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn unpack_device(
+    file: &tar::Entry<impl Read>,
+    _bound: Option<&Path>,
+    _destination: &Path,
+    events: &dyn EventHandler,
+) -> crate::Result<bool> {
+    events.handle(Event::Warning(format!(
+        "Skipping device node '{}', devices are only supported on Unix.",
+        EscapedPathDisplay::new(&file.path()?)
+    )));
+    Ok(false)
+}
+
 /// List contents of `archive`, returning a vector of archive entries
 pub fn list_archive(
     mut archive: tar::Archive<impl Read + Send + 'static>,
+    contents: ContentOptions,
 ) -> impl Iterator<Item = crate::Result<FileInArchive>> {
     struct Files(Receiver<crate::Result<FileInArchive>>);
     impl Iterator for Files {
@@ -63,89 +362,520 @@ pub fn list_archive(
         }
     }
 
-    let (tx, rx) = mpsc::channel();
+    let (tx, rx) = mpsc::sync_channel(list::LISTED_AHEAD);
     thread::spawn(move || {
         for file in archive.entries().expect("entries is only used once") {
             let file_in_archive = (|| {
-                let file = file?;
+                let mut file = file?;
                 let path = file.path()?.into_owned();
                 let is_dir = file.header().entry_type().is_dir();
-                Ok(FileInArchive { path, is_dir })
+                let is_executable = !is_dir && file.header().mode().is_ok_and(|mode| mode & 0o111 != 0);
+                let mut file_in_archive = FileInArchive {
+                    path,
+                    is_dir,
+                    is_symlink: file.header().entry_type().is_symlink(),
+                    is_executable,
+                    size: file.size(),
+                    preview: None,
+                    file_type: None,
+                };
+                if contents.is_needed() && file.header().entry_type().is_file() {
+                    list::read_contents(&mut file_in_archive, &mut file, &contents)?;
+                }
+                Ok(file_in_archive)
             })();
-            tx.send(file_in_archive).unwrap();
+            // The listing stopped early, after an error
+            if tx.send(file_in_archive).is_err() {
+                return;
+            }
+        }
+        // The end of the archive and the checksum of its compression, once every entry is read
+        if contents.verify {
+            if let Err(err) = io::copy(&mut archive.into_inner(), &mut io::sink()) {
+                let _ = tx.send(Err(err.into()));
+            }
         }
     });
 
     Files(rx)
 }
 
+/// Reads every entry of `archive`, returns them with where their headers start.
+pub fn index_archive(mut archive: tar::Archive<impl Read>) -> crate::Result<Vec<IndexedEntry>> {
+    let mut entries = vec![];
+    // Long names and extended headers come before the header of their entry, so an entry starts
+    // where the previous one ended
+    let mut offset = 0;
+    for file in archive.entries()? {
+        let file = file?;
+        let is_dir = file.header().entry_type().is_dir();
+        entries.push(IndexedEntry {
+            offset,
+            file: FileInArchive {
+                path: file.path()?.into_owned(),
+                is_dir,
+                is_symlink: file.header().entry_type().is_symlink(),
+                is_executable: !is_dir && file.header().mode().is_ok_and(|mode| mode & 0o111 != 0),
+                size: file.size(),
+                preview: None,
+                file_type: None,
+            },
+        });
+        offset = file.raw_file_position() + file.size().next_multiple_of(512);
+    }
+    Ok(entries)
+}
+
+/// Gives the entries of `archive` that `filter` keeps to `append`, returns how many there were.
+///
+/// Hard links, device nodes and FIFOs are skipped with a warning.
+pub fn copy_entries(
+    mut archive: tar::Archive<impl Read>,
+    filter: &EntryFilter,
+    mut append: impl FnMut(CopiedEntry) -> crate::Result<()>,
+) -> crate::Result<usize> {
+    let mut copied = 0;
+    for file in archive.entries()? {
+        let mut file = file?;
+        let path = file.path()?.into_owned();
+        let entry_type = file.header().entry_type();
+        let kind = match entry_kind(entry_type) {
+            Some(kind) if entry_type != EntryType::Link => kind,
+            _ => {
+                warning!(
+                    "Skipping '{}', hard links and special files can't be copied.",
+                    EscapedPathDisplay::new(&path)
+                );
+                continue;
+            }
+        };
+        if !filter.matches(Some(kind)) || !filter.matches_path(&path) {
+            continue;
+        }
+
+        let header = file.header();
+        let entry = CopiedEntry {
+            kind,
+            mode: Some(header.mode()? & 0o7777),
+            mtime: header.mtime()?,
+            link_target: file.link_name()?.map(Cow::into_owned),
+            size: file.size(),
+            path,
+            data: &mut file,
+        };
+        append(entry)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Appends `entry`, copied from another archive, to `builder`.
+pub fn append_copied_entry(builder: &mut tar::Builder<impl Write>, entry: CopiedEntry) -> crate::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(entry.mtime);
+    match entry.kind {
+        EntryKind::File => {
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(entry.mode.unwrap_or(0o644));
+            header.set_size(entry.size);
+            builder.append_data(&mut header, &entry.path, entry.data)?;
+        }
+        EntryKind::Directory => {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(entry.mode.unwrap_or(0o755));
+            header.set_size(0);
+            builder.append_data(&mut header, &entry.path, io::empty())?;
+        }
+        EntryKind::Symlink => {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_mode(entry.mode.unwrap_or(0o777));
+            header.set_size(0);
+            let target = entry.link_target.unwrap_or_default();
+            builder.append_link(&mut header, &entry.path, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Files up to this size are read ahead of time, in parallel, before being appended
+const SMALL_FILE_SIZE: u64 = 64 * 1024;
+
+/// Number of entries that are read ahead of time together
+const BATCH_SIZE: usize = 256;
+
+/// Capacity of the buffer coalescing the small writes of headers and small files
+const WRITE_BUFFER_CAPACITY: usize = 1024 * 1024;
+
 /// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
+///
+/// Trees of tiny files spend most of their time opening and reading each file, so entries are
+/// walked in batches whose small files are read in parallel, then appended in order.
+///
+/// If `index` is given, every appended entry is added to it. If `checkpoint` is given, the entries
+/// it has from earlier runs are left out, and the archive is ended early once its time is up.
 pub fn build_archive_from_paths<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
-    file_visibility_policy: FileVisibilityPolicy,
-    quiet: bool,
+    build_options: &BuildOptions,
+    mut index: Option<&mut Vec<IndexedEntry>>,
+    mut checkpoint: Option<&mut Checkpoint>,
 ) -> crate::Result<W>
 where
     W: Write,
 {
+    let writer = OffsetWriter::new(BufWriter::with_capacity(WRITE_BUFFER_CAPACITY, writer));
     let mut builder = tar::Builder::new(writer);
     let output_handle = Handle::from_path(output_path);
 
-    for filename in input_filenames {
-        let previous_location = utils::cd_into_same_dir_as(filename)?;
+    'inputs: for filename in input_filenames {
+        if let Some(formats) = build_options.expanded_formats(filename) {
+            // Archived or not as a whole, like a single entry
+            if checkpoint
+                .as_ref()
+                .is_some_and(|checkpoint| checkpoint.is_done(filename))
+            {
+                continue;
+            }
+            if checkpoint.as_mut().is_some_and(|checkpoint| checkpoint.is_due()) {
+                break;
+            }
+            build_options.expand_archive(filename, &formats, |entry| append_copied_entry(&mut builder, entry))?;
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                checkpoint.record(filename.clone());
+            }
+            continue;
+        }
 
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
+        let (previous_location, filename) = utils::cd_into_base_dir(filename, build_options.base_directory())?;
 
-        for entry in file_visibility_policy.build_walker(filename) {
-            let entry = entry?;
-            let path = entry.path();
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut walker = build_options.file_visibility_policy.build_walker(filename).peekable();
+        while let Some(entry) = walker.next() {
+            let path = entry?.into_path();
+            // Archived by an earlier run
+            if !checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.is_done(&path)) {
+                batch.push(path);
+            }
+            if batch.len() < BATCH_SIZE && walker.peek().is_some() {
+                continue;
+            }
 
-            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning!(
-                        "The output file and the input file are the same: `{}`, skipping...",
-                        output_path.display()
-                    );
-                    continue;
+            let prepared: Vec<PreparedEntry> = batch.par_drain(..).map(PreparedEntry::new).collect();
+            for entry in prepared {
+                if checkpoint.as_mut().is_some_and(|checkpoint| checkpoint.is_due()) {
+                    env::set_current_dir(previous_location)?;
+                    break 'inputs;
+                }
+                let path = checkpoint.is_some().then(|| entry.path.clone());
+                append_entry(
+                    &mut builder,
+                    entry,
+                    output_path,
+                    &output_handle,
+                    build_options,
+                    index.as_deref_mut(),
+                )?;
+                if let (Some(checkpoint), Some(path)) = (checkpoint.as_mut(), path) {
+                    checkpoint.record(path);
                 }
             }
+        }
+        env::set_current_dir(previous_location)?;
+    }
 
-            // This is printed for every file in `input_filenames` and has
-            // little importance for most users, but would generate lots of
-            // spoken text for users using screen readers, braille displays
-            // and so on
-            if !quiet {
-                info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
-            }
-
-            if path.is_dir() {
-                builder.append_dir(path, path)?;
-            } else {
-                let mut file = match fs::File::open(path) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        if e.kind() == std::io::ErrorKind::NotFound && utils::is_symlink(path) {
-                            // This path is for a broken symlink
-                            // We just ignore it
-                            continue;
-                        }
-                        return Err(e.into());
-                    }
-                };
-                builder.append_file(path, file.file_mut()).map_err(|err| {
-                    FinalError::with_title("Could not create archive")
-                        .detail("Unexpected error while trying to read file")
-                        .detail(format!("Error: {err}."))
-                })?;
+    let writer = builder.into_inner()?.into_inner();
+    Ok(writer.into_inner().map_err(|err| err.into_error())?)
+}
+
+/// An entry to be appended, with its metadata and, if it's a small file, its content
+struct PreparedEntry {
+    path: PathBuf,
+    metadata: std::io::Result<std::fs::Metadata>,
+    content: Option<std::io::Result<Vec<u8>>>,
+}
+
+impl PreparedEntry {
+    fn new(path: PathBuf) -> Self {
+        let metadata = path.metadata();
+        let content = match &metadata {
+            // Special files aren't read, opening a FIFO would block
+            Ok(metadata) if metadata.is_file() && metadata.len() <= SMALL_FILE_SIZE => {
+                let _handle = utils::OPEN_FILES.acquire(1);
+                let content = fs::read(&path);
+                if let Ok(content) = &content {
+                    progress::input_read(content.len() as u64);
+                }
+                Some(content)
             }
+            _ => None,
+        };
+
+        Self {
+            path,
+            metadata,
+            content,
+        }
+    }
+}
+
+/// Appends a single entry to `builder`, unless it must be skipped.
+fn append_entry<W: Write>(
+    builder: &mut tar::Builder<OffsetWriter<W>>,
+    entry: PreparedEntry,
+    output_path: &Path,
+    output_handle: &std::io::Result<Handle>,
+    build_options: &BuildOptions,
+    index: Option<&mut Vec<IndexedEntry>>,
+) -> crate::Result<()> {
+    let PreparedEntry {
+        path,
+        metadata,
+        content,
+    } = entry;
+    let path = path.as_path();
+
+    // This is printed for every file in `input_filenames` and has
+    // little importance for most users, but would generate lots of
+    // spoken text for users using screen readers, braille displays
+    // and so on
+    if !build_options.quiet {
+        info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
+    }
+
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::NotFound && utils::is_symlink(path) {
+                // This path is for a broken symlink
+                // We just ignore it
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+    };
+
+    if let Some(kind) = SpecialFileKind::from_metadata(&metadata) {
+        if !utils::should_store_special_file(path, kind, build_options.special_files, CompressionFormat::Tar)? {
+            return Ok(());
+        }
+    } else if let Ok(handle) = output_handle {
+        // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
+        //
+        // This must come after the special files check, opening a FIFO would block
+        if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+            warning!(
+                "The output file and the input file are the same: `{}`, skipping...",
+                output_path.display()
+            );
+            return Ok(());
         }
-        env::set_current_dir(previous_location)?;
     }
 
-    Ok(builder.into_inner()?)
+    let entry_name = build_options.entry_name(path);
+    let mut header = entry_header(&metadata, build_options)?;
+    if let Some(index) = index {
+        let is_dir = metadata.is_dir();
+        index.push(IndexedEntry {
+            offset: builder.get_ref().offset(),
+            file: FileInArchive {
+                path: entry_name.to_path_buf(),
+                is_dir,
+                is_symlink: header.entry_type().is_symlink(),
+                is_executable: !is_dir && header.mode()? & 0o111 != 0,
+                size: header.size()?,
+                preview: None,
+                file_type: None,
+            },
+        });
+    }
+    if build_options.selinux {
+        if let Some(context) = selinux::file_context(path)? {
+            append_pax_record(builder, selinux::PAX_KEY, &context)?;
+        }
+    }
+    let data: Box<dyn Read> = match content {
+        Some(content) => Box::new(Cursor::new(content?)),
+        None if metadata.is_file() => Box::new(Tracked::new(fs::File::open(path)?)),
+        None => Box::new(std::io::empty()),
+    };
+
+    let append_result = if build_options.absolute_paths {
+        append_with_absolute_name(builder, header, &entry_name, data)
+    } else {
+        builder.append_data(&mut header, &entry_name, data)
+    };
+    append_result.map_err(|err| {
+        FinalError::with_title("Could not create archive")
+            .detail("Unexpected error while trying to read file")
+            .detail(format!("Error: {err}."))
+    })?;
+
+    Ok(())
+}
+
+/// Builds the header of an entry for a file with the given `metadata`, leaving its name empty.
+fn entry_header(metadata: &std::fs::Metadata, build_options: &BuildOptions) -> crate::Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(metadata);
+
+    #[cfg(unix)]
+    if let Some(SpecialFileKind::CharDevice | SpecialFileKind::BlockDevice) = SpecialFileKind::from_metadata(metadata) {
+        use std::os::unix::fs::MetadataExt;
+
+        let (major, minor) = utils::device_numbers(metadata.rdev());
+        header.set_device_major(major)?;
+        header.set_device_minor(minor)?;
+    }
+
+    if let Some(epoch) = build_options.source_date_epoch {
+        header.set_mtime(header.mtime()?.min(epoch));
+    }
+
+    Ok(header)
+}
+
+/// Appends a pax extended header with a single record, which applies to the entry appended next.
+fn append_pax_record(builder: &mut tar::Builder<impl Write>, key: &str, value: &[u8]) -> std::io::Result<()> {
+    // Records are "<length> <key>=<value>\n", where the length counts its own digits
+    let unprefixed_len = key.len() + value.len() + 3;
+    let mut len = unprefixed_len + unprefixed_len.to_string().len();
+    len = unprefixed_len + len.to_string().len();
+
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+
+    let mut header = tar::Header::new_ustar();
+    header.set_path("././@PaxHeader")?;
+    header.set_entry_type(EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(record.len() as u64);
+    header.set_cksum();
+    builder.append(&header, record.as_slice())
+}
+
+/// The SELinux context stored in the pax records of `file`.
+fn selinux_context(file: &mut tar::Entry<impl Read>) -> crate::Result<Option<Vec<u8>>> {
+    let Some(extensions) = file.pax_extensions()? else {
+        return Ok(None);
+    };
+    for extension in extensions {
+        let extension = extension?;
+        if let Ok(selinux::PAX_KEY | selinux::PAX_XATTR_KEY) = extension.key() {
+            return Ok(Some(extension.value_bytes().to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// The extended attributes stored in the pax records of `file`, like GNU tar and bsdtar do, but
+/// the SELinux context, which is restored with `--selinux`.
+fn extended_attributes(file: &mut tar::Entry<impl Read>) -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let Some(extensions) = file.pax_extensions()? else {
+        return Ok(vec![]);
+    };
+    let mut attributes = vec![];
+    for extension in extensions {
+        let extension = extension?;
+        if extension.key_bytes() == selinux::PAX_XATTR_KEY.as_bytes() {
+            continue;
+        }
+        if let Some(name) = extension.key_bytes().strip_prefix(b"SCHILY.xattr.") {
+            attributes.push((name.to_vec(), extension.value_bytes().to_vec()));
+        }
+    }
+    Ok(attributes)
+}
+
+/// Gives `destination` the extended attributes of `entry_path`, those the filesystem or the user
+/// can't set go to `lost_metadata`.
+#[cfg(unix)]
+fn restore_extended_attributes(
+    destination: &Path,
+    entry_path: &Path,
+    attributes: &[(Vec<u8>, Vec<u8>)],
+    lost_metadata: &LostMetadata,
+) -> crate::Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    for (name, value) in attributes {
+        let name = OsStr::from_bytes(name);
+        if let Err(err) = xattr::set(destination, name, value) {
+            let reason = format!("'{}': {err}", name.to_string_lossy());
+            lost_metadata.record(MetadataKind::ExtendedAttributes, entry_path, reason)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_extended_attributes(
+    destination: &Path,
+    entry_path: &Path,
+    attributes: &[(Vec<u8>, Vec<u8>)],
+    lost_metadata: &LostMetadata,
+) -> crate::Result<()> {
+    if attributes.is_empty() {
+        return Ok(());
+    }
+    let reason = "extended attributes are only restored on Unix";
+    lost_metadata.record(MetadataKind::ExtendedAttributes, entry_path, reason)
+}
+
+/// Appends an entry under an absolute `entry_name`.
+///
+/// `tar::Builder` refuses to write absolute entry names, so the name is written to the header by
+/// hand, using the GNU extension for names that don't fit in it.
+fn append_with_absolute_name<W: Write>(
+    builder: &mut tar::Builder<W>,
+    mut header: tar::Header,
+    entry_name: &Path,
+    data: impl Read,
+) -> std::io::Result<()> {
+    let name = entry_name.as_os_str().as_encoded_bytes();
+    let name_field = &mut header.as_old_mut().name;
+    if name.len() < name_field.len() {
+        name_field[..name.len()].copy_from_slice(name);
+    } else {
+        let mut long_name_header = tar::Header::new_gnu();
+        long_name_header.as_old_mut().name[..13].copy_from_slice(b"././@LongLink");
+        long_name_header.set_mode(0o644);
+        long_name_header.set_entry_type(EntryType::GNULongName);
+        long_name_header.set_size(name.len() as u64 + 1);
+        long_name_header.set_cksum();
+        builder.append(&long_name_header, name.chain(&[0][..]))?;
+
+        let truncated_len = name_field.len();
+        name_field.copy_from_slice(&name[..truncated_len]);
+    }
+
+    header.set_cksum();
+    builder.append(&header, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selinux_context_pax_record() {
+        // Lengths of records around a power of ten, where their own digits count
+        for context_len in [0, 74, 75, 76, 974, 975] {
+            let context = vec![b'x'; context_len];
+            let mut builder = tar::Builder::new(vec![]);
+            append_pax_record(&mut builder, selinux::PAX_KEY, &context).unwrap();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            builder.append_data(&mut header, "file.txt", &b"data"[..]).unwrap();
+            let archive = builder.into_inner().unwrap();
+
+            let mut archive = tar::Archive::new(archive.as_slice());
+            let mut entries = archive.entries().unwrap();
+            let mut file = entries.next().unwrap().unwrap();
+            assert_eq!(file.path().unwrap(), Path::new("file.txt"));
+            assert_eq!(selinux_context(&mut file).unwrap(), Some(context));
+            assert!(entries.next().is_none());
+        }
+    }
 }
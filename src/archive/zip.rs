@@ -3,6 +3,7 @@
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+    collections::HashMap,
     env,
     io::{self, prelude::*},
     path::{Path, PathBuf},
@@ -14,87 +15,364 @@ use filetime_creation::{set_file_mtime, FileTime};
 use fs_err as fs;
 use same_file::Handle;
 use time::OffsetDateTime;
-use zip::{self, read::ZipFile, DateTime, ZipArchive};
+use zip::{self, read::ZipFile, result::ZipError, unstable::write::FileOptionsExt, AesMode, DateTime, ZipArchive};
 
 use crate::{
-    error::FinalError,
-    info,
-    list::FileInArchive,
-    utils::{
-        self, cd_into_same_dir_as, get_invalid_utf8_paths, pretty_format_list_of_paths, strip_cur_dir, Bytes,
-        EscapedPathDisplay, FileVisibilityPolicy,
+    archive::{
+        cancel::CancellableReader,
+        directories::DeferredDirectories,
+        events::{Event, EventReader},
+        filter::EntryFilter,
+        metadata::MetadataKind,
+        names::EntryDestinations,
+        BuildOptions, CopiedEntry, ExtractionContext, UnpackOptions,
     },
+    cli::{EntryKind, ZipEncryption},
+    error::{Error, FinalError},
+    extension::CompressionFormat,
+    info,
+    list::{self, ContentOptions, FileInArchive},
+    progress::Tracked,
+    utils::{self, get_invalid_utf8_paths, pretty_format_list_of_paths, EscapedPathDisplay, SpecialFileKind},
     warning,
 };
 
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+
 /// Unpacks the archive given by `archive` into the folder given by `output_folder`.
-/// Assumes that output_folder is empty
-pub fn unpack_archive<R>(mut archive: ZipArchive<R>, output_folder: &Path, quiet: bool) -> crate::Result<usize>
+/// Assumes that output_folder is empty, unless an extraction is resumed with `--ignore-existing`
+pub fn unpack_archive<R>(
+    archive: ZipArchive<R>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<usize>
+where
+    R: Read + Seek,
+{
+    let mut context = ExtractionContext::default();
+    unpack_entries(archive, output_folder, unpack_options, &mut context).map_err(|err| context.add_to(err))
+}
+
+/// The loop of `unpack_archive`, keeping `context` up to date for its errors
+fn unpack_entries<R>(
+    mut archive: ZipArchive<R>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize>
 where
     R: Read + Seek,
 {
-    assert!(output_folder.read_dir().expect("dir exists").count() == 0);
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
 
+    let events = unpack_options.events.as_ref();
+    let mut noticed_stripped_root = false;
     let mut unpacked_files = 0;
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    let mut deferred_directories = DeferredDirectories::default();
 
     for idx in 0..archive.len() {
-        let mut file = archive.by_index(idx)?;
-        let file_path = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
+        unpack_options.cancel.check()?;
+        let mut file = match unpack_options.password.get() {
+            Some(password) => archive
+                .by_index_decrypt(idx, password.as_bytes())
+                .map_err(|err| match err {
+                    ZipError::InvalidPassword => invalid_password_error(),
+                    err => err.into(),
+                })?,
+            None => archive.by_index(idx)?,
         };
+        let entry_path = PathBuf::from(file.name());
+        let filter = &unpack_options.filter;
+        if !filter.matches(Some(entry_kind(&file))) || !filter.matches_path(&entry_path) {
+            continue;
+        }
+        context.start_entry(&entry_path, Some(file.header_start()));
 
-        let file_path = output_folder.join(file_path);
+        unpack_options.notice_stripped_root(&entry_path, &mut noticed_stripped_root);
+        let Some(file_path) = destinations.destination(&entry_path, file.name().ends_with('/')) else {
+            continue;
+        };
+        let mtime = last_modified_time(&file).map(|mtime| mtime.unix_seconds());
+        if file.is_file() && destinations.is_extracted(&file_path, file.size(), mtime) {
+            continue;
+        }
 
         display_zip_comment_if_exists(&file);
 
         match file.name().ends_with('/') {
             _is_dir @ true => {
-                // This is printed for every file in the archive and has little
-                // importance for most users, but would generate lots of
-                // spoken text for users using screen readers, braille displays
-                // and so on
-                if !quiet {
-                    info!(inaccessible, "File {} extracted to \"{}\"", idx, file_path.display());
-                }
                 fs::create_dir_all(&file_path)?;
+                deferred_directories.defer(&file_path, &entry_path, file.unix_mode(), last_modified_time(&file))?;
             }
             _is_file @ false => {
-                if let Some(path) = file_path.parent() {
-                    if !path.exists() {
-                        fs::create_dir_all(path)?;
-                    }
-                }
-                let file_path = strip_cur_dir(file_path.as_path());
-
-                // same reason is in _is_dir: long, often not needed text
-                if !quiet {
-                    info!(
-                        inaccessible,
-                        "{:?} extracted. ({})",
-                        file_path.display(),
-                        Bytes::new(file.size()),
-                    );
+                extract_file(&mut file, &entry_path, &file_path, unpack_options)?;
+                #[cfg(unix)]
+                if let Some(mode) = file.unix_mode() {
+                    let permissions = fs::set_permissions(&file_path, std::fs::Permissions::from_mode(mode));
+                    unpack_options
+                        .lost_metadata
+                        .check(MetadataKind::Permissions, &entry_path, permissions)?;
                 }
+            }
+        }
 
-                let mut output_file = fs::File::create(file_path)?;
-                io::copy(&mut file, &mut output_file)?;
+        events.handle(Event::EntryFinished {
+            destination: &file_path,
+            size: file.size(),
+        });
+        unpacked_files += 1;
+        context.finish_entry();
+    }
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
 
-                set_last_modified_time(&file, file_path)?;
-            }
+    Ok(unpacked_files)
+}
+
+fn entry_kind(file: &ZipFile) -> EntryKind {
+    if file.is_dir() {
+        EntryKind::Directory
+    } else if file.is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    }
+}
+
+/// Whether some entries of `archive` are encrypted, which can't be extracted without a password.
+pub fn has_encrypted_entries<R>(archive: &mut ZipArchive<R>) -> bool
+where
+    R: Read + Seek,
+{
+    (0..archive.len()).any(|idx| {
+        matches!(
+            archive.by_index(idx),
+            Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED))
+        )
+    })
+}
+
+fn invalid_password_error() -> Error {
+    FinalError::with_title("Invalid password")
+        .detail("The password does not decrypt the entries of this zip archive")
+        .into()
+}
+
+/// Unpacks the zip archive read from `reader` into `output_folder`, one entry at a time, without
+/// seeking nor buffering it.
+///
+/// The permissions are only in the central directory, after the entries, so they're applied last.
+/// Fails with `Error::UnsupportedZipArchive` or `Error::InvalidZipArchive` for archives that can
+/// only be read from their central directory, like those with sizes after the entry data, which
+/// `ZipArchive` has to be used for instead.
+pub fn unpack_stream(reader: impl Read, output_folder: &Path, unpack_options: &UnpackOptions) -> crate::Result<usize> {
+    let mut context = ExtractionContext::default();
+    let mut reader = StreamReader::new(reader);
+    unpack_stream_entries(&mut reader, output_folder, unpack_options, &mut context).map_err(|err| match err {
+        // Not about the entry, the archive is read again with `ZipArchive`
+        Error::UnsupportedZipArchive(_) | Error::InvalidZipArchive(_) => err,
+        err => context.add_to(err),
+    })
+}
+
+/// The loop of `unpack_stream`, keeping `context` up to date for its errors
+fn unpack_stream_entries(
+    reader: &mut StreamReader<impl Read>,
+    output_folder: &Path,
+    unpack_options: &UnpackOptions,
+    context: &mut ExtractionContext,
+) -> crate::Result<usize> {
+    assert!(unpack_options.ignore_existing.is_some() || output_folder.read_dir().expect("dir exists").count() == 0);
+    if unpack_options.filter.filters_types() {
+        return Err(Error::UnsupportedZipArchive(
+            "the types of the entries are only known from the central directory",
+        ));
+    }
+
+    let events = unpack_options.events.as_ref();
+    let mut noticed_stripped_root = false;
+    let mut unpacked_files = 0;
+    let mut destinations = EntryDestinations::new(output_folder, unpack_options)?;
+    // Extracted entries and their raw names, until their permissions are known
+    let mut extracted = vec![];
+
+    loop {
+        match reader.peek_signature()? {
+            LOCAL_FILE_HEADER_SIGNATURE => {}
+            // Its first header would be read as a local one, the end comes first in empty archives
+            CENTRAL_DIRECTORY_HEADER_SIGNATURE | END_OF_CENTRAL_DIRECTORY_SIGNATURE => break,
+            _ => return Err(Error::InvalidZipArchive("Invalid local file header")),
+        }
+        let Some(mut file) = zip::read::read_zipfile_from_stream(reader)? else {
+            break;
+        };
+        unpack_options.cancel.check()?;
+        let entry_path = PathBuf::from(file.name());
+        let is_dir = file.name().ends_with('/');
+        // Dropping the entry skips its data
+        if !unpack_options.filter.matches_path(&entry_path) {
+            continue;
+        }
+        context.start_entry(&entry_path, None);
+
+        unpack_options.notice_stripped_root(&entry_path, &mut noticed_stripped_root);
+        let Some(file_path) = destinations.destination(&entry_path, is_dir) else {
+            continue;
+        };
+        let mtime = last_modified_time(&file).map(|mtime| mtime.unix_seconds());
+        if file.is_file() && destinations.is_extracted(&file_path, file.size(), mtime) {
+            continue;
         }
 
-        #[cfg(unix)]
-        unix_set_permissions(&file_path, &file)?;
+        if is_dir {
+            fs::create_dir_all(&file_path)?;
+        } else {
+            extract_file(&mut file, &entry_path, &file_path, unpack_options)?;
+        }
 
+        events.handle(Event::EntryFinished {
+            destination: &file_path,
+            size: file.size(),
+        });
+        extracted.push((
+            file.name_raw().to_vec(),
+            entry_path,
+            file_path,
+            is_dir,
+            last_modified_time(&file),
+        ));
         unpacked_files += 1;
+        context.finish_entry();
     }
 
+    let modes = read_central_directory_modes(reader)?;
+    let mut deferred_directories = DeferredDirectories::default();
+    for (name, entry_path, file_path, is_dir, modification_time) in extracted {
+        let mode = modes.get(&name).copied();
+        if is_dir {
+            deferred_directories.defer(&file_path, &entry_path, mode, modification_time)?;
+        } else {
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                let permissions = fs::set_permissions(&file_path, std::fs::Permissions::from_mode(mode));
+                unpack_options
+                    .lost_metadata
+                    .check(MetadataKind::Permissions, &entry_path, permissions)?;
+            }
+        }
+    }
+    deferred_directories.apply_all(&unpack_options.lost_metadata)?;
+    destinations.report_renames();
+
     Ok(unpacked_files)
 }
 
+/// Reads the central directory headers, returns the unix modes of the entries by raw name.
+fn read_central_directory_modes(reader: &mut impl Read) -> crate::Result<HashMap<Vec<u8>, u32>> {
+    const SYSTEM_UNIX: u16 = 3;
+
+    let mut modes = HashMap::new();
+    loop {
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature)?;
+        if u32::from_le_bytes(signature) != CENTRAL_DIRECTORY_HEADER_SIGNATURE {
+            return Ok(modes);
+        }
+
+        // The fields of the header after its signature, up to the name
+        let mut header = [0; 42];
+        reader.read_exact(&mut header)?;
+        let u16_at = |offset: usize| u16::from_le_bytes([header[offset], header[offset + 1]]);
+        let version_made_by = u16_at(0);
+        let (name_length, extra_length, comment_length) = (u16_at(24), u16_at(26), u16_at(28));
+        let external_attributes = u32::from_le_bytes(header[34..38].try_into().expect("4 bytes"));
+
+        let mut name = vec![0; name_length as usize];
+        reader.read_exact(&mut name)?;
+        let rest = u64::from(extra_length) + u64::from(comment_length);
+        io::copy(&mut reader.take(rest), &mut io::sink())?;
+
+        let mode = external_attributes >> 16;
+        if version_made_by >> 8 == SYSTEM_UNIX && mode != 0 {
+            modes.insert(name, mode);
+        }
+    }
+}
+
+/// Reader of a zip stream that can look at the signature of the next header.
+///
+/// `read_zipfile_from_stream` reads a whole local file header before checking its signature,
+/// which would swallow the start of the central directory.
+struct StreamReader<R> {
+    inner: R,
+    /// Bytes read ahead, returned before the rest
+    peeked: Vec<u8>,
+}
+
+impl<R: Read> StreamReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, peeked: vec![] }
+    }
+
+    /// The signature of the next header, which is left to be read.
+    fn peek_signature(&mut self) -> io::Result<u32> {
+        let mut signature = [0; 4];
+        self.read_exact(&mut signature)?;
+        self.peeked.splice(0..0, signature);
+        Ok(u32::from_le_bytes(signature))
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.peeked.is_empty() {
+            return self.inner.read(buf);
+        }
+        let len = buf.len().min(self.peeked.len());
+        buf[..len].copy_from_slice(&self.peeked[..len]);
+        self.peeked.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Extracts the data of the file entry `file` to `file_path`, with its modification time.
+fn extract_file(
+    file: &mut ZipFile,
+    entry_path: &Path,
+    file_path: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<()> {
+    let events = unpack_options.events.as_ref();
+    if let Some(path) = file_path.parent() {
+        if !path.exists() {
+            fs::create_dir_all(path)?;
+        }
+    }
+    events.handle(Event::EntryStarted {
+        path: entry_path,
+        size: file.size(),
+    });
+    let mut output_file = fs::File::create(file_path)?;
+    let mut reader = CancellableReader::new(EventReader::new(&mut *file, events), &unpack_options.cancel);
+    io::copy(&mut reader, &mut output_file)?;
+
+    if let Some(modification_time) = last_modified_time(file) {
+        let times = set_file_mtime(file_path, modification_time);
+        unpack_options
+            .lost_metadata
+            .check(MetadataKind::ModificationTime, entry_path, times)?;
+    }
+    Ok(())
+}
+
 /// List contents of `archive`, returning a vector of archive entries
-pub fn list_archive<R>(mut archive: ZipArchive<R>) -> impl Iterator<Item = crate::Result<FileInArchive>>
+pub fn list_archive<R>(
+    mut archive: ZipArchive<R>,
+    contents: ContentOptions,
+) -> impl Iterator<Item = crate::Result<FileInArchive>>
 where
     R: Read + Seek + Send + 'static,
 {
@@ -107,22 +385,50 @@ where
         }
     }
 
-    let (tx, rx) = mpsc::channel();
+    let (tx, rx) = mpsc::sync_channel(list::LISTED_AHEAD);
     thread::spawn(move || {
         for idx in 0..archive.len() {
             let maybe_file_in_archive = (|| {
-                let file = match archive.by_index(idx) {
+                // Not decrypted, the names and sizes of encrypted entries aren't encrypted
+                let file = match archive.by_index_raw(idx) {
                     Ok(f) => f,
                     Err(e) => return Some(Err(e.into())),
                 };
 
-                let path = file.enclosed_name()?.to_owned();
+                // Rebuilt from its components, without the trailing slash of directories
+                let path: PathBuf = file.enclosed_name()?.components().collect();
                 let is_dir = file.is_dir();
-
-                Some(Ok(FileInArchive { path, is_dir }))
+                let is_executable = !is_dir && file.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+                let is_symlink = file.is_symlink();
+                let size = file.size();
+                // The contents of encrypted files aren't read, the password isn't asked for to list them
+                let readable = !is_dir && !is_symlink && !file.encrypted();
+                drop(file);
+
+                let mut file_in_archive = FileInArchive {
+                    path,
+                    is_dir,
+                    is_symlink,
+                    is_executable,
+                    size,
+                    preview: None,
+                    file_type: None,
+                };
+                // Files that can't be decompressed, like ones of unsupported methods, are only listed
+                if contents.is_needed() && readable {
+                    if let Ok(file) = archive.by_index(idx) {
+                        if let Err(e) = list::read_contents(&mut file_in_archive, file, &contents) {
+                            return Some(Err(e.into()));
+                        }
+                    }
+                }
+                Some(Ok(file_in_archive))
             })();
             if let Some(file_in_archive) = maybe_file_in_archive {
-                tx.send(file_in_archive).unwrap();
+                // The listing stopped early, after an error
+                if tx.send(file_in_archive).is_err() {
+                    break;
+                }
             }
         }
     });
@@ -130,13 +436,128 @@ where
     Files(rx)
 }
 
+/// Reads the zip stream `reader` to its end, which checks the checksums of its entries, and gives
+/// each file entry to `test_entry`, see `ouch test --recursive`.
+pub fn test_stream(
+    reader: impl Read,
+    mut test_entry: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut reader = StreamReader::new(reader);
+    loop {
+        match reader.peek_signature()? {
+            LOCAL_FILE_HEADER_SIGNATURE => {}
+            CENTRAL_DIRECTORY_HEADER_SIGNATURE | END_OF_CENTRAL_DIRECTORY_SIGNATURE => break,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid local file header")),
+        }
+        let Some(mut file) = zip::read::read_zipfile_from_stream(&mut reader)? else {
+            break;
+        };
+        if file.is_file() {
+            let path: PathBuf = Path::new(file.name()).components().collect();
+            test_entry(&path, &mut file)?;
+        }
+    }
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(())
+}
+
+/// Gives the entries of `archive` that `filter` keeps to `append`, returns how many there were.
+pub fn copy_entries<R>(
+    mut archive: ZipArchive<R>,
+    filter: &EntryFilter,
+    mut append: impl FnMut(CopiedEntry) -> crate::Result<()>,
+) -> crate::Result<usize>
+where
+    R: Read + Seek,
+{
+    let mut copied = 0;
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let kind = entry_kind(&file);
+        // Rebuilt from its components, without the trailing slash of directories
+        let path: PathBuf = Path::new(file.name()).components().collect();
+        if !filter.matches(Some(kind)) || !filter.matches_path(&path) {
+            continue;
+        }
+
+        let mode = file.unix_mode().map(|mode| mode & 0o7777);
+        let mtime = file
+            .last_modified()
+            .and_then(|time| OffsetDateTime::try_from(time).ok())
+            .map_or(0, |time| time.unix_timestamp().max(0) as u64);
+        // The target of symlinks is their content
+        let link_target = match kind {
+            EntryKind::Symlink => {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                Some(PathBuf::from(target))
+            }
+            _ => None,
+        };
+        let entry = CopiedEntry {
+            path,
+            kind,
+            mode,
+            mtime,
+            size: if kind == EntryKind::File { file.size() } else { 0 },
+            link_target,
+            data: &mut file,
+        };
+        append(entry)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Appends `entry`, copied from another archive, to `writer`.
+pub fn append_copied_entry<W>(writer: &mut zip::ZipWriter<W>, entry: CopiedEntry) -> crate::Result<()>
+where
+    W: Write + Seek,
+{
+    let name = entry.path.to_str().ok_or_else(|| {
+        FinalError::with_title("Zip requires that all directories names are valid UTF-8").detail(format!(
+            "Entry '{}' has a non-UTF-8 name",
+            EscapedPathDisplay::new(&entry.path)
+        ))
+    })?;
+    let last_modified_time = OffsetDateTime::from_unix_timestamp(entry.mtime as i64)
+        .ok()
+        .and_then(|time| DateTime::try_from(time).ok())
+        .unwrap_or_default();
+    let options = zip::write::SimpleFileOptions::default()
+        .large_file(true)
+        .last_modified_time(last_modified_time);
+    let options = match entry.mode {
+        Some(mode) => options.unix_permissions(mode),
+        None => options,
+    };
+
+    match entry.kind {
+        EntryKind::File => {
+            writer.start_file(name, options)?;
+            io::copy(entry.data, writer)?;
+        }
+        EntryKind::Directory => writer.add_directory(name, options)?,
+        EntryKind::Symlink => {
+            let target = entry.link_target.unwrap_or_default();
+            let target = target.to_str().ok_or_else(|| {
+                FinalError::with_title("Zip requires that all symlink targets are valid UTF-8").detail(format!(
+                    "Entry '{}' has a non-UTF-8 target",
+                    EscapedPathDisplay::new(&entry.path)
+                ))
+            })?;
+            writer.add_symlink(name, target, options)?;
+        }
+    }
+    Ok(())
+}
+
 /// Compresses the archives given by `input_filenames` into the file given previously to `writer`.
 pub fn build_archive_from_paths<W>(
     input_filenames: &[PathBuf],
     output_path: &Path,
     writer: W,
-    file_visibility_policy: FileVisibilityPolicy,
-    quiet: bool,
+    build_options: &BuildOptions,
 ) -> crate::Result<W>
 where
     W: Write + Seek,
@@ -144,7 +565,12 @@ where
     let mut writer = zip::ZipWriter::new(writer);
     // always use ZIP64 to allow compression of files larger than 4GB
     // the format is widely supported and the extra 20B is negligible in most cases
-    let options = zip::write::FileOptions::default().large_file(true);
+    let options = zip::write::SimpleFileOptions::default().large_file(true);
+    let options = match (&build_options.password, build_options.encryption) {
+        (Some(password), ZipEncryption::Aes256) => options.with_aes_encryption(AesMode::Aes256, password),
+        (Some(password), ZipEncryption::ZipCrypto) => options.with_deprecated_encryption(password.as_bytes()),
+        (None, _) => options,
+    };
     let output_handle = Handle::from_path(output_path);
 
     #[cfg(not(unix))]
@@ -165,32 +591,22 @@ where
     }
 
     for filename in input_filenames {
-        let previous_location = cd_into_same_dir_as(filename)?;
+        if let Some(formats) = build_options.expanded_formats(filename) {
+            build_options.expand_archive(filename, &formats, |entry| append_copied_entry(&mut writer, entry))?;
+            continue;
+        }
 
-        // Unwrap safety:
-        //   paths should be canonicalized by now, and the root directory rejected.
-        let filename = filename.file_name().unwrap();
+        let (previous_location, filename) = utils::cd_into_base_dir(filename, build_options.base_directory())?;
 
-        for entry in file_visibility_policy.build_walker(filename) {
+        for entry in build_options.file_visibility_policy.build_walker(filename) {
             let entry = entry?;
             let path = entry.path();
 
-            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
-            if let Ok(handle) = &output_handle {
-                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
-                    warning!(
-                        "The output file and the input file are the same: `{}`, skipping...",
-                        output_path.display()
-                    );
-                    continue;
-                }
-            }
-
             // This is printed for every file in `input_filenames` and has
             // little importance for most users, but would generate lots of
             // spoken text for users using screen readers, braille displays
             // and so on
-            if !quiet {
+            if !build_options.quiet {
                 info!(inaccessible, "Compressing '{}'.", EscapedPathDisplay::new(path));
             }
 
@@ -206,16 +622,36 @@ where
                 }
             };
 
+            if let Some(kind) = SpecialFileKind::from_metadata(&metadata) {
+                utils::should_store_special_file(path, kind, build_options.special_files, CompressionFormat::Zip)?;
+                continue;
+            }
+
+            // If the output_path is the same as the input file, warn the user and skip the input (in order to avoid compression recursion)
+            //
+            // This must come after the special files check, opening a FIFO would block
+            if let Ok(handle) = &output_handle {
+                if matches!(Handle::from_path(path), Ok(x) if &x == handle) {
+                    warning!(
+                        "The output file and the input file are the same: `{}`, skipping...",
+                        output_path.display()
+                    );
+                    continue;
+                }
+            }
+
             #[cfg(unix)]
             let options = options.unix_permissions(metadata.permissions().mode());
+            let last_modified_time = get_last_modified_time(&metadata, build_options);
 
-            let entry_name = path.to_str().ok_or_else(|| {
+            let entry_name = build_options.entry_name(path);
+            let entry_name = entry_name.to_str().ok_or_else(|| {
                 FinalError::with_title("Zip requires that all directories names are valid UTF-8")
                     .detail(format!("File at '{path:?}' has a non-UTF-8 name"))
             })?;
 
             if metadata.is_dir() {
-                writer.add_directory(entry_name, options)?;
+                writer.add_directory(entry_name, options.last_modified_time(last_modified_time))?;
             } else {
                 #[cfg(not(unix))]
                 let options = if is_executable::is_executable(path) {
@@ -224,12 +660,13 @@ where
                     options
                 };
 
-                let mut file = fs::File::open(path)?;
-
-                // Updated last modified time
-                let last_modified_time = options.last_modified_time(get_last_modified_time(&file));
-
-                writer.start_file(entry_name, last_modified_time)?;
+                let mut file = Tracked::new(fs::File::open(path)?);
+                let options = options.last_modified_time(last_modified_time);
+                let options = match build_options.zip_align {
+                    Some(align) => options.with_alignment(align),
+                    None => options,
+                };
+                writer.start_file(entry_name, options)?;
                 io::copy(&mut file, &mut writer)?;
             }
         }
@@ -258,36 +695,18 @@ fn display_zip_comment_if_exists(file: &ZipFile) {
     }
 }
 
-fn get_last_modified_time(file: &fs::File) -> DateTime {
-    file.metadata()
-        .and_then(|metadata| metadata.modified())
+fn get_last_modified_time(metadata: &std::fs::Metadata, build_options: &BuildOptions) -> DateTime {
+    metadata
+        .modified()
         .ok()
+        .map(|time| build_options.clamp_time(time))
         .and_then(|time| DateTime::try_from(OffsetDateTime::from(time)).ok())
         .unwrap_or_default()
 }
 
-fn set_last_modified_time(zip_file: &ZipFile, path: &Path) -> crate::Result<()> {
-    let modification_time = zip_file.last_modified().to_time();
-
-    let Ok(time_in_seconds) = modification_time else {
-        return Ok(());
-    };
+fn last_modified_time(zip_file: &ZipFile) -> Option<FileTime> {
+    let time_in_seconds = OffsetDateTime::try_from(zip_file.last_modified()?).ok()?;
 
     // Zip does not support nanoseconds, so we can assume zero here
-    let modification_time = FileTime::from_unix_time(time_in_seconds.unix_timestamp(), 0);
-
-    set_file_mtime(path, modification_time)?;
-
-    Ok(())
-}
-
-#[cfg(unix)]
-fn unix_set_permissions(file_path: &Path, file: &ZipFile) -> crate::Result<()> {
-    use std::fs::Permissions;
-
-    if let Some(mode) = file.unix_mode() {
-        fs::set_permissions(file_path, Permissions::from_mode(mode))?;
-    }
-
-    Ok(())
+    Some(FileTime::from_unix_time(time_in_seconds.unix_timestamp(), 0))
 }
@@ -5,14 +5,20 @@
 use std::{
     ffi::OsString,
     ops::ControlFlow,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use crate::{
     error::FinalError,
-    extension::{build_archive_file_suggestion, Extension, PRETTY_SUPPORTED_ALIASES, PRETTY_SUPPORTED_EXTENSIONS},
+    extension::{
+        build_archive_file_suggestion, flatten_compression_formats, pretty_supported_aliases,
+        pretty_supported_extensions, separate_known_extensions_from_name, CompressionFormat, Extension,
+    },
     info,
-    utils::{pretty_format_list_of_paths, try_infer_extension, user_wants_to_continue, EscapedPathDisplay},
+    utils::{
+        choose_format, detect_format_from_magic, pretty_format_list_of_paths, try_infer_extension,
+        user_wants_to_continue, EscapedPathDisplay,
+    },
     warning, QuestionAction, QuestionPolicy, Result,
 };
 
@@ -30,7 +36,13 @@ pub fn check_mime_type(
     if formats.is_empty() {
         // File with no extension
         // Try to detect it automatically and prompt the user about it
-        if let Some(detected_format) = try_infer_extension(path) {
+        let mut candidates = detect_format_from_magic(path);
+        if candidates.len() > 1 {
+            match choose_format(path, &candidates, question_policy)? {
+                Some(chosen_format) => formats.push(chosen_format),
+                None => return Ok(ControlFlow::Break(())),
+            }
+        } else if let Some(detected_format) = candidates.pop() {
             // Inferring the file extension can have unpredicted consequences (e.g. the user just
             // mistyped, ...) which we should always inform the user about.
             info!(
@@ -45,6 +57,12 @@ pub fn check_mime_type(
                 return Ok(ControlFlow::Break(()));
             }
         }
+    } else if formats
+        .last()
+        .and_then(|ext| ext.compression_formats.last())
+        .is_some_and(|format| !format.has_signature())
+    {
+        // Nothing to sniff, any content could be of this format
     } else if let Some(detected_format) = try_infer_extension(path) {
         // File ending with extension
         // Try to detect the extension and warn the user if it differs from the written one
@@ -161,8 +179,8 @@ pub fn check_missing_formats_when_decompressing(files: &[PathBuf], formats: &[Ve
 
     error = error
         .detail("Decompression formats are detected automatically from file extension")
-        .hint(format!("Supported extensions are: {}", PRETTY_SUPPORTED_EXTENSIONS))
-        .hint(format!("Supported aliases are: {}", PRETTY_SUPPORTED_ALIASES));
+        .hint(format!("Supported extensions are: {}", pretty_supported_extensions()))
+        .hint(format!("Supported aliases are: {}", pretty_supported_aliases()));
 
     // If there's exactly one file, give a suggestion to use `--format`
     if let &[path] = files_with_broken_extension.as_slice() {
@@ -253,3 +271,265 @@ pub fn check_invalid_compression_with_non_archive_format(
 
     Err(error.into())
 }
+
+/// Check if `--prefix` can be used, it must be a relative path that doesn't escape the
+/// extraction directory, and the output must be an archive.
+pub fn check_prefix(prefix: &Path, formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_archive = formats.first().map(Extension::is_archive).unwrap_or(false);
+    if !is_archive {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--prefix' only applies to archive formats, which store file paths")
+        .hint("Formats that bundle files into an archive are tar, zip and 7z.")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "tar")
+        ));
+
+        return Err(error.into());
+    }
+
+    let is_valid = prefix.components().count() > 0
+        && prefix
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !is_valid {
+        let error = FinalError::with_title(format!("Invalid prefix '{}'", EscapedPathDisplay::new(prefix)))
+            .detail("The prefix must be a relative path that doesn't contain '..'")
+            .hint("Example: --prefix myproject-1.2.3/");
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check if `--zip-align` can be used, the output must be a zip archive.
+pub fn check_zip_align(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_zip = formats
+        .first()
+        .map(|format| format.compression_formats[0] == CompressionFormat::Zip)
+        .unwrap_or(false);
+
+    if !is_zip {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--zip-align' only applies to zip archives")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "zip")
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--password` is used to compress to a zip archive, the only one that's encrypted,
+/// rather than writing the others in plain text.
+pub fn check_password(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_zip = formats
+        .first()
+        .map(|format| format.compression_formats[0] == CompressionFormat::Zip)
+        .unwrap_or(false);
+
+    if !is_zip {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--password' only applies to zip archives, other formats would not be encrypted")
+        .hint("Use a .zip extension to get an encrypted archive");
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--selinux` is used to compress to a tar archive, the only one storing contexts
+pub fn check_selinux(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_tar = formats
+        .first()
+        .map(|format| format.compression_formats[0] == CompressionFormat::Tar)
+        .unwrap_or(false);
+
+    if !is_tar {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--selinux' only applies to tar archives")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "tar")
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--block-size` is used to compress to xz, the only format it applies to
+pub fn check_block_size(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_archive = formats.first().is_some_and(Extension::is_archive);
+    let formats = flatten_compression_formats(formats);
+
+    if !formats.contains(&CompressionFormat::Lzma) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--block-size' only applies to xz compression")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, if is_archive { "tar.xz" } else { "xz" })
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--long` is used to compress with zstd, the only format it applies to
+pub fn check_long_window(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_archive = formats.first().is_some_and(Extension::is_archive);
+    let formats = flatten_compression_formats(formats);
+
+    if !formats.contains(&CompressionFormat::Zstd) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--long' only applies to zstd compression")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, if is_archive { "tar.zst" } else { "zst" })
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--dict` is used to compress with zstd, the only format it applies to
+pub fn check_dict(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let is_archive = formats.first().is_some_and(Extension::is_archive);
+    let formats = flatten_compression_formats(formats);
+
+    if !formats.contains(&CompressionFormat::Zstd) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--dict' only applies to zstd compression")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, if is_archive { "tar.zst" } else { "zst" })
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--index` is used to compress to a tar.zst archive, the only one it supports
+pub fn check_index(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let formats = flatten_compression_formats(formats);
+
+    if formats != [CompressionFormat::Tar, CompressionFormat::Zstd] {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--index' only applies to tar.zst archives")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "tar.zst")
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--seekable` is used to compress to a format ending with zst, like "file.zst" or
+/// "archive.tar.zst"
+pub fn check_seekable(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let formats = flatten_compression_formats(formats);
+
+    if formats.last() != Some(&CompressionFormat::Zstd) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--seekable' only applies to zstd compression")
+        .hint("Add .zst to the end of the output file name");
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--max-duration` and `--resume` are used to compress to a tar archive, which can be
+/// ended after any entry
+pub fn check_checkpoint(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let formats = flatten_compression_formats(formats);
+
+    if formats.first() != Some(&CompressionFormat::Tar) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--max-duration' and '--resume' only apply to tar archives")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "tar")
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Check that `--expand-archives` is used to compress to a tar or zip archive, which the entries of
+/// the input archives are copied to
+pub fn check_expand_archives(formats: &[Extension], output_path: &Path) -> Result<()> {
+    let formats = flatten_compression_formats(formats);
+
+    if !matches!(formats.first(), Some(CompressionFormat::Tar | CompressionFormat::Zip)) {
+        let error = FinalError::with_title(format!(
+            "Cannot compress to '{}'.",
+            EscapedPathDisplay::new(output_path)
+        ))
+        .detail("'--expand-archives' only applies to tar and zip archives")
+        .hint(format!(
+            "Compress to '{}' instead",
+            suggested_output(output_path, "tar")
+        ));
+
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// `output_path` with its known extensions replaced by `extensions`, like "backup.tar" for
+/// "backup.zip" and "tar", to suggest a format that can do what was asked.
+fn suggested_output(output_path: &Path, extensions: &str) -> String {
+    let (pathbase, _) = separate_known_extensions_from_name(output_path);
+    format!("{}.{extensions}", EscapedPathDisplay::new(pathbase))
+}
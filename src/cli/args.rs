@@ -1,11 +1,11 @@
-use std::{ffi::OsString, path::PathBuf};
+use std::{ffi::OsString, path::PathBuf, time::Duration};
 
 use clap::{Parser, ValueHint};
 
 // Ouch command line options (docstrings below are part of --help)
 /// A command-line utility for easily compressing and decompressing files and directories.
 ///
-/// Supported formats: tar, zip, gz, 7z, xz/lzma, bz/bz2, lz4, sz (Snappy), zst and rar.
+/// Supported formats: tar, zip, gz, 7z, cpio, deb, rpm, ar, iso, cab, squashfs, xz/lzma, bz/bz2, lz4, sz (Snappy), br (Brotli), lz (lzip), Z (compress), lzo (lzop), zst and rar.
 ///
 /// Repository: https://github.com/ouch-org/ouch
 #[derive(Parser, Debug, PartialEq)]
@@ -33,15 +33,64 @@ pub struct CliArgs {
     #[arg(short = 'q', long, global = true)]
     pub quiet: bool,
 
+    /// Silences all output except errors, implies --quiet
+    #[arg(long, global = true)]
+    pub silent: bool,
+
+    /// Explain how files are processed, like which formats were detected
+    #[arg(short, long, conflicts_with = "quiet", global = true)]
+    pub verbose: bool,
+
     /// Ignores files matched by git's ignore files
     #[arg(short = 'g', long, global = true)]
     pub gitignore: bool,
 
-    /// Specify the format of the archive
-    #[arg(short, long, global = true)]
+    /// Force the formats, like "tar.zst", instead of detecting them from the extensions, for files
+    /// with misleading or missing extensions
+    #[arg(short, long, global = true, value_parser = FormatValueParser, hide_possible_values = true)]
     pub format: Option<OsString>,
 
+    /// Keep the leading '/' of absolute paths, when archiving and extracting
+    #[arg(short = 'P', long, global = true)]
+    pub absolute_paths: bool,
+
+    /// How to display the progress of compressions and extractions
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub progress: ProgressMode,
+
+    /// Print how much was processed after SECS seconds without any other output
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub heartbeat: Option<u64>,
+
+    /// Send a desktop notification when compressing or decompressing finishes or fails
+    #[arg(long, global = true)]
+    pub notify: bool,
+
+    /// Print the time, CPU time, peak memory and bytes read and written when the run ends
+    #[arg(long, global = true)]
+    pub stats: bool,
+
+    /// Directory for temporary files, where archives that can't be streamed are buffered
+    #[arg(long, global = true, value_name = "PATH", env = "TMPDIR", hide_env_values = true, value_hint = ValueHint::DirPath)]
+    pub temp_dir: Option<PathBuf>,
+
     // Ouch and claps subcommands
+    /// Number of threads to use, 0 uses one per CPU core
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub threads: usize,
+
+    /// Use the options of a profile from the configuration file
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Print the full documentation as a man page, and exit
+    #[arg(long, exclusive = true)]
+    pub help_man: bool,
+
+    /// Print the full documentation as Markdown, and exit
+    #[arg(long, exclusive = true)]
+    pub help_markdown: bool,
+
     #[command(subcommand)]
     pub cmd: Subcommand,
 }
@@ -50,18 +99,19 @@ pub struct CliArgs {
 #[allow(rustdoc::bare_urls)]
 pub enum Subcommand {
     /// Compress one or more files into one output file
-    #[command(visible_alias = "c")]
+    #[command(visible_alias = "c", allow_missing_positional = true)]
     Compress {
         /// Files to be compressed
-        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        #[arg(required_unless_present = "files_from0", value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
-        /// The resulting file. Its extensions can be used to specify the compression formats
+        /// The resulting file. Its extensions can be used to specify the compression formats.
+        /// `[user@]host:path` streams it to the host with `ssh`, or `$OUCH_SSH_COMMAND`
         #[arg(required = true, value_hint = ValueHint::FilePath)]
         output: PathBuf,
 
-        /// Compression level, applied to all formats
-        #[arg(short, long, group = "compression-level")]
+        /// Compression level, applied to all formats, instead of the `[levels]` of the configuration file
+        #[arg(short, long, group = "compression-level", allow_negative_numbers = true)]
         level: Option<i16>,
 
         /// Fastest compression level possible,
@@ -71,19 +121,257 @@ pub enum Subcommand {
 
         /// Slowest (and best) compression level possible,
         /// conflicts with --level and --fast
-        #[arg(long, group = "compression-level")]
+        #[arg(long, visible_alias = "best", group = "compression-level")]
         slow: bool,
+
+        /// What to do with FIFOs, sockets and device nodes found in the inputs
+        #[arg(long, value_enum, default_value_t)]
+        special_files: SpecialFilesPolicy,
+
+        /// Only compress the files matching PATTERN, like '*.rs', can be repeated, the directories
+        /// are still searched
+        #[arg(long, value_name = "PATTERN")]
+        include: Vec<String>,
+
+        /// Leave out the files and directories matching PATTERN, like 'target/' or '*.o', can be
+        /// repeated, the patterns are like the ones of .gitignore files
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Read more files to compress from FILE, separated by null bytes (use "-" for stdin)
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        files_from0: Option<PathBuf>,
+
+        /// Find the files to compress in DIR, and store their paths relative to it
+        #[arg(short = 'C', long = "directory", value_name = "DIR", value_hint = ValueHint::DirPath)]
+        base_directory: Option<PathBuf>,
+
+        /// Place all files inside of a leading directory in the archive, e.g. "myproject-1.2.3/"
+        #[arg(long, value_name = "DIR")]
+        prefix: Option<PathBuf>,
+
+        /// Convert the names of the entries to this Unicode normalization form, so that names
+        /// written on macOS and on Linux match byte for byte
+        #[arg(long, value_enum, value_name = "FORM", default_value_t)]
+        normalize: NameNormalization,
+
+        /// Align the data of every file in zip archives to N bytes, like zipalign does
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+        zip_align: Option<u16>,
+
+        /// Uncompressed size of the blocks of xz, which are compressed and decompressed in
+        /// parallel, like "16M", 3 times the dictionary size of the level by default
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        block_size: Option<u64>,
+
+        /// Enable the long distance matching of zstd, with a window of 2^WINDOW_LOG bytes, 27 by
+        /// default, which finds the repetitions of large files like disk images and database dumps
+        #[arg(
+            long = "long",
+            value_name = "WINDOW_LOG",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "27",
+            value_parser = clap::value_parser!(u32).range(10..=31),
+            conflicts_with_all = ["index", "seekable"]
+        )]
+        long_window: Option<u32>,
+
+        /// Compress zstd with the dictionary in FILE, made by 'ouch train-dict', which small
+        /// similar files compress much better with, it's needed to decompress them
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with_all = ["index", "seekable"])]
+        dict: Option<PathBuf>,
+
+        /// Write a seek index next to tar.zst archives, as "<archive>.idx", so that their
+        /// entries can be listed and reached without decompressing everything before them
+        #[arg(long)]
+        index: bool,
+
+        /// Write the zstd compression in the zstd seekable format, so that parts of tar.zst
+        /// archives can be extracted without decompressing everything before them
+        #[arg(long)]
+        seekable: bool,
+
+        /// Stop after this long, like "90m" or "2h", ending the tar archive after the entry being
+        /// written and listing its entries in "<archive>.resume", to continue from with '--resume'
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        max_duration: Option<Duration>,
+
+        /// Leave out the entries listed in this state file, written by an earlier run stopped by
+        /// '--max-duration'
+        #[arg(long, value_name = "STATE", value_hint = ValueHint::FilePath)]
+        resume: Option<PathBuf>,
+
+        /// Add the entries of the tar and zip archives given as inputs to the tar or zip output,
+        /// instead of the archives themselves
+        #[arg(long, conflicts_with_all = ["index", "password"])]
+        expand_archives: bool,
+
+        /// Also print the checksum of the archive, computed while writing it, to stdout
+        #[arg(long, value_name = "ALGORITHM", value_enum)]
+        emit_checksum: Option<ChecksumAlgorithm>,
+
+        /// Encrypt the files of zip archives with PASSWORD, using AES-256 unless '--encryption'
+        /// says otherwise
+        #[arg(long, value_name = "PASSWORD")]
+        password: Option<String>,
+
+        /// Encryption used with '--password'
+        #[arg(long, value_name = "METHOD", value_enum, default_value_t, requires = "password")]
+        encryption: ZipEncryption,
+
+        /// Run the steps of a pipeline from the configuration file after compressing, its
+        /// profile is applied to the compression
+        #[arg(long, value_name = "NAME", conflicts_with = "profile")]
+        pipeline: Option<String>,
+
+        /// Store the SELinux contexts of the files in tar archives, on Linux
+        #[arg(long)]
+        selinux: bool,
+
+        /// Upload the archive to URL once it's compressed and tested, the value of
+        /// $OUCH_UPLOAD_AUTHORIZATION is sent as the Authorization header if it's set
+        #[arg(long, value_name = "URL")]
+        upload: Option<String>,
+
+        /// HTTP method of the '--upload' request
+        #[arg(long, value_enum, default_value_t, requires = "upload")]
+        upload_method: UploadMethod,
     },
     /// Decompresses one or more files, optionally into another folder
     #[command(visible_alias = "d")]
     Decompress {
-        /// Files to be decompressed
+        /// Files to be decompressed, `[user@]host:path` streams one from the host with `ssh`, or
+        /// `$OUCH_SSH_COMMAND`
         #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
         files: Vec<PathBuf>,
 
-        /// Place results in a directory other than the current one
-        #[arg(short = 'd', long = "dir", value_hint = ValueHint::FilePath)]
+        /// Only extract the entries whose paths match these patterns, given after '--', like
+        /// 'ouch d archive.tar.gz -- docs/**', the same as '--only'
+        #[arg(last = true, value_name = "PATTERN")]
+        patterns: Vec<String>,
+
+        /// Place results in a directory other than the current one, creating it if needed
+        #[arg(
+            short = 'd',
+            long = "dir",
+            visible_short_alias = 'C',
+            visible_alias = "directory",
+            value_hint = ValueHint::DirPath
+        )]
         output_dir: Option<PathBuf>,
+
+        /// Record the paths created by this extraction, so that 'ouch undo' can remove them
+        #[arg(long)]
+        record: bool,
+
+        /// Write the paths of every file and directory created by this extraction to a file, one per line
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        manifest_out: Option<PathBuf>,
+
+        /// Separate the paths written to '--manifest-out' with null bytes instead of newlines
+        #[arg(long, requires = "manifest_out")]
+        manifest_null: bool,
+
+        /// Recreate the character and block devices stored in tar archives, which usually requires root
+        #[arg(long)]
+        devices: bool,
+
+        /// Only allow writing inside of the output directory, using Landlock and seccomp on Linux
+        #[arg(long)]
+        sandbox: bool,
+
+        /// When running as root, extract as USER, a helper process restores the owners stored in tar archives
+        #[arg(long, value_name = "USER", conflicts_with = "record")]
+        run_as: Option<String>,
+
+        /// How symlinks are extracted on Windows
+        #[arg(long, value_enum, default_value_t)]
+        windows_links: WindowsLinksPolicy,
+
+        /// What to do with entries whose names only differ by case, on case-insensitive filesystems
+        #[arg(long, value_enum, default_value_t)]
+        case_collisions: CaseCollisionsPolicy,
+
+        /// What to do with entries whose names are too long for the filesystem
+        #[arg(long, value_enum, default_value_t)]
+        long_names: LongNamesPolicy,
+
+        /// What to do with file entries that have the same path as an earlier one
+        #[arg(long, value_enum, default_value_t)]
+        duplicate_entries: DuplicateEntriesPolicy,
+
+        /// Convert the names of the extracted entries to this Unicode normalization form, so that
+        /// names written on macOS and on Linux match byte for byte
+        #[arg(long, value_enum, value_name = "FORM", default_value_t)]
+        normalize: NameNormalization,
+
+        /// Decompress zstd with the dictionary in FILE, the one it was compressed with
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        dict: Option<PathBuf>,
+
+        /// Only extract entries of these types, like "f" for regular files, directories holding
+        /// them are still created
+        #[arg(long = "type", value_enum, value_delimiter = ',', value_name = "TYPE")]
+        types: Vec<EntryKind>,
+
+        /// Only extract the entries whose paths are in FILE, one per line or separated by null bytes
+        /// like the output of 'ouch list --null', with everything inside of them, "-" reads them
+        /// from stdin
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        only_from: Option<PathBuf>,
+
+        /// Only extract the entries whose paths match PATTERN, like 'docs/**', can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        only: Vec<String>,
+
+        /// Match the patterns of '--only' and the ones after '--' ignoring case, like "readme.txt"
+        /// with "README.TXT"
+        #[arg(long)]
+        ignore_case: bool,
+
+        /// Password of encrypted zip archives, asked for when one is needed if not given
+        #[arg(long, value_name = "PASSWORD")]
+        password: Option<String>,
+
+        /// Compress the extracted files with ALGORITHM, using the btrfs compression property
+        #[arg(long, value_enum, value_name = "ALGORITHM", conflicts_with = "nocow")]
+        fs_compression: Option<FsCompression>,
+
+        /// Disable copy-on-write for the extracted files, on btrfs
+        #[arg(long)]
+        nocow: bool,
+
+        /// Whether the extracted files get the quarantine attribute, which makes Gatekeeper check
+        /// them before they're opened, on macOS
+        #[arg(long, value_enum, default_value_t)]
+        quarantine: QuarantinePolicy,
+
+        /// Restore the SELinux contexts stored in tar archives, which usually requires root, on Linux
+        #[arg(long)]
+        selinux: bool,
+
+        /// Only extract the files installed by .deb packages, without their control files
+        #[arg(long)]
+        deb_data_only: bool,
+
+        /// Fail when metadata of an entry can't be restored, like owners without root, instead
+        /// of warning about it at the end
+        #[arg(long)]
+        strict_metadata: bool,
+
+        /// Extract in place, where the files are otherwise moved to once extracted, skipping the
+        /// files already there with the size of their entry, and its modification time with
+        /// 'mtime', to resume an interrupted extraction
+        #[arg(
+            long,
+            value_enum,
+            value_name = "CHECK",
+            num_args = 0..=1,
+            default_missing_value = "size",
+            conflicts_with_all = ["record", "manifest_out"]
+        )]
+        ignore_existing: Option<IgnoreExisting>,
     },
     /// List contents of an archive
     #[command(visible_aliases = ["l", "ls"])]
@@ -95,7 +383,371 @@ pub enum Subcommand {
         /// Show archive contents as a tree
         #[arg(short, long)]
         tree: bool,
+
+        /// Only list the first N entries, reading no further into the archive
+        #[arg(long, value_name = "N", conflicts_with = "tail")]
+        head: Option<usize>,
+
+        /// Only list the last N entries
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+
+        /// Only print how many entries there are, and the compressed and uncompressed sizes
+        #[arg(long, conflicts_with_all = ["tree", "head", "tail"])]
+        summary: bool,
+
+        /// Only list entries of these types, like "l" for symlinks
+        #[arg(long = "type", value_enum, value_delimiter = ',', value_name = "TYPE")]
+        types: Vec<EntryKind>,
+
+        /// Show the first N lines of small text files under their entries, not for rar archives
+        #[arg(long, value_name = "N", conflicts_with_all = ["tree", "summary"])]
+        preview: Option<usize>,
+
+        /// Show the type of files, like "PNG image" or "ELF executable", sniffed from their first
+        /// bytes, not for rar archives
+        #[arg(long, conflicts_with = "summary")]
+        detect_types: bool,
+
+        /// Only print the paths of the entries, each followed by a null byte, for 'fzf --read0',
+        /// 'xargs -0' or 'ouch decompress --only-from'
+        #[arg(long, conflicts_with_all = ["tree", "summary", "preview", "detect_types"])]
+        null: bool,
+    },
+    /// Copy entries of an archive into a new one, tar or zip, without extracting them
+    ///
+    /// Like to convert a zip archive to tar.zst, the times and permissions of the entries are kept
+    #[command(visible_alias = "convert")]
+    Copy {
+        /// Archive whose entries are copied
+        #[arg(value_hint = ValueHint::FilePath)]
+        source: PathBuf,
+
+        /// Archive to create, like "assets.tar.zst"
+        #[arg(value_hint = ValueHint::FilePath)]
+        destination: PathBuf,
+
+        /// Only copy the entries whose paths match PATTERN, like 'assets/**', can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        only: Vec<String>,
+
+        /// Match the patterns of '--only' ignoring case, like "readme.txt" with "README.TXT"
+        #[arg(long, requires = "only")]
+        ignore_case: bool,
     },
+    /// Compare the contents of an archive with a directory, reporting every difference
+    Compare {
+        /// Archive to compare
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+
+        /// Directory, or file for single file formats, to compare the archive with
+        #[arg(value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+    },
+    /// Read archives and compressed files to their end without extracting them, checking every
+    /// checksum on the way
+    Test {
+        /// Archives and compressed files to test
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::FilePath)]
+        archives: Vec<PathBuf>,
+
+        /// Also test the tar and zip archives and the compressed files inside, like a zip of zips,
+        /// as they're read
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Scan a tar archive once and write its seek index next to it, like `compress --index`, so
+    /// that listing it is near-instant and extracting some entries with '--only' stops after them
+    Index {
+        /// Archive to index
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+    },
+    /// Print the formats of files, from their names and their contents, for scripts routing them
+    /// to different tools
+    Detect {
+        /// Files whose formats to print
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+
+        /// Print a JSON array of objects with the "path", "extension", "formats" and "sniffed"
+        /// format of each file
+        #[arg(long)]
+        json: bool,
+    },
+    /// Train a zstd dictionary on sample files, for 'compress --dict' to compress many small
+    /// similar files much better
+    TrainDict {
+        /// Sample files, or directories holding them, like a few hundred of the files to compress
+        #[arg(required = true, num_args = 1.., value_hint = ValueHint::AnyPath)]
+        samples: Vec<PathBuf>,
+
+        /// Dictionary to write
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+
+        /// Largest size of the dictionary, in bytes or with a suffix like "200K"
+        #[arg(long, value_parser = parse_size, default_value = "110K")]
+        max_size: u64,
+    },
+    /// Split a file into parts of at most SIZE, named like "file.tar.zst.001", which are joined
+    /// back when decompressing the first one
+    Split {
+        /// File to split
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+
+        /// Largest size of each part, in bytes or with a suffix like "500M" or "1G"
+        #[arg(short, long, value_parser = parse_size)]
+        size: u64,
+    },
+    /// Remove the files and directories created by the last extraction recorded with --record
+    Undo,
+    /// Remove the temporary files left by interrupted runs, which is also done on startup
+    CleanTemp,
+}
+
+mod supported_formats {
+    include!("../extension/supported.rs");
+}
+
+/// Parser of `--format`, which accepts any value, combinations like "tar.gz" are validated
+/// later, but lists the supported formats so they can be completed by shells
+#[derive(Clone)]
+struct FormatValueParser;
+
+impl clap::builder::TypedValueParser for FormatValueParser {
+    type Value = OsString;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        Ok(value.to_owned())
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        let names = supported_formats::SUPPORTED_EXTENSIONS
+            .iter()
+            .chain(supported_formats::SUPPORTED_ALIASES);
+        Some(Box::new(names.map(|name| clap::builder::PossibleValue::new(*name))))
+    }
+}
+
+/// Parser of sizes like "4096", "500K", "1.5G" or "2GiB", in powers of 1024
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let number_end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(number_end);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{value}'"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("unknown unit in '{value}', expected K, M, G or T")),
+    };
+
+    let size = (number * multiplier as f64) as u64;
+    if size == 0 {
+        return Err("the size must be at least 1 byte".to_string());
+    }
+    Ok(size)
+}
+
+/// Parser of durations like "45s", "90m", "2h" or "1h30m"
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let mut rest = value.trim();
+    let mut seconds = 0;
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit_end = rest[number_end..]
+            .find(|c: char| c.is_ascii_digit())
+            .map_or(rest.len(), |end| number_end + end);
+        let number: u64 = rest[..number_end]
+            .parse()
+            .map_err(|_| format!("invalid duration '{value}'"))?;
+        let multiplier = match rest[number_end..unit_end].trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit in '{value}', expected s, m, h or d")),
+        };
+        seconds += number * multiplier;
+        rest = &rest[unit_end..];
+    }
+
+    if seconds == 0 {
+        return Err("the duration must be at least 1 second".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Policy for files that are neither regular files, directories nor symlinks
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpecialFilesPolicy {
+    /// Store them in the archive, skipping the ones the format can't represent
+    Store,
+    /// Leave them out of the archive, with a warning
+    #[default]
+    Skip,
+    /// Abort the compression
+    Error,
+}
+
+/// What to do with entries whose names only differ by case, on case-insensitive filesystems
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseCollisionsPolicy {
+    /// Extract them under another name, like "file (case 2).txt"
+    #[default]
+    Rename,
+    /// Leave them out, with a warning
+    Skip,
+    /// Let the last one overwrite the others
+    Overwrite,
+}
+
+/// What to do with entries whose names are too long for the filesystem
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LongNamesPolicy {
+    /// Fail to extract them
+    #[default]
+    Error,
+    /// Shorten them, keeping their extension and adding a hash of the full name
+    Truncate,
+    /// Leave them out, with a warning
+    Skip,
+}
+
+/// What to do with file entries that have the same path as an earlier one, like the later
+/// versions of a file appended to a tar archive
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateEntriesPolicy {
+    /// Let the last one overwrite the others, with a warning
+    #[default]
+    KeepLast,
+    /// Leave the later ones out, with a warning
+    KeepFirst,
+    /// Extract the later ones under another name, like "file (version 2).txt"
+    VersionSuffix,
+}
+
+/// What makes a file the entry already extracted, for `--ignore-existing`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IgnoreExisting {
+    /// It has the size of the entry
+    Size,
+    /// It has the size and the modification time of the entry
+    Mtime,
+}
+
+/// Unicode normalization form entry names are converted to, for `--normalize`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameNormalization {
+    /// Keep the names as they are
+    #[default]
+    None,
+    /// Composed characters, like Linux and Windows usually write them
+    Nfc,
+    /// Decomposed characters, like older macOS filesystems write them
+    Nfd,
+}
+
+/// Type of archive entries, for `--type`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular files
+    #[value(name = "f")]
+    File,
+    /// Directories
+    #[value(name = "d")]
+    Directory,
+    /// Symlinks
+    #[value(name = "l")]
+    Symlink,
+}
+
+/// Algorithm of `--fs-compression`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsCompression {
+    Zstd,
+    Lzo,
+    Zlib,
+}
+
+/// How symlinks are extracted on Windows, where creating them requires a privilege or developer mode
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowsLinksPolicy {
+    /// Symlinks if they can be created, like "junction" otherwise
+    #[default]
+    Auto,
+    /// Symlinks, failing if they can't be created
+    Symlink,
+    /// Junctions to directories, and copies of files
+    Junction,
+    /// Copies of the files and directories they point to
+    Copy,
+    /// Leave them out, with a warning
+    Skip,
+}
+
+/// Whether extracted files get the quarantine attribute of macOS
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuarantinePolicy {
+    /// When the archive has it, like for downloaded archives, with the same value
+    #[default]
+    Inherit,
+    /// Always, with the value of the archive if it has one
+    Always,
+    /// Never, even when the archive has it
+    Never,
+}
+
+/// Encryption of the zip archives compressed with `--password`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZipEncryption {
+    /// AES with a 256-bit key
+    #[default]
+    Aes256,
+    /// The original zip encryption, easily broken, for tools that can't read AES
+    #[value(name = "zipcrypto")]
+    ZipCrypto,
+}
+
+/// Algorithm of `--emit-checksum`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+}
+
+/// HTTP method of `--upload`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UploadMethod {
+    #[default]
+    Put,
+    Post,
+}
+
+/// How the progress of long operations is displayed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A status line if stderr is a terminal, plain lines otherwise
+    #[default]
+    Auto,
+    /// A status line redrawn in place
+    Bar,
+    /// A plain line every few seconds, without terminal control codes
+    Plain,
+    /// Nothing
+    Off,
 }
 
 #[cfg(test)]
@@ -130,13 +782,50 @@ mod tests {
             accessible: false,
             hidden: false,
             quiet: false,
+            silent: false,
+            verbose: false,
             gitignore: false,
+            threads: 0,
+            profile: None,
+            help_man: false,
+            help_markdown: false,
             format: None,
+            absolute_paths: false,
+            progress: ProgressMode::Auto,
+            heartbeat: None,
+            notify: false,
+            stats: false,
+            temp_dir: None,
             // This is usually replaced in assertion tests
             cmd: Subcommand::Decompress {
                 // Put a crazy value here so no test can assert it unintentionally
                 files: vec!["\x00\x11\x22".into()],
+                patterns: vec![],
                 output_dir: None,
+                record: false,
+                manifest_out: None,
+                manifest_null: false,
+                devices: false,
+                sandbox: false,
+                run_as: None,
+                windows_links: WindowsLinksPolicy::Auto,
+                case_collisions: CaseCollisionsPolicy::Rename,
+                long_names: LongNamesPolicy::Error,
+                duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                normalize: NameNormalization::None,
+                dict: None,
+                types: vec![],
+                only_from: None,
+                only: vec![],
+                ignore_case: false,
+                password: None,
+                fs_compression: None,
+                nocow: false,
+                quarantine: QuarantinePolicy::Inherit,
+                selinux: false,
+                deb_data_only: false,
+                strict_metadata: false,
+                ignore_existing: None,
             },
         }
     }
@@ -148,7 +837,32 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["file.tar.gz"]),
+                    patterns: vec![],
                     output_dir: None,
+                    record: false,
+                    manifest_out: None,
+                    manifest_null: false,
+                    devices: false,
+                    sandbox: false,
+                    run_as: None,
+                    windows_links: WindowsLinksPolicy::Auto,
+                    case_collisions: CaseCollisionsPolicy::Rename,
+                    long_names: LongNamesPolicy::Error,
+                    duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                    normalize: NameNormalization::None,
+                    dict: None,
+                    types: vec![],
+                    only_from: None,
+                    only: vec![],
+                    ignore_case: false,
+                    password: None,
+                    fs_compression: None,
+                    nocow: false,
+                    quarantine: QuarantinePolicy::Inherit,
+                    selinux: false,
+                    deb_data_only: false,
+                    strict_metadata: false,
+                    ignore_existing: None,
                 },
                 ..mock_cli_args()
             }
@@ -158,7 +872,32 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["file.tar.gz"]),
+                    patterns: vec![],
                     output_dir: None,
+                    record: false,
+                    manifest_out: None,
+                    manifest_null: false,
+                    devices: false,
+                    sandbox: false,
+                    run_as: None,
+                    windows_links: WindowsLinksPolicy::Auto,
+                    case_collisions: CaseCollisionsPolicy::Rename,
+                    long_names: LongNamesPolicy::Error,
+                    duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                    normalize: NameNormalization::None,
+                    dict: None,
+                    types: vec![],
+                    only_from: None,
+                    only: vec![],
+                    ignore_case: false,
+                    password: None,
+                    fs_compression: None,
+                    nocow: false,
+                    quarantine: QuarantinePolicy::Inherit,
+                    selinux: false,
+                    deb_data_only: false,
+                    strict_metadata: false,
+                    ignore_existing: None,
                 },
                 ..mock_cli_args()
             }
@@ -168,7 +907,32 @@ mod tests {
             CliArgs {
                 cmd: Subcommand::Decompress {
                     files: to_paths(["a", "b", "c"]),
+                    patterns: vec![],
                     output_dir: None,
+                    record: false,
+                    manifest_out: None,
+                    manifest_null: false,
+                    devices: false,
+                    sandbox: false,
+                    run_as: None,
+                    windows_links: WindowsLinksPolicy::Auto,
+                    case_collisions: CaseCollisionsPolicy::Rename,
+                    long_names: LongNamesPolicy::Error,
+                    duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                    normalize: NameNormalization::None,
+                    dict: None,
+                    types: vec![],
+                    only_from: None,
+                    only: vec![],
+                    ignore_case: false,
+                    password: None,
+                    fs_compression: None,
+                    nocow: false,
+                    quarantine: QuarantinePolicy::Inherit,
+                    selinux: false,
+                    deb_data_only: false,
+                    strict_metadata: false,
+                    ignore_existing: None,
                 },
                 ..mock_cli_args()
             }
@@ -183,6 +947,29 @@ mod tests {
                     level: None,
                     fast: false,
                     slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: None,
+                    base_directory: None,
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
                 },
                 ..mock_cli_args()
             }
@@ -196,6 +983,29 @@ mod tests {
                     level: None,
                     fast: false,
                     slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: None,
+                    base_directory: None,
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
                 },
                 ..mock_cli_args()
             }
@@ -209,6 +1019,29 @@ mod tests {
                     level: None,
                     fast: false,
                     slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: None,
+                    base_directory: None,
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
                 },
                 ..mock_cli_args()
             }
@@ -233,6 +1066,29 @@ mod tests {
                         level: None,
                         fast: false,
                         slow: false,
+                        special_files: SpecialFilesPolicy::Skip,
+                        include: vec![],
+                        exclude: vec![],
+                        files_from0: None,
+                        base_directory: None,
+                        prefix: None,
+                        normalize: NameNormalization::None,
+                        zip_align: None,
+                        block_size: None,
+                        long_window: None,
+                        dict: None,
+                        index: false,
+                        seekable: false,
+                        max_duration: None,
+                        resume: None,
+                        expand_archives: false,
+                        emit_checksum: None,
+                        password: None,
+                        encryption: ZipEncryption::Aes256,
+                        pipeline: None,
+                        selinux: false,
+                        upload: None,
+                        upload_method: UploadMethod::Put,
                     },
                     format: Some("tar.gz".into()),
                     ..mock_cli_args()
@@ -241,6 +1097,200 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_clap_cli_files_from0() {
+        test!(
+            "ouch compress --files-from0 - out.tar.zst",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: vec![],
+                    output: PathBuf::from("out.tar.zst"),
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: Some("-".into()),
+                    base_directory: None,
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
+                },
+                ..mock_cli_args()
+            }
+        );
+        test!(
+            "ouch compress --files-from0 list a out.tar.zst",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: to_paths(["a"]),
+                    output: PathBuf::from("out.tar.zst"),
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: Some("list".into()),
+                    base_directory: None,
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
+                },
+                ..mock_cli_args()
+            }
+        );
+    }
+
+    #[test]
+    fn test_clap_cli_directory() {
+        test!(
+            "ouch compress -C project src docs out.tar",
+            CliArgs {
+                cmd: Subcommand::Compress {
+                    files: to_paths(["src", "docs"]),
+                    output: PathBuf::from("out.tar"),
+                    level: None,
+                    fast: false,
+                    slow: false,
+                    special_files: SpecialFilesPolicy::Skip,
+                    include: vec![],
+                    exclude: vec![],
+                    files_from0: None,
+                    base_directory: Some("project".into()),
+                    prefix: None,
+                    normalize: NameNormalization::None,
+                    zip_align: None,
+                    block_size: None,
+                    long_window: None,
+                    dict: None,
+                    index: false,
+                    seekable: false,
+                    max_duration: None,
+                    resume: None,
+                    expand_archives: false,
+                    emit_checksum: None,
+                    password: None,
+                    encryption: ZipEncryption::Aes256,
+                    pipeline: None,
+                    selinux: false,
+                    upload: None,
+                    upload_method: UploadMethod::Put,
+                },
+                ..mock_cli_args()
+            }
+        );
+        for input in ["ouch decompress a.zip -C out", "ouch decompress a.zip -d out"] {
+            test!(
+                input,
+                CliArgs {
+                    cmd: Subcommand::Decompress {
+                        files: to_paths(["a.zip"]),
+                        patterns: vec![],
+                        output_dir: Some("out".into()),
+                        record: false,
+                        manifest_out: None,
+                        manifest_null: false,
+                        devices: false,
+                        sandbox: false,
+                        run_as: None,
+                        windows_links: WindowsLinksPolicy::Auto,
+                        case_collisions: CaseCollisionsPolicy::Rename,
+                        long_names: LongNamesPolicy::Error,
+                        duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                        normalize: NameNormalization::None,
+                        dict: None,
+                        types: vec![],
+                        only_from: None,
+                        only: vec![],
+                        ignore_case: false,
+                        password: None,
+                        fs_compression: None,
+                        nocow: false,
+                        quarantine: QuarantinePolicy::Inherit,
+                        selinux: false,
+                        deb_data_only: false,
+                        strict_metadata: false,
+                        ignore_existing: None,
+                    },
+                    ..mock_cli_args()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_clap_cli_progress() {
+        test!(
+            "ouch decompress a.zip --progress plain",
+            CliArgs {
+                cmd: Subcommand::Decompress {
+                    files: to_paths(["a.zip"]),
+                    patterns: vec![],
+                    output_dir: None,
+                    record: false,
+                    manifest_out: None,
+                    manifest_null: false,
+                    devices: false,
+                    sandbox: false,
+                    run_as: None,
+                    windows_links: WindowsLinksPolicy::Auto,
+                    case_collisions: CaseCollisionsPolicy::Rename,
+                    long_names: LongNamesPolicy::Error,
+                    duplicate_entries: DuplicateEntriesPolicy::KeepLast,
+                    normalize: NameNormalization::None,
+                    dict: None,
+                    types: vec![],
+                    only_from: None,
+                    only: vec![],
+                    ignore_case: false,
+                    password: None,
+                    fs_compression: None,
+                    nocow: false,
+                    quarantine: QuarantinePolicy::Inherit,
+                    selinux: false,
+                    deb_data_only: false,
+                    strict_metadata: false,
+                    ignore_existing: None,
+                },
+                progress: ProgressMode::Plain,
+                ..mock_cli_args()
+            }
+        );
+        assert!(CliArgs::try_parse_from(args_splitter("ouch d a.zip --progress fancy")).is_err());
+    }
+
     #[test]
     fn test_clap_cli_err() {
         assert!(CliArgs::try_parse_from(args_splitter("ouch c")).is_err());
@@ -248,4 +1298,14 @@ mod tests {
         assert!(CliArgs::try_parse_from(args_splitter("ouch d")).is_err());
         assert!(CliArgs::try_parse_from(args_splitter("ouch l")).is_err());
     }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 3600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("90").is_err());
+        assert!(parse_duration("2 weeks").is_err());
+        assert!(parse_duration("0m").is_err());
+    }
 }
@@ -0,0 +1,295 @@
+//! Full documentation rendered at runtime from the clap definitions, for `--help-man` and
+//! `--help-markdown`.
+
+use std::{fmt::Write as _, io};
+
+use clap::{Arg, Command};
+use clap_mangen::Man;
+
+use crate::extension::pretty_supported_aliases;
+
+/// A row of the table of formats
+struct FormatDoc {
+    extensions: &'static str,
+    description: &'static str,
+    /// Range of the levels accepted by `--level`, if the format has any
+    levels: Option<String>,
+}
+
+/// The supported formats, and the compression levels ouch passes to each of them
+fn format_docs() -> Vec<FormatDoc> {
+    let levels = |min: i32, max: i32| Some(format!("{min} to {max}"));
+
+    vec![
+        FormatDoc {
+            extensions: "tar",
+            description: "Tar archive",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "zip",
+            description: "Zip archive",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "7z",
+            description: "7z archive",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "cpio",
+            description: "Cpio archive, written in the newc format",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "deb",
+            description: "Debian package, can only be extracted, with its control files in DEBIAN",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "rpm",
+            description: "RPM package, can only be extracted, without its metadata",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "a, ar",
+            description: "ar archive, like static libraries, can only be extracted",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "iso",
+            description: "ISO 9660 image, with Joliet and Rock Ridge, can only be extracted",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "cab",
+            description: "Microsoft cabinet, can only be extracted",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "squashfs, sqsh",
+            description: "SquashFS image, can only be extracted",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "rar",
+            description: "RAR archive, can only be decompressed",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "gz",
+            description: "Gzip compression",
+            levels: levels(0, 9),
+        },
+        FormatDoc {
+            extensions: "bz, bz2",
+            description: "Bzip2 compression",
+            levels: levels(1, 9),
+        },
+        FormatDoc {
+            extensions: "lz4",
+            description: "LZ4 compression",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "xz, lzma",
+            description: "XZ compression",
+            levels: levels(0, 9),
+        },
+        FormatDoc {
+            extensions: "sz",
+            description: "Snappy compression",
+            levels: levels(0, 9),
+        },
+        FormatDoc {
+            extensions: "br",
+            description: "Brotli compression",
+            levels: levels(0, 11),
+        },
+        FormatDoc {
+            extensions: "lz",
+            description: "Lzip compression",
+            levels: levels(0, 9),
+        },
+        FormatDoc {
+            extensions: "Z",
+            description: "Unix compress (LZW), can only be decompressed",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "lzo",
+            description: "Lzop compression",
+            levels: None,
+        },
+        FormatDoc {
+            extensions: "zst",
+            description: "Zstandard compression",
+            levels: levels(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level()),
+        },
+    ]
+}
+
+/// Renders the man page of `cmd`, followed by the pages of its subcommands.
+pub fn render_man(cmd: Command, out: &mut impl io::Write) -> io::Result<()> {
+    let mut cmd = cmd;
+    cmd.build();
+
+    let man = Man::new(cmd.clone());
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+
+    writeln!(out, ".SH FORMATS")?;
+    for format in format_docs() {
+        let levels = match &format.levels {
+            Some(levels) => format!(", levels {levels}"),
+            None => String::new(),
+        };
+        writeln!(out, ".TP")?;
+        writeln!(out, "\\fB{}\\fR", roff_escape(format.extensions))?;
+        writeln!(out, "{}", roff_escape(&format!("{}{levels}", format.description)))?;
+    }
+    writeln!(out, ".PP")?;
+    writeln!(
+        out,
+        "{}",
+        roff_escape(&format!(
+            "Aliases: {}, they stand for tar followed by a compression format.",
+            pretty_supported_aliases()
+        ))
+    )?;
+
+    man.render_version_section(out)?;
+
+    for subcommand in visible_subcommands(&cmd) {
+        let name = subcommand.get_bin_name().unwrap_or_default().replace(' ', "-");
+        Man::new(subcommand.clone().name(name)).render(out)?;
+    }
+    Ok(())
+}
+
+/// Renders the documentation of `cmd` and its subcommands as Markdown.
+pub fn render_markdown(cmd: Command) -> String {
+    let mut cmd = cmd;
+    cmd.build();
+
+    let mut out = String::new();
+    let name = cmd.get_name().to_owned();
+    let _ = writeln!(out, "# {name}\n");
+    if let Some(about) = cmd.get_long_about().or(cmd.get_about()) {
+        let _ = writeln!(out, "{about}\n");
+    }
+    render_command_markdown(&mut cmd, &mut out, false);
+
+    let _ = writeln!(out, "## Commands\n");
+    for subcommand in visible_subcommands(&cmd) {
+        let mut subcommand = subcommand.clone();
+        let _ = writeln!(out, "### {}\n", subcommand.get_bin_name().unwrap_or_default());
+        if let Some(about) = subcommand.get_long_about().or(subcommand.get_about()) {
+            let _ = writeln!(out, "{about}\n");
+        }
+        render_command_markdown(&mut subcommand, &mut out, true);
+    }
+
+    let _ = writeln!(out, "## Formats\n");
+    let _ = writeln!(out, "| Extensions | Format | Compression levels |");
+    let _ = writeln!(out, "| --- | --- | --- |");
+    for format in format_docs() {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} |",
+            format.extensions,
+            format.description,
+            format.levels.as_deref().unwrap_or("-")
+        );
+    }
+    let _ = writeln!(
+        out,
+        "\nAliases: {}, they stand for tar followed by a compression format.",
+        pretty_supported_aliases()
+    );
+
+    out
+}
+
+/// Renders the usage, arguments and options of a single command, the global options are only
+/// listed for the main command.
+fn render_command_markdown(cmd: &mut Command, out: &mut String, is_subcommand: bool) {
+    let usage = cmd.render_usage().to_string();
+    let usage = usage.strip_prefix("Usage: ").unwrap_or(&usage);
+    let _ = writeln!(out, "```\n{usage}\n```\n");
+
+    let (positionals, options): (Vec<&Arg>, Vec<&Arg>) = cmd
+        .get_arguments()
+        .filter(|arg| !(arg.is_hide_set() || is_subcommand && arg.is_global_set()))
+        .partition(|arg| arg.is_positional());
+
+    for (title, args) in [("Arguments", positionals), ("Options", options)] {
+        if args.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "**{title}:**\n");
+        for arg in args {
+            let _ = write!(out, "- `{}`", arg_display(arg));
+            if let Some(help) = arg.get_long_help().or(arg.get_help()) {
+                let _ = write!(out, ": {}", help.to_string().replace('\n', " "));
+            }
+            let possible_values: Vec<_> = arg
+                .get_possible_values()
+                .into_iter()
+                .filter(|value| !value.is_hide_set() && !arg.is_hide_possible_values_set())
+                .map(|value| format!("`{}`", value.get_name()))
+                .collect();
+            if !possible_values.is_empty() {
+                let _ = write!(out, " (possible values: {})", possible_values.join(", "));
+            }
+            let _ = writeln!(out);
+        }
+        let _ = writeln!(out);
+    }
+}
+
+/// How an argument is written in the command line, like `-d, --dir <OUTPUT_DIR>` or `<FILES>...`.
+fn arg_display(arg: &Arg) -> String {
+    let value_names = arg.get_value_names().map(|names| {
+        names
+            .iter()
+            .map(|name| format!("<{name}>"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    let takes_value = arg.get_num_args().is_some_and(|range| range.takes_values());
+
+    if arg.is_positional() {
+        let name = value_names.unwrap_or_else(|| format!("<{}>", arg.get_id().as_str().to_uppercase()));
+        let is_multiple = arg.get_num_args().is_some_and(|range| range.max_values() > 1);
+        return if is_multiple { format!("{name}...") } else { name };
+    }
+
+    let mut names = vec![];
+    if let Some(short) = arg.get_short() {
+        names.push(format!("-{short}"));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("--{long}"));
+    }
+    let mut display = names.join(", ");
+    if takes_value {
+        let value_name = value_names.unwrap_or_else(|| format!("<{}>", arg.get_id().as_str().to_uppercase()));
+        let _ = write!(display, " {value_name}");
+    }
+    display
+}
+
+fn visible_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+}
+
+/// Escapes the characters that have a special meaning in roff.
+fn roff_escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
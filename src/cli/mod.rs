@@ -1,34 +1,122 @@
 //! CLI related functions, uses the clap argparsing definitions from `args.rs`.
 
 mod args;
+mod docs;
 
 use std::{
-    io,
+    env,
+    ffi::OsString,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     vec::Vec,
 };
 
-use clap::Parser;
+use bstr::ByteSlice;
+use clap::{
+    error::{ContextKind, ContextValue, ErrorKind},
+    CommandFactory, Parser,
+};
 use fs_err as fs;
 
-pub use self::args::{CliArgs, Subcommand};
-use crate::{accessible::set_accessible, utils::FileVisibilityPolicy, QuestionPolicy};
+pub use self::args::{
+    CaseCollisionsPolicy, ChecksumAlgorithm, CliArgs, DuplicateEntriesPolicy, EntryKind, FsCompression, IgnoreExisting,
+    LongNamesPolicy, NameNormalization, ProgressMode, QuarantinePolicy, SpecialFilesPolicy, Subcommand, UploadMethod,
+    WindowsLinksPolicy, ZipEncryption,
+};
+use crate::{
+    accessible::set_accessible,
+    config::Config,
+    error::FinalError,
+    macros::{set_silent, set_verbose},
+    ssh::SshPath,
+    utils::{self, EscapedPathDisplay, FileVisibilityPolicy},
+    QuestionPolicy,
+};
 
 impl CliArgs {
     /// A helper method that calls `clap::Parser::parse`.
     ///
     /// And:
-    ///   1. Make paths absolute.
-    ///   2. Checks the QuestionPolicy.
+    ///   1. Print the documentation and exit, if `--help-man` or `--help-markdown` was given.
+    ///   2. Expand the alias from the configuration file given instead of a subcommand.
+    ///   3. Apply the options of the profile given with `--profile`, or of the pipeline's profile.
+    ///   4. Read the files listed by `--files-from0`.
+    ///   5. Make paths absolute, relative to `--directory` if it was given.
+    ///   6. Reject `--sandbox` for files on other hosts, which are read by running `ssh`.
+    ///   7. Checks the QuestionPolicy.
     pub fn parse_and_validate_args() -> crate::Result<(Self, QuestionPolicy, FileVisibilityPolicy)> {
-        let mut args = Self::parse();
+        print_docs_if_asked()?;
+        let mut args = parse_with_aliases()?;
+
+        if let Some(profile) = &args.profile {
+            Config::load()?.profile(profile)?.apply(&mut args);
+        }
+        if let Subcommand::Compress {
+            pipeline: Some(pipeline),
+            ..
+        } = &args.cmd
+        {
+            let config = Config::load()?;
+            if let Some(profile) = &config.pipeline(pipeline)?.profile {
+                config.profile(profile)?.apply(&mut args);
+            }
+        }
 
         set_accessible(args.accessible);
+        set_silent(args.silent);
+        set_verbose(args.verbose);
+        args.quiet |= args.silent;
+
+        if let Subcommand::Compress {
+            files,
+            files_from0: Some(list_path),
+            ..
+        } = &mut args.cmd
+        {
+            files.extend(read_null_delimited_paths(list_path)?);
+        }
+
+        if let Subcommand::Compress {
+            files,
+            base_directory: Some(base_directory),
+            ..
+        } = &mut args.cmd
+        {
+            *base_directory = utils::canonicalize(&*base_directory)?;
+            for file in files.iter_mut() {
+                *file = base_directory.join(&*file);
+            }
+        }
+
+        if let Subcommand::Compress { files, .. } | Subcommand::List { archives: files, .. } = &mut args.cmd {
+            *files = canonicalize_files(files)?;
+        }
 
-        let (Subcommand::Compress { files, .. }
-        | Subcommand::Decompress { files, .. }
-        | Subcommand::List { archives: files, .. }) = &mut args.cmd;
-        *files = canonicalize_files(files)?;
+        if let Subcommand::Decompress { files, .. } = &mut args.cmd {
+            // Files on other hosts are read over SSH as they're named
+            *files = files
+                .iter()
+                .map(|file| match SshPath::remote(file) {
+                    Some(_) => Ok(file.clone()),
+                    None => utils::canonicalize(file),
+                })
+                .collect::<io::Result<_>>()?;
+        }
+
+        if let Subcommand::Decompress {
+            files, sandbox: true, ..
+        } = &args.cmd
+        {
+            if let Some(remote) = files.iter().find(|file| SshPath::remote(file).is_some()) {
+                let error = FinalError::with_title("'--sandbox' can't be used with files on other hosts")
+                    .detail(format!(
+                        "'{}' is read with 'ssh', which the sandbox can't run",
+                        EscapedPathDisplay::new(remote)
+                    ))
+                    .hint("Copy the file to this host first, or extract it without '--sandbox'");
+                return Err(error.into());
+            }
+        }
 
         let skip_questions_positively = match (args.yes, args.no) {
             (false, false) => QuestionPolicy::Ask,
@@ -47,6 +135,85 @@ impl CliArgs {
     }
 }
 
+/// Handles `--help-man` and `--help-markdown`, which, like `--help`, don't need a subcommand.
+fn print_docs_if_asked() -> io::Result<()> {
+    let Ok(matches) = CliArgs::command().subcommand_required(false).try_get_matches() else {
+        // Errors are reported when parsing again
+        return Ok(());
+    };
+
+    if matches.get_flag("help_man") {
+        docs::render_man(CliArgs::command(), &mut io::stdout().lock())?;
+    } else if matches.get_flag("help_markdown") {
+        io::stdout()
+            .lock()
+            .write_all(docs::render_markdown(CliArgs::command()).as_bytes())?;
+    } else {
+        return Ok(());
+    }
+    std::process::exit(0);
+}
+
+/// Parses the arguments, with the alias from the configuration file replaced by its arguments if
+/// one was given instead of a subcommand, like `ouch backup`.
+fn parse_with_aliases() -> crate::Result<CliArgs> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let error = match CliArgs::try_parse_from(&args) {
+        Ok(parsed) => return Ok(parsed),
+        Err(error) => error,
+    };
+
+    // Only unknown subcommands can be aliases, so the configuration isn't read otherwise
+    if error.kind() == ErrorKind::InvalidSubcommand {
+        if let Some(ContextValue::String(name)) = error.get(ContextKind::InvalidSubcommand) {
+            let position = args.iter().skip(1).position(|arg| arg == name.as_str());
+            if let (Some(position), Some(expansion)) = (position, Config::load()?.alias(name)) {
+                args.splice(position + 1..=position + 1, expansion);
+                return Ok(CliArgs::parse_from(args));
+            }
+        }
+    }
+    error.exit()
+}
+
 fn canonicalize_files(files: &[impl AsRef<Path>]) -> io::Result<Vec<PathBuf>> {
-    files.iter().map(fs::canonicalize).collect()
+    files.iter().map(utils::canonicalize).collect()
+}
+
+/// Read the content of `list_path`, or stdin if it's "-".
+fn read_list(list_path: &Path) -> io::Result<Vec<u8>> {
+    if list_path == Path::new("-") {
+        let mut content = vec![];
+        io::stdin().lock().read_to_end(&mut content)?;
+        Ok(content)
+    } else {
+        fs::read(list_path)
+    }
+}
+
+fn paths_of_list<'a>(paths: impl Iterator<Item = &'a [u8]>) -> io::Result<Vec<PathBuf>> {
+    paths
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            path.to_path()
+                .map(Path::to_path_buf)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file list contains an invalid path"))
+        })
+        .collect()
+}
+
+/// Read a list of paths separated by null bytes from `list_path`, or from stdin if it's "-".
+pub fn read_null_delimited_paths(list_path: &Path) -> io::Result<Vec<PathBuf>> {
+    paths_of_list(read_list(list_path)?.split_str(b"\0"))
+}
+
+/// Read a list of paths from `list_path`, or from stdin if it's "-", separated by null bytes if
+/// there are some, one per line otherwise.
+pub fn read_path_list(list_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let content = read_list(list_path)?;
+    if content.contains(&0) {
+        paths_of_list(content.split_str(b"\0"))
+    } else {
+        paths_of_list(content.lines())
+    }
 }
@@ -0,0 +1,222 @@
+//! Implementation of `ouch compare`, which checks an archive against a directory.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use fs_err as fs;
+use once_cell::sync::OnceCell;
+
+use crate::{
+    archive::{cancel::CancellationToken, filter::EntryFilter, metadata::LostMetadata, UnpackOptions},
+    cli::{
+        CaseCollisionsPolicy, DuplicateEntriesPolicy, LongNamesPolicy, NameNormalization, ProgressMode,
+        WindowsLinksPolicy,
+    },
+    commands::decompress::decompress_file,
+    error::FinalError,
+    extension::Extension,
+    info,
+    progress::CliEvents,
+    utils::{self, EscapedPathDisplay, FileVisibilityPolicy},
+    QuestionPolicy,
+};
+
+/// Modification times closer than this are considered equal, zip archives store them with a
+/// two seconds precision
+const MODIFICATION_TIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// A difference between an archive and the directory it's compared against
+enum Difference {
+    MissingOnDisk,
+    MissingFromArchive,
+    Type,
+    Content,
+    Permissions { archive: u32, disk: u32 },
+    ModificationTime,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingOnDisk => write!(f, "missing on disk"),
+            Self::MissingFromArchive => write!(f, "missing from the archive"),
+            Self::Type => write!(f, "type differs"),
+            Self::Content => write!(f, "content differs"),
+            Self::Permissions { archive, disk } => {
+                write!(f, "permissions differ ({archive:o} in the archive, {disk:o} on disk)")
+            }
+            Self::ModificationTime => write!(f, "modification time differs"),
+        }
+    }
+}
+
+/// Compares the contents of the archive at `archive_path` with `disk_path`, printing every
+/// difference found, fails if there's any.
+///
+/// The archive is extracted to a temporary directory first, like `ouch decompress` would, so
+/// an archive with a single root directory is compared against the contents of that directory.
+pub fn compare_archive(
+    archive_path: &Path,
+    formats: Vec<Extension>,
+    file_name: &Path,
+    disk_path: &Path,
+    question_policy: QuestionPolicy,
+    file_visibility_policy: FileVisibilityPolicy,
+) -> crate::Result<()> {
+    let temp_dir = utils::scratch_dir()?;
+    let unpack_options = UnpackOptions {
+        absolute_paths: false,
+        events: Box::new(CliEvents::new(ProgressMode::Off, true, 0)),
+        cancel: CancellationToken::new(),
+        quiet: true,
+        devices: false,
+        ownership: None,
+        windows_links: WindowsLinksPolicy::default(),
+        case_collisions: CaseCollisionsPolicy::default(),
+        long_names: LongNamesPolicy::default(),
+        duplicate_entries: DuplicateEntriesPolicy::default(),
+        filter: EntryFilter::default(),
+        password: OnceCell::new(),
+        selinux: false,
+        deb_data_only: false,
+        codec_threads: 1,
+        normalize: NameNormalization::None,
+        zstd_dictionary: None,
+        lost_metadata: LostMetadata::default(),
+        ignore_existing: None,
+    };
+
+    let extracted = decompress_file(
+        archive_path,
+        formats,
+        temp_dir.path(),
+        temp_dir.path().join(file_name),
+        question_policy,
+        &unpack_options,
+    )?;
+    // Nothing was created if the archive is empty
+    let archive_root = extracted.unwrap_or_else(|| temp_dir.path().to_path_buf());
+
+    let archive_entries = walk(&archive_root, &file_visibility_policy)?;
+    let disk_entries = walk(disk_path, &file_visibility_policy)?;
+
+    let mut difference_count = 0;
+    for path in archive_entries.union(&disk_entries) {
+        let difference = match (archive_entries.contains(path), disk_entries.contains(path)) {
+            (true, false) => Some(Difference::MissingOnDisk),
+            (false, true) => Some(Difference::MissingFromArchive),
+            _ => compare_entries(&archive_root.join(path), &disk_path.join(path))?,
+        };
+
+        if let Some(difference) = difference {
+            println!("{}: {difference}", EscapedPathDisplay::new(&disk_path.join(path)));
+            difference_count += 1;
+        }
+    }
+
+    if difference_count > 0 {
+        let error = FinalError::with_title(format!(
+            "'{}' and '{}' differ",
+            EscapedPathDisplay::new(archive_path),
+            EscapedPathDisplay::new(disk_path)
+        ))
+        .detail(format!("Found {difference_count} differences"));
+
+        return Err(error.into());
+    }
+
+    info!(
+        accessible,
+        "'{}' and '{}' have the same contents.",
+        EscapedPathDisplay::new(archive_path),
+        EscapedPathDisplay::new(disk_path)
+    );
+    Ok(())
+}
+
+/// The paths of `root` and everything inside of it, relative to `root`.
+fn walk(root: &Path, file_visibility_policy: &FileVisibilityPolicy) -> crate::Result<BTreeSet<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    for entry in file_visibility_policy.build_walker(root) {
+        let entry = entry?;
+        let path = entry
+            .path()
+            .strip_prefix(root)
+            .expect("walked paths are inside of the root");
+        paths.insert(path.to_path_buf());
+    }
+    Ok(paths)
+}
+
+/// Compares an extracted entry with the one on disk, symlinks are compared, not followed.
+fn compare_entries(archive_path: &Path, disk_path: &Path) -> crate::Result<Option<Difference>> {
+    let archive_metadata = fs::symlink_metadata(archive_path)?;
+    let disk_metadata = fs::symlink_metadata(disk_path)?;
+
+    if archive_metadata.file_type() != disk_metadata.file_type() {
+        return Ok(Some(Difference::Type));
+    }
+
+    if archive_metadata.is_symlink() {
+        let is_same_target = fs::read_link(archive_path)? == fs::read_link(disk_path)?;
+        return Ok((!is_same_target).then_some(Difference::Content));
+    }
+
+    // Directories are compared through their contents, and their modification times change
+    // while they're extracted
+    if !archive_metadata.is_file() {
+        return Ok(None);
+    }
+
+    if archive_metadata.len() != disk_metadata.len() || !have_same_content(archive_path, disk_path)? {
+        return Ok(Some(Difference::Content));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let archive = archive_metadata.permissions().mode() & 0o7777;
+        let disk = disk_metadata.permissions().mode() & 0o7777;
+        if archive != disk {
+            return Ok(Some(Difference::Permissions { archive, disk }));
+        }
+    }
+
+    let archive_modified = archive_metadata.modified()?;
+    let disk_modified = disk_metadata.modified()?;
+    let distance = archive_modified
+        .duration_since(disk_modified)
+        .or_else(|_| disk_modified.duration_since(archive_modified))
+        .unwrap_or_default();
+    if distance >= MODIFICATION_TIME_TOLERANCE {
+        return Ok(Some(Difference::ModificationTime));
+    }
+
+    Ok(None)
+}
+
+/// Whether the files at `a` and `b` have the same bytes.
+fn have_same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = BufReader::new(fs::File::open(a)?);
+    let mut b = BufReader::new(fs::File::open(b)?);
+
+    loop {
+        let (a_buf, b_buf) = (a.fill_buf()?, b.fill_buf()?);
+        if a_buf.is_empty() || b_buf.is_empty() {
+            return Ok(a_buf.is_empty() && b_buf.is_empty());
+        }
+
+        let len = a_buf.len().min(b_buf.len());
+        if a_buf[..len] != b_buf[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    io::{self, BufWriter, Cursor, Seek, Write},
+    io::{self, BufWriter, Seek, Write},
     path::{Path, PathBuf},
 };
 
@@ -7,10 +7,22 @@ use fs_err as fs;
 
 use super::warn_user_about_loading_sevenz_in_memory;
 use crate::{
-    archive,
+    archive::{
+        self,
+        checkpoint::Checkpoint,
+        index::{FramedEncoder, SeekIndex, SeekableEncoder},
+        BuildOptions,
+    },
     commands::warn_user_about_loading_zip_in_memory,
-    extension::{split_first_compression_format, CompressionFormat::*, Extension},
-    utils::{user_wants_to_continue, FileVisibilityPolicy},
+    error::FinalError,
+    extension::{
+        split_first_compression_format,
+        CompressionFormat::{self, *},
+        Extension,
+    },
+    info,
+    progress::Tracked,
+    utils::{user_wants_to_continue, Checksum, EscapedPathDisplay, LzipEncoder, LzopEncoder, SpillBuffer},
     QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
@@ -19,7 +31,17 @@ use crate::{
 /// # Arguments:
 /// - `files`: is the list of paths to be compressed: ["dir/file1.txt", "dir/file2.txt"]
 /// - `extensions`: is a list of compression formats for compressing, example: [Tar, Gz] (in compression order)
-/// - `output_file` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `output_file` is where the archive is written, a file or an SSH transfer
+/// - `output_path` is the resulting compressed file name, example: "archive.tar.gz"
+/// - `build_options` tells archive formats how to walk and store `files`
+/// - `codec_threads` is the number of threads the codecs that support it may use, at least 1
+/// - `index` writes a seek index next to tar.zst archives, see `archive::index`
+/// - `seekable` writes the outermost zstd compression in the zstd seekable format
+/// - `xz_block_size` is the size of the blocks of xz, see `chain_writer_encoder`
+/// - `zstd_window_log` enables the long distance matching of zstd, see `chain_writer_encoder`
+/// - `zstd_dictionary` is the dictionary zstd compresses with, see `--dict`
+/// - `checkpoint` stops tar archives at a time budget or resumes them, see `archive::checkpoint`
+/// - `checksum` gets everything written to `output_file`
 ///
 /// # Return value
 /// - Returns `Ok(true)` if compressed all files normally.
@@ -28,82 +50,97 @@ use crate::{
 pub fn compress_files(
     files: Vec<PathBuf>,
     extensions: Vec<Extension>,
-    output_file: fs::File,
+    output_file: impl Write + Send + 'static,
     output_path: &Path,
-    quiet: bool,
     question_policy: QuestionPolicy,
-    file_visibility_policy: FileVisibilityPolicy,
-    level: Option<i16>,
+    build_options: BuildOptions,
+    levels: &Levels,
+    codec_threads: usize,
+    xz_block_size: Option<u64>,
+    zstd_window_log: Option<u32>,
+    zstd_dictionary: Option<&[u8]>,
+    index: bool,
+    seekable: bool,
+    mut checkpoint: Option<Checkpoint>,
+    checksum: Option<&Checksum>,
 ) -> crate::Result<bool> {
     // If the input files contain a directory, then the total size will be underestimated
+    let output_file: Box<dyn Send + Write> = match checksum {
+        Some(checksum) => Box::new(checksum.writer(output_file)),
+        None => Box::new(output_file),
+    };
     let file_writer = BufWriter::with_capacity(BUFFER_CAPACITY, output_file);
 
-    let mut writer: Box<dyn Send + Write> = Box::new(file_writer);
+    if index {
+        // Formats were checked to be tar.zst
+        let mut encoder = FramedEncoder::new(file_writer, zstd_level(levels.get(Zstd)), codec_threads as u32);
+        if seekable {
+            encoder = encoder.with_seek_table();
+        }
+        let mut entries = vec![];
+        let encoder = archive::tar::build_archive_from_paths(
+            &files,
+            output_path,
+            encoder,
+            &build_options,
+            Some(&mut entries),
+            checkpoint.as_mut(),
+        )?;
+        let (mut file_writer, _, frames) = encoder.finish()?;
+        file_writer.flush()?;
+        SeekIndex::new(frames, entries).write(output_path)?;
+        save_checkpoint(checkpoint, output_path)?;
+        return Ok(true);
+    }
 
-    // Grab previous encoder and wrap it inside of a new one
-    let chain_writer_encoder = |format: &_, encoder| -> crate::Result<_> {
-        let encoder: Box<dyn Send + Write> = match format {
-            Gzip => Box::new(
-                // by default, ParCompress uses a default compression level of 3
-                // instead of the regular default that flate2 uses
-                gzp::par::compress::ParCompress::<gzp::deflate::Gzip>::builder()
-                    .compression_level(
-                        level.map_or_else(Default::default, |l| gzp::Compression::new((l as u32).clamp(0, 9))),
-                    )
-                    .from_writer(encoder),
-            ),
-            Bzip => Box::new(bzip2::write::BzEncoder::new(
-                encoder,
-                level.map_or_else(Default::default, |l| bzip2::Compression::new((l as u32).clamp(1, 9))),
-            )),
-            Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
-            Lzma => Box::new(xz2::write::XzEncoder::new(
-                encoder,
-                level.map_or(6, |l| (l as u32).clamp(0, 9)),
-            )),
-            Snappy => Box::new(
-                gzp::par::compress::ParCompress::<gzp::snap::Snap>::builder()
-                    .compression_level(gzp::par::compress::Compression::new(
-                        level.map_or_else(Default::default, |l| (l as u32).clamp(0, 9)),
-                    ))
-                    .from_writer(encoder),
-            ),
-            Zstd => {
-                let zstd_encoder = zstd::stream::write::Encoder::new(
-                    encoder,
-                    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
-                        (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
-                    }),
-                );
-                // Safety:
-                //     Encoder::new() can only fail if `level` is invalid, but the level
-                //     is `clamp`ed and therefore guaranteed to be valid
-                Box::new(zstd_encoder.unwrap().auto_finish())
-            }
-            Tar | Zip | Rar | SevenZip => unreachable!(),
-        };
-        Ok(encoder)
-    };
+    let mut writer: Box<dyn Send + Write> = Box::new(file_writer);
 
     let (first_format, formats) = split_first_compression_format(&extensions);
 
-    for format in formats.iter().rev() {
-        writer = chain_writer_encoder(format, writer)?;
+    // Formats were checked to end with zst if `seekable`
+    let chain_encoder = |format: &CompressionFormat, writer, outermost: bool| -> crate::Result<Box<dyn Send + Write>> {
+        if seekable && outermost {
+            let level = zstd_level(levels.get(Zstd));
+            return Ok(Box::new(SeekableEncoder::new(writer, level, codec_threads as u32)));
+        }
+        chain_writer_encoder(
+            format,
+            writer,
+            levels.get(*format),
+            codec_threads,
+            xz_block_size,
+            zstd_window_log,
+            zstd_dictionary,
+        )
+    };
+
+    for (position, format) in formats.iter().rev().enumerate() {
+        writer = chain_encoder(format, writer, position == 0)?;
     }
 
     match first_format {
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
-            writer = chain_writer_encoder(&first_format, writer)?;
-            let mut reader = fs::File::open(&files[0]).unwrap();
+        Gzip | Bzip | Lz4 | Lzma | Snappy | Brotli | Lzip | Lzw | Lzop | Zstd => {
+            writer = chain_encoder(&first_format, writer, formats.is_empty())?;
+            let mut reader = Tracked::new(fs::File::open(&files[0])?);
 
             io::copy(&mut reader, &mut writer)?;
         }
         Tar => {
-            archive::tar::build_archive_from_paths(&files, output_path, &mut writer, file_visibility_policy, quiet)?;
+            archive::tar::build_archive_from_paths(
+                &files,
+                output_path,
+                &mut writer,
+                &build_options,
+                None,
+                checkpoint.as_mut(),
+            )?;
             writer.flush()?;
+            // The encoders finish when dropped, the archive is complete before its state file
+            drop(writer);
+            save_checkpoint(checkpoint, output_path)?;
         }
         Zip => {
-            if !formats.is_empty() {
+            if !formats.is_empty() && SpillBuffer::is_in_memory() {
                 warn_user_about_loading_zip_in_memory();
 
                 if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
@@ -111,17 +148,11 @@ pub fn compress_files(
                 }
             }
 
-            let mut vec_buffer = Cursor::new(vec![]);
+            let mut buffer = SpillBuffer::new()?;
 
-            archive::zip::build_archive_from_paths(
-                &files,
-                output_path,
-                &mut vec_buffer,
-                file_visibility_policy,
-                quiet,
-            )?;
-            vec_buffer.rewind()?;
-            io::copy(&mut vec_buffer, &mut writer)?;
+            archive::zip::build_archive_from_paths(&files, output_path, &mut buffer, &build_options)?;
+            buffer.rewind()?;
+            io::copy(&mut buffer, &mut writer)?;
         }
         Rar => {
             #[cfg(feature = "unrar")]
@@ -131,7 +162,7 @@ pub fn compress_files(
             return Err(archive::rar_stub::no_support());
         }
         SevenZip => {
-            if !formats.is_empty() {
+            if !formats.is_empty() && SpillBuffer::is_in_memory() {
                 warn_user_about_loading_sevenz_in_memory();
 
                 if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
@@ -139,12 +170,202 @@ pub fn compress_files(
                 }
             }
 
-            let mut vec_buffer = Cursor::new(vec![]);
-            archive::sevenz::compress_sevenz(&files, output_path, &mut vec_buffer, file_visibility_policy, quiet)?;
-            vec_buffer.rewind()?;
-            io::copy(&mut vec_buffer, &mut writer)?;
+            let mut buffer = SpillBuffer::new()?;
+            archive::sevenz::compress_sevenz(&files, output_path, &mut buffer, &build_options)?;
+            buffer.rewind()?;
+            io::copy(&mut buffer, &mut writer)?;
+        }
+        Cpio => {
+            archive::cpio::build_archive_from_paths(&files, output_path, &mut writer, &build_options)?;
+            writer.flush()?;
+        }
+        Deb => {
+            let error = FinalError::with_title("Cannot compress to the .deb format")
+                .detail("Debian packages can only be extracted")
+                .hint("Use 'dpkg-deb --build' to create them");
+            return Err(error.into());
+        }
+        Rpm => {
+            let error = FinalError::with_title("Cannot compress to the .rpm format")
+                .detail("RPM packages can only be extracted")
+                .hint("Use 'rpmbuild' to create them");
+            return Err(error.into());
+        }
+        Ar => {
+            let error = FinalError::with_title("Cannot compress to the .ar format")
+                .detail("ar archives can only be extracted")
+                .hint("Use 'ar rcs' to create them");
+            return Err(error.into());
+        }
+        Iso => {
+            let error = FinalError::with_title("Cannot compress to the .iso format")
+                .detail("ISO 9660 images can only be extracted")
+                .hint("Use 'xorriso -as mkisofs' to create them");
+            return Err(error.into());
+        }
+        Cab => {
+            #[cfg(not(feature = "cab"))]
+            return Err(archive::cab_stub::no_support());
+
+            #[cfg(feature = "cab")]
+            {
+                let error = FinalError::with_title("Cannot compress to the .cab format")
+                    .detail("Microsoft cabinets can only be extracted")
+                    .hint("Use 'makecab' or 'gcab --create' to create them");
+                return Err(error.into());
+            }
+        }
+        Squashfs => {
+            let error = FinalError::with_title("Cannot compress to the .squashfs format")
+                .detail("SquashFS images can only be extracted")
+                .hint("Use 'mksquashfs' to create them");
+            return Err(error.into());
         }
     }
 
     Ok(true)
 }
+
+/// The compression levels of formats, from `--level` or the configuration file
+#[derive(Debug, Default)]
+pub struct Levels {
+    /// The level given in the command line, for every format
+    pub level: Option<i16>,
+    /// The levels of the `[levels]` table of the configuration file
+    pub defaults: Vec<(CompressionFormat, i16)>,
+}
+
+impl Levels {
+    /// The level of `format`, `None` for the default of its encoder
+    pub fn get(&self, format: CompressionFormat) -> Option<i16> {
+        self.level.or_else(|| {
+            self.defaults
+                .iter()
+                .find(|(default_format, _)| *default_format == format)
+                .map(|&(_, level)| level)
+        })
+    }
+}
+
+/// Writes the state file of `checkpoint` if the time budget ran out before every entry of
+/// `output_path` was archived.
+fn save_checkpoint(checkpoint: Option<Checkpoint>, output_path: &Path) -> crate::Result<()> {
+    let Some(checkpoint) = checkpoint.filter(Checkpoint::stopped) else {
+        return Ok(());
+    };
+    let state_path = checkpoint.write(output_path)?;
+    info!(
+        accessible,
+        "The time budget ran out after {} entries, compress the same files with '--resume {}' to archive the others.",
+        checkpoint.archived(),
+        EscapedPathDisplay::new(&state_path)
+    );
+    Ok(())
+}
+
+/// Wraps `encoder` in an encoder of `format`, using up to `codec_threads` threads if it can.
+///
+/// `xz_block_size` is the uncompressed size of the blocks of xz, which are compressed and
+/// decompressed in parallel. `zstd_window_log` enables the long distance matching of zstd, with a
+/// window of 2^`zstd_window_log` bytes, and `zstd_dictionary` is the dictionary zstd compresses
+/// with, see `--dict`.
+pub fn chain_writer_encoder(
+    format: &CompressionFormat,
+    encoder: Box<dyn Send + Write>,
+    level: Option<i16>,
+    codec_threads: usize,
+    xz_block_size: Option<u64>,
+    zstd_window_log: Option<u32>,
+    zstd_dictionary: Option<&[u8]>,
+) -> crate::Result<Box<dyn Send + Write>> {
+    let encoder: Box<dyn Send + Write> = match format {
+        Gzip => Box::new(
+            // by default, ParCompress uses a default compression level of 3
+            // instead of the regular default that flate2 uses
+            gzp::par::compress::ParCompress::<gzp::deflate::Gzip>::builder()
+                .num_threads(codec_threads)
+                .expect("there's at least one thread")
+                .compression_level(level.map_or_else(Default::default, |l| gzp::Compression::new(clamp_level(l, 0, 9))))
+                .from_writer(encoder),
+        ),
+        Bzip => Box::new(bzip2::write::BzEncoder::new(
+            encoder,
+            level.map_or_else(Default::default, |l| bzip2::Compression::new(clamp_level(l, 1, 9))),
+        )),
+        Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(encoder).auto_finish()),
+        Lzma if codec_threads > 1 || xz_block_size.is_some() => {
+            let stream = liblzma::stream::MtStreamBuilder::new()
+                .threads(codec_threads as u32)
+                .preset(level.map_or(6, |l| clamp_level(l, 0, 9)))
+                .check(liblzma::stream::Check::Crc64)
+                // 0 lets liblzma choose, 3 times the dictionary size
+                .block_size(xz_block_size.unwrap_or(0))
+                .encoder()
+                .map_err(io::Error::from)?;
+            Box::new(liblzma::write::XzEncoder::new_stream(encoder, stream))
+        }
+        Lzma => Box::new(liblzma::write::XzEncoder::new(
+            encoder,
+            level.map_or(6, |l| clamp_level(l, 0, 9)),
+        )),
+        Snappy => Box::new(
+            gzp::par::compress::ParCompress::<gzp::snap::Snap>::builder()
+                .num_threads(codec_threads)
+                .expect("there's at least one thread")
+                .compression_level(gzp::par::compress::Compression::new(
+                    level.map_or_else(Default::default, |l| clamp_level(l, 0, 9)),
+                ))
+                .from_writer(encoder),
+        ),
+        Brotli => Box::new(brotli::CompressorWriter::new(
+            encoder,
+            BUFFER_CAPACITY,
+            level.map_or(11, |l| clamp_level(l, 0, 11)),
+            BROTLI_WINDOW_BITS,
+        )),
+        Lzip => Box::new(LzipEncoder::new(encoder, level.map_or(6, |l| clamp_level(l, 0, 9)))?),
+        Lzop => Box::new(LzopEncoder::new(encoder)?),
+        Lzw => {
+            let error = FinalError::with_title("Cannot compress to the .Z format")
+                .detail("Files of the Unix compress tool can only be decompressed")
+                .hint("Use .gz, .xz or .zst instead");
+            return Err(error.into());
+        }
+        Zstd => {
+            let mut zstd_encoder = match zstd_dictionary {
+                Some(dictionary) => {
+                    zstd::stream::write::Encoder::with_dictionary(encoder, zstd_level(level), dictionary)?
+                }
+                // Safety:
+                //     Encoder::new() can only fail if `level` is invalid, but the level
+                //     is `clamp`ed and therefore guaranteed to be valid
+                None => zstd::stream::write::Encoder::new(encoder, zstd_level(level)).unwrap(),
+            };
+            if codec_threads > 1 {
+                zstd_encoder.multithread(codec_threads as u32)?;
+            }
+            if let Some(window_log) = zstd_window_log {
+                zstd_encoder.long_distance_matching(true)?;
+                zstd_encoder.window_log(window_log)?;
+            }
+            Box::new(zstd_encoder.auto_finish())
+        }
+        Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
+    };
+    Ok(encoder)
+}
+
+/// Base-2 logarithm of the brotli window size, the default of the `brotli` tool
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// `level` clamped to `min..=max`, negative levels are the lowest
+fn clamp_level(level: i16, min: u32, max: u32) -> u32 {
+    u32::try_from(level).unwrap_or(0).clamp(min, max)
+}
+
+/// The zstd level for `level`, clamped to the ones zstd supports
+fn zstd_level(level: Option<i16>) -> i32 {
+    level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
+        (l as i32).clamp(zstd::zstd_safe::min_c_level(), zstd::zstd_safe::max_c_level())
+    })
+}
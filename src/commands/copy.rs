@@ -0,0 +1,106 @@
+//! Implementation of `ouch copy`, which copies entries of an archive into a new one, without
+//! extracting them.
+
+use std::{
+    io::{self, BufWriter, Seek, Write},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::{self, filter::EntryFilter, CopiedEntry},
+    commands::{compress::chain_writer_encoder, warn_user_about_loading_zip_in_memory},
+    error::FinalError,
+    extension::CompressionFormat::{self, *},
+    info,
+    utils::{user_wants_to_continue, EscapedPathDisplay, SpillBuffer},
+    QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
+};
+
+/// Copies the entries of the archive at `source` that `filter` keeps into `output_file`, returns
+/// how many there were, or `None` if the user cancelled.
+///
+/// Formats are in compression order, like `[Tar, Zstd]`, both archives must be tar or zip.
+#[allow(clippy::too_many_arguments)]
+pub fn copy_archive(
+    source: &Path,
+    source_formats: &[CompressionFormat],
+    output_file: fs::File,
+    output_path: &Path,
+    output_formats: &[CompressionFormat],
+    filter: &EntryFilter,
+    codec_threads: usize,
+    question_policy: QuestionPolicy,
+    quiet: bool,
+) -> crate::Result<Option<usize>> {
+    for (path, formats) in [(source, source_formats), (output_path, output_formats)] {
+        if !matches!(formats.first(), Some(Tar | Zip)) {
+            let error = FinalError::with_title(format!("Cannot copy '{}'", EscapedPathDisplay::new(path)))
+                .detail("Only the entries of tar and zip archives can be copied")
+                .hint("Use 'ouch decompress' and 'ouch compress' for the other formats");
+            return Err(error.into());
+        }
+    }
+
+    let mut writer: Box<dyn Send + Write> = Box::new(BufWriter::with_capacity(BUFFER_CAPACITY, output_file));
+    for format in output_formats.iter().skip(1).rev() {
+        writer = chain_writer_encoder(format, writer, None, codec_threads, None, None, None)?;
+    }
+
+    let log_entry = |entry: &CopiedEntry| {
+        if !quiet {
+            info!(inaccessible, "Copying '{}'.", EscapedPathDisplay::new(&entry.path));
+        }
+    };
+
+    let copied = match output_formats[0] {
+        Tar => {
+            let mut builder = tar::Builder::new(writer);
+            let copied = read_entries(source, source_formats, filter, question_policy, |entry| {
+                log_entry(&entry);
+                archive::tar::append_copied_entry(&mut builder, entry)
+            })?;
+            builder.into_inner()?.flush()?;
+            copied
+        }
+        Zip => {
+            if output_formats.len() > 1 && SpillBuffer::is_in_memory() {
+                warn_user_about_loading_zip_in_memory();
+                if !user_wants_to_continue(output_path, question_policy, QuestionAction::Compression)? {
+                    return Ok(None);
+                }
+            }
+
+            let mut zip_writer = zip::ZipWriter::new(SpillBuffer::new()?);
+            let copied = read_entries(source, source_formats, filter, question_policy, |entry| {
+                log_entry(&entry);
+                archive::zip::append_copied_entry(&mut zip_writer, entry)
+            })?;
+            let mut buffer = zip_writer.finish()?;
+            buffer.rewind()?;
+            io::copy(&mut buffer, &mut writer)?;
+            writer.flush()?;
+            copied
+        }
+        _ => unreachable!("the formats were checked"),
+    };
+    Ok(copied)
+}
+
+/// Reads the entries of the archive at `source` that `filter` keeps, giving them to `append`.
+fn read_entries(
+    source: &Path,
+    formats: &[CompressionFormat],
+    filter: &EntryFilter,
+    question_policy: QuestionPolicy,
+    append: impl FnMut(CopiedEntry) -> crate::Result<()>,
+) -> crate::Result<Option<usize>> {
+    if formats.len() > 1 && formats[0] == Zip && SpillBuffer::is_in_memory() {
+        warn_user_about_loading_zip_in_memory();
+        if !user_wants_to_continue(source, question_policy, QuestionAction::Decompression)? {
+            return Ok(None);
+        }
+    }
+    archive::copy_entries_from(source, formats, filter, append).map(Some)
+}
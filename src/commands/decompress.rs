@@ -1,39 +1,103 @@
 use std::{
-    io::{self, BufReader, Read},
+    collections::BTreeSet,
+    io::{self, BufReader, Read, Seek, SeekFrom},
     ops::ControlFlow,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use fs_err as fs;
 
 use crate::{
-    commands::{warn_user_about_loading_sevenz_in_memory, warn_user_about_loading_zip_in_memory},
+    archive::{
+        cancel::CancellableReader,
+        index::{SeekIndex, SeekableDecoder},
+        UnpackOptions,
+    },
+    commands::{
+        list, warn_user_about_loading_iso_in_memory, warn_user_about_loading_sevenz_in_memory,
+        warn_user_about_loading_squashfs_in_memory, warn_user_about_loading_zip_in_memory,
+    },
+    error::FinalError,
     extension::{
-        split_first_compression_format,
+        flatten_compression_formats, split_first_compression_format,
         CompressionFormat::{self, *},
         Extension,
     },
     info,
-    utils::{self, nice_directory_display, user_wants_to_continue},
-    QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
+    list::ContentOptions,
+    macros::is_running_in_verbose_mode,
+    progress::Tracked,
+    ssh::{SshPath, SshReader},
+    utils::{
+        self, nice_directory_display, user_wants_to_continue, EscapedPathDisplay, LzipDecoder, LzopDecoder, LzwDecoder,
+        PartsReader, SpecialFileKind, SpillBuffer,
+    },
+    Error, QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
+/// The file decompressed, on this machine or read from another host
+enum Input {
+    Local(PartsReader),
+    Remote(SshReader),
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Local(reader) => reader.read(buf),
+            Self::Remote(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Local(reader) => reader.seek(position),
+            Self::Remote(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek in a file read over SSH",
+            )),
+        }
+    }
+}
+
 // Decompress a file
 //
 // File at input_file_path is opened for reading, example: "archive.tar.gz"
 // formats contains each format necessary for decompression, example: [Gz, Tar] (in decompression order)
 // output_dir it's where the file will be decompressed to, this function assumes that the directory exists
 // output_file_path is only used when extracting single file formats, not archive formats like .tar or .zip
+//
+// Returns the file or directory that was created, if any
 pub fn decompress_file(
     input_file_path: &Path,
     formats: Vec<Extension>,
     output_dir: &Path,
-    output_file_path: PathBuf,
+    mut output_file_path: PathBuf,
     question_policy: QuestionPolicy,
-    quiet: bool,
-) -> crate::Result<()> {
+    unpack_options: &UnpackOptions,
+) -> crate::Result<Option<PathBuf>> {
     assert!(output_dir.exists());
-    let reader = fs::File::open(input_file_path)?;
+    explain_decompression(input_file_path, &formats);
+    // Read over SSH, it can only be streamed, a copy is made of what must be read in place
+    let remote = SshPath::remote(input_file_path);
+    // Everything is read from it, so every copy stops once cancelled
+    let open_input = || -> crate::Result<_> {
+        let reader = match &remote {
+            Some(remote) => Input::Remote(remote.open()?),
+            None => Input::Local(PartsReader::open(input_file_path)?),
+        };
+        Ok(CancellableReader::new(Tracked::new(reader), &unpack_options.cancel))
+    };
+
+    let resumed_roots = match unpack_options.ignore_existing {
+        Some(_) => match archive_roots(input_file_path, &formats, question_policy, unpack_options)? {
+            Some(roots) => Some(roots),
+            None => return Ok(None),
+        },
+        None => None,
+    };
 
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
@@ -42,21 +106,27 @@ pub fn decompress_file(
     // in-memory decompression/copying first.
     //
     // Any other Zip decompression done can take up the whole RAM and freeze ouch.
-    if let [Extension {
-        compression_formats: [Zip],
-        ..
-    }] = formats.as_slice()
+    if let (
+        None,
+        [Extension {
+            compression_formats: [Zip],
+            ..
+        }],
+    ) = (&remote, formats.as_slice())
     {
-        let zip_archive = zip::ZipArchive::new(reader)?;
-        let files_unpacked = if let ControlFlow::Continue(files) = smart_unpack(
-            |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, quiet),
+        let mut zip_archive = zip::ZipArchive::new(open_input()?)?;
+        ask_zip_password(&mut zip_archive, input_file_path, unpack_options)?;
+        let (files_unpacked, created_path) = if let ControlFlow::Continue(unpacked) = smart_unpack(
+            |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, unpack_options),
             output_dir,
             &output_file_path,
             question_policy,
+            unpack_options,
+            resumed_roots.as_ref(),
         )? {
-            files
+            unpacked
         } else {
-            return Ok(());
+            return Ok(None);
         };
 
         // this is only printed once, so it doesn't result in much text. On the other hand,
@@ -70,99 +140,227 @@ pub fn decompress_file(
             files_unpacked
         );
 
-        return Ok(());
+        return Ok(created_path);
     }
 
-    // Will be used in decoder chaining
-    let reader = BufReader::with_capacity(BUFFER_CAPACITY, reader);
-    let mut reader: Box<dyn Read> = Box::new(reader);
-
     // Grab previous decoder and wrap it inside of a new one
     let chain_reader_decoder = |format: &CompressionFormat, decoder: Box<dyn Read>| -> crate::Result<Box<dyn Read>> {
         let decoder: Box<dyn Read> = match format {
             Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
             Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
             Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-            Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
+            Lzma if unpack_options.codec_threads > 1 => {
+                let stream = liblzma::stream::MtStreamBuilder::new()
+                    .threads(unpack_options.codec_threads as u32)
+                    // Like the single threaded decoder, which has no memory limit
+                    .memlimit_threading(u64::MAX)
+                    .memlimit_stop(u64::MAX)
+                    .decoder()
+                    .map_err(io::Error::from)?;
+                Box::new(liblzma::read::XzDecoder::new_stream(decoder, stream))
+            }
+            Lzma => Box::new(liblzma::read::XzDecoder::new(decoder)),
             Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-            Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-            Tar | Zip | Rar | SevenZip => unreachable!(),
+            Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
+            Lzip => Box::new(LzipDecoder::new(BufReader::new(decoder))),
+            Lzw => Box::new(LzwDecoder::new(decoder)),
+            Lzop => Box::new(LzopDecoder::new(decoder)),
+            Zstd => Box::new(utils::zstd_decoder(decoder, unpack_options.zstd_dictionary.as_deref())?),
+            Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
         };
         Ok(decoder)
     };
 
     let (first_extension, extensions) = split_first_compression_format(&formats);
 
-    for format in extensions.iter().rev() {
-        reader = chain_reader_decoder(format, reader)?;
-    }
+    // The input decoded by every format but the first one
+    let open_decoded = || -> crate::Result<Box<dyn Read>> {
+        let mut reader: Box<dyn Read> = Box::new(BufReader::with_capacity(BUFFER_CAPACITY, open_input()?));
+        for format in extensions.iter().rev() {
+            reader = chain_reader_decoder(format, reader)?;
+        }
+        Ok(reader)
+    };
+    let mut reader = open_decoded()?;
 
-    let files_unpacked = match first_extension {
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
+    let (files_unpacked, created_path) = match first_extension {
+        Gzip | Bzip | Lz4 | Lzma | Snappy | Brotli | Lzip | Lzw | Lzop | Zstd => {
             reader = chain_reader_decoder(&first_extension, reader)?;
 
-            let mut writer = match utils::ask_to_create_file(&output_file_path, question_policy)? {
-                Some(file) => file,
-                None => return Ok(()),
+            // An existing FIFO is written to as is, streaming into the process reading from it,
+            // it isn't overwritten nor reported as created
+            let output_is_fifo = fs::metadata(&output_file_path)
+                .is_ok_and(|metadata| SpecialFileKind::from_metadata(&metadata) == Some(SpecialFileKind::Fifo));
+
+            // Like "data.bin" of `--format gz`, which has no extension to drop
+            if !output_is_fifo && same_file::is_same_file(input_file_path, &output_file_path).unwrap_or(false) {
+                let error = FinalError::with_title(format!(
+                    "Cannot decompress '{}' into itself",
+                    EscapedPathDisplay::new(input_file_path)
+                ))
+                .hint("Decompress it to another directory with '--dir'");
+                return Err(error.into());
+            }
+
+            let mut writer = if output_is_fifo {
+                fs::OpenOptions::new().write(true).open(&output_file_path)?
+            } else {
+                match utils::ask_to_overwrite_or_rename(&output_file_path, question_policy)? {
+                    Some(path) => {
+                        output_file_path = path;
+                        fs::File::create(&output_file_path)?
+                    }
+                    None => return Ok(None),
+                }
             };
 
-            io::copy(&mut reader, &mut writer)?;
+            let copied = io::copy(&mut reader, &mut writer);
+            if unpack_options.cancel.is_cancelled() {
+                // What was decompressed so far is of no use
+                if !output_is_fifo {
+                    fs::remove_file(&output_file_path)?;
+                }
+                unpack_options.cancel.check()?;
+            }
+            copied?;
 
-            1
+            (1, (!output_is_fifo).then_some(output_file_path))
         }
         Tar => {
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::tar::unpack_archive(reader, output_dir, quiet),
+            // Indexed archives, see `--index` and `ouch index`, are only decoded from the frame of
+            // the first entry the filter keeps to the end of the last one
+            let index = match unpack_options.filter.is_active() && remote.is_none() {
+                true => SeekIndex::read_for(input_file_path),
+                false => None,
+            };
+            if let Some(index) = &index {
+                let range = index.range_of(&unpack_options.filter);
+                reader = match (extensions.as_slice(), range.frame.compressed_offset) {
+                    // Frames are only written for tar.zst archives
+                    ([Zstd], offset) if offset > 0 => {
+                        let mut input = open_input()?;
+                        input.seek(SeekFrom::Start(offset))?;
+                        let input = Box::new(BufReader::with_capacity(BUFFER_CAPACITY, input));
+                        let mut reader = chain_reader_decoder(&Zstd, input)?;
+                        io::copy(
+                            &mut (&mut reader).take(range.start - range.frame.uncompressed_offset),
+                            &mut io::sink(),
+                        )?;
+                        reader
+                    }
+                    _ => {
+                        io::copy(&mut (&mut reader).take(range.start), &mut io::sink())?;
+                        reader
+                    }
+                };
+                if let Some(end) = range.end {
+                    reader = Box::new(reader.take(end - range.start));
+                }
+            }
+
+            // Parts of tar.zst archives in the zstd seekable format are reached through the seek
+            // table, skipping the contents of the other entries instead of decoding them
+            let seekable = match extensions.as_slice() {
+                [Zstd] if index.is_none() && unpack_options.filter.is_active() && remote.is_none() => {
+                    SeekableDecoder::new(open_input()?)?
+                }
+                _ => None,
+            };
+            type UnpackResult = crate::Result<usize>;
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = match seekable {
+                Some(decoder) => Box::new(move |output_dir| {
+                    crate::archive::tar::unpack_seekable_archive(decoder, output_dir, unpack_options)
+                }),
+                None => {
+                    Box::new(move |output_dir| crate::archive::tar::unpack_archive(reader, output_dir, unpack_options))
+                }
+            };
+
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                unpack_fn,
                 output_dir,
                 &output_file_path,
                 question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
             )? {
-                files
+                unpacked
             } else {
-                return Ok(());
+                return Ok(None);
             }
         }
         Zip => {
-            if formats.len() > 1 {
-                warn_user_about_loading_zip_in_memory();
-
-                if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
-                }
-            }
-
-            let mut vec = vec![];
-            io::copy(&mut reader, &mut vec)?;
-            let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
-
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, quiet),
+            // Streamed entry by entry, the archive is only buffered if it can't be read that way
+            let streamed = smart_unpack(
+                |output_dir| crate::archive::zip::unpack_stream(reader, output_dir, unpack_options),
                 output_dir,
                 &output_file_path,
                 question_policy,
-            )? {
-                files
+                unpack_options,
+                resumed_roots.as_ref(),
+            );
+            let unpacked = match streamed {
+                Err(Error::UnsupportedZipArchive(reason) | Error::InvalidZipArchive(reason)) => {
+                    info!(
+                        accessible,
+                        "Cannot stream the zip archive ({reason}), reading it again to buffer it."
+                    );
+                    if SpillBuffer::is_in_memory() {
+                        warn_user_about_loading_zip_in_memory();
+
+                        if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
+                            return Ok(None);
+                        }
+                    }
+
+                    let mut zip_archive = zip::ZipArchive::new(SpillBuffer::from_reader(&mut open_decoded()?)?)?;
+                    ask_zip_password(&mut zip_archive, input_file_path, unpack_options)?;
+                    smart_unpack(
+                        |output_dir| crate::archive::zip::unpack_archive(zip_archive, output_dir, unpack_options),
+                        output_dir,
+                        &output_file_path,
+                        question_policy,
+                        unpack_options,
+                        resumed_roots.as_ref(),
+                    )?
+                }
+                streamed => streamed?,
+            };
+
+            if let ControlFlow::Continue(unpacked) = unpacked {
+                unpacked
             } else {
-                return Ok(());
+                return Ok(None);
             }
         }
         #[cfg(feature = "unrar")]
         Rar => {
             type UnpackResult = crate::Result<usize>;
-            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if formats.len() > 1 {
-                let mut temp_file = tempfile::NamedTempFile::new()?;
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if formats.len() > 1
+                || utils::joined_path(input_file_path).is_some()
+                || remote.is_some()
+            {
+                // Kept in the output directory by default, the only one `--sandbox` allows writing to
+                let mut temp_file = utils::temp_file_in(output_dir)?;
                 io::copy(&mut reader, &mut temp_file)?;
-                Box::new(move |output_dir| crate::archive::rar::unpack_archive(temp_file.path(), output_dir, quiet))
+                Box::new(move |output_dir| {
+                    crate::archive::rar::unpack_archive(temp_file.path(), output_dir, unpack_options)
+                })
             } else {
-                Box::new(|output_dir| crate::archive::rar::unpack_archive(input_file_path, output_dir, quiet))
+                Box::new(|output_dir| crate::archive::rar::unpack_archive(input_file_path, output_dir, unpack_options))
             };
 
-            if let ControlFlow::Continue(files) =
-                smart_unpack(unpack_fn, output_dir, &output_file_path, question_policy)?
-            {
-                files
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                unpack_fn,
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
             } else {
-                return Ok(());
+                return Ok(None);
             }
         }
         #[cfg(not(feature = "unrar"))]
@@ -170,26 +368,174 @@ pub fn decompress_file(
             return Err(crate::archive::rar_stub::no_support());
         }
         SevenZip => {
-            if formats.len() > 1 {
+            if formats.len() > 1 && SpillBuffer::is_in_memory() {
                 warn_user_about_loading_sevenz_in_memory();
 
                 if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
+                    return Ok(None);
+                }
+            }
+
+            let buffer = SpillBuffer::from_reader(&mut reader)?;
+
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                |output_dir| crate::archive::sevenz::decompress_sevenz(buffer, output_dir, unpack_options),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        Cpio => {
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                |output_dir| crate::archive::cpio::unpack_archive(reader, output_dir, unpack_options),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        Deb => {
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                |output_dir| crate::archive::deb::unpack_archive(reader, output_dir, unpack_options),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        Rpm => {
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                |output_dir| crate::archive::rpm::unpack_archive(reader, output_dir, unpack_options),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        Ar => {
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                |output_dir| crate::archive::ar::unpack_archive(reader, output_dir, unpack_options),
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        Iso => {
+            // Read in place when it isn't compressed, images are often large
+            type UnpackResult = crate::Result<usize>;
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if formats.len() > 1 || remote.is_some() {
+                if SpillBuffer::is_in_memory() {
+                    warn_user_about_loading_iso_in_memory();
+
+                    if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
+                        return Ok(None);
+                    }
                 }
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(move |output_dir| crate::archive::iso::unpack_archive(buffer, output_dir, unpack_options))
+            } else {
+                let image = open_input()?;
+                Box::new(move |output_dir| crate::archive::iso::unpack_archive(image, output_dir, unpack_options))
+            };
+
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                unpack_fn,
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
             }
+        }
+        #[cfg(feature = "cab")]
+        Cab => {
+            // Cabinets are small, they're buffered if they're compressed
+            type UnpackResult = crate::Result<usize>;
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if formats.len() > 1 || remote.is_some() {
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(move |output_dir| crate::archive::cab::unpack_archive(buffer, output_dir, unpack_options))
+            } else {
+                let cabinet = open_input()?;
+                Box::new(move |output_dir| crate::archive::cab::unpack_archive(cabinet, output_dir, unpack_options))
+            };
 
-            let mut vec = vec![];
-            io::copy(&mut reader, &mut vec)?;
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                unpack_fn,
+                output_dir,
+                &output_file_path,
+                question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
+            )? {
+                unpacked
+            } else {
+                return Ok(None);
+            }
+        }
+        #[cfg(not(feature = "cab"))]
+        Cab => {
+            return Err(crate::archive::cab_stub::no_support());
+        }
+        Squashfs => {
+            // Read in place when it isn't compressed, images are often large
+            type UnpackResult = crate::Result<usize>;
+            let unpack_fn: Box<dyn FnOnce(&Path) -> UnpackResult> = if formats.len() > 1 || remote.is_some() {
+                if SpillBuffer::is_in_memory() {
+                    warn_user_about_loading_squashfs_in_memory();
 
-            if let ControlFlow::Continue(files) = smart_unpack(
-                |output_dir| crate::archive::sevenz::decompress_sevenz(io::Cursor::new(vec), output_dir, quiet),
+                    if !user_wants_to_continue(input_file_path, question_policy, QuestionAction::Decompression)? {
+                        return Ok(None);
+                    }
+                }
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(move |output_dir| crate::archive::squashfs::unpack_archive(buffer, output_dir, unpack_options))
+            } else {
+                let image = open_input()?;
+                Box::new(move |output_dir| crate::archive::squashfs::unpack_archive(image, output_dir, unpack_options))
+            };
+
+            if let ControlFlow::Continue(unpacked) = smart_unpack(
+                unpack_fn,
                 output_dir,
                 &output_file_path,
                 question_policy,
+                unpack_options,
+                resumed_roots.as_ref(),
             )? {
-                files
+                unpacked
             } else {
-                return Ok(());
+                return Ok(None);
             }
         }
     };
@@ -205,41 +551,192 @@ pub fn decompress_file(
     );
     info!(accessible, "Files unpacked: {}", files_unpacked);
 
+    Ok(created_path)
+}
+
+/// The names of the files and directories of the output directory that the entries extracted
+/// out of the archive at `input_file_path` go in, read before extracting it with
+/// `--ignore-existing`, so that it's resumed where `smart_unpack` moved the files the first time.
+///
+/// Returns `None` if the user didn't want to load the archive in memory.
+fn archive_roots(
+    input_file_path: &Path,
+    formats: &[Extension],
+    question_policy: QuestionPolicy,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<Option<BTreeSet<PathBuf>>> {
+    if SshPath::remote(input_file_path).is_some() || utils::joined_path(input_file_path).is_some() {
+        let error = FinalError::with_title(format!(
+            "Cannot resume the extraction of '{}'",
+            EscapedPathDisplay::new(input_file_path)
+        ))
+        .detail("'--ignore-existing' lists the archive before extracting it")
+        .detail("Files on other hosts and files split in parts can only be read once");
+        return Err(error.into());
+    }
+
+    let formats = flatten_compression_formats(formats);
+    let Some(entries) = list::archive_entries(input_file_path, &formats, ContentOptions::default(), question_policy)?
+    else {
+        return Ok(None);
+    };
+    let filter = &unpack_options.filter;
+    let mut roots = BTreeSet::new();
+    for entry in entries {
+        let entry = entry?;
+        if !filter.matches(Some(entry.kind())) || !filter.matches_path(&entry.path) {
+            continue;
+        }
+        // Extracted outside of the output directory
+        if unpack_options.absolute_paths && entry.path.is_absolute() {
+            continue;
+        }
+        let first_name = entry
+            .path
+            .components()
+            .find(|component| !matches!(component, Component::Prefix(_) | Component::RootDir | Component::CurDir));
+        if let Some(Component::Normal(root)) = first_name {
+            roots.insert(PathBuf::from(root));
+        }
+    }
+    Ok(Some(roots))
+}
+
+/// Asks for the password of the zip archive at `input_file_path` if some of its entries are
+/// encrypted and none was given yet, it's then tried on the following archives too.
+fn ask_zip_password<R>(
+    zip_archive: &mut zip::ZipArchive<R>,
+    input_file_path: &Path,
+    unpack_options: &UnpackOptions,
+) -> crate::Result<()>
+where
+    R: Read + Seek,
+{
+    if unpack_options.password.get().is_none() && crate::archive::zip::has_encrypted_entries(zip_archive) {
+        unpack_options
+            .password
+            .get_or_try_init(|| utils::ask_password(input_file_path))?;
+    }
     Ok(())
 }
 
-// This function is named 'smart_unpack'. It takes four parameters:
-// - 'unpack_fn': a closure that accepts a reference to a Path and returns a Result of type usize.
-// - 'output_dir': a reference to a Path representing the output directory.
-// - 'output_file_path': a reference to a Path representing the path where the file will be written.
-// - 'question_policy': an instance of QuestionPolicy struct which handles user interaction during extraction.
-fn smart_unpack(
-    unpack_fn: impl FnOnce(&Path) -> crate::Result<usize>, // Closure that performs some operation on the provided path and returns a result of type usize.
-    output_dir: &Path, // Reference to a Path object pointing to the output directory.
-    output_file_path: &Path, // Reference to a Path object pointing to the location where the file should be created or extracted.
-    _question_policy: QuestionPolicy, // An instance of QuestionPolicy struct used for handling user interactions during extraction.
-) -> crate::Result<ControlFlow<(), usize>> { // The function returns a Result containing either ControlFlow with no value (()) and usize as payload or an error.
+/// With `--verbose`, tells which formats were detected and how the file is read, which is why
+/// some archives need more memory or disk space than others.
+fn explain_decompression(input_file_path: &Path, formats: &[Extension]) {
+    if !is_running_in_verbose_mode() {
+        return;
+    }
 
-    // Logs information about the output directory and file path.
+    let chain = flatten_compression_formats(formats);
+    let detected: Vec<String> = chain.iter().map(ToString::to_string).collect();
+    let how = match chain.as_slice() {
+        [Zip | Rar] => "reading it in place",
+        [Rar, ..] => "copying it to a temporary file first",
+        [Zip, ..] => "streaming it, or buffering it if it can't be streamed",
+        [SevenZip, ..] if SpillBuffer::is_in_memory() => "buffering it in memory first",
+        [SevenZip, ..] => "buffering it in a temporary file first",
+        _ => "streaming it",
+    };
     info!(
         accessible,
-         "Debug smart unpack output_dir: {}, output_file_path {}.",
-        nice_directory_display(output_dir),
-        nice_directory_display(output_file_path)
+        "Detected {} in '{}', {how}.",
+        detected.join(" ← "),
+        EscapedPathDisplay::new(input_file_path)
     );
+}
 
-    // Asserts that the output directory exists. If not, it will panic.
+/// Unpacks an archive with some heuristics
+/// - If the archive contains only one file, it will be extracted to the `output_dir`
+/// - If the archive contains multiple files, it will be extracted to a subdirectory of the
+///   output_dir named after the archive (given by `output_file_path`)
+///
+/// Returns the number of unpacked files, and the file or directory that was created in `output_dir`
+///
+/// Note: This functions assumes that `output_dir` exists
+fn smart_unpack(
+    unpack_fn: impl FnOnce(&Path) -> crate::Result<usize>,
+    output_dir: &Path,
+    output_file_path: &Path,
+    question_policy: QuestionPolicy,
+    unpack_options: &UnpackOptions,
+    resumed_roots: Option<&BTreeSet<PathBuf>>,
+) -> crate::Result<ControlFlow<(), (usize, Option<PathBuf>)>> {
     assert!(output_dir.exists());
-
-    // Attempts to create a directory at the given 'output_file_path'. If successful, logs success message; otherwise, logs failure message along with the error details.
-    match fs::create_dir(&output_file_path) {
-        Ok(_) => info!(accessible, "Directory created at {}", nice_directory_display(output_file_path)),
-        Err(e) => info!(accessible, "Failed to create directory at {}, error: {}", nice_directory_display(output_file_path), e),
+    // Resumed in place, where the files would have been moved to, so where an earlier extraction
+    // left them
+    if let Some(roots) = resumed_roots {
+        let (unpack_dir, created_path) = match roots.len() {
+            0 => (output_dir, None),
+            1 => (output_dir, roots.first().map(|root| output_dir.join(root))),
+            _ => {
+                fs::create_dir_all(output_file_path)?;
+                (output_file_path, Some(output_file_path.to_path_buf()))
+            }
+        };
+        let files = unpack_fn(unpack_dir)?;
+        if let Some(ownership) = &unpack_options.ownership {
+            ownership.apply(unpack_dir, unpack_dir)?;
+        }
+        return Ok(ControlFlow::Continue((files, created_path)));
     }
 
-    // Calls the provided closure on 'output_file_path' and returns its result if successful. Otherwise, propagates the error upwards.
-    let files = unpack_fn(output_file_path)?;
+    // Not in `--temp-dir`, moving the extracted files out of it is only atomic on the same filesystem
+    let temp_dir = utils::staging_dir_in(output_dir)?;
+    let temp_dir_path = temp_dir.path();
+    info!(
+        accessible,
+        "Created temporary directory {} to hold decompressed elements.",
+        nice_directory_display(temp_dir_path)
+    );
+
+    let files = unpack_fn(temp_dir_path)?;
+
+    let root_element_count = fs::read_dir(temp_dir_path)?.count();
+    let created_path = if root_element_count == 0 {
+        // Everything was extracted to absolute paths, nothing is left to move
+        None
+    } else if root_element_count == 1 {
+        // Only one file in the root directory, so we can just move it to the output directory
+        let file = fs::read_dir(temp_dir_path)?.next().expect("item exists")?;
+        let file_path = file.path();
+        let file_name = file_path
+            .file_name()
+            .expect("Should be safe because paths in archives should not end with '..'");
+        // Before moving, need to check if a file with the same name already exists
+        let Some(correct_path) = utils::ask_to_overwrite_or_rename(&output_dir.join(file_name), question_policy)?
+        else {
+            return Ok(ControlFlow::Break(()));
+        };
+        fs::rename(&file_path, &correct_path)?;
+        if let Some(ownership) = &unpack_options.ownership {
+            ownership.apply(&file_path, &correct_path)?;
+        }
+        info!(
+            accessible,
+            "Successfully moved {} to {}.",
+            nice_directory_display(&file_path),
+            nice_directory_display(&correct_path)
+        );
+        Some(correct_path)
+    } else {
+        // Multiple files in the root directory, so:
+        // Rename  the temporary directory to the archive name, which is output_file_path
+        // One case to handle tough is we need to check if a file with the same name already exists
+        let Some(output_file_path) = utils::ask_to_overwrite_or_rename(output_file_path, question_policy)? else {
+            return Ok(ControlFlow::Break(()));
+        };
+        fs::rename(temp_dir_path, &output_file_path)?;
+        if let Some(ownership) = &unpack_options.ownership {
+            ownership.apply(temp_dir_path, &output_file_path)?;
+        }
+        info!(
+            accessible,
+            "Successfully moved {} to {}.",
+            nice_directory_display(temp_dir_path),
+            nice_directory_display(&output_file_path)
+        );
+        Some(output_file_path)
+    };
 
-    // Returns a Result wrapping ControlFlow containing no value (()) and the number of extracted files as payload.
-    Ok(ControlFlow::Continue(files))
+    Ok(ControlFlow::Continue((files, created_path)))
 }
@@ -0,0 +1,88 @@
+//! Implementation of `ouch detect`, which prints the formats of files without reading them
+//! further than their signatures.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::{
+    extension::{self, flatten_compression_formats, Extension},
+    utils::{self, EscapedPathDisplay},
+};
+
+/// What was detected of a file
+#[derive(Serialize)]
+struct Detection {
+    path: String,
+    /// The formats as they'd be given to `--format`, like "tar.gz", if any was detected
+    extension: Option<String>,
+    /// The formats, innermost first, like ["tar", "gzip"]
+    formats: Vec<String>,
+    /// The format the contents look like, if it isn't the outermost one of `extension`
+    sniffed: Option<String>,
+}
+
+impl Detection {
+    fn new(path: &Path, formats: Option<&[Extension]>) -> crate::Result<Self> {
+        // Fails on missing files, like the other commands
+        fs::metadata(path)?;
+
+        // Parts like "archive.tar.zst.001" are detected as "archive.tar.zst"
+        let mut formats = match formats {
+            Some(formats) => formats.to_vec(),
+            None => extension::extensions_from_path(utils::joined_path(path).unwrap_or(path)),
+        };
+        let mut sniffed = utils::try_infer_extension(path);
+        if formats.is_empty() {
+            formats.extend(sniffed.take());
+        } else if sniffed.as_ref().is_some_and(|sniffed| {
+            formats
+                .last()
+                .is_some_and(|outer| outer.compression_formats.ends_with(sniffed.compression_formats))
+        }) {
+            sniffed = None;
+        }
+
+        let extension =
+            (!formats.is_empty()).then(|| formats.iter().map(ToString::to_string).collect::<Vec<_>>().join("."));
+        Ok(Self {
+            path: path.to_string_lossy().into_owned(),
+            extension,
+            formats: flatten_compression_formats(&formats)
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            sniffed: sniffed.map(|sniffed| sniffed.to_string()),
+        })
+    }
+}
+
+/// Prints the formats of `files`, from their names, or from `formats` if given, and from their
+/// contents, as lines like "archive.tar.gz: tar.gz" or as a JSON array.
+pub fn detect_formats(files: &[PathBuf], formats: Option<&[Extension]>, json: bool) -> crate::Result<()> {
+    let detections = files
+        .iter()
+        .map(|path| Detection::new(path, formats))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &detections).map_err(io::Error::from)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+
+    for (detection, path) in detections.iter().zip(files) {
+        let path = EscapedPathDisplay::new(path);
+        match (&detection.extension, &detection.sniffed) {
+            (Some(extension), Some(sniffed)) => writeln!(stdout, "{path}: {extension} (contents look like {sniffed})")?,
+            (Some(extension), None) => writeln!(stdout, "{path}: {extension}")?,
+            (None, _) => writeln!(stdout, "{path}: unknown")?,
+        }
+    }
+    Ok(())
+}
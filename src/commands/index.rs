@@ -0,0 +1,58 @@
+//! Implementation of `ouch index`, which writes the seek index of an existing tar archive.
+
+use std::{
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::index::{Frame, SeekIndex},
+    error::FinalError,
+    extension::CompressionFormat::{self, *},
+    progress::Tracked,
+    utils::{self, EscapedPathDisplay, LzipDecoder, LzopDecoder, LzwDecoder},
+    BUFFER_CAPACITY,
+};
+
+/// Scans the tar archive at `archive_path` and writes its sidecar, returns how many entries it has.
+///
+/// `formats` are in decompression order, like `[Tar, Gzip]`.
+pub fn index_archive(archive_path: &Path, formats: &[CompressionFormat]) -> crate::Result<usize> {
+    if formats.first() != Some(&Tar) {
+        let error = FinalError::with_title(format!("Cannot index '{}'", EscapedPathDisplay::new(archive_path)))
+            .detail("Only tar archives can be indexed")
+            .hint("Zip, 7z and rar archives already list their entries without being decompressed");
+        return Err(error.into());
+    }
+
+    let reader = BufReader::with_capacity(BUFFER_CAPACITY, Tracked::new(fs::File::open(archive_path)?));
+    let mut reader: Box<dyn Read> = Box::new(reader);
+    for format in formats.iter().skip(1).rev() {
+        reader = match format {
+            Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Bzip => Box::new(bzip2::read::BzDecoder::new(reader)),
+            Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            Lzma => Box::new(liblzma::read::XzDecoder::new(reader)),
+            Snappy => Box::new(snap::read::FrameDecoder::new(reader)),
+            Brotli => Box::new(brotli::Decompressor::new(reader, BUFFER_CAPACITY)),
+            Lzip => Box::new(LzipDecoder::new(BufReader::new(reader))),
+            Lzw => Box::new(LzwDecoder::new(reader)),
+            Lzop => Box::new(LzopDecoder::new(reader)),
+            Zstd => Box::new(utils::zstd_decoder(reader, None)?),
+            Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
+        };
+    }
+
+    let entries = crate::archive::tar::index_archive(tar::Archive::new(reader))?;
+    let count = entries.len();
+    // The compression wasn't split into frames, it can only be decoded from the start
+    let frames = vec![Frame {
+        compressed_offset: 0,
+        uncompressed_offset: 0,
+    }];
+    SeekIndex::new(frames, entries).write(archive_path)?;
+
+    Ok(count)
+}
@@ -1,15 +1,20 @@
 use std::{
-    io::{self, BufReader, Read},
+    io::{BufReader, Read},
     path::Path,
 };
 
 use fs_err as fs;
 
 use crate::{
-    commands::warn_user_about_loading_zip_in_memory,
+    archive::index::SeekIndex,
+    commands::{
+        warn_user_about_loading_iso_in_memory, warn_user_about_loading_squashfs_in_memory,
+        warn_user_about_loading_zip_in_memory,
+    },
     extension::CompressionFormat::{self, *},
-    list::{self, FileInArchive, ListOptions},
-    utils::user_wants_to_continue,
+    list::{self, ContentOptions, FileInArchive, ListOptions},
+    progress::Tracked,
+    utils::{self, user_wants_to_continue, LzipDecoder, LzopDecoder, LzwDecoder, SpillBuffer},
     QuestionAction, QuestionPolicy, BUFFER_CAPACITY,
 };
 
@@ -21,7 +26,31 @@ pub fn list_archive_contents(
     list_options: ListOptions,
     question_policy: QuestionPolicy,
 ) -> crate::Result<()> {
-    let reader = fs::File::open(archive_path)?;
+    // Indexed archives, see `--index` and `ouch index`, can be listed without decompressing them
+    // The index doesn't have the contents of the entries
+    if let ([Tar, ..], false) = (formats.as_slice(), list_options.contents.is_needed()) {
+        if let Some(index) = SeekIndex::read_for(archive_path) {
+            list::list_files(archive_path, index.into_files(), list_options)?;
+            return Ok(());
+        }
+    }
+
+    let Some(files) = archive_entries(archive_path, &formats, list_options.contents, question_policy)? else {
+        return Ok(());
+    };
+    list::list_files(archive_path, files, list_options)?;
+    Ok(())
+}
+
+/// The entries of the archive at `archive_path`, with what `contents` asks for out of them, or
+/// `None` if the user didn't want to load it in memory.
+pub fn archive_entries(
+    archive_path: &Path,
+    formats: &[CompressionFormat],
+    contents: ContentOptions,
+    question_policy: QuestionPolicy,
+) -> crate::Result<Option<Box<dyn Iterator<Item = crate::Result<FileInArchive>>>>> {
+    let reader = Tracked::new(fs::File::open(archive_path)?);
 
     // Zip archives are special, because they require io::Seek, so it requires it's logic separated
     // from decoder chaining.
@@ -30,12 +59,9 @@ pub fn list_archive_contents(
     // in-memory decompression/copying first.
     //
     // Any other Zip decompression done can take up the whole RAM and freeze ouch.
-    if let &[Zip] = formats.as_slice() {
+    if let [Zip] = formats {
         let zip_archive = zip::ZipArchive::new(reader)?;
-        let files = crate::archive::zip::list_archive(zip_archive);
-        list::list_files(archive_path, files, list_options)?;
-
-        return Ok(());
+        return Ok(Some(Box::new(crate::archive::zip::list_archive(zip_archive, contents))));
     }
 
     // Will be used in decoder chaining
@@ -49,10 +75,14 @@ pub fn list_archive_contents(
                 Gzip => Box::new(flate2::read::GzDecoder::new(decoder)),
                 Bzip => Box::new(bzip2::read::BzDecoder::new(decoder)),
                 Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(decoder)),
-                Lzma => Box::new(xz2::read::XzDecoder::new(decoder)),
+                Lzma => Box::new(liblzma::read::XzDecoder::new(decoder)),
                 Snappy => Box::new(snap::read::FrameDecoder::new(decoder)),
-                Zstd => Box::new(zstd::stream::Decoder::new(decoder)?),
-                Tar | Zip | Rar | SevenZip => unreachable!(),
+                Brotli => Box::new(brotli::Decompressor::new(decoder, BUFFER_CAPACITY)),
+                Lzip => Box::new(LzipDecoder::new(BufReader::new(decoder))),
+                Lzw => Box::new(LzwDecoder::new(decoder)),
+                Lzop => Box::new(LzopDecoder::new(decoder)),
+                Zstd => Box::new(utils::zstd_decoder(decoder, None)?),
+                Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => unreachable!(),
             };
             Ok(decoder)
         };
@@ -62,28 +92,31 @@ pub fn list_archive_contents(
     }
 
     let files: Box<dyn Iterator<Item = crate::Result<FileInArchive>>> = match formats[0] {
-        Tar => Box::new(crate::archive::tar::list_archive(tar::Archive::new(reader))),
+        Tar => Box::new(crate::archive::tar::list_archive(tar::Archive::new(reader), contents)),
         Zip => {
-            if formats.len() > 1 {
+            if formats.len() > 1 && SpillBuffer::is_in_memory() {
                 warn_user_about_loading_zip_in_memory();
 
                 if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
+                    return Ok(None);
                 }
             }
 
-            let mut vec = vec![];
-            io::copy(&mut reader, &mut vec)?;
-            let zip_archive = zip::ZipArchive::new(io::Cursor::new(vec))?;
+            let zip_archive = zip::ZipArchive::new(SpillBuffer::from_reader(&mut reader)?)?;
 
-            Box::new(crate::archive::zip::list_archive(zip_archive))
+            Box::new(crate::archive::zip::list_archive(zip_archive, contents))
         }
         #[cfg(feature = "unrar")]
         Rar => {
             if formats.len() > 1 {
-                let mut temp_file = tempfile::NamedTempFile::new()?;
-                io::copy(&mut reader, &mut temp_file)?;
-                Box::new(crate::archive::rar::list_archive(temp_file.path()))
+                let mut temp_file = crate::utils::temp_file_in(&std::env::temp_dir())?;
+                std::io::copy(&mut reader, &mut temp_file)?;
+                match contents.verify {
+                    true => Box::new(crate::archive::rar::test_archive(temp_file.path(), contents)?),
+                    false => Box::new(crate::archive::rar::list_archive(temp_file.path())),
+                }
+            } else if contents.verify {
+                Box::new(crate::archive::rar::test_archive(archive_path, contents)?)
             } else {
                 Box::new(crate::archive::rar::list_archive(archive_path))
             }
@@ -96,25 +129,68 @@ pub fn list_archive_contents(
             if formats.len() > 1 {
                 warn_user_about_loading_zip_in_memory();
                 if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
-                    return Ok(());
+                    return Ok(None);
                 }
             }
 
-            let mut files = Vec::new();
-
-            sevenz_rust::decompress_file_with_extract_fn(archive_path, ".", |entry, _, _| {
-                files.push(Ok(FileInArchive {
-                    path: entry.name().into(),
-                    is_dir: entry.is_directory(),
-                }));
-                Ok(true)
-            })?;
-            Box::new(files.into_iter())
+            Box::new(crate::archive::sevenz::list_archive(
+                archive_path.to_path_buf(),
+                contents,
+            ))
         }
-        Gzip | Bzip | Lz4 | Lzma | Snappy | Zstd => {
+        Cpio => Box::new(crate::archive::cpio::list_archive(reader, contents)),
+        Deb => Box::new(crate::archive::deb::list_archive(reader, contents)?),
+        Rpm => Box::new(crate::archive::rpm::list_archive(reader, contents)?),
+        Ar => Box::new(crate::archive::ar::list_archive(reader, contents)?),
+        Iso => {
+            // Read in place when it isn't compressed
+            if formats.len() > 1 {
+                if SpillBuffer::is_in_memory() {
+                    warn_user_about_loading_iso_in_memory();
+                    if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
+                        return Ok(None);
+                    }
+                }
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(crate::archive::iso::list_archive(buffer, contents)?)
+            } else {
+                let image = Tracked::new(fs::File::open(archive_path)?);
+                Box::new(crate::archive::iso::list_archive(image, contents)?)
+            }
+        }
+        #[cfg(feature = "cab")]
+        Cab => {
+            if formats.len() > 1 {
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(crate::archive::cab::list_archive(buffer, contents)?)
+            } else {
+                let cabinet = Tracked::new(fs::File::open(archive_path)?);
+                Box::new(crate::archive::cab::list_archive(cabinet, contents)?)
+            }
+        }
+        #[cfg(not(feature = "cab"))]
+        Cab => {
+            return Err(crate::archive::cab_stub::no_support());
+        }
+        Squashfs => {
+            // Read in place when it isn't compressed
+            if formats.len() > 1 {
+                if SpillBuffer::is_in_memory() {
+                    warn_user_about_loading_squashfs_in_memory();
+                    if !user_wants_to_continue(archive_path, question_policy, QuestionAction::Decompression)? {
+                        return Ok(None);
+                    }
+                }
+                let buffer = SpillBuffer::from_reader(&mut reader)?;
+                Box::new(crate::archive::squashfs::list_archive(buffer, contents)?)
+            } else {
+                let image = Tracked::new(fs::File::open(archive_path)?);
+                Box::new(crate::archive::squashfs::list_archive(image, contents)?)
+            }
+        }
+        Gzip | Bzip | Lz4 | Lzma | Snappy | Brotli | Lzip | Lzw | Lzop | Zstd => {
             panic!("Not an archive! This should never happen, if it does, something is wrong with `CompressionFormat::is_archive()`. Please report this error!");
         }
     };
-    list::list_files(archive_path, files, list_options)?;
-    Ok(())
+    Ok(Some(files))
 }
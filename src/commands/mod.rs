@@ -1,26 +1,59 @@
 //! Receive command from the cli and call the respective function for that command.
 
+mod compare;
 mod compress;
+mod copy;
 mod decompress;
+mod detect;
+mod index;
 mod list;
+mod pipeline;
+mod split;
+mod test;
+mod train_dict;
+mod undo;
+mod upload;
 
-use std::{ops::ControlFlow, path::PathBuf};
+use std::{
+    io::Write,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use fs_err as fs;
+use once_cell::sync::OnceCell;
 use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use utils::colors;
 
 use crate::{
+    archive::{
+        cancel::CancellationToken, checkpoint::Checkpoint, filter::EntryFilter, index::SeekIndex,
+        metadata::LostMetadata, BuildOptions, UnpackOptions,
+    },
     check,
-    cli::Subcommand,
-    commands::{compress::compress_files, decompress::decompress_file, list::list_archive_contents},
+    cli::{self, ProgressMode, Subcommand},
+    commands::{
+        compress::{compress_files, Levels},
+        decompress::decompress_file,
+        list::list_archive_contents,
+    },
+    config::Config,
     error::{Error, FinalError},
-    extension::{self, parse_format},
-    info,
-    list::ListOptions,
-    utils::{self, to_utf, EscapedPathDisplay, FileVisibilityPolicy},
+    extension::{self, parse_format, CompressionFormat},
+    heartbeat, info,
+    list::{ContentOptions, ListOptions},
+    privileges::OwnershipHelper,
+    progress::{CliEvents, Progress},
+    quarantine, sandbox, selinux, ssh, storage,
+    utils::{self, to_utf, Bytes, EscapedPathDisplay, FileVisibilityPolicy, ThreadAllocation},
     warning, CliArgs, QuestionPolicy,
 };
 
+/// Files each extraction keeps open at once: the archive, a temporary copy of it, the entry
+/// being written and its directory
+const FILES_OPEN_PER_EXTRACTION: usize = 4;
+
 /// Warn the user that (de)compressing this .zip archive might freeze their system.
 fn warn_user_about_loading_zip_in_memory() {
     const ZIP_IN_MEMORY_LIMITATION_WARNING: &str = "\n\
@@ -41,6 +74,26 @@ fn warn_user_about_loading_sevenz_in_memory() {
     warning!("{}", SEVENZ_IN_MEMORY_LIMITATION_WARNING);
 }
 
+/// Warn the user that decompressing this .iso image might freeze their system.
+fn warn_user_about_loading_iso_in_memory() {
+    const ISO_IN_MEMORY_LIMITATION_WARNING: &str = "\n\
+        \tThe format '.iso' is limited and cannot be decompressed using encoding streams.\n\
+        \tWhen using '.iso' with other formats, decompression must be done in-memory\n\
+        \tCareful, you might run out of RAM if the image is too large!";
+
+    warning!("{}", ISO_IN_MEMORY_LIMITATION_WARNING);
+}
+
+/// Warn the user that decompressing this .squashfs image might freeze their system.
+fn warn_user_about_loading_squashfs_in_memory() {
+    const SQUASHFS_IN_MEMORY_LIMITATION_WARNING: &str = "\n\
+        \tThe format '.squashfs' is limited and cannot be decompressed using encoding streams.\n\
+        \tWhen using '.squashfs' with other formats, decompression must be done in-memory\n\
+        \tCareful, you might run out of RAM if the image is too large!";
+
+    warning!("{}", SQUASHFS_IN_MEMORY_LIMITATION_WARNING);
+}
+
 /// This function checks what command needs to be run and performs A LOT of ahead-of-time checks
 /// to assume everything is OK.
 ///
@@ -50,6 +103,24 @@ pub fn run(
     question_policy: QuestionPolicy,
     file_visibility_policy: FileVisibilityPolicy,
 ) -> crate::Result<()> {
+    utils::set_temp_dir(args.temp_dir.clone());
+    if !matches!(args.cmd, Subcommand::CleanTemp) {
+        // Best effort, `ouch clean-temp` reports the errors
+        if let Ok(removed @ 1..) = utils::remove_orphaned_temp_files() {
+            info!(
+                inaccessible,
+                "Removed {removed} temporary files left by interrupted runs."
+            );
+        }
+    }
+    // `--run-as` forks its helper process first, while there is no other thread, and `--sandbox`
+    // only confines the threads started after it
+    let starts_late =
+        matches!(args.cmd, Subcommand::Decompress { ref run_as, sandbox, .. } if run_as.is_some() || sandbox);
+    if let Some(seconds) = args.heartbeat.filter(|_| !starts_late) {
+        heartbeat::start(Duration::from_secs(seconds));
+    }
+
     match args.cmd {
         Subcommand::Compress {
             files,
@@ -57,11 +128,35 @@ pub fn run(
             level,
             fast,
             slow,
+            special_files,
+            include,
+            exclude,
+            files_from0: _,
+            base_directory,
+            prefix,
+            normalize,
+            zip_align,
+            block_size,
+            long_window,
+            dict,
+            index,
+            seekable,
+            max_duration,
+            resume,
+            expand_archives,
+            emit_checksum,
+            password,
+            encryption,
+            pipeline,
+            selinux,
+            upload: upload_url,
+            upload_method,
         } => {
             // After cleaning, if there are no input files left, exit
             if files.is_empty() {
                 return Err(FinalError::with_title("No files to compress").into());
             }
+            let file_visibility_policy = file_visibility_policy.patterns(&include, &exclude)?;
 
             // Formats from path extension, like "file.tar.gz.xz" -> vec![Tar, Gzip, Lzma]
             let (formats_from_flag, formats) = match args.format {
@@ -79,11 +174,55 @@ pub fn run(
                 formats_from_flag.as_ref(),
             )?;
             check::check_archive_formats_position(&formats, &output_path)?;
-
-            let output_file = match utils::ask_to_create_file(&output_path, question_policy)? {
-                Some(writer) => writer,
-                None => return Ok(()),
-            };
+            if let Some(prefix) = &prefix {
+                check::check_prefix(prefix, &formats, &output_path)?;
+            }
+            if zip_align.is_some() {
+                check::check_zip_align(&formats, &output_path)?;
+            }
+            if block_size.is_some() {
+                check::check_block_size(&formats, &output_path)?;
+            }
+            if long_window.is_some() {
+                check::check_long_window(&formats, &output_path)?;
+            }
+            if dict.is_some() {
+                check::check_dict(&formats, &output_path)?;
+            }
+            let zstd_dictionary = dict.map(fs::read).transpose()?;
+            if index {
+                check::check_index(&formats, &output_path)?;
+            }
+            if seekable {
+                check::check_seekable(&formats, &output_path)?;
+            }
+            if max_duration.is_some() || resume.is_some() {
+                check::check_checkpoint(&formats, &output_path)?;
+            }
+            if expand_archives {
+                check::check_expand_archives(&formats, &output_path)?;
+            }
+            if password.is_some() {
+                check::check_password(&formats, &output_path)?;
+            }
+            if selinux {
+                selinux::check_supported()?;
+                check::check_selinux(&formats, &output_path)?;
+            }
+            if upload_url.is_some() {
+                upload::check_supported()?;
+            }
+            let ssh_destination = ssh::SshPath::parse(&output_path);
+            if let Some(destination) = &ssh_destination {
+                destination.check_options(&[
+                    ("--index", index),
+                    ("--max-duration", max_duration.is_some()),
+                    ("--resume", resume.is_some()),
+                    ("--pipeline", pipeline.is_some()),
+                    ("--upload", upload_url.is_some()),
+                ])?;
+            }
+            let source_date_epoch = utils::source_date_epoch()?;
 
             let level = if fast {
                 Some(1) // Lowest level of compression
@@ -92,17 +231,97 @@ pub fn run(
             } else {
                 level
             };
+            let levels = Levels {
+                level,
+                defaults: if level.is_none() {
+                    Config::load()?.levels()?
+                } else {
+                    vec![]
+                },
+            };
+
+            let checkpoint = (max_duration.is_some() || resume.is_some())
+                .then(|| Checkpoint::new(max_duration, resume.as_deref()))
+                .transpose()?;
+
+            let mut ssh_transfer = ssh_destination
+                .as_ref()
+                .map(|destination| destination.connect(question_policy))
+                .transpose()?;
+            let output_file: Box<dyn Write + Send> = match &mut ssh_transfer {
+                Some(transfer) => Box::new(transfer.writer()),
+                None => match utils::ask_to_create_file(&output_path, question_policy)? {
+                    Some(writer) => Box::new(writer),
+                    None => return Ok(()),
+                },
+            };
 
+            let build_options = BuildOptions {
+                file_visibility_policy,
+                special_files,
+                base_directory,
+                prefix,
+                normalize,
+                absolute_paths: args.absolute_paths,
+                zip_align,
+                password,
+                encryption,
+                source_date_epoch,
+                selinux,
+                quiet: args.quiet,
+                expand_archives,
+            };
+
+            // A single output is written, every thread goes to its codecs
+            let parallel_codec = formats
+                .iter()
+                .flat_map(|format| format.compression_formats)
+                .any(|format| {
+                    matches!(
+                        format,
+                        CompressionFormat::Gzip
+                            | CompressionFormat::Snappy
+                            | CompressionFormat::Zstd
+                            | CompressionFormat::Lzma
+                    )
+                });
+            let codec_threads = ThreadAllocation::new(args.threads, &[0], parallel_codec).codec_threads;
+
+            // Uploaded archives are tested first, like `ouch test` would
+            let tested_formats = extension::flatten_compression_formats(&formats);
+            let checksum = emit_checksum.map(utils::Checksum::new);
+            let progress_mode = if args.quiet { ProgressMode::Off } else { args.progress };
+            let total = match progress_mode {
+                ProgressMode::Off => 0,
+                _ => utils::total_size(&files, &build_options.file_visibility_policy),
+            };
+            let progress = Progress::new(progress_mode, "Compressing", total);
             let compress_result = compress_files(
                 files,
                 formats,
                 output_file,
                 &output_path,
-                args.quiet,
                 question_policy,
-                file_visibility_policy,
-                level,
+                build_options,
+                &levels,
+                codec_threads,
+                block_size,
+                long_window,
+                zstd_dictionary.as_deref(),
+                index,
+                seekable,
+                checkpoint,
+                checksum.as_ref(),
             );
+            drop(progress);
+
+            // Nothing is left on this machine to delete, the host only gets complete archives
+            let compress_result = match (ssh_transfer, compress_result) {
+                (Some(transfer), Ok(true)) => transfer.finish().map(|()| true),
+                (Some(transfer), Ok(false)) => transfer.abort().map_or(Ok(false), Err),
+                (Some(transfer), Err(err)) => Err(transfer.abort().unwrap_or(err)),
+                (None, compress_result) => compress_result,
+            };
 
             if let Ok(true) = compress_result {
                 // this is only printed once, so it doesn't result in much text. On the other hand,
@@ -110,7 +329,7 @@ pub fn run(
                 // as screen readers may not read a commands exit code, making it hard to reason
                 // about whether the command succeeded without such a message
                 info!(accessible, "Successfully compressed '{}'.", to_utf(&output_path));
-            } else {
+            } else if ssh_destination.is_none() {
                 // If Ok(false) or Err() occurred, delete incomplete file at `output_path`
                 //
                 // if deleting fails, print an extra alert message pointing
@@ -131,15 +350,63 @@ pub fn run(
             }
 
             compress_result?;
+
+            if let Some(checksum) = checksum {
+                // On stdout, in the format of `sha256sum`, for scripts to capture
+                println!("{}  {}", checksum.to_hex(), output_path.display());
+            }
+
+            if let Some(name) = pipeline {
+                let config = Config::load()?;
+                pipeline::run_pipeline(&name, config.pipeline(&name)?, &output_path)?;
+            }
+
+            if let Some(url) = upload_url {
+                test::test_file(&output_path, &tested_formats, false, question_policy)?;
+                upload::upload_archive(&output_path, &url, upload_method)?;
+            }
         }
-        Subcommand::Decompress { files, output_dir } => {
+        Subcommand::Decompress {
+            files,
+            patterns,
+            output_dir,
+            record,
+            manifest_out,
+            manifest_null,
+            devices,
+            sandbox,
+            run_as,
+            windows_links,
+            case_collisions,
+            long_names,
+            duplicate_entries,
+            normalize,
+            dict,
+            types,
+            only_from,
+            only,
+            ignore_case,
+            password,
+            fs_compression,
+            nocow,
+            quarantine,
+            selinux,
+            deb_data_only,
+            strict_metadata,
+            ignore_existing,
+        } => {
             let mut output_paths = vec![];
             let mut formats = vec![];
 
             if let Some(format) = args.format {
                 let format = parse_format(&format)?;
                 for path in files.iter() {
-                    let file_name = path.file_name().ok_or_else(|| Error::NotFound {
+                    // Parts like "archive.tar.zst.001" are decompressed together, as "archive.tar.zst"
+                    let name = ssh::path_on_host(path).unwrap_or_else(|| utils::joined_path(path).unwrap_or(path));
+                    // The known extensions are dropped even if they're misleading, "backup.zip" of
+                    // `--format tar.gz` is extracted to "backup"
+                    let (pathbase, _) = extension::separate_known_extensions_from_name(name);
+                    let file_name = pathbase.file_name().ok_or_else(|| Error::NotFound {
                         error_title: format!("{} does not have a file name", EscapedPathDisplay::new(path)),
                     })?;
                     output_paths.push(file_name.as_ref());
@@ -147,10 +414,16 @@ pub fn run(
                 }
             } else {
                 for path in files.iter() {
-                    let (pathbase, mut file_formats) = extension::separate_known_extensions_from_name(path);
+                    let remote = ssh::path_on_host(path);
+                    let name = remote.unwrap_or_else(|| utils::joined_path(path).unwrap_or(path));
+                    let (pathbase, mut file_formats) = extension::separate_known_extensions_from_name(name);
 
-                    if let ControlFlow::Break(_) = check::check_mime_type(path, &mut file_formats, question_policy)? {
-                        return Ok(());
+                    // Files on other hosts can't be sniffed before they're read
+                    if remote.is_none() {
+                        if let ControlFlow::Break(_) = check::check_mime_type(path, &mut file_formats, question_policy)?
+                        {
+                            return Ok(());
+                        }
                     }
 
                     output_paths.push(pathbase);
@@ -159,33 +432,182 @@ pub fn run(
             }
 
             check::check_missing_formats_when_decompressing(&files, &formats)?;
+            quarantine::check_supported(quarantine)?;
+            // Read before the sandbox, which may not allow reading it
+            let mut filter = EntryFilter::new(types).only(&[only, patterns].concat(), ignore_case)?;
+            if let Some(list_path) = &only_from {
+                filter = filter.only_paths(&cli::read_path_list(list_path)?);
+            }
+            if selinux {
+                selinux::check_supported()?;
+            }
 
             // The directory that will contain the output files
             // We default to the current directory if the user didn't specify an output directory with --dir
+            let mut created_output_dir = None;
             let output_dir = if let Some(dir) = output_dir {
+                if !dir.exists() {
+                    created_output_dir = Some(dir.clone());
+                }
                 utils::create_dir_if_non_existent(&dir)?;
                 dir
             } else {
                 PathBuf::from(".")
             };
 
-            files
-                .par_iter()
-                .zip(formats)
-                .zip(output_paths)
-                .try_for_each(|((input_path, formats), file_name)| {
-                    let output_file_path = output_dir.join(file_name); // Path used by single file format archives
-                    decompress_file(
-                        input_path,
-                        formats,
-                        &output_dir,
-                        output_file_path,
-                        question_policy,
-                        args.quiet,
-                    )
-                })?;
+            // Set before creating the temporary directories, so that everything inherits them
+            if let Some(algorithm) = fs_compression {
+                storage::set_compression(&output_dir, algorithm)?;
+            }
+            if nocow {
+                storage::set_nocow(&output_dir)?;
+            }
+
+            if sandbox {
+                // The files written after the extraction must exist beforehand to be allowed
+                let mut writable_paths = vec![output_dir.clone()];
+                writable_paths.extend(utils::temp_dir().map(Path::to_path_buf));
+                if let Some(manifest_path) = &manifest_out {
+                    fs::File::create(manifest_path)?;
+                    writable_paths.push(manifest_path.clone());
+                }
+                if record {
+                    let record_dir = undo::record_path()?.parent().map(Path::to_path_buf);
+                    if let Some(record_dir) = record_dir {
+                        fs::create_dir_all(&record_dir)?;
+                        writable_paths.push(record_dir);
+                    }
+                }
+                let writable_paths: Vec<&Path> = writable_paths.iter().map(PathBuf::as_path).collect();
+                utils::open_temp_registry();
+                sandbox::confine(&writable_paths)?;
+            }
+
+            // Started after the sandbox, which also confines the helper process
+            let ownership = match &run_as {
+                Some(user) => {
+                    let mut owned_paths = vec![];
+                    owned_paths.extend(created_output_dir.as_deref());
+                    if let Some(manifest_path) = &manifest_out {
+                        fs::File::create(manifest_path)?;
+                        owned_paths.push(manifest_path.as_path());
+                    }
+                    Some(OwnershipHelper::run_as(user, &output_dir, &owned_paths)?)
+                }
+                None => None,
+            };
+            if let Some(seconds) = args.heartbeat.filter(|_| run_as.is_some() || sandbox) {
+                heartbeat::start(Duration::from_secs(seconds));
+            }
+
+            // Only the xz decoder uses more than one thread, the others all go to decompressing
+            // files side by side
+            let input_sizes: Vec<u64> = files
+                .iter()
+                .map(|path| path.metadata().map_or(0, |metadata| metadata.len()))
+                .collect();
+            let parallel_codec = formats
+                .iter()
+                .flatten()
+                .flat_map(|format| format.compression_formats)
+                .any(|format| *format == CompressionFormat::Lzma);
+            let allocation = ThreadAllocation::new(args.threads, &input_sizes, parallel_codec);
+
+            let unpack_options = UnpackOptions {
+                absolute_paths: args.absolute_paths,
+                events: Box::new(CliEvents::new(args.progress, args.quiet, input_sizes.iter().sum())),
+                cancel: CancellationToken::new(),
+                quiet: args.quiet,
+                devices,
+                ownership,
+                windows_links,
+                case_collisions,
+                long_names,
+                duplicate_entries,
+                filter,
+                password: password.map(OnceCell::with_value).unwrap_or_default(),
+                selinux,
+                deb_data_only,
+                codec_threads: allocation.codec_threads,
+                normalize,
+                zstd_dictionary: dict.map(fs::read).transpose()?,
+                lost_metadata: LostMetadata::new(strict_metadata),
+                ignore_existing,
+            };
+
+            let thread_pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(allocation.workers)
+                .build()
+                .map_err(|err| FinalError::with_title("Could not start the worker threads").detail(err.to_string()))?;
+
+            let _interrupt = utils::cancel_on_interrupt(&unpack_options.cancel);
+            let created_paths = thread_pool.install(|| {
+                files
+                    .par_iter()
+                    .zip(formats)
+                    .zip(output_paths)
+                    .map(|((input_path, formats), file_name)| {
+                        let _handles = utils::OPEN_FILES.acquire(FILES_OPEN_PER_EXTRACTION);
+                        let output_file_path = output_dir.join(file_name); // Path used by single file format archives
+                        let created_path = decompress_file(
+                            input_path,
+                            formats,
+                            &output_dir,
+                            output_file_path,
+                            question_policy,
+                            &unpack_options,
+                        )?;
+
+                        if let Some(root) = &created_path {
+                            if let Some(value) = quarantine::archive_quarantine(input_path, quarantine)? {
+                                for path in undo::list_created_paths(std::slice::from_ref(root))? {
+                                    quarantine::set_quarantine(&path, &value)?;
+                                }
+                            }
+                        }
+                        Ok(created_path)
+                    })
+                    .collect::<crate::Result<Vec<_>>>()
+            })?;
+
+            for path in unpack_options.filter.unmatched_paths() {
+                warning!(
+                    "'{}' from '--only-from' is in none of the archives, it was not extracted.",
+                    EscapedPathDisplay::new(&path)
+                );
+            }
+            for pattern in unpack_options.filter.unmatched_patterns() {
+                warning!("'{pattern}' matched none of the entries of the archives, nothing was extracted for it.");
+            }
+            unpack_options.lost_metadata.report(unpack_options.events.as_ref());
+
+            if record || manifest_out.is_some() {
+                // If the output directory was created, everything else is inside of it
+                let created_roots = match created_output_dir {
+                    Some(dir) => vec![dir],
+                    None => created_paths.into_iter().flatten().collect(),
+                };
+                let created_paths = undo::list_created_paths(&created_roots)?;
+
+                if let Some(manifest_path) = manifest_out {
+                    undo::write_manifest(&manifest_path, &created_paths, manifest_null)?;
+                }
+                if record {
+                    undo::record_extraction(&created_paths)?;
+                }
+            }
         }
-        Subcommand::List { archives: files, tree } => {
+        Subcommand::List {
+            archives: files,
+            tree,
+            head,
+            tail,
+            summary,
+            types,
+            preview,
+            detect_types,
+            null,
+        } => {
             let mut formats = vec![];
 
             if let Some(format) = args.format {
@@ -208,14 +630,202 @@ pub fn run(
             // Ensure we were not told to list the content of a non-archive compressed file
             check::check_for_non_archive_formats(&files, &formats)?;
 
-            let list_options = ListOptions { tree };
+            let list_options = ListOptions {
+                tree,
+                head,
+                tail,
+                summary,
+                null,
+                filter: EntryFilter::new(types),
+                contents: ContentOptions {
+                    preview,
+                    detect_types,
+                    ..Default::default()
+                },
+            };
 
             for (i, (archive_path, formats)) in files.iter().zip(formats).enumerate() {
-                if i > 0 {
+                if i > 0 && !null {
                     println!();
                 }
                 let formats = extension::flatten_compression_formats(&formats);
-                list_archive_contents(archive_path, formats, list_options, question_policy)?;
+                list_archive_contents(archive_path, formats, list_options.clone(), question_policy)?;
+            }
+        }
+        Subcommand::Copy {
+            source,
+            destination,
+            only,
+            ignore_case,
+        } => {
+            let source_formats = {
+                let mut formats = extension::extensions_from_path(&source);
+                if let ControlFlow::Break(_) = check::check_mime_type(&source, &mut formats, question_policy)? {
+                    return Ok(());
+                }
+                extension::flatten_compression_formats(&formats)
+            };
+            let output_formats = match args.format {
+                Some(format) => parse_format(&format)?,
+                None => extension::extensions_from_path(&destination),
+            };
+            check::check_archive_formats_position(&output_formats, &destination)?;
+            let output_formats = extension::flatten_compression_formats(&output_formats);
+            let filter = EntryFilter::default().only(&only, ignore_case)?;
+
+            if same_file::is_same_file(&source, &destination).unwrap_or(false) {
+                let error = FinalError::with_title(format!(
+                    "Cannot copy '{}' into itself",
+                    EscapedPathDisplay::new(&source)
+                ))
+                .hint("Give another path to the copy");
+                return Err(error.into());
+            }
+            let output_file = match utils::ask_to_create_file(&destination, question_policy)? {
+                Some(file) => file,
+                None => return Ok(()),
+            };
+
+            let codec_threads = ThreadAllocation::new(args.threads, &[0], true).codec_threads;
+            let copy_result = copy::copy_archive(
+                &source,
+                &source_formats,
+                output_file,
+                &destination,
+                &output_formats,
+                &filter,
+                codec_threads,
+                question_policy,
+                args.quiet,
+            );
+            match copy_result {
+                Ok(Some(count)) => info!(
+                    accessible,
+                    "Copied {count} entries of '{}' to '{}'.",
+                    EscapedPathDisplay::new(&source),
+                    EscapedPathDisplay::new(&destination)
+                ),
+                // The incomplete copy is of no use
+                Ok(None) => utils::remove_file_or_dir(&destination)?,
+                Err(err) => {
+                    let _ = utils::remove_file_or_dir(&destination);
+                    return Err(err);
+                }
+            }
+        }
+        Subcommand::Compare { archive, path } => {
+            let (file_name, formats) = match args.format {
+                Some(format) => (archive.file_name().map(PathBuf::from), parse_format(&format)?),
+                None => {
+                    let (pathbase, mut formats) = extension::separate_known_extensions_from_name(&archive);
+                    if let ControlFlow::Break(_) = check::check_mime_type(&archive, &mut formats, question_policy)? {
+                        return Ok(());
+                    }
+                    (pathbase.file_name().map(PathBuf::from), formats)
+                }
+            };
+
+            check::check_missing_formats_when_decompressing(
+                std::slice::from_ref(&archive),
+                std::slice::from_ref(&formats),
+            )?;
+
+            let file_name = file_name.ok_or_else(|| Error::NotFound {
+                error_title: format!("{} does not have a file name", EscapedPathDisplay::new(&archive)),
+            })?;
+            compare::compare_archive(
+                &archive,
+                formats,
+                &file_name,
+                &path,
+                question_policy,
+                file_visibility_policy,
+            )?;
+        }
+        Subcommand::Test { archives, recursive } => {
+            let mut formats = vec![];
+            for archive in &archives {
+                let archive_formats = match &args.format {
+                    Some(format) => parse_format(format)?,
+                    None => {
+                        let mut formats = extension::extensions_from_path(archive);
+                        if let ControlFlow::Break(_) = check::check_mime_type(archive, &mut formats, question_policy)? {
+                            return Ok(());
+                        }
+                        formats
+                    }
+                };
+                formats.push(archive_formats);
+            }
+            check::check_missing_formats_when_decompressing(&archives, &formats)?;
+
+            let formats: Vec<_> = formats
+                .iter()
+                .map(|formats| extension::flatten_compression_formats(formats))
+                .collect();
+            test::test_files(&archives, &formats, recursive, question_policy)?;
+        }
+        Subcommand::Index { archive } => {
+            let formats = match args.format {
+                Some(format) => parse_format(&format)?,
+                None => {
+                    let mut formats = extension::extensions_from_path(&archive);
+                    if let ControlFlow::Break(_) = check::check_mime_type(&archive, &mut formats, question_policy)? {
+                        return Ok(());
+                    }
+                    formats
+                }
+            };
+            let formats = extension::flatten_compression_formats(&formats);
+            let count = index::index_archive(&archive, &formats)?;
+            info!(
+                accessible,
+                "Indexed {count} entries of '{}' in '{}'.",
+                EscapedPathDisplay::new(&archive),
+                EscapedPathDisplay::new(&SeekIndex::sidecar_path(&archive))
+            );
+        }
+        Subcommand::Detect { files, json } => {
+            let formats = args.format.map(|format| parse_format(&format)).transpose()?;
+            detect::detect_formats(&files, formats.as_deref(), json)?;
+        }
+        Subcommand::TrainDict {
+            samples,
+            output,
+            max_size,
+        } => {
+            let trained =
+                train_dict::train_dictionary(&samples, &output, max_size, file_visibility_policy, question_policy)?;
+            if let Some((size, count)) = trained {
+                info!(
+                    accessible,
+                    "Trained a dictionary of {} on {count} samples into '{}', compress with '--dict {}' to use it.",
+                    Bytes::new(size as u64),
+                    EscapedPathDisplay::new(&output),
+                    EscapedPathDisplay::new(&output)
+                );
+            }
+        }
+        Subcommand::Split { file, size } => {
+            if let Some(count) = split::split_file(&file, size, question_policy)? {
+                info!(
+                    accessible,
+                    "Split '{}' into {count} parts, decompress '{}' to join them.",
+                    EscapedPathDisplay::new(&file),
+                    EscapedPathDisplay::new(&utils::part_path(&file, 1))
+                );
+            }
+        }
+        Subcommand::Undo => undo::undo_last_extraction(question_policy)?,
+        Subcommand::CleanTemp => {
+            let removed = utils::remove_orphaned_temp_files()?;
+            if removed == 0 {
+                info!(accessible, "No temporary files were left by interrupted runs.");
+            } else {
+                info!(
+                    accessible,
+                    "Removed {removed} temporary files left by interrupted runs."
+                );
             }
         }
     }
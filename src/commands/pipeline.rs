@@ -0,0 +1,39 @@
+//! Running the steps of a pipeline from the configuration file, after compressing.
+
+use std::{path::Path, process::Command};
+
+use crate::{config::Pipeline, error::FinalError, info, utils::EscapedPathDisplay};
+
+/// Runs the steps of `pipeline` one after the other with the shell, stopping at the first one
+/// that fails. The path of the new archive is given to them in `$OUCH_ARCHIVE`.
+pub fn run_pipeline(name: &str, pipeline: &Pipeline, archive_path: &Path) -> crate::Result<()> {
+    let step_count = pipeline.steps.len();
+
+    for (i, step) in pipeline.steps.iter().enumerate() {
+        info!(accessible, "Running step {}/{step_count} of '{name}': {step}", i + 1);
+
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        let status = command.arg(step).env("OUCH_ARCHIVE", archive_path).status()?;
+
+        if !status.success() {
+            let error = FinalError::with_title(format!("Step {}/{step_count} of the pipeline '{name}' failed", i + 1))
+                .detail(format!("Command: {step}"))
+                .detail(format!("Exit status: {status}"))
+                .detail(format!(
+                    "The archive '{}' was kept",
+                    EscapedPathDisplay::new(archive_path)
+                ));
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
+}
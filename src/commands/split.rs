@@ -0,0 +1,34 @@
+//! Implementation of `ouch split`, which cuts a file into numbered parts.
+
+use std::{
+    io::{self, Read},
+    path::Path,
+};
+
+use fs_err as fs;
+
+use crate::{utils, QuestionPolicy};
+
+/// Splits the file at `path` into parts of at most `part_size` bytes, next to it.
+///
+/// Returns how many parts were written, or `None` if the user didn't want to overwrite one.
+pub fn split_file(path: &Path, part_size: u64, question_policy: QuestionPolicy) -> crate::Result<Option<usize>> {
+    let mut input = fs::File::open(path)?;
+    // An empty file still gets a part, which decompresses to it
+    let count = fs::metadata(path)?.len().div_ceil(part_size).max(1) as usize;
+
+    // A part left by an earlier split would be joined with the new ones
+    if !utils::clear_path(&utils::part_path(path, count + 1), question_policy)? {
+        return Ok(None);
+    }
+
+    for number in 1..=count {
+        let part_path = utils::part_path(path, number);
+        let Some(mut part) = utils::ask_to_create_file(&part_path, question_policy)? else {
+            return Ok(None);
+        };
+        io::copy(&mut (&mut input).take(part_size), &mut part)?;
+    }
+
+    Ok(Some(count))
+}
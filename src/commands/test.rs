@@ -0,0 +1,95 @@
+//! Implementation of `ouch test`, which checks archives and compressed files without extracting
+//! them.
+
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    archive::nested, commands::list::archive_entries, error::FinalError, extension::CompressionFormat, info,
+    list::ContentOptions, utils::EscapedPathDisplay, QuestionPolicy, BUFFER_CAPACITY,
+};
+
+/// Tests each of `files`, whose `formats` are in decompression order, reporting the errors of
+/// each one, fails if any of them did.
+pub fn test_files(
+    files: &[PathBuf],
+    formats: &[Vec<CompressionFormat>],
+    recursive: bool,
+    question_policy: QuestionPolicy,
+) -> crate::Result<()> {
+    if let ([path], [formats]) = (files, formats) {
+        return test_file(path, formats, recursive, question_policy);
+    }
+
+    let mut corrupted = vec![];
+    for (path, formats) in files.iter().zip(formats) {
+        if let Err(err) = test_file(path, formats, recursive, question_policy) {
+            eprintln!("{err}");
+            corrupted.push(path);
+        }
+    }
+    if corrupted.is_empty() {
+        return Ok(());
+    }
+
+    let mut error = FinalError::with_title(format!("{} of {} files failed the test", corrupted.len(), files.len()));
+    for path in corrupted {
+        error = error.detail(format!("'{}'", EscapedPathDisplay::new(path)));
+    }
+    Err(error.into())
+}
+
+/// Reads the whole archive or compressed file at `path`, whose `formats` are in decompression
+/// order, so that every checksum is checked, fails at the first error found.
+///
+/// With `recursive`, the archives and compressed files inside of the archive are tested too, as
+/// they're read.
+pub fn test_file(
+    path: &Path,
+    formats: &[CompressionFormat],
+    recursive: bool,
+    question_policy: QuestionPolicy,
+) -> crate::Result<()> {
+    if !formats[0].is_archive_format() {
+        let mut reader = BufReader::with_capacity(BUFFER_CAPACITY, fs::File::open(path)?);
+        nested::test_formats(formats, &mut reader).map_err(|err| failed(path, err.into(), None))?;
+        info!(accessible, "'{}' is OK.", EscapedPathDisplay::new(path));
+        return Ok(());
+    }
+
+    let contents = ContentOptions {
+        verify: true,
+        recursive,
+        ..Default::default()
+    };
+    let Some(files) = archive_entries(path, formats, contents, question_policy)? else {
+        return Ok(());
+    };
+    let mut count = 0;
+    for file in files {
+        file.map_err(|err| failed(path, err, Some(count)))?;
+        count += 1;
+    }
+    info!(
+        accessible,
+        "'{}' is OK, its {count} entries were tested.",
+        EscapedPathDisplay::new(path)
+    );
+    Ok(())
+}
+
+/// `err`, saying it happened while testing `path`, after `tested` entries of it.
+fn failed(path: &Path, err: crate::Error, tested: Option<usize>) -> crate::Error {
+    let error = err
+        .to_final_error()
+        .detail(format!("While testing '{}'", EscapedPathDisplay::new(path)));
+    match tested {
+        Some(tested) => error.detail(format!("{tested} entries were tested before the error")),
+        None => error,
+    }
+    .into()
+}
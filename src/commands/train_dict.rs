@@ -0,0 +1,56 @@
+//! Implementation of `ouch train-dict`, which builds a zstd dictionary out of sample files, for
+//! `compress --dict`.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    error::FinalError,
+    utils::{self, Bytes, FileVisibilityPolicy},
+    QuestionPolicy,
+};
+
+/// Trains a dictionary of at most `max_size` bytes on the files at `samples` and inside of the
+/// directories among them, and writes it to `output_path`.
+///
+/// Returns the size of the dictionary and the number of samples, or `None` if the user didn't want
+/// to overwrite `output_path`.
+pub fn train_dictionary(
+    samples: &[PathBuf],
+    output_path: &Path,
+    max_size: u64,
+    file_visibility_policy: FileVisibilityPolicy,
+    question_policy: QuestionPolicy,
+) -> crate::Result<Option<(usize, usize)>> {
+    let mut contents = vec![];
+    for path in samples {
+        for entry in file_visibility_policy.build_walker(path) {
+            let path = entry?.into_path();
+            if path.is_file() {
+                contents.push(fs::read(path)?);
+            }
+        }
+    }
+
+    // zstd is built without its error messages, the samples tell more
+    let dictionary = zstd::dict::from_samples(&contents, max_size as usize).map_err(|_| {
+        let total_size: usize = contents.iter().map(Vec::len).sum();
+        FinalError::with_title("Cannot train a zstd dictionary")
+            .detail(format!(
+                "There are {} samples of {} in total",
+                contents.len(),
+                Bytes::new(total_size as u64)
+            ))
+            .hint("Give more samples, at least a few hundred small files, or a smaller '--max-size'")
+    })?;
+
+    let Some(mut output) = utils::ask_to_create_file(output_path, question_policy)? else {
+        return Ok(None);
+    };
+    output.write_all(&dictionary)?;
+    Ok(Some((dictionary.len(), contents.len())))
+}
@@ -0,0 +1,156 @@
+//! Recording of the paths created by an extraction, for `--manifest-out` and for `ouch undo`,
+//! which removes them.
+
+use std::path::{self, Path, PathBuf};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+use crate::{
+    error::FinalError,
+    info,
+    utils::{self, Confirmation, EscapedPathDisplay, QuestionKind},
+    warning, QuestionPolicy,
+};
+
+/// Lists every file and directory inside of the `roots` created by an extraction, including
+/// themselves, parents come before their children.
+pub fn list_created_paths(roots: &[PathBuf]) -> crate::Result<Vec<PathBuf>> {
+    fn visit(path: PathBuf, paths: &mut Vec<PathBuf>) -> crate::Result<()> {
+        // Don't follow symlinks, they are removed but not their targets
+        let is_dir = fs::symlink_metadata(&path)?.is_dir();
+        paths.push(path.clone());
+
+        if is_dir {
+            for entry in fs::read_dir(&path)? {
+                visit(entry?.path(), paths)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut paths = vec![];
+    for root in roots {
+        visit(path::absolute(root)?, &mut paths)?;
+    }
+    Ok(paths)
+}
+
+/// Where the record of the last extraction is kept, in the state directory of the XDG base
+/// directory specification
+pub fn record_path() -> crate::Result<PathBuf> {
+    let state_dir = utils::state_dir().ok_or_else(|| {
+        FinalError::with_title("Could not find where to record the extraction")
+            .detail("None of XDG_STATE_HOME, HOME and LOCALAPPDATA are set")
+    })?;
+
+    Ok(state_dir.join("last-extraction"))
+}
+
+/// Joins `paths`, each followed by `delimiter`.
+fn join_paths(paths: &[PathBuf], delimiter: u8) -> Vec<u8> {
+    let mut joined = Vec::new();
+    for path in paths {
+        joined.extend_from_slice(<[u8]>::from_path(path).expect("paths are valid UTF-8 off Unix"));
+        joined.push(delimiter);
+    }
+    joined
+}
+
+/// Writes `created_paths` to `manifest_path`, one per line, or null-delimited if `null_delimited`.
+pub fn write_manifest(manifest_path: &Path, created_paths: &[PathBuf], null_delimited: bool) -> crate::Result<()> {
+    let delimiter = if null_delimited { b'\0' } else { b'\n' };
+    fs::write(manifest_path, join_paths(created_paths, delimiter))?;
+
+    info!(
+        accessible,
+        "Wrote the {} created paths to '{}'.",
+        created_paths.len(),
+        EscapedPathDisplay::new(manifest_path)
+    );
+    Ok(())
+}
+
+/// Records `created_paths`, replacing the previous record, so `ouch undo` can remove them.
+pub fn record_extraction(created_paths: &[PathBuf]) -> crate::Result<()> {
+    let record_path = record_path()?;
+    if let Some(parent) = record_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Null-delimited, paths can contain newlines
+    fs::write(&record_path, join_paths(created_paths, b'\0'))?;
+
+    info!(
+        accessible,
+        "Recorded {} created paths, use 'ouch undo' to remove them.",
+        created_paths.len()
+    );
+    Ok(())
+}
+
+/// Removes the paths created by the last extraction recorded with `--record`.
+///
+/// Directories that aren't empty after that, because other files were put in them since the
+/// extraction, are kept.
+pub fn undo_last_extraction(question_policy: QuestionPolicy) -> crate::Result<()> {
+    let record_path = record_path()?;
+    let record = match fs::read(&record_path) {
+        Ok(record) => record,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let error = FinalError::with_title("Nothing to undo")
+                .detail("No extraction was recorded")
+                .hint("Use 'ouch decompress --record' to record the paths created by an extraction.");
+            return Err(error.into());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let created_paths: Vec<PathBuf> = record
+        .split_str("\0")
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_path().map(Path::to_path_buf))
+        .collect::<Result<_, _>>()
+        .map_err(|_| FinalError::with_title("The record of the last extraction is corrupted"))?;
+
+    let wants_to_undo = match question_policy {
+        QuestionPolicy::AlwaysYes => true,
+        QuestionPolicy::AlwaysNo => false,
+        QuestionPolicy::Ask => {
+            let count = created_paths.len().to_string();
+            Confirmation::new(
+                "Do you want to remove the COUNT paths created by the last extraction?",
+                Some("COUNT"),
+            )
+            .ask(Some(&count), QuestionKind::Undo)?
+        }
+    };
+    if !wants_to_undo {
+        return Ok(());
+    }
+
+    // Children before their parents
+    let mut removed = 0;
+    for path in created_paths.iter().rev() {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            // Already removed
+            continue;
+        };
+
+        let result = if metadata.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match result {
+            Ok(()) => removed += 1,
+            Err(err) => warning!("Could not remove '{}': {err}", EscapedPathDisplay::new(path)),
+        }
+    }
+
+    fs::remove_file(&record_path)?;
+    info!(accessible, "Removed {removed} paths created by the last extraction.");
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! `--upload`, which sends the new archive to a URL with an HTTP request, so that publishing it
+//! doesn't need another tool.
+
+use std::path::Path;
+
+use crate::{cli::UploadMethod, error::FinalError};
+
+/// Environment variable whose value is sent as the Authorization header, out of the command line
+/// that other users can see
+#[cfg(feature = "upload")]
+const AUTHORIZATION_VAR: &str = "OUCH_UPLOAD_AUTHORIZATION";
+
+/// Fails if this build can't upload, before anything is compressed.
+pub fn check_supported() -> crate::Result<()> {
+    if cfg!(not(feature = "upload")) {
+        let error = FinalError::with_title("Cannot use '--upload'")
+            .detail("Uploads are disabled for this build")
+            .hint("Build ouch with the 'upload' feature");
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Uploads the archive at `archive_path` to `url` with a `method` request, streaming it, the
+/// archive is kept either way.
+#[cfg(feature = "upload")]
+pub fn upload_archive(archive_path: &Path, url: &str, method: UploadMethod) -> crate::Result<()> {
+    use fs_err as fs;
+
+    use crate::{info, utils::EscapedPathDisplay};
+
+    let path = EscapedPathDisplay::new(archive_path);
+    let failed = |detail: String| {
+        FinalError::with_title(format!("Could not upload '{path}' to {url}"))
+            .detail(detail)
+            .detail(format!("The archive '{path}' was kept"))
+    };
+
+    let file = fs::File::open(archive_path)?;
+    let size = file.metadata()?.len();
+    let method = match method {
+        UploadMethod::Put => "PUT",
+        UploadMethod::Post => "POST",
+    };
+    let mut request = ureq::request(method, url)
+        .set("Content-Type", "application/octet-stream")
+        .set("Content-Length", &size.to_string());
+    if let Ok(authorization) = std::env::var(AUTHORIZATION_VAR) {
+        request = request.set("Authorization", &authorization);
+    }
+
+    info!(accessible, "Uploading '{path}' to {url}.");
+    match request.send(file) {
+        Ok(response) => {
+            info!(
+                accessible,
+                "Uploaded '{path}', the server answered {} {}.",
+                response.status(),
+                response.status_text()
+            );
+            Ok(())
+        }
+        Err(ureq::Error::Status(code, response)) => {
+            Err(failed(format!("The server answered {code} {}", response.status_text())).into())
+        }
+        Err(err) => Err(failed(err.to_string()).into()),
+    }
+}
+
+#[cfg(not(feature = "upload"))]
+pub fn upload_archive(_archive_path: &Path, _url: &str, _method: UploadMethod) -> crate::Result<()> {
+    unreachable!("'--upload' was refused by `check_supported`")
+}
@@ -0,0 +1,317 @@
+//! The configuration file, `$XDG_CONFIG_HOME/ouch/config.toml`.
+//!
+//! It holds named profiles, each bundling options that are applied with `--profile NAME`,
+//! pipelines of commands run on the archive after compressing it with `--pipeline NAME`, the
+//! compression levels of formats when no level is given, and aliases of commands:
+//!
+//! ```toml
+//! [levels]
+//! zstd = 15
+//! xz = 6
+//!
+//! [alias]
+//! backup = "compress --profile backup ~/docs"
+//!
+//! [profile.backup]
+//! format = "tar.zst"
+//! level = 15
+//!
+//! [pipeline.release]
+//! profile = "backup"
+//! steps = ["gpg --encrypt --recipient team \"$OUCH_ARCHIVE\"", "sha256sum \"$OUCH_ARCHIVE.gpg\""]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::Deserialize;
+
+use crate::{
+    cli::{CliArgs, Subcommand},
+    error::FinalError,
+    extension::CompressionFormat,
+    utils::EscapedPathDisplay,
+};
+
+/// The contents of the configuration file
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub pipeline: BTreeMap<String, Pipeline>,
+    /// Compression levels of formats, by name, like "zstd" or "gzip"
+    #[serde(default)]
+    pub levels: BTreeMap<String, i16>,
+    #[serde(default)]
+    pub alias: BTreeMap<String, Alias>,
+}
+
+/// Arguments given instead of a subcommand, like `ouch backup`, split on whitespace or given one
+/// by one for arguments with spaces, like Cargo's aliases
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Alias {
+    Line(String),
+    Words(Vec<String>),
+}
+
+/// Options bundled under a name, options given in the command line take precedence
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub format: Option<String>,
+    pub level: Option<i16>,
+    pub fast: Option<bool>,
+    pub slow: Option<bool>,
+    pub hidden: Option<bool>,
+    pub gitignore: Option<bool>,
+    pub threads: Option<usize>,
+}
+
+/// Commands run one after the other on a new archive, whose path is in `$OUCH_ARCHIVE`
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    /// Profile applied to the compression
+    pub profile: Option<String>,
+    /// Shell commands
+    #[serde(default)]
+    pub steps: Vec<String>,
+}
+
+impl Config {
+    /// Where the configuration file is, following the XDG base directory specification
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .map(|config_dir| config_dir.join("ouch").join("config.toml"))
+    }
+
+    /// Reads the configuration file, which is empty if it doesn't exist
+    pub fn load() -> crate::Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+
+        Self::parse(&fs::read_to_string(&path)?).map_err(|err| {
+            FinalError::with_title(format!(
+                "Invalid configuration file '{}'",
+                EscapedPathDisplay::new(&path)
+            ))
+            .detail(err.message().to_owned())
+            .into()
+        })
+    }
+
+    fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// The profile called `name`
+    pub fn profile(&self, name: &str) -> crate::Result<&Profile> {
+        self.profile
+            .get(name)
+            .ok_or_else(|| unknown_name_error("profile", name, self.profile.keys()))
+    }
+
+    /// The pipeline called `name`
+    pub fn pipeline(&self, name: &str) -> crate::Result<&Pipeline> {
+        self.pipeline
+            .get(name)
+            .ok_or_else(|| unknown_name_error("pipeline", name, self.pipeline.keys()))
+    }
+
+    /// The arguments the alias `name` stands for, `~` at their start is the home directory
+    pub fn alias(&self, name: &str) -> Option<Vec<OsString>> {
+        let words: Vec<&str> = match self.alias.get(name)? {
+            Alias::Line(line) => line.split_whitespace().collect(),
+            Alias::Words(words) => words.iter().map(String::as_str).collect(),
+        };
+        let home = env::var_os("HOME");
+
+        let expand = |word: &str| match (word.strip_prefix('~'), &home) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+                let mut expanded = home.clone();
+                expanded.push(rest);
+                expanded
+            }
+            _ => OsString::from(word),
+        };
+        Some(words.into_iter().map(expand).collect())
+    }
+
+    /// The compression levels of the `[levels]` table, which are used without `--level`
+    pub fn levels(&self) -> crate::Result<Vec<(CompressionFormat, i16)>> {
+        self.levels
+            .iter()
+            .map(|(name, &level)| match CompressionFormat::compression_from_name(name) {
+                Some(format) => Ok((format, level)),
+                None => {
+                    let mut error =
+                        FinalError::with_title(format!("Unknown compression format '{name}' in '[levels]'"))
+                            .detail("Levels are given to compression formats, like 'zstd', 'xz' or 'gzip'");
+                    if let Some(path) = Config::path() {
+                        error = error.hint(format!("They are defined in '{}'", EscapedPathDisplay::new(&path)));
+                    }
+                    Err(error.into())
+                }
+            })
+            .collect()
+    }
+}
+
+/// Error for a profile or pipeline that isn't in the configuration file
+fn unknown_name_error<'a>(kind: &str, name: &str, names: impl Iterator<Item = &'a String>) -> crate::Error {
+    let names: Vec<&str> = names.map(String::as_str).collect();
+
+    let mut error = FinalError::with_title(format!("Unknown {kind} '{name}'"));
+    error = if names.is_empty() {
+        error.detail(format!("No {kind}s are defined in the configuration file"))
+    } else {
+        error.detail(format!("Available {kind}s: {}", names.join(", ")))
+    };
+    if let Some(path) = Config::path() {
+        error = error.hint(format!("They are defined in '{}'", EscapedPathDisplay::new(&path)));
+    }
+    error.into()
+}
+
+impl Profile {
+    /// Fills the options of `args` that weren't given in the command line
+    pub fn apply(&self, args: &mut CliArgs) {
+        if args.format.is_none() {
+            args.format = self.format.as_ref().map(OsString::from);
+        }
+        args.hidden |= self.hidden.unwrap_or(false);
+        args.gitignore |= self.gitignore.unwrap_or(false);
+        if args.threads == 0 {
+            args.threads = self.threads.unwrap_or(0);
+        }
+
+        if let Subcommand::Compress { level, fast, slow, .. } = &mut args.cmd {
+            // The compression level flags conflict with each other
+            if level.is_none() && !*fast && !*slow {
+                *level = self.level;
+                *fast = self.fast.unwrap_or(false);
+                *slow = self.slow.unwrap_or(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = Config::parse(
+            r#"
+            [profile.backup]
+            format = "tar.zst"
+            level = 15
+            threads = 0
+
+            [profile.quick]
+            fast = true
+            gitignore = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.profile["backup"],
+            Profile {
+                format: Some("tar.zst".into()),
+                level: Some(15),
+                threads: Some(0),
+                ..Profile::default()
+            }
+        );
+        assert_eq!(
+            config.profile["quick"],
+            Profile {
+                fast: Some(true),
+                gitignore: Some(true),
+                ..Profile::default()
+            }
+        );
+
+        assert!(Config::parse("[profile.backup]\nunknown = 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let config = Config::parse(
+            r#"
+            [alias]
+            backup = "compress --profile backup  docs"
+            spaced = ["compress", "my docs", "docs.zip"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.alias("backup").unwrap(),
+            ["compress", "--profile", "backup", "docs"]
+        );
+        assert_eq!(config.alias("spaced").unwrap(), ["compress", "my docs", "docs.zip"]);
+        assert_eq!(config.alias("missing"), None);
+    }
+
+    #[test]
+    fn test_parse_pipelines() {
+        let config = Config::parse(
+            r#"
+            [pipeline.release]
+            profile = "backup"
+            steps = ["sha256sum \"$OUCH_ARCHIVE\"", "./upload.sh"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.pipeline["release"],
+            Pipeline {
+                profile: Some("backup".into()),
+                steps: vec!["sha256sum \"$OUCH_ARCHIVE\"".into(), "./upload.sh".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_levels() {
+        let config = Config::parse(
+            r#"
+            [levels]
+            zstd = 15
+            xz = 6
+            gz = 9
+            "#,
+        )
+        .unwrap();
+
+        let levels = config.levels().unwrap();
+        assert_eq!(
+            levels,
+            [
+                (CompressionFormat::Gzip, 9),
+                (CompressionFormat::Lzma, 6),
+                (CompressionFormat::Zstd, 15)
+            ]
+        );
+
+        assert!(Config::parse("[levels]\ntar = 1").unwrap().levels().is_err());
+        assert!(Config::parse("[levels]\nunknown = 1").unwrap().levels().is_err());
+    }
+}
@@ -7,7 +7,7 @@ use std::{
     fmt::{self, Display},
 };
 
-use crate::{accessible::is_running_in_accessible_mode, utils::colors::*};
+use crate::{accessible::is_running_in_accessible_mode, archive::cancel::Cancelled, utils::colors::*};
 
 /// All errors that can be generated by `ouch`
 #[derive(Debug)]
@@ -39,6 +39,9 @@ pub enum Error {
     /// Recognised but unsupported format
     // currently only RAR when built without the `unrar` feature
     UnsupportedFormat { reason: String },
+    /// An extraction was stopped through its `CancellationToken`, after extracting `extracted`
+    /// entries of the archive, if it was one
+    Cancelled { extracted: Option<usize> },
 }
 
 /// Alias to std's Result with ouch's Error
@@ -122,9 +125,10 @@ impl FinalError {
     }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let err = match self {
+impl Error {
+    /// The message shown to the user for this error
+    pub fn to_final_error(&self) -> FinalError {
+        match self {
             Error::WalkdirError { reason } => FinalError::with_title(reason.to_string()),
             Error::NotFound { error_title } => FinalError::with_title(error_title.to_string()).detail("File not found"),
             Error::CompressingRootFolder => {
@@ -148,14 +152,41 @@ impl fmt::Display for Error {
             Error::UnsupportedFormat { reason } => {
                 FinalError::with_title("Recognised but unsupported format").detail(reason.clone())
             }
-        };
+            Error::Cancelled { extracted } => {
+                let error = FinalError::with_title("Extraction cancelled");
+                match extracted {
+                    Some(extracted) => {
+                        error.detail(format!("{extracted} entries were extracted before the cancellation"))
+                    }
+                    None => error,
+                }
+            }
+        }
+    }
+}
 
-        write!(f, "{err}")
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_final_error())
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
+        // Libraries like `tar` wrap the errors of the readers they're given into their own
+        let mut source = err.get_ref().map(|inner| inner as &(dyn std::error::Error + 'static));
+        while let Some(inner) = source {
+            if inner.is::<Cancelled>() {
+                return Self::Cancelled { extracted: None };
+            }
+            // The source of an `io::Error` skips the error it wraps
+            source = match inner.downcast_ref::<std::io::Error>() {
+                Some(io_err) => io_err
+                    .get_ref()
+                    .map(|inner| inner as &(dyn std::error::Error + 'static)),
+                None => inner.source(),
+            };
+        }
         match err.kind() {
             std::io::ErrorKind::NotFound => Self::NotFound {
                 error_title: err.to_string(),
@@ -183,6 +214,9 @@ impl From<zip::result::ZipError> for Error {
                 reason: FinalError::with_title("Unexpected error in zip archive").detail("File not found"),
             },
             ZipError::UnsupportedArchive(filename) => Self::UnsupportedZipArchive(filename),
+            err => Self::Custom {
+                reason: FinalError::with_title("Unexpected error in zip archive").detail(err.to_string()),
+            },
         }
     }
 }
@@ -7,30 +7,18 @@ use bstr::ByteSlice;
 use self::CompressionFormat::*;
 use crate::{error::Error, warning};
 
-pub const SUPPORTED_EXTENSIONS: &[&str] = &[
-    "tar",
-    "zip",
-    "bz",
-    "bz2",
-    "gz",
-    "lz4",
-    "xz",
-    "lzma",
-    "sz",
-    "zst",
-    #[cfg(feature = "unrar")]
-    "rar",
-    "7z",
-];
-
-pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tlz4", "txz", "tzlma", "tsz", "tzst"];
+// Shared with the CLI definitions, which complete `--format` with them
+include!("extension/supported.rs");
 
-#[cfg(not(feature = "unrar"))]
-pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, gz, lz4, xz, lzma, sz, zst, 7z";
-#[cfg(feature = "unrar")]
-pub const PRETTY_SUPPORTED_EXTENSIONS: &str = "tar, zip, bz, bz2, gz, lz4, xz, lzma, sz, zst, rar, 7z";
+/// The supported extensions, like "tar, zip, bz", for messages
+pub fn pretty_supported_extensions() -> String {
+    SUPPORTED_EXTENSIONS.join(", ")
+}
 
-pub const PRETTY_SUPPORTED_ALIASES: &str = "tgz, tbz, tlz4, txz, tzlma, tsz, tzst";
+/// The supported aliases, like "tgz, tbz", for messages
+pub fn pretty_supported_aliases() -> String {
+    SUPPORTED_ALIASES.join(", ")
+}
 
 /// A wrapper around `CompressionFormat` that allows combinations like `tgz`
 #[derive(Debug, Clone, Eq)]
@@ -86,7 +74,15 @@ pub enum CompressionFormat {
     Lzma,
     /// .sz
     Snappy,
-    /// tar, tgz, tbz, tbz2, txz, tlz4, tlzma, tsz, tzst
+    /// .br
+    Brotli,
+    /// .lz
+    Lzip,
+    /// .Z, from the Unix `compress` tool, can only be decompressed
+    Lzw,
+    /// .lzo
+    Lzop,
+    /// tar, tgz, tbz, tbz2, txz, tlz4, tlzma, tsz, tlz, taz, tzo, tzst
     Tar,
     /// .zst
     Zstd,
@@ -97,47 +93,138 @@ pub enum CompressionFormat {
     Rar,
     /// .7z
     SevenZip,
+    /// .cpio
+    Cpio,
+    /// .deb, Debian packages, can only be extracted
+    Deb,
+    /// .rpm, RPM packages, can only be extracted
+    Rpm,
+    /// .a and .ar, like static libraries, can only be extracted
+    Ar,
+    /// .iso, ISO 9660 images, can only be extracted
+    Iso,
+    /// .cab, Microsoft cabinets, can only be extracted
+    Cab,
+    /// .squashfs and .sqsh, SquashFS images, can only be extracted
+    Squashfs,
+}
+
+impl fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Gzip => "gzip",
+            Bzip => "bzip2",
+            Lz4 => "lz4",
+            Lzma => "xz",
+            Snappy => "snappy",
+            Brotli => "brotli",
+            Lzip => "lzip",
+            Lzw => "compress",
+            Lzop => "lzop",
+            Tar => "tar",
+            Zstd => "zstd",
+            Zip => "zip",
+            Rar => "rar",
+            SevenZip => "7z",
+            Cpio => "cpio",
+            Deb => "deb",
+            Rpm => "rpm",
+            Ar => "ar",
+            Iso => "iso",
+            Cab => "cab",
+            Squashfs => "squashfs",
+        };
+        f.write_str(name)
+    }
 }
 
 impl CompressionFormat {
+    /// Whether files of this format start with a signature, brotli streams don't
+    pub fn has_signature(&self) -> bool {
+        !matches!(self, Brotli)
+    }
+
+    /// The compression format called `name`, like "zstd", or with the extension `name`, like "zst"
+    pub fn compression_from_name(name: &str) -> Option<Self> {
+        let format = match name {
+            "gzip" => Gzip,
+            "bzip2" => Bzip,
+            "brotli" => Brotli,
+            "lzip" => Lzip,
+            "lzop" => Lzop,
+            "snappy" => Snappy,
+            "zstd" => Zstd,
+            _ => match to_extension(name.as_bytes())?.compression_formats {
+                [format] => *format,
+                _ => return None,
+            },
+        };
+        (!format.is_archive_format()).then_some(format)
+    }
+
     /// Currently supported archive formats are .tar (and aliases to it) and .zip
-    fn is_archive_format(&self) -> bool {
+    pub fn is_archive_format(&self) -> bool {
         // Keep this match like that without a wildcard `_` so we don't forget to update it
         match self {
-            Tar | Zip | Rar | SevenZip => true,
+            Tar | Zip | Rar | SevenZip | Cpio | Deb | Rpm | Ar | Iso | Cab | Squashfs => true,
             Gzip => false,
             Bzip => false,
             Lz4 => false,
             Lzma => false,
             Snappy => false,
+            Brotli => false,
+            Lzip => false,
+            Lzw => false,
+            Lzop => false,
             Zstd => false,
         }
     }
 }
 
+/// Every extension and alias with the formats it stands for, the completions of `--format` in
+/// `supported.rs` are checked against it
+const EXTENSION_FORMATS: &[(&str, &[CompressionFormat])] = &[
+    ("tar", &[Tar]),
+    ("tgz", &[Tar, Gzip]),
+    ("tbz", &[Tar, Bzip]),
+    ("tbz2", &[Tar, Bzip]),
+    ("tlz4", &[Tar, Lz4]),
+    ("txz", &[Tar, Lzma]),
+    ("tlzma", &[Tar, Lzma]),
+    ("tsz", &[Tar, Snappy]),
+    ("tlz", &[Tar, Lzip]),
+    ("taz", &[Tar, Lzw]),
+    ("tzo", &[Tar, Lzop]),
+    ("tzst", &[Tar, Zstd]),
+    ("zip", &[Zip]),
+    ("bz", &[Bzip]),
+    ("bz2", &[Bzip]),
+    ("gz", &[Gzip]),
+    ("lz4", &[Lz4]),
+    ("xz", &[Lzma]),
+    ("lzma", &[Lzma]),
+    ("sz", &[Snappy]),
+    ("br", &[Brotli]),
+    ("lz", &[Lzip]),
+    ("Z", &[Lzw]),
+    ("lzo", &[Lzop]),
+    ("zst", &[Zstd]),
+    ("rar", &[Rar]),
+    ("7z", &[SevenZip]),
+    ("cpio", &[Cpio]),
+    ("deb", &[Deb]),
+    ("rpm", &[Rpm]),
+    ("a", &[Ar]),
+    ("ar", &[Ar]),
+    ("iso", &[Iso]),
+    ("cab", &[Cab]),
+    ("squashfs", &[Squashfs]),
+    ("sqsh", &[Squashfs]),
+];
+
 fn to_extension(ext: &[u8]) -> Option<Extension> {
-    Some(Extension::new(
-        match ext {
-            b"tar" => &[Tar],
-            b"tgz" => &[Tar, Gzip],
-            b"tbz" | b"tbz2" => &[Tar, Bzip],
-            b"tlz4" => &[Tar, Lz4],
-            b"txz" | b"tlzma" => &[Tar, Lzma],
-            b"tsz" => &[Tar, Snappy],
-            b"tzst" => &[Tar, Zstd],
-            b"zip" => &[Zip],
-            b"bz" | b"bz2" => &[Bzip],
-            b"gz" => &[Gzip],
-            b"lz4" => &[Lz4],
-            b"xz" | b"lzma" => &[Lzma],
-            b"sz" => &[Snappy],
-            b"zst" => &[Zstd],
-            b"rar" => &[Rar],
-            b"7z" => &[SevenZip],
-            _ => return None,
-        },
-        ext.to_str_lossy(),
-    ))
+    let (_, formats) = EXTENSION_FORMATS.iter().find(|(name, _)| name.as_bytes() == ext)?;
+    Some(Extension::new(formats, ext.to_str_lossy()))
 }
 
 fn split_extension(name: &mut &[u8]) -> Option<Extension> {
@@ -183,7 +270,9 @@ pub fn separate_known_extensions_from_name(path: &Path) -> (&Path, Vec<Extension
 
     if let Ok(name) = name.to_str() {
         let file_stem = name.trim_matches('.');
-        if SUPPORTED_EXTENSIONS.contains(&file_stem) || SUPPORTED_ALIASES.contains(&file_stem) {
+        // A file named "a" is more likely named so than an ar archive that lost its name
+        let is_extension = file_stem != "a" && SUPPORTED_EXTENSIONS.contains(&file_stem);
+        if is_extension || SUPPORTED_ALIASES.contains(&file_stem) {
             warning!("Received a file with name '{file_stem}', but {file_stem} was expected as the extension.");
         }
     }
@@ -262,6 +351,25 @@ mod tests {
         assert_eq!(formats, vec![Tar, Gzip]);
     }
 
+    #[test]
+    fn test_supported_names_are_recognized() {
+        // Formats left out of this build are still recognized, to tell that they aren't supported
+        let left_out = [
+            (!cfg!(feature = "unrar")).then_some("rar"),
+            (!cfg!(feature = "cab")).then_some("cab"),
+        ];
+        let recognized = |alias: bool| {
+            EXTENSION_FORMATS
+                .iter()
+                .filter(|(name, formats)| (formats.len() > 1) == alias && !left_out.contains(&Some(*name)))
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(SUPPORTED_EXTENSIONS, recognized(false));
+        assert_eq!(SUPPORTED_ALIASES, recognized(true));
+    }
+
     #[test]
     fn builds_suggestion_correctly() {
         assert_eq!(build_archive_file_suggestion(Path::new("linux.png"), ".tar"), None);
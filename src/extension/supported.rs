@@ -0,0 +1,34 @@
+// The names of the supported extensions and aliases, included by `extension.rs` and `cli/args.rs`,
+// a test checks that they are the ones `to_extension` recognizes
+
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "tar",
+    "zip",
+    "bz",
+    "bz2",
+    "gz",
+    "lz4",
+    "xz",
+    "lzma",
+    "sz",
+    "br",
+    "lz",
+    "Z",
+    "lzo",
+    "zst",
+    #[cfg(feature = "unrar")]
+    "rar",
+    "7z",
+    "cpio",
+    "deb",
+    "rpm",
+    "a",
+    "ar",
+    "iso",
+    #[cfg(feature = "cab")]
+    "cab",
+    "squashfs",
+    "sqsh",
+];
+
+pub const SUPPORTED_ALIASES: &[&str] = &["tgz", "tbz", "tbz2", "tlz4", "txz", "tlzma", "tsz", "tlz", "taz", "tzo", "tzst"];
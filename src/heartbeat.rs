@@ -0,0 +1,72 @@
+//! `--heartbeat`, which tells that ouch is still working when nothing was printed for a while.
+//!
+//! Screen readers and people watching logs can't see a progress bar, and some operations, like
+//! compressing a single large file, don't print anything until they finish, so without it a slow
+//! operation can't be told apart from a hung one.
+//!
+//! How much was processed is the size of the inputs read so far, counted for the progress display,
+//! see [`progress::Tracked`].
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{info, progress, utils::Bytes};
+
+/// Longest time between two checks for the last output
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the process started, the other times are stored relative to it
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Milliseconds after `START` at which something was last printed
+static LAST_OUTPUT: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a question is waiting for an answer, which isn't the time to interrupt it
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Prints a heartbeat message whenever nothing was printed for `interval`, until the process exits.
+pub fn start(interval: Duration) {
+    output_printed();
+
+    thread::spawn(move || loop {
+        thread::sleep(interval.min(CHECK_INTERVAL));
+
+        if !PAUSED.load(Ordering::Relaxed) && since_last_output() >= interval {
+            let processed = Bytes::new(progress::total_input_read());
+            info!(accessible, "Still working: {processed} processed.");
+            // Silenced messages don't count as output
+            output_printed();
+        }
+    });
+}
+
+/// Records that something was just printed, delaying the next heartbeat.
+pub fn output_printed() {
+    let now = START.elapsed().as_millis() as u64;
+    LAST_OUTPUT.store(now, Ordering::Relaxed);
+}
+
+fn since_last_output() -> Duration {
+    let last_output = Duration::from_millis(LAST_OUTPUT.load(Ordering::Relaxed));
+    START.elapsed().saturating_sub(last_output)
+}
+
+/// Stops heartbeats until the guard is dropped.
+pub fn pause() -> PauseGuard {
+    PAUSED.store(true, Ordering::Relaxed);
+    PauseGuard
+}
+
+pub struct PauseGuard;
+
+impl Drop for PauseGuard {
+    fn drop(&mut self) {
+        output_printed();
+        PAUSED.store(false, Ordering::Relaxed);
+    }
+}
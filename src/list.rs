@@ -1,20 +1,76 @@
 //! Some implementation helpers related to the 'list' command.
 
 use std::{
-    io::{stdout, Write},
+    borrow::Cow,
+    collections::VecDeque,
+    env,
+    io::{self, stdout, Read, Write},
     path::{Path, PathBuf},
 };
 
+use fs_err as fs;
+use once_cell::sync::Lazy;
+
 use self::tree::Tree;
-use crate::{accessible::is_running_in_accessible_mode, utils::EscapedPathDisplay};
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    archive::{filter::EntryFilter, nested},
+    cli::EntryKind,
+    utils::{Bytes, EscapedPathDisplay},
+};
 
 /// Options controlling how archive contents should be listed
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ListOptions {
     /// Whether to show a tree view
     pub tree: bool,
+    /// Only list the first entries
+    pub head: Option<usize>,
+    /// Only list the last entries
+    pub tail: Option<usize>,
+    /// Only print how many entries there are and their total size
+    pub summary: bool,
+    /// Only print the paths of the entries, each followed by a null byte
+    pub null: bool,
+    /// Only list the entries it keeps
+    pub filter: EntryFilter,
+    /// What to read out of the contents of the files
+    pub contents: ContentOptions,
+}
+
+/// What to read out of the contents of the files being listed, which are otherwise skipped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContentOptions {
+    /// Show the first lines of small text files
+    pub preview: Option<usize>,
+    /// Show the type of the files, sniffed from their first bytes
+    pub detect_types: bool,
+    /// Read the files to their end, so that their checksums are checked, see `ouch test`
+    pub verify: bool,
+    /// Also test the archives and compressed files found inside, when verifying
+    pub recursive: bool,
+}
+
+impl ContentOptions {
+    /// Whether the contents of the files are read at all.
+    pub fn is_needed(&self) -> bool {
+        self.preview.is_some() || self.detect_types || self.verify
+    }
 }
 
+/// How many entries the threads reading archives may list ahead of the ones printed, so that
+/// huge archives are listed in bounded memory
+pub const LISTED_AHEAD: usize = 1024;
+
+/// Largest files whose first lines are shown by `--preview`
+pub const PREVIEW_MAX_SIZE: u64 = 64 * 1024;
+
+/// Longest preview lines, longer ones are cut
+const PREVIEW_LINE_WIDTH: usize = 100;
+
+/// How much of the files is read to detect their types
+const SNIFF_SIZE: u64 = 8 * 1024;
+
 /// Represents a single file in an archive, used in `list::list_files()`
 #[derive(Debug, Clone)]
 pub struct FileInArchive {
@@ -23,9 +79,113 @@ pub struct FileInArchive {
 
     /// Whether this file is a directory
     pub is_dir: bool,
+
+    /// Whether this file is a symlink
+    pub is_symlink: bool,
+
+    /// Whether this file has any executable bit set
+    pub is_executable: bool,
+
+    /// Uncompressed size, zero for directories
+    pub size: u64,
+
+    /// The first lines of the file, if it's a small text file and they were asked for
+    pub preview: Option<Vec<String>>,
+
+    /// The type of the file, like "PNG image", if it was asked for
+    pub file_type: Option<String>,
+}
+
+impl FileInArchive {
+    pub fn kind(&self) -> EntryKind {
+        match (self.is_dir, self.is_symlink) {
+            (true, _) => EntryKind::Directory,
+            (false, true) => EntryKind::Symlink,
+            (false, false) => EntryKind::File,
+        }
+    }
 }
 
-/// Actually print the files
+/// Reads what `options` asks for out of `reader`, the contents of `file`.
+pub fn read_contents(file: &mut FileInArchive, mut reader: impl Read, options: &ContentOptions) -> io::Result<()> {
+    let previewed = options.preview.is_some() && file.size <= PREVIEW_MAX_SIZE;
+    let limit = match (previewed, options.detect_types) {
+        (true, _) => PREVIEW_MAX_SIZE,
+        (false, true) => SNIFF_SIZE,
+        (false, false) => 0,
+    };
+    let mut contents = Vec::with_capacity(file.size.min(limit) as usize);
+    (&mut reader).take(limit).read_to_end(&mut contents)?;
+
+    if options.detect_types {
+        file.file_type = Some(detect_type(&contents[..contents.len().min(SNIFF_SIZE as usize)]));
+    }
+    if let Some(lines) = options.preview.filter(|_| previewed && is_text(&contents)) {
+        file.preview = Some(preview_lines(&String::from_utf8_lossy(&contents), lines));
+    }
+    if options.verify {
+        nested::test_entry(&file.path, io::Cursor::new(contents).chain(reader), options.recursive)?;
+    }
+    Ok(())
+}
+
+/// The type of a file starting with `head`, like "ELF executable", "text" or "data".
+fn detect_type(head: &[u8]) -> String {
+    use infer::MatcherType;
+
+    if head.is_empty() {
+        return "empty".to_string();
+    }
+    match infer::get(head) {
+        Some(kind) => {
+            let category = match kind.matcher_type() {
+                MatcherType::App => "executable",
+                MatcherType::Archive => "archive",
+                MatcherType::Audio => "audio",
+                MatcherType::Book => "book",
+                MatcherType::Doc => "document",
+                MatcherType::Font => "font",
+                MatcherType::Image => "image",
+                MatcherType::Text => "text",
+                MatcherType::Video => "video",
+                MatcherType::Custom => "file",
+            };
+            format!("{} {category}", kind.extension().to_uppercase())
+        }
+        None if is_text(head) => "text".to_string(),
+        None => "data".to_string(),
+    }
+}
+
+/// Whether `contents` is UTF-8 text, which may be cut in the middle of a character.
+fn is_text(contents: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(contents) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    };
+    valid && !contents.contains(&0)
+}
+
+/// The first `lines` lines of `text`, cut if they're too long.
+fn preview_lines(text: &str, lines: usize) -> Vec<String> {
+    text.lines()
+        .take(lines)
+        .map(|line| {
+            // Control characters could change what the terminal shows
+            let mut line: String = line
+                .chars()
+                .map(|c| if c.is_control() && c != '\t' { '\u{FFFD}' } else { c })
+                .collect();
+            if let Some((cut, _)) = line.char_indices().nth(PREVIEW_LINE_WIDTH) {
+                line.truncate(cut);
+                line.push('…');
+            }
+            line
+        })
+        .collect()
+}
+
+/// Actually print the files, as they come, except for the tree view which needs all of them
 /// Returns an Error, if one of the files can't be read
 pub fn list_files(
     archive: &Path,
@@ -33,40 +193,247 @@ pub fn list_files(
     list_options: ListOptions,
 ) -> crate::Result<()> {
     let out = &mut stdout().lock();
-    let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+    if !list_options.null {
+        let _ = writeln!(out, "Archive: {}", EscapedPathDisplay::new(archive));
+    }
+
+    let filter = &list_options.filter;
+    let files = files.into_iter().filter(|file| match file {
+        Ok(file) => filter.matches(Some(file.kind())),
+        Err(_) => true,
+    });
 
-    if list_options.tree {
+    if list_options.summary {
+        return print_summary(out, archive, files);
+    }
+
+    // Dropping the rest of the entries stops the threads reading them
+    let mut files = files.take(list_options.head.unwrap_or(usize::MAX));
+    let mut last_files;
+    let files: &mut dyn Iterator<Item = crate::Result<FileInArchive>> = match list_options.tail {
+        Some(count) => {
+            last_files = last_entries(files, count)?.into_iter().map(Ok);
+            &mut last_files
+        }
+        None => &mut files,
+    };
+
+    if list_options.null {
+        for file in files {
+            // Unescaped, to be given back as they are
+            let _ = out.write_all(file?.path.as_os_str().as_encoded_bytes());
+            let _ = out.write_all(b"\0");
+        }
+    } else if list_options.tree {
         let tree = files.into_iter().collect::<crate::Result<Tree>>()?;
         tree.print(out);
     } else {
         for file in files {
-            let FileInArchive { path, is_dir } = file?;
-            print_entry(out, EscapedPathDisplay::new(&path), is_dir);
+            let FileInArchive {
+                path,
+                is_dir,
+                is_executable,
+                preview,
+                file_type,
+                ..
+            } = file?;
+            print_entry(
+                out,
+                EscapedPathDisplay::new(&path),
+                is_dir,
+                is_executable,
+                file_type.as_deref(),
+            );
+            if let Some(preview) = preview {
+                print_preview(out, &preview);
+            }
         }
     }
     Ok(())
 }
 
-/// Print an entry and highlight directories, either by coloring them
-/// if that's supported or by adding a trailing /
-fn print_entry(out: &mut impl Write, name: impl std::fmt::Display, is_dir: bool) {
-    use crate::utils::colors::*;
-
-    if is_dir {
-        // if colors are deactivated, print final / to mark directories
-        if BLUE.is_empty() {
-            let _ = writeln!(out, "{name}/");
-        // if in ACCESSIBLE mode, use colors but print final / in case colors
-        // aren't read out aloud with a screen reader or aren't printed on a
-        // braille reader
-        } else if is_running_in_accessible_mode() {
-            let _ = writeln!(out, "{}{}{}/{}", *BLUE, *STYLE_BOLD, name, *ALL_RESET);
+/// Prints how many entries `files` has and their total size, exact for scripts and readable
+fn print_summary(
+    out: &mut impl Write,
+    archive: &Path,
+    files: impl IntoIterator<Item = crate::Result<FileInArchive>>,
+) -> crate::Result<()> {
+    let (mut file_count, mut dir_count, mut size) = (0, 0, 0);
+    for file in files {
+        let file = file?;
+        if file.is_dir {
+            dir_count += 1;
         } else {
-            let _ = writeln!(out, "{}{}{}{}", *BLUE, *STYLE_BOLD, name, *ALL_RESET);
+            file_count += 1;
         }
+        size += file.size;
+    }
+    let compressed_size = fs::metadata(archive)?.len();
+
+    let _ = writeln!(out, "Entries: {}", file_count + dir_count);
+    let _ = writeln!(out, "Files: {file_count}");
+    let _ = writeln!(out, "Directories: {dir_count}");
+    let _ = writeln!(out, "Uncompressed size: {size} ({})", Bytes::new(size));
+    let _ = writeln!(
+        out,
+        "Compressed size: {compressed_size} ({})",
+        Bytes::new(compressed_size)
+    );
+    Ok(())
+}
+
+/// The last `count` entries of `files`, keeping no more than that in memory
+fn last_entries(
+    files: impl Iterator<Item = crate::Result<FileInArchive>>,
+    count: usize,
+) -> crate::Result<VecDeque<FileInArchive>> {
+    let mut last = VecDeque::with_capacity(count.min(LISTED_AHEAD));
+    for file in files {
+        let file = file?;
+        if last.len() == count {
+            last.pop_front();
+        }
+        if count > 0 {
+            last.push_back(file);
+        }
+    }
+    Ok(last)
+}
+
+/// Print an entry and highlight it by its type, either by coloring it if that's supported
+/// or by adding a trailing / to directories
+///
+/// Colors come from `LS_COLORS` if it's set, otherwise only directories are colored. The type
+/// of the file follows it in brackets, if it was detected.
+fn print_entry(
+    out: &mut impl Write,
+    name: impl std::fmt::Display,
+    is_dir: bool,
+    is_executable: bool,
+    file_type: Option<&str>,
+) {
+    use crate::utils::colors::*;
+
+    let file_type = file_type.map_or_else(String::new, |file_type| format!("  {}[{file_type}]{}", *BLACK, *RESET));
+
+    // if colors are deactivated, print final / to mark directories
+    if BLUE.is_empty() {
+        let marker = if is_dir { "/" } else { "" };
+        let _ = writeln!(out, "{name}{marker}{file_type}");
+        return;
+    }
+
+    let name = name.to_string();
+    let style = match &*LS_COLORS {
+        Some(ls_colors) => ls_colors.style(&name, is_dir, is_executable).map(Cow::Borrowed),
+        None => is_dir.then(|| Cow::Owned(format!("{}{}", *BLUE, *STYLE_BOLD))),
+    };
+
+    // if in ACCESSIBLE mode, use colors but print final / in case colors
+    // aren't read out aloud with a screen reader or aren't printed on a
+    // braille reader
+    let marker = if is_dir && is_running_in_accessible_mode() {
+        "/"
     } else {
-        // not a dir -> just print the file name
-        let _ = writeln!(out, "{name}");
+        ""
+    };
+
+    match style {
+        Some(style) => {
+            let _ = writeln!(out, "{style}{name}{marker}{}{file_type}", *ALL_RESET);
+        }
+        None => {
+            let _ = writeln!(out, "{name}{marker}{file_type}");
+        }
+    }
+}
+
+/// Prints the first lines of a file under its entry, marked by a bar
+fn print_preview(out: &mut impl Write, preview: &[String]) {
+    use crate::utils::colors::*;
+
+    for line in preview {
+        let _ = writeln!(out, "{}  │ {}{line}", *BLACK, *RESET);
+    }
+}
+
+/// Parsed `LS_COLORS`, if it's set
+static LS_COLORS: Lazy<Option<ls_colors::LsColors>> = Lazy::new(|| {
+    env::var("LS_COLORS")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| ls_colors::LsColors::parse(&value))
+});
+
+/// Support for the `LS_COLORS` environment variable, as set by `dircolors`
+mod ls_colors {
+    /// Styles for each kind of entry, as ANSI escape sequences
+    #[derive(Debug, Default, PartialEq)]
+    pub struct LsColors {
+        file: Option<String>,
+        directory: Option<String>,
+        executable: Option<String>,
+        /// Styles for file name suffixes, like "*.tar.gz"
+        suffixes: Vec<(String, String)>,
+    }
+
+    impl LsColors {
+        /// Parses `LS_COLORS`, a list of `key=style` pairs separated by ':', unknown keys are ignored
+        pub fn parse(value: &str) -> Self {
+            let mut ls_colors = Self::default();
+
+            for (key, style) in value.split(':').filter_map(|pair| pair.split_once('=')) {
+                let style = format!("\x1b[{style}m");
+                match key {
+                    "fi" => ls_colors.file = Some(style),
+                    "di" => ls_colors.directory = Some(style),
+                    "ex" => ls_colors.executable = Some(style),
+                    _ => {
+                        if let Some(suffix) = key.strip_prefix('*') {
+                            ls_colors.suffixes.push((suffix.to_ascii_lowercase(), style));
+                        }
+                    }
+                }
+            }
+
+            ls_colors
+        }
+
+        /// The style of an entry, directories and executables take precedence over suffixes
+        pub fn style(&self, name: &str, is_dir: bool, is_executable: bool) -> Option<&str> {
+            if is_dir {
+                return self.directory.as_deref();
+            }
+            if is_executable && self.executable.is_some() {
+                return self.executable.as_deref();
+            }
+
+            let name = name.to_ascii_lowercase();
+            self.suffixes
+                .iter()
+                .filter(|(suffix, _)| name.ends_with(suffix.as_str()))
+                .max_by_key(|(suffix, _)| suffix.len())
+                .map(|(_, style)| style.as_str())
+                .or(self.file.as_deref())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_ls_colors() {
+            let ls_colors =
+                LsColors::parse("di=01;34:ex=01;32:*.tar=01;31:*.tar.gz=01;35:*.PNG=01;33:invalid:ln=01;36");
+
+            assert_eq!(ls_colors.style("src", true, false), Some("\x1b[01;34m"));
+            assert_eq!(ls_colors.style("src/run.sh", false, true), Some("\x1b[01;32m"));
+            assert_eq!(ls_colors.style("backup.tar", false, false), Some("\x1b[01;31m"));
+            assert_eq!(ls_colors.style("backup.tar.gz", false, false), Some("\x1b[01;35m"));
+            assert_eq!(ls_colors.style("image.png", false, false), Some("\x1b[01;33m"));
+            assert_eq!(ls_colors.style("README", false, false), None);
+        }
     }
 }
 
@@ -145,11 +512,22 @@ mod tree {
             };
 
             print!("{prefix}{final_part}");
-            let is_dir = match self.file {
-                Some(FileInArchive { is_dir, .. }) => is_dir,
-                None => true,
+            let (is_dir, is_executable, file_type) = match &self.file {
+                Some(FileInArchive {
+                    is_dir,
+                    is_executable,
+                    file_type,
+                    ..
+                }) => (*is_dir, *is_executable, file_type.as_deref()),
+                None => (true, false, None),
             };
-            super::print_entry(out, <Vec<u8> as ByteVec>::from_os_str_lossy(name).as_bstr(), is_dir);
+            super::print_entry(
+                out,
+                <Vec<u8> as ByteVec>::from_os_str_lossy(name).as_bstr(),
+                is_dir,
+                is_executable,
+                file_type,
+            );
 
             // Construct prefix for children, adding either a line if this isn't
             // the last entry in the parent dir or empty space if it is.
@@ -2,6 +2,34 @@
 
 use std::io;
 
+use once_cell::sync::OnceCell;
+
+/// Whether to suppress all output but errors, see `--silent`.
+pub static SILENT: OnceCell<bool> = OnceCell::new();
+
+pub fn is_running_in_silent_mode() -> bool {
+    SILENT.get().copied().unwrap_or(false)
+}
+
+pub fn set_silent(value: bool) {
+    if SILENT.get().is_none() {
+        SILENT.set(value).unwrap();
+    }
+}
+
+/// Whether to explain how files are processed, see `--verbose`.
+pub static VERBOSE: OnceCell<bool> = OnceCell::new();
+
+pub fn is_running_in_verbose_mode() -> bool {
+    VERBOSE.get().copied().unwrap_or(false)
+}
+
+pub fn set_verbose(value: bool) {
+    if VERBOSE.get().is_none() {
+        VERBOSE.set(value).unwrap();
+    }
+}
+
 /// Macro that prints \[INFO\] messages, wraps [`eprintln`].
 ///
 /// There are essentially two different versions of the `info!()` macro:
@@ -25,15 +53,19 @@ macro_rules! info {
 
         use $crate::{macros::stderr_check, utils::colors::{YELLOW, RESET}};
 
-        let mut stderr = stderr().lock();
+        if !$crate::macros::is_running_in_silent_mode() {
+            let mut stderr = stderr().lock();
+            $crate::progress::clear_status_line(&mut stderr);
+            $crate::heartbeat::output_printed();
 
-        if $crate::accessible::is_running_in_accessible_mode() {
-            stderr_check(write!(stderr, "{}Info:{} ", *YELLOW, *RESET));
-        } else {
-            stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
-        }
+            if $crate::accessible::is_running_in_accessible_mode() {
+                stderr_check(write!(stderr, "{}Info:{} ", *YELLOW, *RESET));
+            } else {
+                stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
+            }
 
-        stderr_check(writeln!(stderr, $($arg)*));
+            stderr_check(writeln!(stderr, $($arg)*));
+        }
     }};
     // Inccessible (long/no important) info message.
     // Print info message if ACCESSIBLE is not turned on
@@ -42,9 +74,11 @@ macro_rules! info {
 
         use $crate::{macros::stderr_check, utils::colors::{YELLOW, RESET}};
 
-        let mut stderr = stderr().lock();
+        if !$crate::macros::is_running_in_silent_mode() && !$crate::accessible::is_running_in_accessible_mode() {
+            let mut stderr = stderr().lock();
+            $crate::progress::clear_status_line(&mut stderr);
+            $crate::heartbeat::output_printed();
 
-        if !$crate::accessible::is_running_in_accessible_mode() {
             stderr_check(write!(stderr, "{}[INFO]{} ", *YELLOW, *RESET));
             stderr_check(writeln!(stderr, $($arg)*));
         }
@@ -59,15 +93,19 @@ macro_rules! warning {
 
         use $crate::{macros::stderr_check, utils::colors::{ORANGE, RESET}};
 
-        let mut stderr = stderr().lock();
+        if !$crate::macros::is_running_in_silent_mode() {
+            let mut stderr = stderr().lock();
+            $crate::progress::clear_status_line(&mut stderr);
+            $crate::heartbeat::output_printed();
 
-        if $crate::accessible::is_running_in_accessible_mode() {
-            stderr_check(write!(stderr, "{}Warning:{} ", *ORANGE, *RESET));
-        } else {
-            stderr_check(write!(stderr, "{}[WARNING]{} ", *ORANGE, *RESET));
-        }
+            if $crate::accessible::is_running_in_accessible_mode() {
+                stderr_check(write!(stderr, "{}Warning:{} ", *ORANGE, *RESET));
+            } else {
+                stderr_check(write!(stderr, "{}[WARNING]{} ", *ORANGE, *RESET));
+            }
 
-        stderr_check(writeln!(stderr, $($arg)*));
+            stderr_check(writeln!(stderr, $($arg)*));
+        }
     }};
 }
 
@@ -6,12 +6,23 @@ pub mod archive;
 pub mod check;
 pub mod cli;
 pub mod commands;
+pub mod config;
 pub mod error;
 pub mod extension;
+pub mod heartbeat;
 pub mod list;
+pub mod notification;
+pub mod privileges;
+pub mod progress;
+pub mod quarantine;
+pub mod sandbox;
+pub mod selinux;
+pub mod ssh;
+pub mod stats;
+pub mod storage;
 pub mod utils;
 
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, time::Instant};
 
 use cli::CliArgs;
 use error::{Error, Result};
@@ -28,13 +39,25 @@ static CURRENT_DIRECTORY: Lazy<PathBuf> = Lazy::new(|| env::current_dir().unwrap
 pub const EXIT_FAILURE: i32 = libc::EXIT_FAILURE;
 
 fn main() {
-    if let Err(err) = run() {
+    let result = run();
+    utils::remove_temp_registry();
+    if let Err(err) = result {
         eprintln!("{err}");
         std::process::exit(EXIT_FAILURE);
     }
 }
 
 fn run() -> Result<()> {
+    utils::raise_open_files_limit();
     let (args, skip_questions_positively, file_visibility_policy) = CliArgs::parse_and_validate_args()?;
-    commands::run(args, skip_questions_positively, file_visibility_policy)
+    let (started, stats) = (Instant::now(), args.stats);
+    let job = args.notify.then(|| notification::Job::new(&args.cmd)).flatten();
+    let result = commands::run(args, skip_questions_positively, file_visibility_policy);
+    if let Some(job) = job {
+        job.notify(&result);
+    }
+    if stats {
+        stats::report(started);
+    }
+    result
 }
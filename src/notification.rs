@@ -0,0 +1,98 @@
+//! `--notify`, which sends a desktop notification when compressing or decompressing is done.
+//!
+//! Large archives take long enough to switch to another window in the meantime, the notification
+//! tells when to come back, and whether it worked.
+
+use std::{path::Path, time::Instant};
+
+use crate::{cli::Subcommand, utils::EscapedPathDisplay, warning};
+
+/// A compression or decompression to notify about when it's done
+pub struct Job {
+    /// What is being done, like "Compressing 'photos.tar.gz'"
+    description: String,
+    start: Instant,
+}
+
+impl Job {
+    /// The job of `command`, if it compresses or decompresses and notifications are supported.
+    pub fn new(command: &Subcommand) -> Option<Self> {
+        if cfg!(not(feature = "notifications")) {
+            warning!("Desktop notifications are disabled for this build, '--notify' is ignored.");
+            return None;
+        }
+
+        // Notifications are sent over D-Bus, which needs the sockets the sandbox denies
+        if let Subcommand::Decompress { sandbox: true, .. } = command {
+            warning!("Desktop notifications can't be sent from '--sandbox', '--notify' is ignored.");
+            return None;
+        }
+
+        let name = |path: &Path| EscapedPathDisplay::new(path.file_name().map_or(path, Path::new)).to_string();
+        let description = match command {
+            Subcommand::Compress { output, .. } => format!("Compressing '{}'", name(output)),
+            Subcommand::Decompress { files, .. } if files.len() == 1 => {
+                format!("Decompressing '{}'", name(&files[0]))
+            }
+            Subcommand::Decompress { files, .. } => format!("Decompressing {} archives", files.len()),
+            _ => return None,
+        };
+        Some(Self {
+            description,
+            start: Instant::now(),
+        })
+    }
+
+    /// Sends the notification of the job, which ended with `result`.
+    pub fn notify(self, result: &crate::Result<()>) {
+        let elapsed = format_duration(self.start.elapsed().as_secs());
+        let (summary, body) = match result {
+            Ok(()) => ("ouch: done", format!("{} finished in {elapsed}.", self.description)),
+            Err(_) => (
+                "ouch: failed",
+                format!(
+                    "{} failed after {elapsed}, the error is in the terminal.",
+                    self.description
+                ),
+            ),
+        };
+        send(summary, &body);
+    }
+}
+
+/// `seconds` like "42s", "3m 05s" or "1h 02m".
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds:02}s"),
+        _ => format!("{hours}h {minutes:02}m"),
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn send(summary: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("ouch")
+        .summary(summary)
+        .body(body)
+        .show();
+    if let Err(err) = result {
+        warning!("Couldn't send the desktop notification: {err}");
+    }
+}
+
+#[cfg(not(feature = "notifications"))]
+fn send(_summary: &str, _body: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(185), "3m 05s");
+        assert_eq!(format_duration(3720), "1h 02m");
+    }
+}
@@ -0,0 +1,321 @@
+//! `--run-as`, which extracts archives as an unprivileged user.
+//!
+//! Before dropping its privileges, ouch forks a helper process that stays root, its only job is
+//! restoring the owners stored in the archive. The extraction sends it the owner of every entry,
+//! and once the entries are moved to their final place, asks it to apply them. The helper only
+//! changes the owners of paths inside of the output directory, which it opened before the switch,
+//! walking to them one directory at a time without following symlinks, so that the extraction
+//! can't redirect it by replacing a directory with a symlink.
+
+#[cfg(not(unix))]
+use std::path::Path;
+
+use crate::error::FinalError;
+
+fn run_as_error(detail: impl ToString) -> crate::Error {
+    FinalError::with_title("Could not run as another user")
+        .detail(detail.to_string())
+        .into()
+}
+
+#[cfg(unix)]
+pub use unix::OwnershipHelper;
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        ffi::{CString, OsStr},
+        io::{self, Read, Write},
+        mem::MaybeUninit,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::{ffi::OsStrExt, fs::lchown, net::UnixStream},
+        },
+        path::{Component, Path, PathBuf},
+        ptr,
+        sync::Mutex,
+    };
+
+    use super::run_as_error;
+    use crate::error::FinalError;
+
+    /// Queue the owner of a path
+    const QUEUE: u8 = b'Q';
+    /// Apply the owners queued under a path, which was moved
+    const APPLY: u8 = b'A';
+
+    /// The connection to the helper process that restores owners
+    pub struct OwnershipHelper {
+        stream: Mutex<UnixStream>,
+        /// The output directory, the paths sent to the helper are relative to it
+        output_dir: PathBuf,
+    }
+
+    impl OwnershipHelper {
+        /// Switches to `user`, which becomes the owner of `owned_paths`, and starts the helper,
+        /// which may only change owners inside of `output_dir`.
+        ///
+        /// This must be called before any other thread is started.
+        pub fn run_as(user: &str, output_dir: &Path, owned_paths: &[&Path]) -> crate::Result<Self> {
+            // Safety: `geteuid` has no preconditions
+            if unsafe { libc::geteuid() } != 0 {
+                return Err(run_as_error("'--run-as' requires running as root"));
+            }
+            let (uid, gid) = lookup_user(user)?;
+
+            for path in owned_paths {
+                lchown(path, Some(uid), Some(gid))?;
+            }
+            let root = open_directory(libc::AT_FDCWD, output_dir.as_os_str())?;
+
+            let (stream, helper_stream) = UnixStream::pair()?;
+            // Safety: no other threads are running, so the child can keep using the allocator
+            match unsafe { libc::fork() } {
+                -1 => return Err(io::Error::last_os_error().into()),
+                0 => {
+                    drop(stream);
+                    let code = match serve(helper_stream, root) {
+                        Ok(()) => 0,
+                        Err(_) => 1,
+                    };
+                    // Safety: the child exits without running the destructors of the parent's state
+                    unsafe { libc::_exit(code) };
+                }
+                _ => {
+                    drop(helper_stream);
+                    drop(root);
+                }
+            }
+
+            // Safety: plain system calls, their results are checked
+            let dropped =
+                unsafe { libc::setgroups(0, ptr::null()) == 0 && libc::setgid(gid) == 0 && libc::setuid(uid) == 0 };
+            if !dropped {
+                return Err(run_as_error(format!(
+                    "Could not switch to '{user}': {}",
+                    io::Error::last_os_error()
+                )));
+            }
+
+            Ok(Self {
+                stream: Mutex::new(stream),
+                output_dir: output_dir.to_path_buf(),
+            })
+        }
+
+        /// Queues the owner of the entry extracted at `path`.
+        pub fn record(&self, path: &Path, uid: u32, gid: u32) -> crate::Result<()> {
+            let mut message = vec![QUEUE];
+            message.extend_from_slice(&uid.to_le_bytes());
+            message.extend_from_slice(&gid.to_le_bytes());
+            push_bytes(&mut message, path.as_os_str().as_bytes());
+
+            self.stream.lock().unwrap().write_all(&message)?;
+            Ok(())
+        }
+
+        /// Applies the owners queued under `from`, whose contents are now under `to`, inside of
+        /// the output directory.
+        pub fn apply(&self, from: &Path, to: &Path) -> crate::Result<()> {
+            let to = to
+                .strip_prefix(&self.output_dir)
+                .map_err(|_| run_as_error(format!("'{}' is outside of the output directory", to.display())))?;
+            let mut message = vec![APPLY];
+            push_bytes(&mut message, from.as_os_str().as_bytes());
+            push_bytes(&mut message, to.as_os_str().as_bytes());
+
+            let mut stream = self.stream.lock().unwrap();
+            stream.write_all(&message)?;
+            let error = read_bytes(&mut *stream)?;
+            if !error.is_empty() {
+                let error = FinalError::with_title("Could not restore the owners of the extracted files")
+                    .detail(String::from_utf8_lossy(&error).into_owned());
+                return Err(error.into());
+            }
+            Ok(())
+        }
+    }
+
+    /// The user and group ids of `user`.
+    fn lookup_user(user: &str) -> crate::Result<(u32, u32)> {
+        let name = CString::new(user).map_err(|_| run_as_error(format!("Invalid user name '{user}'")))?;
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+        let mut result = ptr::null_mut();
+
+        // Safety: every pointer is valid for the duration of the call, and `buffer.len()` is its size
+        let code = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if code != 0 || result.is_null() {
+            return Err(run_as_error(format!("Unknown user '{user}'")));
+        }
+
+        // Safety: `getpwnam_r` succeeded, so it initialized `passwd`
+        let passwd = unsafe { passwd.assume_init() };
+        Ok((passwd.pw_uid, passwd.pw_gid))
+    }
+
+    fn push_bytes(message: &mut Vec<u8>, bytes: &[u8]) {
+        message.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        message.extend_from_slice(bytes);
+    }
+
+    fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; read_u32(reader)? as usize];
+        reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_path(reader: &mut impl Read) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(OsStr::from_bytes(&read_bytes(reader)?)))
+    }
+
+    /// The loop of the helper process, it runs until the extraction closes the connection.
+    fn serve(mut stream: UnixStream, root: OwnedFd) -> io::Result<()> {
+        let mut queued: Vec<(PathBuf, u32, u32)> = vec![];
+
+        loop {
+            let mut tag = [0];
+            if stream.read(&mut tag)? == 0 {
+                return Ok(());
+            }
+
+            match tag[0] {
+                QUEUE => {
+                    let uid = read_u32(&mut stream)?;
+                    let gid = read_u32(&mut stream)?;
+                    queued.push((read_path(&mut stream)?, uid, gid));
+                }
+                APPLY => {
+                    let from = read_path(&mut stream)?;
+                    let to = read_path(&mut stream)?;
+
+                    let mut errors = vec![];
+                    queued.retain(|(path, uid, gid)| {
+                        let Ok(relative) = path.strip_prefix(&from) else {
+                            return true;
+                        };
+                        if let Err(err) = change_owner(&root, &to.join(relative), *uid, *gid) {
+                            errors.push(format!("{}: {err}", path.display()));
+                        }
+                        false
+                    });
+
+                    let mut reply = vec![];
+                    push_bytes(&mut reply, errors.join("\n").as_bytes());
+                    stream.write_all(&reply)?;
+                }
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            }
+        }
+    }
+
+    /// Opens the directory `name` of the directory `dir`, failing if it's a symlink.
+    fn open_directory(dir: libc::c_int, name: &OsStr) -> io::Result<OwnedFd> {
+        let name = CString::new(name.as_bytes())?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // Safety: `name` is a valid C string for the duration of the call
+        let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `openat` succeeded, so `fd` is an open file descriptor that nothing else owns
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Changes the owner of `path`, relative to the directory `root`, without following symlinks,
+    /// neither for `path` nor for the directories on the way to it.
+    fn change_owner(root: &OwnedFd, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        let outside_of_root = || io::Error::new(io::ErrorKind::PermissionDenied, "outside of the output directory");
+
+        let mut names = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::CurDir => {}
+                _ => return Err(outside_of_root()),
+            }
+        }
+        let Some((name, parents)) = names.split_last() else {
+            return Err(outside_of_root());
+        };
+
+        let mut dir = None;
+        for parent in parents {
+            let parent_fd = dir.as_ref().unwrap_or(root).as_raw_fd();
+            dir = Some(open_directory(parent_fd, parent)?);
+        }
+        let dir = dir.as_ref().unwrap_or(root);
+
+        let name = CString::new(name.as_bytes())?;
+        // Safety: `dir` is open and `name` is a valid C string for the duration of the call
+        let code = unsafe { libc::fchownat(dir.as_raw_fd(), name.as_ptr(), uid, gid, libc::AT_SYMLINK_NOFOLLOW) };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::os::unix::fs::{symlink, MetadataExt};
+
+        use fs_err as fs;
+
+        use super::*;
+
+        #[test]
+        fn test_change_owner() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().join("root");
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(root.join("sub/file"), "content").unwrap();
+            fs::write(dir.path().join("outside"), "content").unwrap();
+            symlink(dir.path(), root.join("link")).unwrap();
+
+            let metadata = fs::metadata(root.join("sub/file")).unwrap();
+            let (uid, gid) = (metadata.uid(), metadata.gid());
+            let root_fd = open_directory(libc::AT_FDCWD, root.as_os_str()).unwrap();
+            change_owner(&root_fd, Path::new("sub/file"), uid, gid).unwrap();
+            change_owner(&root_fd, Path::new("./sub"), uid, gid).unwrap();
+
+            // A directory replaced by a symlink isn't followed, nor are paths going up
+            assert!(change_owner(&root_fd, Path::new("link/outside"), uid, gid).is_err());
+            assert!(change_owner(&root_fd, Path::new("../outside"), uid, gid).is_err());
+            assert!(change_owner(&root_fd, Path::new("/outside"), uid, gid).is_err());
+            assert!(change_owner(&root_fd, Path::new(""), uid, gid).is_err());
+        }
+    }
+}
+
+/// `--run-as` relies on Unix users and processes.
+#[cfg(not(unix))]
+pub struct OwnershipHelper;
+
+#[cfg(not(unix))]
+impl OwnershipHelper {
+    pub fn run_as(_user: &str, _output_dir: &Path, _owned_paths: &[&Path]) -> crate::Result<Self> {
+        Err(run_as_error("'--run-as' is only supported on Unix"))
+    }
+
+    pub fn record(&self, _path: &Path, _uid: u32, _gid: u32) -> crate::Result<()> {
+        Ok(())
+    }
+
+    pub fn apply(&self, _from: &Path, _to: &Path) -> crate::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! Progress display for compressions and extractions.
+//!
+//! A status line at the bottom of stderr shows how much of the inputs was read, out of their
+//! total size, the current throughput and the estimated time left, followed by the entry being
+//! extracted and how much of it was written. It's redrawn in place periodically by a separate
+//! thread, so a stalled operation shows a throughput of zero instead of freezing the line.
+//!
+//! When stderr isn't a terminal, like in CI logs, or in accessible mode, the same information is
+//! printed as a plain line every few seconds instead.
+//!
+//! The inputs are counted as they're read, see [`Tracked`], and extracted entries are followed
+//! through the events of extractions, see [`CliEvents`].
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    accessible::is_running_in_accessible_mode,
+    archive::events::{Event, EventHandler},
+    cli::ProgressMode,
+    heartbeat, info,
+    macros::stderr_check,
+    utils::{strip_cur_dir, Bytes, EscapedPathDisplay},
+    warning,
+};
+
+/// Time between two redraws of the status line
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time between two lines of plain progress output
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Entry names longer than this are shortened, so the status line fits in one line
+const MAX_NAME_WIDTH: usize = 48;
+
+/// Whether a status line is drawn on stderr, which must be cleared before printing anything else
+static STATUS_LINE_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Bytes of the inputs read so far, the archives when extracting and the files when compressing
+static INPUT_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Counts `bytes` more bytes of the inputs as read, for those that aren't read through [`Tracked`].
+pub fn input_read(bytes: u64) {
+    INPUT_READ.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Bytes of the inputs read so far by the whole process.
+pub fn total_input_read() -> u64 {
+    INPUT_READ.load(Ordering::Relaxed)
+}
+
+/// Counts what's read from an input towards the progress of the whole operation.
+pub struct Tracked<R> {
+    inner: R,
+}
+
+impl<R> Tracked<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        input_read(read as u64);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Tracked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Clears the status line, if there's one, so that other messages can be printed in its place.
+pub fn clear_status_line(stderr: &mut impl Write) {
+    if STATUS_LINE_DRAWN.swap(false, Ordering::Relaxed) {
+        stderr_check(write!(stderr, "\r\x1b[2K"));
+    }
+}
+
+/// Progress display of an extraction, stops and clears the status line when dropped.
+pub struct Progress {
+    handle: ProgressHandle,
+    ticker: Option<thread::JoinHandle<()>>,
+}
+
+/// Cloneable handle used to report progress, does nothing if the display is disabled.
+#[derive(Clone, Default)]
+pub struct ProgressHandle(Option<Arc<Shared>>);
+
+struct Shared {
+    state: Mutex<State>,
+    finished: AtomicBool,
+    plain: bool,
+    /// What is being done, like "Extracting", which starts the plain lines
+    action: &'static str,
+    /// Size of all the inputs, 0 if it's unknown
+    total: u64,
+    /// `INPUT_READ` when the operation started
+    read_before: u64,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entry_name: String,
+    entry_size: u64,
+    entry_written: u64,
+    /// Bytes of the inputs read at the last draw
+    read_at_draw: u64,
+}
+
+impl Progress {
+    /// Creates the progress display of `action`, like "Compressing", whose inputs are `total`
+    /// bytes long, `ProgressMode::Auto` picks the status line if stderr is a terminal, and plain
+    /// lines otherwise.
+    ///
+    /// In accessible mode only plain lines are shown, screen readers would read every redraw.
+    pub fn new(mode: ProgressMode, action: &'static str, total: u64) -> Self {
+        let plain = match mode {
+            ProgressMode::Auto if is_running_in_accessible_mode() => Some(true),
+            ProgressMode::Auto => Some(atty::isnt(atty::Stream::Stderr)),
+            ProgressMode::Bar => Some(false),
+            ProgressMode::Plain => Some(true),
+            ProgressMode::Off => None,
+        };
+        let Some(plain) = plain else {
+            return Self {
+                handle: ProgressHandle(None),
+                ticker: None,
+            };
+        };
+        let interval = if plain { PLAIN_INTERVAL } else { REDRAW_INTERVAL };
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            finished: AtomicBool::new(false),
+            plain,
+            action,
+            total,
+            read_before: INPUT_READ.load(Ordering::Relaxed),
+            started: Instant::now(),
+        });
+
+        let ticker = thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                let mut last_draw = Instant::now();
+                while !shared.finished.load(Ordering::Relaxed) {
+                    thread::park_timeout(interval);
+                    if shared.finished.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    shared.draw(last_draw.elapsed());
+                    last_draw = Instant::now();
+                }
+            }
+        });
+
+        Self {
+            handle: ProgressHandle(Some(shared)),
+            ticker: Some(ticker),
+        }
+    }
+
+    /// A handle to report progress with.
+    pub fn handle(&self) -> ProgressHandle {
+        self.handle.clone()
+    }
+
+    /// See [`ProgressHandle::start_entry`].
+    pub fn start_entry(&self, path: &Path, size: u64) {
+        self.handle.start_entry(path, size);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let (Some(shared), Some(ticker)) = (&self.handle.0, self.ticker.take()) {
+            shared.finished.store(true, Ordering::Relaxed);
+            ticker.thread().unpark();
+            let _ = ticker.join();
+            clear_status_line(&mut io::stderr().lock());
+        }
+    }
+}
+
+impl ProgressHandle {
+    /// Starts showing the progress of the entry at `path`, which is `size` bytes long.
+    pub fn start_entry(&self, path: &Path, size: u64) {
+        if let Some(shared) = &self.0 {
+            let mut state = shared.state.lock().unwrap();
+            state.entry_name = shorten_name(&path.to_string_lossy());
+            state.entry_size = size;
+            state.entry_written = 0;
+        }
+    }
+
+    /// Stops showing the current entry, until the next one starts.
+    pub fn finish_entry(&self) {
+        if let Some(shared) = &self.0 {
+            shared.state.lock().unwrap().entry_name.clear();
+        }
+    }
+
+    /// Reports that `bytes` more bytes of the current entry were written.
+    pub fn advance(&self, bytes: u64) {
+        if let Some(shared) = &self.0 {
+            shared.state.lock().unwrap().entry_written += bytes;
+        }
+    }
+}
+
+impl Shared {
+    fn draw(&self, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let read = INPUT_READ.load(Ordering::Relaxed).saturating_sub(self.read_before);
+        let bytes_per_second = read.saturating_sub(state.read_at_draw) as f64 / elapsed.as_secs_f64();
+        state.read_at_draw = read;
+
+        let mut status = if self.total > 0 {
+            // Archives can be read more than once, like the central directory of zip archives
+            let read = read.min(self.total);
+            format!(
+                "{:>3}%  {} / {}  ({}/s, {} left)",
+                read * 100 / self.total,
+                Bytes::new(read),
+                Bytes::new(self.total),
+                Bytes::new(bytes_per_second as u64),
+                TimeLeft::estimate(self.started.elapsed(), read, self.total),
+            )
+        } else {
+            format!("{}  ({}/s)", Bytes::new(read), Bytes::new(bytes_per_second as u64))
+        };
+        if !state.entry_name.is_empty() {
+            status.push_str(&format!(
+                "  {}  {} / {}",
+                state.entry_name,
+                // Archive metadata read between entries is counted towards the current one
+                Bytes::new(state.entry_written.min(state.entry_size)),
+                Bytes::new(state.entry_size),
+            ));
+        }
+
+        if self.plain {
+            info!(accessible, "{} {}", self.action, status.trim_start());
+        } else {
+            let mut stderr = io::stderr().lock();
+            stderr_check(write!(stderr, "\r\x1b[2K{status}"));
+            stderr_check(stderr.flush());
+            STATUS_LINE_DRAWN.store(true, Ordering::Relaxed);
+            heartbeat::output_printed();
+        }
+    }
+}
+
+/// Estimated time until the operation ends, at its average throughput so far
+struct TimeLeft(Option<Duration>);
+
+impl TimeLeft {
+    fn estimate(elapsed: Duration, read: u64, total: u64) -> Self {
+        if read == 0 {
+            return Self(None);
+        }
+        Self(Some(elapsed.mul_f64((total - read) as f64 / read as f64)))
+    }
+}
+
+impl fmt::Display for TimeLeft {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(time_left) = self.0 else {
+            return f.write_str("?");
+        };
+        let seconds = time_left.as_secs();
+        match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+            (0, 0, seconds) => write!(f, "{seconds}s"),
+            (0, minutes, seconds) => write!(f, "{minutes}m {seconds:02}s"),
+            (hours, minutes, _) => write!(f, "{hours}h {minutes:02}m"),
+        }
+    }
+}
+
+/// Keeps the end of `name`, which is the most relevant part of a path.
+fn shorten_name(name: &str) -> String {
+    let char_count = name.chars().count();
+    if char_count <= MAX_NAME_WIDTH {
+        return name.to_owned();
+    }
+
+    let tail: String = name.chars().skip(char_count - (MAX_NAME_WIDTH - 3)).collect();
+    format!("...{tail}")
+}
+
+/// How the command line interface shows the events of extractions: the progress display, and a
+/// message for every entry, unless `quiet`.
+pub struct CliEvents {
+    progress: Progress,
+    quiet: bool,
+}
+
+impl CliEvents {
+    /// Shows the extraction of archives that are `total` bytes long.
+    pub fn new(mode: ProgressMode, quiet: bool, total: u64) -> Self {
+        Self {
+            progress: Progress::new(if quiet { ProgressMode::Off } else { mode }, "Extracting", total),
+            quiet,
+        }
+    }
+}
+
+impl EventHandler for CliEvents {
+    fn handle(&self, event: Event<'_>) {
+        match event {
+            Event::EntryStarted { path, size } => self.progress.start_entry(path, size),
+            Event::BytesWritten(bytes) => self.progress.handle.advance(bytes),
+            Event::EntryFinished { destination, size } => {
+                self.progress.handle.finish_entry();
+                // This is printed for every file in the archive and has little
+                // importance for most users, but would generate lots of
+                // spoken text for users using screen readers, braille displays
+                // and so on
+                if !self.quiet {
+                    info!(
+                        inaccessible,
+                        "{:?} extracted. ({})",
+                        strip_cur_dir(destination),
+                        Bytes::new(size),
+                    );
+                }
+            }
+            Event::Conflict {
+                path,
+                reason,
+                renamed_to: Some(renamed_to),
+            } => {
+                info!(
+                    accessible,
+                    "'{}' was extracted to '{}', {reason}.",
+                    EscapedPathDisplay::new(path),
+                    EscapedPathDisplay::new(renamed_to)
+                );
+            }
+            Event::Conflict {
+                path,
+                reason,
+                renamed_to: None,
+            } => {
+                warning!("Skipping '{}', {reason}.", EscapedPathDisplay::new(path));
+            }
+            Event::Warning(message) => warning!("{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shorten_name() {
+        assert_eq!(shorten_name("src/main.rs"), "src/main.rs");
+
+        let long_name = format!("{}/file.txt", "directory".repeat(10));
+        let shortened = shorten_name(&long_name);
+        assert_eq!(shortened.chars().count(), MAX_NAME_WIDTH);
+        assert!(shortened.starts_with("..."));
+        assert!(shortened.ends_with("directory/file.txt"));
+    }
+
+    #[test]
+    fn test_time_left() {
+        let time_left = |elapsed, read| TimeLeft::estimate(Duration::from_secs(elapsed), read, 100).to_string();
+
+        assert_eq!(time_left(10, 0), "?");
+        assert_eq!(time_left(10, 50), "10s");
+        assert_eq!(time_left(90, 50), "1m 30s");
+        assert_eq!(time_left(3900, 50), "1h 05m");
+        assert_eq!(time_left(10, 100), "0s");
+    }
+}
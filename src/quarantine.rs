@@ -0,0 +1,154 @@
+//! `--quarantine`, which decides whether extracted files get the `com.apple.quarantine`
+//! attribute, that makes Gatekeeper check files downloaded from the internet before opening them.
+//!
+//! By default, like Archive Utility, the files extracted from a quarantined archive get the same
+//! attribute, which tells which application downloaded it and when.
+
+use std::path::Path;
+
+use crate::{cli::QuarantinePolicy, error::FinalError};
+
+/// Fails before extracting anything if `policy` can't be followed on this system.
+#[cfg(target_os = "macos")]
+pub fn check_supported(_policy: QuarantinePolicy) -> crate::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn check_supported(policy: QuarantinePolicy) -> crate::Result<()> {
+    if policy == QuarantinePolicy::Always {
+        return Err(FinalError::with_title("Cannot quarantine the extracted files")
+            .detail("'--quarantine always' is only supported on macOS")
+            .into());
+    }
+    Ok(())
+}
+
+/// Value of the quarantine attribute to give to the files extracted from `archive`, `None` to
+/// leave them alone.
+#[cfg(target_os = "macos")]
+pub fn archive_quarantine(archive: &Path, policy: QuarantinePolicy) -> crate::Result<Option<Vec<u8>>> {
+    if policy == QuarantinePolicy::Never {
+        return Ok(None);
+    }
+    let value = macos::get_xattr(archive, macos::QUARANTINE).map_err(|err| {
+        FinalError::with_title(format!(
+            "Could not read the quarantine attribute of '{}'",
+            crate::utils::EscapedPathDisplay::new(archive)
+        ))
+        .detail(err.to_string())
+    })?;
+
+    match (value, policy) {
+        (Some(value), _) => Ok(Some(value)),
+        (None, QuarantinePolicy::Always) => Ok(Some(macos::new_quarantine())),
+        (None, _) => Ok(None),
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn archive_quarantine(_archive: &Path, _policy: QuarantinePolicy) -> crate::Result<Option<Vec<u8>>> {
+    // Only macOS has it, and `check_supported` rejected `--quarantine always`
+    Ok(None)
+}
+
+/// Gives the quarantine attribute `value` to `path`, to symlinks themselves rather than their
+/// targets.
+#[cfg(target_os = "macos")]
+pub fn set_quarantine(path: &Path, value: &[u8]) -> crate::Result<()> {
+    macos::set_xattr(path, macos::QUARANTINE, value).map_err(|err| {
+        FinalError::with_title(format!(
+            "Could not quarantine '{}'",
+            crate::utils::EscapedPathDisplay::new(path)
+        ))
+        .detail(err.to_string())
+        .into()
+    })
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn set_quarantine(_path: &Path, _value: &[u8]) -> crate::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use std::{
+        ffi::CString,
+        io,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    pub const QUARANTINE: &str = "com.apple.quarantine";
+
+    /// Value like the ones of files downloaded by other applications, with flags, the time in
+    /// hexadecimal and the application, like "0081;65a1b2c3;ouch;".
+    pub fn new_quarantine() -> Vec<u8> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        format!("0081;{time:08x};ouch;").into_bytes()
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Reads the attribute `name` of `path`, `None` if it doesn't have it or the filesystem
+    /// doesn't support attributes.
+    pub fn get_xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+
+        // Safety: the strings are valid C strings, and a null buffer only asks for the size
+        let size = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0, 0, 0) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOATTR | libc::ENOTSUP) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        let mut value = vec![0u8; size as usize];
+        // Safety: the strings are valid C strings, and `value` is valid for `value.len()` bytes
+        let size = unsafe {
+            libc::getxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(size as usize);
+        Ok(Some(value))
+    }
+
+    pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+
+        // Safety: the strings are valid C strings, and `value` is valid for `value.len()` bytes
+        let code = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! `--sandbox`, which confines an extraction to the paths it's meant to write to.
+//!
+//! On Linux, Landlock only lets the process read files and write inside of the given paths, and
+//! a seccomp filter denies system calls an extraction never needs, like opening sockets or
+//! executing programs. Both only apply to the calling thread and the threads it spawns afterwards,
+//! so it must be called before starting any other thread.
+
+use std::path::Path;
+
+use crate::error::FinalError;
+
+/// Confines the process, it may only write inside of `writable_paths` from now on.
+///
+/// Threads that were already running aren't confined.
+#[cfg(target_os = "linux")]
+pub fn confine(writable_paths: &[&Path]) -> crate::Result<()> {
+    landlock::restrict_writes(writable_paths)?;
+    seccomp::deny_unneeded_syscalls()?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn confine(_writable_paths: &[&Path]) -> crate::Result<()> {
+    Err(sandbox_error("'--sandbox' is only supported on Linux"))
+}
+
+fn sandbox_error(detail: impl ToString) -> crate::Error {
+    FinalError::with_title("Could not sandbox the extraction")
+        .detail(detail.to_string())
+        .into()
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::path::Path;
+
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI,
+    };
+
+    use super::sandbox_error;
+    use crate::warning;
+
+    /// The newest Landlock ABI whose access rights are handled, older kernels enforce a subset
+    const LANDLOCK_ABI: ABI = ABI::V3;
+
+    /// Allows reading everything, but writing only inside of `writable_paths`.
+    pub fn restrict_writes(writable_paths: &[&Path]) -> crate::Result<()> {
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/"], AccessFs::from_read(LANDLOCK_ABI))))
+            .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(writable_paths, AccessFs::from_all(LANDLOCK_ABI))))
+            .and_then(|ruleset| ruleset.restrict_self())
+            .map_err(sandbox_error)?;
+
+        match status.ruleset {
+            RulesetStatus::FullyEnforced => {}
+            RulesetStatus::PartiallyEnforced => {
+                warning!("The kernel only supports part of the Landlock restrictions used by '--sandbox'.");
+            }
+            RulesetStatus::NotEnforced => {
+                return Err(sandbox_error("Landlock is not supported or enabled in this kernel"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    use super::sandbox_error;
+
+    /// The `AUDIT_ARCH_*` value of the system calls this filter was written for
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// Set in the numbers of the x32 system calls, which have the same `AUDIT_ARCH` as the x86_64
+    /// ones, so they'd otherwise get past the checks of the x86_64 numbers
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// System calls an extraction never makes, they fail with `EPERM`
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_socket,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_bpf,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_kexec_load,
+        // The operations submitted to io_uring don't go through seccomp
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+    ];
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn jump_if_equal(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn jump_if_greater_or_equal(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// Installs a filter making the system calls in `DENIED_SYSCALLS` fail, and killing the
+    /// process if it makes system calls of another architecture, or x32 ones, which would bypass
+    /// the filter.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn deny_unneeded_syscalls() -> crate::Result<()> {
+        // Offsets of the fields of `struct seccomp_data`
+        const NR_OFFSET: u32 = 0;
+        const ARCH_OFFSET: u32 = 4;
+
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let mut filter = vec![
+            statement(load, ARCH_OFFSET),
+            jump_if_equal(AUDIT_ARCH, 1, 0),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, NR_OFFSET),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump_if_greater_or_equal(X32_SYSCALL_BIT, 0, 1),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+        for &syscall in DENIED_SYSCALLS {
+            filter.push(jump_if_equal(syscall as u32, 0, 1));
+            filter.push(statement(
+                libc::BPF_RET | libc::BPF_K,
+                libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            ));
+        }
+        filter.push(statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+
+        // Safety: `program` points to `filter`, which outlives the calls, and the kernel copies it
+        let installed = unsafe {
+            libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0
+                && libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program) == 0
+        };
+        if !installed {
+            return Err(sandbox_error(format!(
+                "Could not install the seccomp filter: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The filter only knows the system call numbers of some architectures, Landlock still applies.
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn deny_unneeded_syscalls() -> crate::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(all(test, target_arch = "x86_64"))]
+    mod tests {
+        use std::{env, io, os::unix::process::ExitStatusExt, process::Command};
+
+        use super::*;
+
+        /// Set for the test process that installs the filter, which stays installed until it exits
+        const CHILD_VARIABLE: &str = "OUCH_TEST_SECCOMP_CHILD";
+
+        /// Runs in its own process, started by `test_deny_unneeded_syscalls`.
+        #[test]
+        fn seccomp_child() {
+            if env::var_os(CHILD_VARIABLE).is_none() {
+                return;
+            }
+            deny_unneeded_syscalls().unwrap();
+
+            // Safety: invalid arguments at worst, the call is denied before they're read
+            let code = unsafe { libc::syscall(libc::SYS_io_uring_setup, 1, std::ptr::null_mut::<u8>()) };
+            assert_eq!(code, -1);
+            assert_eq!(io::Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+
+            // Safety: a socket, which is leaked if the call isn't stopped
+            unsafe {
+                libc::syscall(
+                    libc::SYS_socket | X32_SYSCALL_BIT as libc::c_long,
+                    libc::AF_INET,
+                    libc::SOCK_STREAM,
+                    0,
+                )
+            };
+            panic!("the x32 system call was allowed");
+        }
+
+        #[test]
+        fn test_deny_unneeded_syscalls() {
+            let status = Command::new(env::current_exe().unwrap())
+                .args(["--exact", "sandbox::seccomp::tests::seccomp_child", "--test-threads=1"])
+                .env(CHILD_VARIABLE, "1")
+                .output()
+                .unwrap()
+                .status;
+            assert_eq!(status.signal(), Some(libc::SIGSYS), "{status}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! `--selinux`, which stores the SELinux contexts of files in tar archives and restores them, so
+//! that restored systems boot without a relabel.
+//!
+//! Contexts are stored like GNU tar does, in the `RHT.security.selinux` pax record of each entry.
+
+use std::path::Path;
+
+use crate::error::FinalError;
+
+/// Key of the pax record holding the context of an entry, written by GNU tar
+pub const PAX_KEY: &str = "RHT.security.selinux";
+/// Key of the same record, written by the tools storing every extended attribute
+pub const PAX_XATTR_KEY: &str = "SCHILY.xattr.security.selinux";
+
+/// Fails before doing anything if SELinux contexts can't be stored or restored on this system.
+#[cfg(target_os = "linux")]
+pub fn check_supported() -> crate::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_supported() -> crate::Result<()> {
+    Err(FinalError::with_title("Cannot use SELinux contexts")
+        .detail("'--selinux' is only supported on Linux")
+        .into())
+}
+
+/// The SELinux context of `path`, of symlinks themselves, `None` if it has none, like on systems
+/// without SELinux.
+#[cfg(target_os = "linux")]
+pub fn file_context(path: &Path) -> crate::Result<Option<Vec<u8>>> {
+    let context = linux::get_xattr(path, linux::XATTR_NAME).map_err(|err| {
+        FinalError::with_title(format!(
+            "Could not read the SELinux context of '{}'",
+            crate::utils::EscapedPathDisplay::new(path)
+        ))
+        .detail(err.to_string())
+    })?;
+
+    // The kernel ends contexts with a null byte, which GNU tar doesn't store
+    Ok(context.map(|mut context| {
+        if context.last() == Some(&0) {
+            context.pop();
+        }
+        context
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn file_context(_path: &Path) -> crate::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+/// Gives the SELinux context `context` to `path`, to symlinks themselves.
+#[cfg(target_os = "linux")]
+pub fn set_file_context(path: &Path, context: &[u8]) -> crate::Result<()> {
+    linux::set_xattr(path, linux::XATTR_NAME, context).map_err(|err| {
+        let error = FinalError::with_title(format!(
+            "Could not restore the SELinux context of '{}'",
+            crate::utils::EscapedPathDisplay::new(path)
+        ))
+        .detail(err.to_string());
+
+        match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) => error.hint("SELinux must be enabled to restore contexts"),
+            Some(libc::EPERM | libc::EACCES) => error.hint("Restoring contexts usually requires root"),
+            _ => error,
+        }
+        .into()
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_file_context(_path: &Path, _context: &[u8]) -> crate::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    pub const XATTR_NAME: &str = "security.selinux";
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Reads the attribute `name` of `path`, `None` if it doesn't have it or the filesystem
+    /// doesn't support attributes.
+    pub fn get_xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+
+        // Safety: the strings are valid C strings, and a null buffer only asks for the size
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENODATA | libc::EOPNOTSUPP) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        let mut value = vec![0u8; size as usize];
+        // Safety: the strings are valid C strings, and `value` is valid for `value.len()` bytes
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(size as usize);
+        Ok(Some(value))
+    }
+
+    pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+
+        // Safety: the strings are valid C strings, and `value` is valid for `value.len()` bytes
+        let code = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! Files on other hosts, named `[user@]host:path` like for `scp`: archives are compressed to them
+//! and decompressed from them through the `ssh` command, streamed without a copy on this machine,
+//! like backups often are.
+
+use std::{
+    env,
+    io::{self, Read},
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio},
+};
+
+use crate::{error::FinalError, QuestionPolicy};
+
+/// Environment variable with the command that runs SSH, like "ssh -p 2222", "ssh" by default
+const SSH_COMMAND_VAR: &str = "OUCH_SSH_COMMAND";
+
+/// Exit code of the remote script when the destination exists and mustn't be replaced
+const EXISTS_EXIT_CODE: i32 = 17;
+
+/// The path on the host of `path`, if it names a file on another host, see `SshPath::remote`.
+pub fn path_on_host(path: &Path) -> Option<&Path> {
+    SshPath::remote(path)?;
+    let (_, on_host) = path.to_str()?.split_once(':')?;
+    Some(Path::new(on_host))
+}
+
+/// A file on another host, like "user@host:/backups/home.tar.zst"
+pub struct SshPath {
+    /// "user@host" or "host"
+    host: String,
+    /// Relative to the home directory on the host, unless absolute
+    path: String,
+}
+
+impl SshPath {
+    /// The destination `output` names, if any: like for `scp`, a colon before any slash, "./a:b"
+    /// is a local file.
+    pub fn parse(output: &Path) -> Option<Self> {
+        let (host, path) = output.to_str()?.split_once(':')?;
+        // Drive letters, like "C:\archive.zip"
+        let is_drive = cfg!(windows) && host.len() == 1;
+        let is_host = !host.is_empty() && !host.starts_with('-') && !host.contains(['/', '\\']) && !is_drive;
+        (is_host && !path.is_empty()).then(|| Self {
+            host: host.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+
+    /// The file on another host `path` names, unless there's a local file by that name.
+    pub fn remote(path: &Path) -> Option<Self> {
+        Self::parse(path).filter(|_| !path.exists())
+    }
+
+    /// Starts reading the file from the host.
+    pub fn open(&self) -> crate::Result<SshReader> {
+        let script = format!("cat {}", shell_quote(&self.path));
+        let mut child = self
+            .command(&script)
+            // Questions are still answered on stdin while it's read
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| self.failed("read", format!("Could not run SSH: {err}")))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(SshReader {
+            path: self.to_string(),
+            child,
+            stdout,
+        })
+    }
+
+    /// Fails if one of the `options`, the flags given with whether they were, needs the archive on
+    /// this machine once it's written.
+    pub fn check_options(&self, options: &[(&str, bool)]) -> crate::Result<()> {
+        if let Some((option, _)) = options.iter().find(|(_, given)| *given) {
+            let error = FinalError::with_title(format!("Cannot use '{option}' when compressing to '{self}'"))
+                .detail(format!("'{option}' needs the archive on this machine"))
+                .hint("Compress to a local file, then copy it with 'scp'");
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    /// Starts sending a file to the host, the destination is replaced by it once it's complete,
+    /// if it already exists and `question_policy` allows it.
+    pub fn connect(&self, question_policy: QuestionPolicy) -> crate::Result<SshTransfer<'_>> {
+        let path = shell_quote(&self.path);
+        let partial = shell_quote(&self.partial_path());
+        let mut script = String::new();
+        if question_policy != QuestionPolicy::AlwaysYes {
+            script.push_str(&format!("if [ -e {path} ]; then exit {EXISTS_EXIT_CODE}; fi; "));
+        }
+        script.push_str(&format!("cat > {partial}"));
+
+        let mut child = self
+            .command(&script)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| self.failed("write", format!("Could not run SSH: {err}")))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok(SshTransfer {
+            destination: self,
+            child,
+            stdin: Some(stdin),
+        })
+    }
+
+    /// The file written on the host until it's complete
+    fn partial_path(&self) -> String {
+        format!("{}.part", self.path)
+    }
+
+    /// The SSH command running `script` on the host
+    fn command(&self, script: &str) -> Command {
+        let ssh = env::var(SSH_COMMAND_VAR).unwrap_or_default();
+        let mut words = ssh.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or("ssh"));
+        command.args(words).arg(&self.host).arg(script).stdout(Stdio::null());
+        command
+    }
+
+    /// Error of the SSH command that failed to `action` the file
+    fn failed(&self, action: &str, detail: impl ToString) -> crate::Error {
+        FinalError::with_title(format!("Could not {action} '{self}' over SSH"))
+            .detail(detail.to_string())
+            .into()
+    }
+
+    /// Errors for the exit `status` of an SSH command writing the file
+    fn check(&self, status: ExitStatus) -> crate::Result<()> {
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(EXISTS_EXIT_CODE) => {
+                let error = FinalError::with_title(format!("Could not write '{self}' over SSH"))
+                    .detail("The file already exists on the host")
+                    .hint("Use '--yes' to replace it");
+                Err(error.into())
+            }
+            // Its own error was already printed by SSH
+            _ => Err(self.failed("write", format!("SSH exited with {status}"))),
+        }
+    }
+}
+
+impl std::fmt::Display for SshPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.path)
+    }
+}
+
+/// An archive being sent to an `SshPath`
+pub struct SshTransfer<'a> {
+    destination: &'a SshPath,
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl SshTransfer<'_> {
+    /// Where the archive is written
+    pub fn writer(&mut self) -> ChildStdin {
+        self.stdin.take().expect("the writer is taken once")
+    }
+
+    /// Moves the partial file to its destination, once the whole archive was written to `writer`
+    /// and it was dropped.
+    ///
+    /// That's another SSH command, since the end of the archive can't be told apart from a
+    /// dropped connection on the host.
+    pub fn finish(mut self) -> crate::Result<()> {
+        drop(self.stdin.take());
+        let destination = self.destination;
+        destination.check(self.child.wait()?)?;
+
+        let script = format!(
+            "mv -f {} {}",
+            shell_quote(&destination.partial_path()),
+            shell_quote(&destination.path)
+        );
+        let status = destination.command(&script).stdin(Stdio::null()).status()?;
+        destination.check(status)
+    }
+
+    /// Stops the transfer, removing the partial file from the host if it can, returns why the
+    /// host refused the file if it did, which is why it couldn't be written.
+    pub fn abort(mut self) -> Option<crate::Error> {
+        let refused = match self.child.try_wait() {
+            Ok(Some(status)) => self.destination.check(status).err(),
+            _ => None,
+        };
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+
+        let destination = self.destination;
+        let script = format!("rm -f {}", shell_quote(&destination.partial_path()));
+        let _ = destination.command(&script).stdin(Stdio::null()).status();
+        refused
+    }
+}
+
+/// A file read from a host, failing at its end if its SSH command did
+pub struct SshReader {
+    /// Like "user@host:/backups/home.tar.zst"
+    path: String,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for SshReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            // Its own error was already printed by SSH
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "Could not read '{}' over SSH, it exited with {status}",
+                    self.path
+                )));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for SshReader {
+    fn drop(&mut self) {
+        // Stopped before the end, like when a question is answered no
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Quotes `path` for the shell of the host, keeping the "~/" of the home directory unquoted.
+fn shell_quote(path: &str) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', r"'\''"));
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", quote(rest)),
+        None => quote(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_destination() {
+        let destination = SshPath::parse(Path::new("me@host:/backups/home.tar.zst")).unwrap();
+        assert_eq!(destination.host, "me@host");
+        assert_eq!(destination.path, "/backups/home.tar.zst");
+        assert!(SshPath::parse(Path::new("host:home.tar.zst")).is_some());
+
+        assert!(SshPath::parse(Path::new("home.tar.zst")).is_none());
+        assert!(SshPath::parse(Path::new("./a:b.tar.zst")).is_none());
+        assert!(SshPath::parse(Path::new("-oProxyCommand=x:a.tar")).is_none());
+        assert!(SshPath::parse(Path::new("host:")).is_none());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/a b/c.tar"), "'/a b/c.tar'");
+        assert_eq!(shell_quote("it's.tar"), r"'it'\''s.tar'");
+        assert_eq!(shell_quote("~/x.tar"), "~/'x.tar'");
+    }
+}
@@ -0,0 +1,102 @@
+//! `--stats`, a report of the resources used by the run, printed when it ends.
+//!
+//! The time it took isn't enough to compare formats and levels, or to tell whether a slow run was
+//! busy compressing or waiting for the disk, so the CPU time, the peak memory and the bytes read
+//! and written are reported too, where the platform tells them.
+
+use std::time::{Duration, Instant};
+
+use crate::{info, utils::Bytes};
+
+/// What the run used, besides the time it took
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Usage {
+    user_time: Duration,
+    system_time: Duration,
+    /// Largest resident set size, in bytes
+    peak_memory: u64,
+    /// Bytes read and written, if the platform counts them
+    io: Option<(u64, u64)>,
+}
+
+/// Prints the resources used by the process and its children, like `--pipeline` commands, since
+/// `start`.
+pub fn report(start: Instant) {
+    info!(accessible, "Elapsed time: {:.2}s.", start.elapsed().as_secs_f64());
+    let Some(usage) = usage() else {
+        return;
+    };
+    info!(
+        accessible,
+        "CPU time: {:.2}s user, {:.2}s system. Peak memory: {}.",
+        usage.user_time.as_secs_f64(),
+        usage.system_time.as_secs_f64(),
+        Bytes::new(usage.peak_memory)
+    );
+    if let Some((read, written)) = usage.io {
+        info!(
+            accessible,
+            "Read {}, written {}.",
+            Bytes::new(read),
+            Bytes::new(written)
+        );
+    }
+}
+
+#[cfg(unix)]
+fn usage() -> Option<Usage> {
+    let rusage = |who| {
+        // Safety: `rusage` is plain integers, for which zeros are valid
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        // Safety: `usage` is a valid `rusage` to write to
+        (unsafe { libc::getrusage(who, &mut usage) } == 0).then_some(usage)
+    };
+    let time = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    let (own, children) = (rusage(libc::RUSAGE_SELF)?, rusage(libc::RUSAGE_CHILDREN)?);
+
+    // The peak is in kibibytes, but in bytes on Apple platforms
+    let unit = if cfg!(target_vendor = "apple") { 1 } else { 1024 };
+    Some(Usage {
+        user_time: time(own.ru_utime) + time(children.ru_utime),
+        system_time: time(own.ru_stime) + time(children.ru_stime),
+        peak_memory: own.ru_maxrss.max(children.ru_maxrss) as u64 * unit,
+        io: io_counters(),
+    })
+}
+
+#[cfg(not(unix))]
+fn usage() -> Option<Usage> {
+    None
+}
+
+/// The bytes read and written by the process, counted by Linux.
+#[cfg(unix)]
+fn io_counters() -> Option<(u64, u64)> {
+    let counters = std::fs::read_to_string("/proc/self/io").ok()?;
+    parse_io_counters(&counters)
+}
+
+/// Reads the `rchar` and `wchar` lines of `/proc/self/io`.
+#[cfg(unix)]
+fn parse_io_counters(counters: &str) -> Option<(u64, u64)> {
+    let counter = |name: &str| {
+        counters
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))?
+            .parse()
+            .ok()
+    };
+    Some((counter("rchar")?, counter("wchar")?))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_counters() {
+        let counters = "rchar: 4096\nwchar: 512\nsyscr: 10\nsyscw: 2\nread_bytes: 0\nwrite_bytes: 8192\n";
+        assert_eq!(parse_io_counters(counters), Some((4096, 512)));
+        assert_eq!(parse_io_counters("syscr: 10\n"), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! `--fs-compression` and `--nocow`, which set storage properties on the output directory.
+//!
+//! Files and directories created inside of a directory inherit these properties on btrfs, so
+//! setting them on the output directory before extracting applies them to every entry.
+
+use std::path::Path;
+
+use crate::{cli::FsCompression, error::FinalError, utils::EscapedPathDisplay};
+
+fn storage_error(dir: &Path, property: &str, detail: impl ToString) -> crate::Error {
+    FinalError::with_title(format!(
+        "Could not set {property} on '{}'",
+        EscapedPathDisplay::new(dir)
+    ))
+    .detail(detail.to_string())
+    .into()
+}
+
+/// Makes the files created in `dir` compressed with `algorithm`.
+///
+/// Uses the `btrfs.compression` property, falling back to the compression inode flag, which
+/// other filesystems, like f2fs, support with their own algorithm.
+#[cfg(target_os = "linux")]
+pub fn set_compression(dir: &Path, algorithm: FsCompression) -> crate::Result<()> {
+    let value = match algorithm {
+        FsCompression::Zstd => "zstd",
+        FsCompression::Lzo => "lzo",
+        FsCompression::Zlib => "zlib",
+    };
+
+    match linux::set_xattr(dir, "btrfs.compression", value) {
+        Ok(()) => Ok(()),
+        Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => linux::add_inode_flags(dir, linux::FS_COMPR_FL)
+            .map_err(|err| storage_error(dir, "compression", format!("The filesystem doesn't support it: {err}"))),
+        Err(err) => Err(storage_error(dir, "compression", err)),
+    }
+}
+
+/// Disables copy-on-write for the files created in `dir`, as databases and disk images need.
+#[cfg(target_os = "linux")]
+pub fn set_nocow(dir: &Path) -> crate::Result<()> {
+    linux::add_inode_flags(dir, linux::FS_NOCOW_FL)
+        .map_err(|err| storage_error(dir, "NOCOW", format!("The filesystem doesn't support it: {err}")))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_compression(dir: &Path, _algorithm: FsCompression) -> crate::Result<()> {
+    Err(storage_error(
+        dir,
+        "compression",
+        "'--fs-compression' is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_nocow(dir: &Path) -> crate::Result<()> {
+    Err(storage_error(dir, "NOCOW", "'--nocow' is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    use fs_err as fs;
+
+    /// Inode flags from `linux/fs.h`, which the `libc` crate doesn't define
+    pub const FS_COMPR_FL: libc::c_int = 0x0000_0004;
+    pub const FS_NOCOW_FL: libc::c_int = 0x0080_0000;
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    pub fn set_xattr(path: &Path, name: &str, value: &str) -> io::Result<()> {
+        let path = c_path(path)?;
+        let name = CString::new(name)?;
+
+        // Safety: the strings are valid C strings, and `value` is valid for `value.len()` bytes
+        let code = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Adds `flags` to the inode flags of `path`, like `chattr +FLAGS`.
+    pub fn add_inode_flags(path: &Path, flags: libc::c_int) -> io::Result<()> {
+        let file = fs::File::open(path)?;
+        let fd = std::os::fd::AsRawFd::as_raw_fd(file.file());
+
+        let mut current: libc::c_int = 0;
+        // Safety: both ioctls read or write a single `int`, like `chattr` does
+        let updated = unsafe {
+            libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut current) == 0 && {
+                current |= flags;
+                libc::ioctl(fd, libc::FS_IOC_SETFLAGS, &current) == 0
+            }
+        };
+        if !updated {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
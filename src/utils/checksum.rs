@@ -0,0 +1,58 @@
+//! `--emit-checksum`, which hashes an archive as it's written instead of reading it again.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::cli::ChecksumAlgorithm;
+
+/// Checksum of everything written through its writers, shared with them
+#[derive(Clone)]
+pub struct Checksum(Arc<Mutex<Sha256>>);
+
+impl Checksum {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Self(Arc::new(Mutex::new(Sha256::new()))),
+        }
+    }
+
+    /// Wraps `inner`, so that what's written to it is added to the checksum.
+    pub fn writer<W>(&self, inner: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            inner,
+            checksum: self.clone(),
+        }
+    }
+
+    /// The checksum of everything written so far, in lowercase hexadecimal.
+    pub fn to_hex(&self) -> String {
+        let digest = self.0.lock().unwrap().clone().finalize();
+        digest.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+    }
+}
+
+/// Writer adding what goes through it to a [`Checksum`]
+pub struct ChecksumWriter<W> {
+    inner: W,
+    checksum: Checksum,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum.0.lock().unwrap().update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
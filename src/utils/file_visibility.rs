@@ -1,5 +1,9 @@
 use std::path::Path;
 
+use ignore::overrides::{Override, OverrideBuilder};
+
+use crate::error::FinalError;
+
 /// Determines which files should be read or ignored during directory walking
 pub struct FileVisibilityPolicy {
     /// Enables reading .ignore files.
@@ -19,6 +23,11 @@ pub struct FileVisibilityPolicy {
 
     /// Enables reading `.git/info/exclude` files.
     pub read_git_exclude: bool,
+
+    /// Patterns of `--include` and `--exclude`.
+    ///
+    /// Empty by default.
+    overrides: Override,
 }
 
 impl Default for FileVisibilityPolicy {
@@ -28,6 +37,7 @@ impl Default for FileVisibilityPolicy {
             read_hidden: true,
             read_git_ignore: false,
             read_git_exclude: false,
+            overrides: Override::empty(),
         }
     }
 }
@@ -67,9 +77,28 @@ impl FileVisibilityPolicy {
         Self { read_hidden, ..self }
     }
 
+    /// Only walks through the files matching one of `include`, if there are some, and leaves
+    /// out the files and directories matching one of `exclude`, which wins over `include`.
+    pub fn patterns(self, include: &[String], exclude: &[String]) -> crate::Result<Self> {
+        let mut builder = OverrideBuilder::new(".");
+        // Overrides are whitelists, unless they start with "!"
+        let globs = include.iter().map(|pattern| (pattern, pattern.clone()));
+        let globs = globs.chain(exclude.iter().map(|pattern| (pattern, format!("!{pattern}"))));
+        for (pattern, glob) in globs {
+            builder.add(&glob).map_err(|err| {
+                FinalError::with_title(format!("Invalid pattern '{pattern}'")).detail(err.to_string())
+            })?;
+        }
+        let overrides = builder
+            .build()
+            .map_err(|err| FinalError::with_title("Invalid patterns").detail(err.to_string()))?;
+        Ok(Self { overrides, ..self })
+    }
+
     /// Walks through a directory using [`ignore::Walk`]
     pub fn build_walker(&self, path: impl AsRef<Path>) -> ignore::Walk {
         ignore::WalkBuilder::new(path)
+            .overrides(self.overrides.clone())
             .git_exclude(self.read_git_exclude)
             .git_ignore(self.read_git_ignore)
             .ignore(self.read_ignore)
@@ -1,15 +1,24 @@
 //! Filesystem utility functions.
 
 use std::{
-    env,
+    borrow::Cow,
+    env, fmt,
     io::Read,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use fs_err as fs;
+use unicode_normalization::UnicodeNormalization;
 
 use super::user_wants_to_overwrite;
-use crate::{extension::Extension, info, utils::EscapedPathDisplay, QuestionPolicy};
+use crate::{
+    cli::{NameNormalization, SpecialFilesPolicy},
+    error::FinalError,
+    extension::{CompressionFormat, Extension},
+    info,
+    utils::{EscapedPathDisplay, FileVisibilityPolicy},
+    warning, QuestionPolicy,
+};
 
 /// Remove `path` asking the user to overwrite if necessary.
 ///
@@ -35,6 +44,29 @@ pub fn remove_file_or_dir(path: &Path) -> crate::Result<()> {
     Ok(())
 }
 
+/// Where ouch keeps its state, in the state directory of the XDG base directory specification.
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("ouch"))
+}
+
+/// Total size of the files at `paths` and inside of them, but those `file_visibility_policy` leaves
+/// out.
+pub fn total_size(paths: &[PathBuf], file_visibility_policy: &FileVisibilityPolicy) -> u64 {
+    paths
+        .iter()
+        .flat_map(|path| file_visibility_policy.build_walker(path))
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
 /// Creates a directory at the path, if there is nothing there.
 pub fn create_dir_if_non_existent(path: &Path) -> crate::Result<()> {
     if !path.exists() {
@@ -46,6 +78,16 @@ pub fn create_dir_if_non_existent(path: &Path) -> crate::Result<()> {
     Ok(())
 }
 
+/// Returns the canonical, absolute form of `path`.
+///
+/// On Windows, this avoids the `\\?\` prefix of the paths returned by `fs::canonicalize` when the
+/// path can be written without it, so `C:\dir` and `\\server\share\dir` are kept as such, which
+/// other programs and the current directory accept. Mapped network drives resolve to the latter.
+pub fn canonicalize(path: impl AsRef<Path>) -> std::io::Result<PathBuf> {
+    let path = fs::canonicalize(path)?;
+    Ok(dunce::simplified(&path).to_path_buf())
+}
+
 /// Returns current directory, but before change the process' directory to the
 /// one that contains the file pointed to by `filename`.
 pub fn cd_into_same_dir_as(filename: &Path) -> crate::Result<PathBuf> {
@@ -57,17 +99,92 @@ pub fn cd_into_same_dir_as(filename: &Path) -> crate::Result<PathBuf> {
     Ok(previous_location)
 }
 
+/// Changes the process' directory to the one `filename` should be stored relative to.
+///
+/// That is `base_directory` if `filename` is inside of it, otherwise the directory containing
+/// `filename`, both are expected to be canonicalized.
+///
+/// Returns the previous current directory, and the path to `filename` from the new one.
+pub fn cd_into_base_dir(filename: &Path, base_directory: Option<&Path>) -> crate::Result<(PathBuf, PathBuf)> {
+    let relative_to_base = base_directory.and_then(|base| Some((base, filename.strip_prefix(base).ok()?)));
+
+    match relative_to_base {
+        Some((base, relative_path)) if !relative_path.as_os_str().is_empty() => {
+            let previous_location = env::current_dir()?;
+            env::set_current_dir(base)?;
+            Ok((previous_location, relative_path.to_path_buf()))
+        }
+        _ => {
+            let previous_location = cd_into_same_dir_as(filename)?;
+            // Unwrap safety:
+            //   paths should be canonicalized by now, and the root directory rejected.
+            let filename = filename.file_name().unwrap();
+            Ok((previous_location, filename.into()))
+        }
+    }
+}
+
 /// Try to detect the file extension by looking for known magic strings
 /// Source: <https://en.wikipedia.org/wiki/List_of_file_signatures>
 pub fn try_infer_extension(path: &Path) -> Option<Extension> {
+    infer_extensions(path).into_iter().next()
+}
+
+/// Every format the contents of the file at `path` look like, most likely first, like
+/// [`infer_extensions`], but also looking inside of compressed files, so a tarball is detected as
+/// "tar.gz" rather than "gz".
+pub fn detect_format_from_magic(path: &Path) -> Vec<Extension> {
+    infer_extensions(path)
+        .into_iter()
+        .map(|extension| match extension.compression_formats {
+            [format] if !format.is_archive_format() => {
+                detect_compressed_tar(path, &extension, *format).unwrap_or(extension)
+            }
+            _ => extension,
+        })
+        .collect()
+}
+
+/// "tar.gz" if the file at `path`, compressed with `format`, decompresses into a tar archive.
+fn detect_compressed_tar(path: &Path, extension: &Extension, format: CompressionFormat) -> Option<Extension> {
+    use crate::extension::CompressionFormat::*;
+    let formats: &'static [CompressionFormat] = match format {
+        Gzip => &[Tar, Gzip],
+        Bzip => &[Tar, Bzip],
+        Lz4 => &[Tar, Lz4],
+        Lzma => &[Tar, Lzma],
+        Snappy => &[Tar, Snappy],
+        Lzip => &[Tar, Lzip],
+        Lzw => &[Tar, Lzw],
+        Lzop => &[Tar, Lzop],
+        Zstd => &[Tar, Zstd],
+        _ => return None,
+    };
+
+    // Only the header of the first entry is decompressed, errors just mean it isn't a tarball
+    let file = std::fs::File::open(path).ok()?;
+    let decoder = crate::archive::nested::chain_decoder(&format, Box::new(file)).ok()?;
+    let mut head = vec![];
+    decoder.take(512).read_to_end(&mut head).ok()?;
+    is_tar(&head).then(|| Extension::new(formats, format!("tar.{extension}")))
+}
+
+fn is_tar(buf: &[u8]) -> bool {
+    buf.len() > 261 && buf[257..=261] == [0x75, 0x73, 0x74, 0x61, 0x72]
+}
+
+/// Every format the contents of the file at `path` look like, most likely first.
+///
+/// Their signatures are at different offsets, so a file can look like more than one.
+pub fn infer_extensions(path: &Path) -> Vec<Extension> {
+    /// The signature of ISO 9660 images is in their first volume descriptor, after 32 KiB
+    const ISO_MAGIC_OFFSET: usize = 16 * 2048 + 1;
+
     fn is_zip(buf: &[u8]) -> bool {
         buf.len() >= 3
             && buf[..=1] == [0x50, 0x4B]
             && (buf[2..=3] == [0x3, 0x4] || buf[2..=3] == [0x5, 0x6] || buf[2..=3] == [0x7, 0x8])
     }
-    fn is_tar(buf: &[u8]) -> bool {
-        buf.len() > 261 && buf[257..=261] == [0x75, 0x73, 0x74, 0x61, 0x72]
-    }
     fn is_gz(buf: &[u8]) -> bool {
         buf.starts_with(&[0x1F, 0x8B, 0x8])
     }
@@ -83,6 +200,15 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
     fn is_sz(buf: &[u8]) -> bool {
         buf.starts_with(&[0xFF, 0x06, 0x00, 0x00, 0x73, 0x4E, 0x61, 0x50, 0x70, 0x59])
     }
+    fn is_lz(buf: &[u8]) -> bool {
+        buf.starts_with(b"LZIP")
+    }
+    fn is_z(buf: &[u8]) -> bool {
+        buf.starts_with(&[0x1F, 0x9D])
+    }
+    fn is_lzo(buf: &[u8]) -> bool {
+        buf.starts_with(&[0x89, 0x4C, 0x5A, 0x4F, 0x00, 0x0D, 0x0A, 0x1A, 0x0A])
+    }
     fn is_zst(buf: &[u8]) -> bool {
         buf.starts_with(&[0x28, 0xB5, 0x2F, 0xFD])
     }
@@ -94,44 +220,73 @@ pub fn try_infer_extension(path: &Path) -> Option<Extension> {
     fn is_sevenz(buf: &[u8]) -> bool {
         buf.starts_with(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C])
     }
+    fn is_deb(buf: &[u8]) -> bool {
+        buf.starts_with(b"!<arch>\ndebian-binary")
+    }
+    fn is_ar(buf: &[u8]) -> bool {
+        buf.starts_with(b"!<arch>\n") && !is_deb(buf)
+    }
+    fn is_rpm(buf: &[u8]) -> bool {
+        buf.starts_with(&[0xED, 0xAB, 0xEE, 0xDB])
+    }
+    fn is_iso(buf: &[u8]) -> bool {
+        buf.get(ISO_MAGIC_OFFSET..ISO_MAGIC_OFFSET + 5) == Some(b"CD001")
+    }
+    fn is_cab(buf: &[u8]) -> bool {
+        buf.starts_with(b"MSCF\0\0\0\0")
+    }
+    fn is_squashfs(buf: &[u8]) -> bool {
+        buf.starts_with(b"hsqs")
+    }
+    fn is_cpio(buf: &[u8]) -> bool {
+        buf.starts_with(b"070701") || buf.starts_with(b"070702") || buf.starts_with(b"070707")
+    }
 
     let buf = {
-        let mut buf = [0; 270];
+        let mut buf = vec![];
 
         // Error cause will be ignored, so use std::fs instead of fs_err
-        let result = std::fs::File::open(path).map(|mut file| file.read(&mut buf));
+        let result =
+            std::fs::File::open(path).and_then(|file| file.take(ISO_MAGIC_OFFSET as u64 + 5).read_to_end(&mut buf));
 
         // In case of file open or read failure, could not infer a extension
         if result.is_err() {
-            return None;
+            return vec![];
         }
+        // Short files are padded with zeros, for the signatures that are a bit into the file
+        buf.resize(buf.len().max(270), 0);
         buf
     };
 
     use crate::extension::CompressionFormat::*;
-    if is_zip(&buf) {
-        Some(Extension::new(&[Zip], "zip"))
-    } else if is_tar(&buf) {
-        Some(Extension::new(&[Tar], "tar"))
-    } else if is_gz(&buf) {
-        Some(Extension::new(&[Gzip], "gz"))
-    } else if is_bz2(&buf) {
-        Some(Extension::new(&[Bzip], "bz2"))
-    } else if is_xz(&buf) {
-        Some(Extension::new(&[Lzma], "xz"))
-    } else if is_lz4(&buf) {
-        Some(Extension::new(&[Lz4], "lz4"))
-    } else if is_sz(&buf) {
-        Some(Extension::new(&[Snappy], "sz"))
-    } else if is_zst(&buf) {
-        Some(Extension::new(&[Zstd], "zst"))
-    } else if is_rar(&buf) {
-        Some(Extension::new(&[Rar], "rar"))
-    } else if is_sevenz(&buf) {
-        Some(Extension::new(&[SevenZip], "7z"))
-    } else {
-        None
-    }
+    type Signature = fn(&[u8]) -> bool;
+    let checks: [(Signature, Extension); 20] = [
+        (is_zip, Extension::new(&[Zip], "zip")),
+        (is_tar, Extension::new(&[Tar], "tar")),
+        (is_gz, Extension::new(&[Gzip], "gz")),
+        (is_bz2, Extension::new(&[Bzip], "bz2")),
+        (is_xz, Extension::new(&[Lzma], "xz")),
+        (is_lz4, Extension::new(&[Lz4], "lz4")),
+        (is_sz, Extension::new(&[Snappy], "sz")),
+        (is_lz, Extension::new(&[Lzip], "lz")),
+        (is_z, Extension::new(&[Lzw], "Z")),
+        (is_lzo, Extension::new(&[Lzop], "lzo")),
+        (is_zst, Extension::new(&[Zstd], "zst")),
+        (is_rar, Extension::new(&[Rar], "rar")),
+        (is_sevenz, Extension::new(&[SevenZip], "7z")),
+        (is_cpio, Extension::new(&[Cpio], "cpio")),
+        (is_deb, Extension::new(&[Deb], "deb")),
+        (is_rpm, Extension::new(&[Rpm], "rpm")),
+        (is_ar, Extension::new(&[Ar], "ar")),
+        (is_iso, Extension::new(&[Iso], "iso")),
+        (is_cab, Extension::new(&[Cab], "cab")),
+        (is_squashfs, Extension::new(&[Squashfs], "squashfs")),
+    ];
+    checks
+        .into_iter()
+        .filter(|(matches, _)| matches(&buf))
+        .map(|(_, extension)| extension)
+        .collect()
 }
 
 /// Returns true if a path is a symlink.
@@ -142,3 +297,216 @@ pub fn is_symlink(path: &Path) -> bool {
         .map(|m| m.file_type().is_symlink())
         .unwrap_or(false)
 }
+
+/// Files that are neither regular files, directories nor symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialFileKind {
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl SpecialFileKind {
+    /// Returns `None` if `metadata` belongs to a regular file, directory or symlink.
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let file_type = metadata.file_type();
+        if file_type.is_fifo() {
+            Some(Self::Fifo)
+        } else if file_type.is_socket() {
+            Some(Self::Socket)
+        } else if file_type.is_char_device() {
+            Some(Self::CharDevice)
+        } else if file_type.is_block_device() {
+            Some(Self::BlockDevice)
+        } else {
+            None
+        }
+    }
+
+    /// Special files only exist on Unix.
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &std::fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+impl fmt::Display for SpecialFileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Self::Fifo => "FIFO",
+            Self::Socket => "socket",
+            Self::CharDevice => "character device",
+            Self::BlockDevice => "block device",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Decides, following `policy`, if the special file at `path` should be added to an archive of
+/// `format`.
+///
+/// * `Ok(true)` means the file should be stored
+/// * `Ok(false)` means the file should be skipped, a warning was already printed
+/// * `Err(_)` if the policy forbids special files
+pub fn should_store_special_file(
+    path: &Path,
+    kind: SpecialFileKind,
+    policy: SpecialFilesPolicy,
+    format: CompressionFormat,
+) -> crate::Result<bool> {
+    // Tar and cpio can represent every special file but sockets, which only exist while a process
+    // listens on them
+    let format_can_store = matches!(format, CompressionFormat::Tar | CompressionFormat::Cpio);
+    match policy {
+        SpecialFilesPolicy::Store if format_can_store && kind != SpecialFileKind::Socket => Ok(true),
+        SpecialFilesPolicy::Store if kind == SpecialFileKind::Socket => {
+            warning!(
+                "'{}' is a socket, which no archive format can store, skipping...",
+                EscapedPathDisplay::new(path)
+            );
+            Ok(false)
+        }
+        SpecialFilesPolicy::Store => {
+            warning!(
+                "'{}' is a {kind}, which {format} archives can't store, skipping... tar and cpio archives can store it",
+                EscapedPathDisplay::new(path)
+            );
+            Ok(false)
+        }
+        SpecialFilesPolicy::Skip => {
+            warning!("'{}' is a {kind}, skipping...", EscapedPathDisplay::new(path));
+            Ok(false)
+        }
+        SpecialFilesPolicy::Error => {
+            let error = FinalError::with_title(format!("Cannot compress '{}'", EscapedPathDisplay::new(path)))
+                .detail(format!("It is a {kind}, not a regular file or directory"))
+                .hint("Use '--special-files skip' to leave special files out of the archive")
+                .hint("Use '--special-files store' to keep them, in tar and cpio archives");
+            Err(error.into())
+        }
+    }
+}
+
+/// Joins the path of an archive entry onto `output_folder`, like archive backends do when unpacking.
+///
+/// Returns `None` if the entry would end up outside of `output_folder`.
+pub fn entry_destination(output_folder: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut destination = output_folder.to_path_buf();
+
+    for component in entry_path.components() {
+        match component {
+            Component::Normal(part) => destination.push(part),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir => return None,
+        }
+    }
+
+    Some(destination)
+}
+
+/// `path` converted to the Unicode normalization form `form`, names that aren't valid UTF-8 are
+/// kept as they are.
+pub fn normalize_path(path: &Path, form: NameNormalization) -> Cow<'_, Path> {
+    let Some(name) = path.to_str() else {
+        return Cow::Borrowed(path);
+    };
+    let normalized: String = match form {
+        NameNormalization::None => return Cow::Borrowed(path),
+        NameNormalization::Nfc => name.nfc().collect(),
+        NameNormalization::Nfd => name.nfd().collect(),
+    };
+
+    if normalized == name {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(PathBuf::from(normalized))
+    }
+}
+
+/// Whether names in `dir` that only differ by case refer to the same file, like on macOS and
+/// Windows by default.
+pub fn is_case_insensitive(dir: &Path) -> crate::Result<bool> {
+    let probe = tempfile::Builder::new().prefix(".ouch-case-probe-").tempfile_in(dir)?;
+    let name = probe.path().file_name().expect("temporary files have a name");
+    let uppercase = name.to_string_lossy().to_uppercase();
+
+    Ok(dir.join(uppercase).exists())
+}
+
+/// The longest file name, in bytes, that can be created in `dir`.
+///
+/// Usually 255, but shorter on some filesystems, like ecryptfs.
+#[cfg(unix)]
+pub fn max_name_length(dir: &Path) -> usize {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(c_path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return DEFAULT_MAX_NAME_LENGTH;
+    };
+    // Safety: `c_path` is a valid C string
+    let length = unsafe { libc::pathconf(c_path.as_ptr(), libc::_PC_NAME_MAX) };
+    usize::try_from(length).unwrap_or(DEFAULT_MAX_NAME_LENGTH)
+}
+
+#[cfg(not(unix))]
+pub fn max_name_length(_dir: &Path) -> usize {
+    DEFAULT_MAX_NAME_LENGTH
+}
+
+/// The limit of most filesystems, used when it can't be queried
+const DEFAULT_MAX_NAME_LENGTH: usize = 255;
+
+/// The major and minor numbers of the device `rdev`, in the encoding of glibc.
+#[cfg(unix)]
+pub fn device_numbers(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0x0000_0fff);
+    let minor = ((rdev >> 12) & 0xffff_ff00) | (rdev & 0x0000_00ff);
+    (major as u32, minor as u32)
+}
+
+/// Creates a character or block device node at `path`, with the given permission bits.
+#[cfg(unix)]
+pub fn create_device(path: &Path, kind: SpecialFileKind, mode: u32, major: u32, minor: u32) -> crate::Result<()> {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::from)?;
+    let kind = if kind == SpecialFileKind::CharDevice {
+        libc::S_IFCHR
+    } else {
+        libc::S_IFBLK
+    };
+    let mode = kind | (mode & 0o7777) as libc::mode_t;
+
+    // Safety: `c_path` is a valid null-terminated string that outlives the call
+    if unsafe { libc::mknod(c_path.as_ptr(), mode, libc::makedev(major, minor)) } != 0 {
+        let err = io::Error::last_os_error();
+        let error = FinalError::with_title(format!(
+            "Could not create device node '{}'",
+            EscapedPathDisplay::new(path)
+        ))
+        .detail(format!("Error: {err}."))
+        .hint("Creating device nodes usually requires running as root.");
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Creates a FIFO (named pipe) at `path` with the given permission bits.
+#[cfg(unix)]
+pub fn create_fifo(path: &Path, mode: u32) -> crate::Result<()> {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::from)?;
+
+    // Safety: `c_path` is a valid null-terminated string that outlives the call
+    if unsafe { libc::mkfifo(c_path.as_ptr(), mode as libc::mode_t) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
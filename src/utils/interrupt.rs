@@ -0,0 +1,55 @@
+//! Ctrl-C during extractions, which cancels them instead of killing the process.
+//!
+//! An extraction killed halfway leaves its temporary directory behind, while a cancelled one
+//! removes it and tells how far it got. A second Ctrl-C kills the process as usual, for
+//! extractions that don't stop quickly enough.
+
+use once_cell::sync::OnceCell;
+
+use crate::archive::cancel::CancellationToken;
+
+/// The token cancelled by Ctrl-C, the signal handler can't be given anything else
+static INTERRUPT_TOKEN: OnceCell<CancellationToken> = OnceCell::new();
+
+/// Makes Ctrl-C cancel `token` instead of killing the process, until the guard is dropped.
+///
+/// Only the first token given is ever cancelled.
+pub fn cancel_on_interrupt(token: &CancellationToken) -> InterruptGuard {
+    let _ = INTERRUPT_TOKEN.set(token.clone());
+    #[cfg(unix)]
+    // Safety: the handler only does async-signal-safe work
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    InterruptGuard
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    const MESSAGE: &[u8] = b"\nCancelling, press Ctrl-C again to stop at once.\n";
+
+    if let Some(token) = INTERRUPT_TOKEN.get() {
+        token.cancel();
+    }
+    // Safety: both are async-signal-safe, and the next Ctrl-C kills the process
+    unsafe {
+        libc::write(libc::STDERR_FILENO, MESSAGE.as_ptr().cast(), MESSAGE.len());
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Restores the default behavior of Ctrl-C when dropped
+pub struct InterruptGuard;
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // Safety: restoring the default disposition is always sound
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+}
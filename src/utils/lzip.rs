@@ -0,0 +1,289 @@
+//! The lzip format, made of members that each have a header, an LZMA stream ended by an end
+//! marker, and a trailer with the CRC32 and the sizes of the member.
+//!
+//! The LZMA stream is the same as the one of .lzma files whose size isn't known, so it's coded
+//! by liblzma, as if it followed a .lzma header made up from the lzip one.
+
+use std::io::{self, BufRead, Read, Write};
+
+use liblzma::{
+    stream::{Action, LzmaOptions, Status, Stream},
+    write::XzEncoder,
+};
+
+const MAGIC: &[u8; 4] = b"LZIP";
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 6;
+const TRAILER_SIZE: usize = 20;
+/// Size of the header of .lzma files, which lzip members don't have
+const LZMA_HEADER_SIZE: usize = 13;
+/// The literal context, literal position and position bits, 3, 0 and 2, which lzip always uses
+const LZMA_PROPERTIES: u8 = (2 * 5) * 9 + 3;
+
+/// Dictionary sizes of the levels, powers of two like the ones of xz
+const DICTIONARY_SIZES: [u32; 10] = [
+    1 << 18,
+    1 << 20,
+    1 << 21,
+    1 << 22,
+    1 << 22,
+    1 << 23,
+    1 << 23,
+    1 << 24,
+    1 << 25,
+    1 << 26,
+];
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decoder of lzip files, which may have several members
+pub struct LzipDecoder<R: BufRead> {
+    reader: R,
+    /// Decoder of the LZMA stream of the current member, `None` between members
+    member: Option<Stream>,
+    crc: crc32fast::Hasher,
+    size: u64,
+    /// Whether a member was read, files have at least one
+    started: bool,
+}
+
+impl<R: BufRead> LzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            member: None,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            started: false,
+        }
+    }
+
+    /// Reads the header of the next member, returns `false` at the end of the file.
+    fn start_member(&mut self) -> io::Result<bool> {
+        if self.started && self.reader.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+
+        let mut header = [0; HEADER_SIZE as usize];
+        self.reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not an lzip file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported lzip version"));
+        }
+
+        // A power of two, minus up to 7 sixteenths of it
+        let exponent = u32::from(header[5] & 0x1F);
+        if !(12..=29).contains(&exponent) {
+            return Err(invalid_data("invalid lzip dictionary size"));
+        }
+        let dictionary_size = (1u32 << exponent) - (1u32 << exponent) / 16 * u32::from(header[5] >> 5);
+
+        let mut lzma_header = [0xFF; LZMA_HEADER_SIZE];
+        lzma_header[0] = LZMA_PROPERTIES;
+        lzma_header[1..5].copy_from_slice(&dictionary_size.to_le_bytes());
+        let mut stream = Stream::new_lzma_decoder(u64::MAX)?;
+        // Nothing is decoded out of the header, but the output can't be empty
+        while stream.total_in() < LZMA_HEADER_SIZE as u64 {
+            stream.process(&lzma_header[stream.total_in() as usize..], &mut [0], Action::Run)?;
+        }
+
+        self.member = Some(stream);
+        self.started = true;
+        Ok(true)
+    }
+
+    /// Checks the trailer of the member that was decoded.
+    fn finish_member(&mut self) -> io::Result<()> {
+        self.member = None;
+        let mut trailer = [0; TRAILER_SIZE];
+        self.reader.read_exact(&mut trailer)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u64::from_le_bytes(trailer[4..12].try_into().unwrap());
+        if crc != std::mem::take(&mut self.crc).finalize() || size != self.size {
+            return Err(invalid_data("corrupted lzip member"));
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for LzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(stream) = &mut self.member else {
+                if !self.start_member()? {
+                    return Ok(0);
+                }
+                continue;
+            };
+
+            let input = self.reader.fill_buf()?;
+            let eof = input.is_empty();
+            let (total_in, total_out) = (stream.total_in(), stream.total_out());
+            let status = stream.process(input, buf, if eof { Action::Finish } else { Action::Run })?;
+            let consumed = (stream.total_in() - total_in) as usize;
+            let read = (stream.total_out() - total_out) as usize;
+            self.reader.consume(consumed);
+            self.crc.update(&buf[..read]);
+            self.size += read as u64;
+
+            if status == Status::StreamEnd {
+                self.finish_member()?;
+            } else if eof && read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated lzip member"));
+            }
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// Encoder of lzip files, with a single member
+pub struct LzipEncoder<W: Write> {
+    /// `None` once finished
+    encoder: Option<XzEncoder<MemberWriter<W>>>,
+    crc: crc32fast::Hasher,
+    size: u64,
+    /// Whether the trailer was written
+    finished: bool,
+}
+
+impl<W: Write> LzipEncoder<W> {
+    /// Writes the header of the member, compressing with `level`, from 0 to 9.
+    pub fn new(mut writer: W, level: u32) -> io::Result<Self> {
+        let dictionary_size = DICTIONARY_SIZES[level.min(9) as usize];
+        let mut header = [0; HEADER_SIZE as usize];
+        header[..4].copy_from_slice(MAGIC);
+        header[4] = VERSION;
+        header[5] = dictionary_size.trailing_zeros() as u8;
+        writer.write_all(&header)?;
+
+        let mut options = LzmaOptions::new_preset(level.min(9))?;
+        options
+            .dict_size(dictionary_size)
+            .literal_context_bits(3)
+            .literal_position_bits(0)
+            .position_bits(2);
+        let stream = Stream::new_lzma_encoder(&options)?;
+        let writer = MemberWriter {
+            inner: writer,
+            skipped: 0,
+            written: HEADER_SIZE,
+        };
+
+        Ok(Self {
+            encoder: Some(XzEncoder::new_stream(writer, stream)),
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            finished: false,
+        })
+    }
+
+    /// Ends the member with its trailer, returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.encoder.take().expect("the encoder was finished").finish()?.inner)
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        let Some(encoder) = self.encoder.as_mut().filter(|_| !self.finished) else {
+            return Ok(());
+        };
+        encoder.try_finish()?;
+
+        let writer = encoder.get_mut();
+        let mut trailer = [0; TRAILER_SIZE];
+        trailer[..4].copy_from_slice(&self.crc.clone().finalize().to_le_bytes());
+        trailer[4..12].copy_from_slice(&self.size.to_le_bytes());
+        trailer[12..].copy_from_slice(&(writer.written + TRAILER_SIZE as u64).to_le_bytes());
+        writer.inner.write_all(&trailer)?;
+        writer.inner.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for LzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let encoder = self.encoder.as_mut().expect("the encoder is only taken when finished");
+        let written = encoder.write(buf)?;
+        self.crc.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // liblzma can't flush .lzma streams in the middle, what was coded so far is written though
+        match &mut self.encoder {
+            Some(encoder) => encoder.get_mut().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for LzipEncoder<W> {
+    fn drop(&mut self) {
+        // Like the other encoders, errors can only be seen by finishing explicitly
+        let _ = self.try_finish();
+    }
+}
+
+/// Writer leaving out the .lzma header written by liblzma, counting the bytes of the member
+struct MemberWriter<W> {
+    inner: W,
+    /// Bytes of the .lzma header left out so far
+    skipped: usize,
+    /// Bytes of the member written so far, without its trailer
+    written: u64,
+}
+
+impl<W: Write> Write for MemberWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let skip = buf.len().min(LZMA_HEADER_SIZE - self.skipped);
+        self.skipped += skip;
+        if skip == buf.len() {
+            return Ok(skip);
+        }
+        let written = self.inner.write(&buf[skip..])?;
+        self.written += written as u64;
+        Ok(skip + written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lzip_roundtrip() {
+        let data: Vec<u8> = (0..200_000u32).flat_map(|i| (i % 1000).to_le_bytes()).collect();
+
+        let mut encoder = LzipEncoder::new(vec![], 6).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.flush().unwrap();
+        let member = encoder.finish().unwrap();
+        assert_eq!(&member[..4], MAGIC);
+        let member_size = u64::from_le_bytes(member[member.len() - 8..].try_into().unwrap());
+        assert_eq!(member_size, member.len() as u64);
+
+        // Files may be made of several members
+        let file = [&member[..], &member[..]].concat();
+        let mut decoded = vec![];
+        LzipDecoder::new(&file[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, [&data[..], &data[..]].concat());
+
+        let mut corrupted = member.clone();
+        let crc_offset = corrupted.len() - TRAILER_SIZE;
+        corrupted[crc_offset] ^= 1;
+        assert!(LzipDecoder::new(&corrupted[..]).read_to_end(&mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! The lzop format, a header followed by blocks of LZO1X data, each with its sizes and checksums,
+//! ended by an empty block.
+//!
+//! Blocks are compressed by `lzokay_native`, but decompressed here, since its decompressor
+//! panics on corrupted data.
+
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 9] = [0x89, b'L', b'Z', b'O', 0x00, 0x0D, 0x0A, 0x1A, 0x0A];
+/// Version of lzop the files are written like
+const VERSION: u16 = 0x1030;
+/// Version of the LZO library the files are written like
+const LIB_VERSION: u16 = 0x2080;
+/// Version of lzop needed to read the files, the first one with the current header
+const VERSION_NEEDED: u16 = 0x0940;
+/// Method of the blocks, LZO1X-999 like the compressor
+const METHOD_LZO1X_999: u8 = 3;
+const LEVEL: u8 = 9;
+
+const F_ADLER32_D: u32 = 0x1;
+const F_ADLER32_C: u32 = 0x2;
+const F_H_EXTRA_FIELD: u32 = 0x40;
+const F_CRC32_D: u32 = 0x100;
+const F_CRC32_C: u32 = 0x200;
+const F_H_FILTER: u32 = 0x800;
+const F_H_CRC32: u32 = 0x1000;
+const F_OS_UNIX: u32 = 0x0300_0000;
+
+/// Size of the blocks written, the one of lzop
+const BLOCK_SIZE: usize = 256 * 1024;
+/// Largest blocks read, the limit of lzop
+const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums of up to 5552 bytes can't overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Reader keeping a copy of what it reads, for the checksum of the header
+struct Recorder<'a, R> {
+    inner: &'a mut R,
+    read: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Compressed data of a block, read instruction by instruction
+struct BlockReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BlockReader<'a> {
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or_else(|| invalid_data("corrupted lzop block"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<usize> {
+        Ok(usize::from(self.bytes(1)?[0]))
+    }
+
+    fn u16_le(&mut self) -> io::Result<usize> {
+        let bytes = self.bytes(2)?;
+        Ok(usize::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+    }
+
+    /// Reads the rest of a length that didn't fit in its instruction, 255 for each zero byte
+    /// followed by the last byte, added to `base`.
+    fn long_length(&mut self, base: usize) -> io::Result<usize> {
+        let mut length = base;
+        loop {
+            match self.byte()? {
+                0 => length += 255,
+                byte => return Ok(length + byte),
+            }
+        }
+    }
+}
+
+/// Decompresses a block of LZO1X data into at most `size` bytes, checking every length and
+/// distance instead of trusting the data.
+pub fn decompress_lzo1x(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let corrupted = || invalid_data("corrupted LZO1X data");
+    let mut reader = BlockReader { data, position: 0 };
+    let mut block = Vec::with_capacity(size);
+    let copy_literals = |block: &mut Vec<u8>, reader: &mut BlockReader, count: usize| {
+        if block.len() + count > size {
+            return Err(corrupted());
+        }
+        block.extend_from_slice(reader.bytes(count)?);
+        Ok(())
+    };
+
+    // Literals the previous instruction copied, which changes the meaning of the short ones
+    let mut state = 0;
+    match data.first() {
+        Some(&first) if first >= 18 => {
+            reader.position += 1;
+            let count = usize::from(first) - 17;
+            copy_literals(&mut block, &mut reader, count)?;
+            state = if count >= 4 { 4 } else { count };
+        }
+        _ => {}
+    }
+
+    loop {
+        let instruction = reader.byte()?;
+        let (distance, length, next_state) = if instruction >= 64 {
+            // Copy of 3 to 8 bytes within 2 KiB
+            let distance = (reader.byte()? << 3) + ((instruction >> 2) & 7) + 1;
+            (distance, (instruction >> 5) + 1, instruction & 3)
+        } else if instruction >= 32 {
+            // Copy within 16 KiB
+            let length = match instruction & 31 {
+                0 => reader.long_length(31)? + 2,
+                length => length + 2,
+            };
+            let distance = reader.u16_le()?;
+            ((distance >> 2) + 1, length, distance & 3)
+        } else if instruction >= 16 {
+            // Copy within 16 to 48 KiB, or the end of the data
+            let length = match instruction & 7 {
+                0 => reader.long_length(7)? + 2,
+                length => length + 2,
+            };
+            let distance = reader.u16_le()?;
+            let offset = ((instruction & 8) << 11) + (distance >> 2);
+            if offset == 0 {
+                if length != 3 {
+                    return Err(corrupted());
+                }
+                break;
+            }
+            (offset + 16384, length, distance & 3)
+        } else if state == 0 {
+            // Run of 4 or more literals
+            let count = match instruction {
+                0 => reader.long_length(15)? + 3,
+                count => count + 3,
+            };
+            copy_literals(&mut block, &mut reader, count)?;
+            state = 4;
+            continue;
+        } else if state < 4 {
+            // Copy of 2 bytes within 1 KiB
+            let distance = (instruction >> 2) + (reader.byte()? << 2) + 1;
+            (distance, 2, instruction & 3)
+        } else {
+            // Copy of 3 bytes from 2 to 3 KiB
+            let distance = (instruction >> 2) + (reader.byte()? << 2) + 2049;
+            (distance, 3, instruction & 3)
+        };
+
+        if distance > block.len() || block.len() + length > size {
+            return Err(corrupted());
+        }
+        // Copies may overlap what they write, repeating it
+        let start = block.len() - distance;
+        for i in start..start + length {
+            block.push(block[i]);
+        }
+        copy_literals(&mut block, &mut reader, next_state)?;
+        state = next_state;
+    }
+
+    Ok(block)
+}
+
+/// Decoder of lzop files
+pub struct LzopDecoder<R: Read> {
+    reader: R,
+    /// Flags of the header, `None` until it's read
+    flags: Option<u32>,
+    /// Decompressed block, and how much of it was given out
+    block: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> LzopDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            flags: None,
+            block: vec![],
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Reads the header, returns its flags.
+    fn read_header(&mut self) -> io::Result<u32> {
+        let mut magic = [0; MAGIC.len()];
+        self.reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not an lzop file"));
+        }
+
+        let mut header = Recorder {
+            inner: &mut self.reader,
+            read: vec![],
+        };
+        let mut fields = [0; 4];
+        header.read_exact(&mut fields)?;
+        let version = u16::from_be_bytes([fields[0], fields[1]]);
+        if version < 0x0900 {
+            return Err(invalid_data("unsupported lzop version"));
+        }
+        // Before 0.94, the header had no version needed, level or high bits of the time
+        let is_recent = version >= 0x0940;
+        if is_recent {
+            header.read_exact(&mut [0; 2])?;
+        }
+        let mut method = [0];
+        header.read_exact(&mut method)?;
+        if !(1..=3).contains(&method[0]) {
+            return Err(invalid_data("unsupported lzop method"));
+        }
+        if is_recent {
+            header.read_exact(&mut [0])?;
+        }
+        let flags = read_u32(&mut header)?;
+        if flags & F_H_FILTER != 0 {
+            return Err(invalid_data("lzop filters are not supported"));
+        }
+        // Mode and time, then the name
+        header.read_exact(&mut vec![0; if is_recent { 12 } else { 8 }])?;
+        let mut name_length = [0];
+        header.read_exact(&mut name_length)?;
+        header.read_exact(&mut vec![0; usize::from(name_length[0])])?;
+
+        let recorded = std::mem::take(&mut header.read);
+        let checksum = read_u32(&mut self.reader)?;
+        let expected = if flags & F_H_CRC32 != 0 {
+            crc32fast::hash(&recorded)
+        } else {
+            adler32(&recorded)
+        };
+        if checksum != expected {
+            return Err(invalid_data("corrupted lzop header"));
+        }
+
+        if flags & F_H_EXTRA_FIELD != 0 {
+            let length = read_u32(&mut self.reader)?;
+            io::copy(&mut (&mut self.reader).take(u64::from(length) + 4), &mut io::sink())?;
+        }
+        Ok(flags)
+    }
+
+    /// Reads the next block, returns `false` after the last one.
+    fn read_block(&mut self, flags: u32) -> io::Result<bool> {
+        let size = read_u32(&mut self.reader)? as usize;
+        if size == 0 {
+            return Ok(false);
+        }
+        let compressed_size = read_u32(&mut self.reader)? as usize;
+        if size > MAX_BLOCK_SIZE || compressed_size > size {
+            return Err(invalid_data("corrupted lzop block"));
+        }
+
+        let mut checksums = vec![];
+        for flag in [F_ADLER32_D, F_CRC32_D] {
+            if flags & flag != 0 {
+                checksums.push((flag, read_u32(&mut self.reader)?));
+            }
+        }
+        let is_compressed = compressed_size < size;
+        if is_compressed {
+            for flag in [F_ADLER32_C, F_CRC32_C] {
+                if flags & flag != 0 {
+                    checksums.push((flag, read_u32(&mut self.reader)?));
+                }
+            }
+        }
+
+        let mut data = vec![0; compressed_size];
+        self.reader.read_exact(&mut data)?;
+        let block = if is_compressed {
+            decompress_lzo1x(&data, size)?
+        } else {
+            // Checksums of compressed data are only there for compressed blocks
+            std::mem::take(&mut data)
+        };
+        if block.len() != size {
+            return Err(invalid_data("corrupted lzop block"));
+        }
+
+        for (flag, checksum) in checksums {
+            let expected = match flag {
+                F_ADLER32_D => adler32(&block),
+                F_CRC32_D => crc32fast::hash(&block),
+                F_ADLER32_C => adler32(&data),
+                _ => crc32fast::hash(&data),
+            };
+            if checksum != expected {
+                return Err(invalid_data("corrupted lzop block"));
+            }
+        }
+
+        self.block = block;
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LzopDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let flags = match self.flags {
+            Some(flags) => flags,
+            None => {
+                let flags = self.read_header()?;
+                self.flags = Some(flags);
+                flags
+            }
+        };
+
+        while self.position == self.block.len() {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            self.done = !self.read_block(flags)?;
+        }
+
+        let read = buf.len().min(self.block.len() - self.position);
+        buf[..read].copy_from_slice(&self.block[self.position..][..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Encoder of lzop files
+pub struct LzopEncoder<W: Write> {
+    /// `None` once finished
+    writer: Option<W>,
+    /// Data of the block being filled
+    block: Vec<u8>,
+    /// Whether the end of the file was written
+    finished: bool,
+}
+
+impl<W: Write> LzopEncoder<W> {
+    /// Writes the header of the file.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = vec![];
+        header.extend_from_slice(&VERSION.to_be_bytes());
+        header.extend_from_slice(&LIB_VERSION.to_be_bytes());
+        header.extend_from_slice(&VERSION_NEEDED.to_be_bytes());
+        header.extend_from_slice(&[METHOD_LZO1X_999, LEVEL]);
+        header.extend_from_slice(&(F_ADLER32_D | F_OS_UNIX).to_be_bytes());
+        // Mode, times and name of the file, unknown like for standard input
+        header.extend_from_slice(&[0; 13]);
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&header)?;
+        writer.write_all(&adler32(&header).to_be_bytes())?;
+
+        Ok(Self {
+            writer: Some(writer),
+            block: Vec::with_capacity(BLOCK_SIZE),
+            finished: false,
+        })
+    }
+
+    fn writer(&mut self) -> &mut W {
+        self.writer.as_mut().expect("the writer is only taken when finished")
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let compressed = lzokay_native::compress(&self.block).map_err(|err| io::Error::other(err.to_string()))?;
+        let block = std::mem::take(&mut self.block);
+        // Blocks that don't get smaller are stored
+        let data = if compressed.len() < block.len() {
+            &compressed
+        } else {
+            &block
+        };
+
+        let writer = self.writer();
+        writer.write_all(&(block.len() as u32).to_be_bytes())?;
+        writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        writer.write_all(&adler32(&block).to_be_bytes())?;
+        writer.write_all(data)?;
+        self.block = block;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the end of the file, returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer.take().expect("the writer is only taken when finished"))
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        let writer = self.writer();
+        writer.write_all(&0u32.to_be_bytes())?;
+        writer.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for LzopEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = buf.len().min(BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..written]);
+        if self.block.len() == BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Blocks can be written early, they're independent
+        self.write_block()?;
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for LzopEncoder<W> {
+    fn drop(&mut self) {
+        // Like the other encoders, errors can only be seen by finishing explicitly
+        let _ = self.try_finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lzop_roundtrip() {
+        let data: Vec<u8> = (0..200_000u32).flat_map(|i| (i % 1000).to_le_bytes()).collect();
+
+        let mut encoder = LzopEncoder::new(vec![]).unwrap();
+        encoder.write_all(&data).unwrap();
+        let file = encoder.finish().unwrap();
+        assert_eq!(file[..MAGIC.len()], MAGIC);
+
+        let mut decoded = vec![];
+        LzopDecoder::new(&file[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        // Corrupted data is an error, unless it still decodes to the same data, like a copy of
+        // another repetition of the same bytes
+        for position in (MAGIC.len()..file.len()).step_by(97) {
+            let mut corrupted = file.clone();
+            corrupted[position] ^= 0x55;
+            let mut decoded = vec![];
+            if LzopDecoder::new(&corrupted[..]).read_to_end(&mut decoded).is_ok() {
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
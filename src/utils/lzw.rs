@@ -0,0 +1,226 @@
+//! Decoder of the .Z files of the Unix `compress` tool, made of LZW codes.
+//!
+//! Codes start at 9 bits and grow up to the maximum of the header. Whenever their width changes,
+//! or the table is cleared, the rest of the group of 8 codes of the previous width is padding, the
+//! way `compress` wrote them.
+
+use std::io::{self, Read};
+
+const MAGIC: [u8; 2] = [0x1F, 0x9D];
+/// Mask of the maximum width of codes in the flags of the header
+const BITS_MASK: u8 = 0x1F;
+/// Flag of the header telling that `CLEAR` codes may reset the table
+const BLOCK_MODE: u8 = 0x80;
+const INIT_BITS: u32 = 9;
+const MAX_BITS: u32 = 16;
+/// Code resetting the table, in block mode
+const CLEAR: u32 = 256;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decoder of .Z files
+pub struct LzwDecoder<R: Read> {
+    reader: R,
+    /// Whether the header was read
+    started: bool,
+    block_mode: bool,
+    max_bits: u32,
+    /// Width of the codes being read
+    bits: u32,
+    /// Largest entry before the width grows, like `compress` the width grows past 9 bits even
+    /// when it's the maximum
+    max_code: u32,
+    /// Codes read since their width last changed, for the padding of their group
+    group_codes: u32,
+    bit_buffer: u32,
+    bit_count: u32,
+    /// Previous code and entry of each code above 255
+    prefixes: Vec<u16>,
+    suffixes: Vec<u8>,
+    /// Next entry of the table
+    free_entry: u32,
+    previous: Option<u32>,
+    /// First byte of the string of the previous code
+    first_byte: u8,
+    /// String of the last code, reversed, that's left to give out
+    pending: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            started: false,
+            block_mode: false,
+            max_bits: MAX_BITS,
+            bits: INIT_BITS,
+            max_code: (1 << INIT_BITS) - 1,
+            group_codes: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            prefixes: vec![0; 1 << MAX_BITS],
+            suffixes: (0..1u32 << MAX_BITS).map(|code| code as u8).collect(),
+            free_entry: 256,
+            previous: None,
+            first_byte: 0,
+            pending: vec![],
+            done: false,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 3];
+        self.reader.read_exact(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(invalid_data("not a .Z file"));
+        }
+        self.max_bits = u32::from(header[2] & BITS_MASK);
+        if !(INIT_BITS..=MAX_BITS).contains(&self.max_bits) {
+            return Err(invalid_data("unsupported .Z code width"));
+        }
+        self.block_mode = header[2] & BLOCK_MODE != 0;
+        self.free_entry = if self.block_mode { CLEAR + 1 } else { 256 };
+        self.started = true;
+        Ok(())
+    }
+
+    /// Reads `count` bits, `None` at the end of the file, which may be in the middle of a code.
+    fn read_bits(&mut self, count: u32) -> io::Result<Option<u32>> {
+        while self.bit_count < count {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            self.bit_buffer |= u32::from(byte[0]) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let bits = self.bit_buffer & ((1 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(Some(bits))
+    }
+
+    /// Skips the rest of the group of 8 codes of the current width.
+    fn skip_group_padding(&mut self) -> io::Result<()> {
+        let padding = (8 - self.group_codes % 8) % 8;
+        for _ in 0..padding {
+            if self.read_bits(self.bits)?.is_none() {
+                break;
+            }
+        }
+        self.group_codes = 0;
+        Ok(())
+    }
+
+    /// Decodes the next code into `pending`, returns `false` at the end of the file.
+    fn decode_code(&mut self) -> io::Result<bool> {
+        loop {
+            if self.free_entry > self.max_code {
+                self.skip_group_padding()?;
+                self.bits += 1;
+                self.max_code = if self.bits == self.max_bits {
+                    1 << self.max_bits
+                } else {
+                    (1 << self.bits) - 1
+                };
+            }
+            let Some(code) = self.read_bits(self.bits)? else {
+                return Ok(false);
+            };
+            self.group_codes += 1;
+
+            let Some(previous) = self.previous else {
+                if code >= 256 {
+                    return Err(invalid_data("corrupted .Z file"));
+                }
+                self.previous = Some(code);
+                self.first_byte = code as u8;
+                self.pending.push(code as u8);
+                return Ok(true);
+            };
+
+            if code == CLEAR && self.block_mode {
+                // The entry after the next code is the first one of the new table
+                self.free_entry = CLEAR;
+                self.skip_group_padding()?;
+                self.bits = INIT_BITS;
+                self.max_code = (1 << INIT_BITS) - 1;
+                continue;
+            }
+
+            let mut current = code;
+            if code >= self.free_entry {
+                // The code being defined, the previous string followed by its first byte
+                if code > self.free_entry {
+                    return Err(invalid_data("corrupted .Z file"));
+                }
+                self.pending.push(self.first_byte);
+                current = previous;
+            }
+            while current >= 256 {
+                self.pending.push(self.suffixes[current as usize]);
+                current = u32::from(self.prefixes[current as usize]);
+            }
+            self.first_byte = current as u8;
+            self.pending.push(self.first_byte);
+
+            if self.free_entry < 1 << self.max_bits {
+                self.prefixes[self.free_entry as usize] = previous as u16;
+                self.suffixes[self.free_entry as usize] = self.first_byte;
+                self.free_entry += 1;
+            }
+            self.previous = Some(code);
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.started {
+            self.read_header()?;
+        }
+
+        let mut read = 0;
+        while read < buf.len() {
+            match self.pending.pop() {
+                Some(byte) => {
+                    buf[read] = byte;
+                    read += 1;
+                }
+                None if self.done => break,
+                None => self.done = !self.decode_code()?,
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(compressed: &[u8]) -> Vec<u8> {
+        let mut decoded = vec![];
+        LzwDecoder::new(compressed).read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    #[test]
+    fn test_lzw_decoder() {
+        let compressed = [
+            0x1F, 0x9D, 0x90, 0x54, 0x9E, 0x08, 0x29, 0xF2, 0x44, 0x8A, 0x93, 0x27, 0x54, 0x02, 0x0E, 0x2C, 0xA8, 0x90,
+            0xA0, 0x41, 0x84,
+        ];
+        assert_eq!(decode(&compressed), b"TOBEORNOTTOBEORTOBEORNOT");
+
+        // Codes of the entry being defined, when a string repeats right away
+        let compressed = [0x1F, 0x9D, 0x90, 0x61, 0x02, 0x0A, 0x1C, 0x48, 0xB0, 0xA0, 0xC1, 0x81];
+        assert_eq!(decode(&compressed), [b'a'; 32]);
+
+        assert!(LzwDecoder::new(&b"\x1F\x8B\x08"[..]).read_to_end(&mut vec![]).is_err());
+    }
+}
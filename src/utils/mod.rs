@@ -3,23 +3,75 @@
 //! In here we have the logic for custom formatting, some file and directory utils, and user
 //! stdin interaction helpers.
 
+mod checksum;
 pub mod colors;
 mod file_visibility;
 mod formatting;
 mod fs;
+mod interrupt;
+mod lzip;
+mod lzop;
+mod lzw;
+mod open_files;
+mod parts;
 mod question;
+mod temp;
+mod temp_registry;
+mod threads;
 
+pub use checksum::{Checksum, ChecksumWriter};
 pub use file_visibility::FileVisibilityPolicy;
 pub use formatting::{
     nice_directory_display, pretty_format_list_of_paths, strip_cur_dir, to_utf, Bytes, EscapedPathDisplay,
 };
 pub use fs::{
-    cd_into_same_dir_as, clear_path, create_dir_if_non_existent, is_symlink, remove_file_or_dir, try_infer_extension,
+    canonicalize, cd_into_base_dir, cd_into_same_dir_as, clear_path, create_dir_if_non_existent,
+    detect_format_from_magic, entry_destination, infer_extensions, is_case_insensitive, is_symlink, max_name_length,
+    normalize_path, remove_file_or_dir, should_store_special_file, state_dir, total_size, try_infer_extension,
+    SpecialFileKind,
 };
+#[cfg(unix)]
+pub use fs::{create_device, create_fifo, device_numbers};
+pub use interrupt::{cancel_on_interrupt, InterruptGuard};
+pub use lzip::{LzipDecoder, LzipEncoder};
+pub use lzop::{decompress_lzo1x, LzopDecoder, LzopEncoder};
+pub use lzw::LzwDecoder;
+pub use open_files::{raise_open_files_limit, OPEN_FILES};
+pub use parts::{joined_path, part_path, PartsReader};
 pub use question::{
-    ask_to_create_file, user_wants_to_continue, user_wants_to_overwrite, QuestionAction, QuestionPolicy,
+    ask_password, ask_to_create_file, ask_to_overwrite_or_rename, choose_format, user_wants_to_continue,
+    user_wants_to_overwrite, Confirmation, QuestionAction, QuestionKind, QuestionPolicy,
 };
+pub use reproducible::source_date_epoch;
+pub use temp::{scratch_dir, set_temp_dir, staging_dir_in, temp_dir, temp_file_in, SpillBuffer};
+pub use temp_registry::{open_temp_registry, remove_orphaned_temp_files, remove_temp_registry};
+pub use threads::ThreadAllocation;
 pub use utf8::{get_invalid_utf8_paths, is_invalid_utf8};
+pub use zstd_window::{zstd_decoder, ZSTD_WINDOW_LOG_MAX};
+
+mod reproducible {
+    use std::env;
+
+    use crate::error::FinalError;
+
+    /// Reads the `SOURCE_DATE_EPOCH` environment variable, used to make builds reproducible.
+    ///
+    /// See https://reproducible-builds.org/specs/source-date-epoch/
+    pub fn source_date_epoch() -> crate::Result<Option<u64>> {
+        let Some(value) = env::var_os("SOURCE_DATE_EPOCH").filter(|value| !value.is_empty()) else {
+            return Ok(None);
+        };
+
+        match value.to_str().and_then(|value| value.parse().ok()) {
+            Some(epoch) => Ok(Some(epoch)),
+            None => Err(FinalError::with_title("Invalid SOURCE_DATE_EPOCH")
+                .detail(format!(
+                    "Expected a number of seconds since the Unix epoch, got {value:?}"
+                ))
+                .into()),
+        }
+    }
+}
 
 mod utf8 {
     use std::{ffi::OsStr, path::PathBuf};
@@ -34,3 +86,36 @@ mod utf8 {
         paths.iter().filter(|path| is_invalid_utf8(path)).collect()
     }
 }
+
+mod zstd_window {
+    use std::io::{BufRead, BufReader, Read};
+
+    use zstd::stream::read::Decoder;
+
+    use crate::error::FinalError;
+
+    /// Base-2 logarithm of the largest window zstd supports, the one of `--long=31`
+    pub const ZSTD_WINDOW_LOG_MAX: u32 = if cfg!(target_pointer_width = "64") { 31 } else { 30 };
+
+    /// A zstd decoder of `reader` that accepts every window size, like the large ones of `--long`,
+    /// which the default decoders reject above 128 MiB, and uses `dictionary`, see `--dict`.
+    ///
+    /// Fails if `reader` needs a dictionary and none was given.
+    pub fn zstd_decoder<'a, R: Read>(reader: R, dictionary: Option<&[u8]>) -> crate::Result<Decoder<'a, BufReader<R>>> {
+        let mut reader = BufReader::with_capacity(zstd::zstd_safe::DCtx::in_size(), reader);
+        let mut decoder = match dictionary {
+            Some(dictionary) => Decoder::with_dictionary(reader, dictionary)?,
+            None => {
+                if zstd::zstd_safe::get_dict_id_from_frame(reader.fill_buf()?).is_some() {
+                    let error = FinalError::with_title("Cannot decompress zstd compressed with a dictionary")
+                        .detail("No dictionary was given")
+                        .hint("Decompress it with '--dict <FILE>'");
+                    return Err(error.into());
+                }
+                Decoder::with_buffer(reader)?
+            }
+        };
+        decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+        Ok(decoder)
+    }
+}
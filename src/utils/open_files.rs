@@ -0,0 +1,134 @@
+//! Keeping the number of open files under the limit of the process.
+//!
+//! The default soft limit is low on some systems, like 256 on macOS, so it's raised to the hard
+//! limit at startup, and code opening files in parallel takes handles from a pool sized after
+//! it, waiting for one to be released instead of failing with `EMFILE`.
+
+use std::sync::{Condvar, Mutex};
+
+use once_cell::sync::Lazy;
+
+/// Handles left out of the pool, for the standard streams, output files, walked directories and
+/// the files opened by dependencies
+const RESERVED_HANDLES: usize = 64;
+
+/// Handles available to code opening files in parallel
+pub static OPEN_FILES: Lazy<OpenFilesPool> =
+    Lazy::new(|| OpenFilesPool::new(open_files_limit().saturating_sub(RESERVED_HANDLES).max(1)));
+
+/// Raises the soft limit of open files as far as the hard limit allows.
+#[cfg(unix)]
+pub fn raise_open_files_limit() {
+    let Some(mut limit) = get_limit() else {
+        return;
+    };
+
+    // macOS refuses limits above `OPEN_MAX`, even if the hard limit is unlimited
+    #[cfg(target_os = "macos")]
+    let hard_limit = limit.rlim_max.min(10240);
+    #[cfg(not(target_os = "macos"))]
+    let hard_limit = limit.rlim_max;
+
+    if limit.rlim_cur < hard_limit {
+        limit.rlim_cur = hard_limit;
+        // Safety: `limit` is a valid `rlimit`, failing leaves the limit as it was
+        unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_open_files_limit() {}
+
+#[cfg(unix)]
+fn get_limit() -> Option<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: `limit` is a valid `rlimit` to write to
+    let code = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+    (code == 0).then_some(limit)
+}
+
+/// How many files the process may have open at once
+#[cfg(unix)]
+fn open_files_limit() -> usize {
+    get_limit()
+        .and_then(|limit| usize::try_from(limit.rlim_cur).ok())
+        .unwrap_or(256)
+}
+
+/// The C runtime of Windows allows 512 open files by default
+#[cfg(not(unix))]
+fn open_files_limit() -> usize {
+    512
+}
+
+/// A counting semaphore of file handles
+pub struct OpenFilesPool {
+    capacity: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl OpenFilesPool {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            available: Mutex::new(capacity),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until `count` handles are available, they're released when the guard is dropped.
+    ///
+    /// Asking for more handles than the pool has takes all of them.
+    pub fn acquire(&self, count: usize) -> OpenFilesGuard<'_> {
+        let count = count.min(self.capacity);
+        let mut available = self.available.lock().unwrap();
+        while *available < count {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= count;
+
+        OpenFilesGuard { pool: self, count }
+    }
+}
+
+/// Handles taken from an `OpenFilesPool`
+pub struct OpenFilesGuard<'a> {
+    pool: &'a OpenFilesPool,
+    count: usize,
+}
+
+impl Drop for OpenFilesGuard<'_> {
+    fn drop(&mut self) {
+        *self.pool.available.lock().unwrap() += self.count;
+        self.pool.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_open_files_pool() {
+        let pool = Arc::new(OpenFilesPool::new(2));
+
+        let first = pool.acquire(1);
+        // More than the capacity takes everything once it's available
+        let waiting = thread::spawn({
+            let pool = Arc::clone(&pool);
+            move || drop(pool.acquire(5))
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.join().unwrap();
+        assert_eq!(*pool.available.lock().unwrap(), 2);
+    }
+}
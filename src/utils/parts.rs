@@ -0,0 +1,165 @@
+//! Files split into numbered parts, like "archive.tar.zst.001", see `ouch split`.
+//!
+//! A path ending with ".001" stands for the concatenation of its parts, ".002" and so on, up to
+//! the first number that's missing.
+
+use std::{
+    ffi::OsString,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+/// Suffix of the first part
+const FIRST_PART: &str = ".001";
+
+/// The path of part `number` of `path`, counting from 1.
+pub fn part_path(path: &Path, number: usize) -> PathBuf {
+    let mut part = OsString::from(path);
+    part.push(format!(".{number:03}"));
+    PathBuf::from(part)
+}
+
+/// The path of the file that `first_part` is the first part of, if it is one.
+pub fn joined_path(first_part: &Path) -> Option<&Path> {
+    let name = <[u8]>::from_path(first_part)?;
+    let joined = name.strip_suffix(FIRST_PART.as_bytes())?;
+    joined.to_path().ok().filter(|joined| joined.file_name().is_some())
+}
+
+/// Reader of a file, or of the concatenation of its parts if it's a first part
+pub struct PartsReader {
+    /// Every part and its size
+    parts: Vec<(PathBuf, u64)>,
+    /// Index of the part being read, `parts.len()` at the end
+    current: usize,
+    /// The part being read, opened on first use
+    file: Option<fs::File>,
+    /// Position in the current part
+    position: u64,
+}
+
+impl PartsReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut parts = vec![(path.to_path_buf(), fs::metadata(path)?.len())];
+        if let Some(joined) = joined_path(path) {
+            for number in 2.. {
+                let part = part_path(joined, number);
+                match fs::metadata(&part) {
+                    Ok(metadata) => parts.push((part, metadata.len())),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(Self {
+            parts,
+            current: 0,
+            file: Some(fs::File::open(path)?),
+            position: 0,
+        })
+    }
+
+    fn len(&self) -> u64 {
+        self.parts.iter().map(|(_, size)| size).sum()
+    }
+
+    fn move_to(&mut self, part: usize, position: u64) {
+        if part != self.current {
+            self.file = None;
+        }
+        self.current = part;
+        self.position = position;
+    }
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some((path, size)) = self.parts.get(self.current) {
+            if self.position >= *size {
+                self.move_to(self.current + 1, 0);
+                continue;
+            }
+            if self.file.is_none() {
+                let mut file = fs::File::open(path)?;
+                file.seek(SeekFrom::Start(self.position))?;
+                self.file = Some(file);
+            }
+
+            let read = self.file.as_mut().expect("the part was opened").read(buf)?;
+            if read == 0 && !buf.is_empty() {
+                // The part was truncated since it was opened
+                self.move_to(self.current + 1, 0);
+                continue;
+            }
+            self.position += read as u64;
+            return Ok(read);
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for PartsReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => {
+                let start: u64 = self.parts[..self.current].iter().map(|(_, size)| size).sum();
+                (start + self.position).checked_add_signed(offset)
+            }
+        };
+        let target = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+
+        let mut start = 0;
+        for (part, (_, size)) in self.parts.iter().enumerate() {
+            if target < start + size {
+                self.move_to(part, target - start);
+                if let Some(file) = &mut self.file {
+                    file.seek(SeekFrom::Start(target - start))?;
+                }
+                return Ok(target);
+            }
+            start += size;
+        }
+        // At or past the end, reads return nothing
+        self.move_to(self.parts.len(), 0);
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_and_seek_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let joined = dir.path().join("file.bin");
+        for (number, content) in [(1, &b"abc"[..]), (2, b""), (3, b"defg"), (5, b"ignored")] {
+            fs::write(part_path(&joined, number), content).unwrap();
+        }
+
+        let first = part_path(&joined, 1);
+        assert_eq!(joined_path(&first), Some(joined.as_path()));
+        let mut reader = PartsReader::open(&first).unwrap();
+
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "abcdefg");
+
+        reader.seek(SeekFrom::Start(2)).unwrap();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "cdefg");
+
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 4);
+        assert_eq!(reader.seek(SeekFrom::Current(-1)).unwrap(), 3);
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"d");
+    }
+}
@@ -6,7 +6,8 @@
 use std::{
     borrow::Cow,
     io::{self, Write},
-    path::Path,
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, PoisonError},
 };
 
 use fs_err as fs;
@@ -15,6 +16,8 @@ use super::{strip_cur_dir, to_utf};
 use crate::{
     accessible::is_running_in_accessible_mode,
     error::{Error, FinalError, Result},
+    extension::Extension,
+    heartbeat, info,
     utils::{self, colors},
 };
 
@@ -38,6 +41,48 @@ pub enum QuestionAction {
     Decompression,
 }
 
+/// Questions that can be asked many times in a single run, and answered for all of them at once
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QuestionKind {
+    /// "Do you want to overwrite 'FILE'?"
+    Overwrite,
+    /// "Do you want to compress/decompress 'FILE'?"
+    Continue(QuestionAction),
+    /// "Do you want to remove the paths created by the last extraction?"
+    Undo,
+}
+
+/// Answers given with `[A]ll` or `n[O]ne`, which apply to the remaining questions of the same kind.
+///
+/// The lock is held while asking, so questions from files processed in parallel don't interleave.
+static QUESTION_STATE: Mutex<QuestionState> = Mutex::new(QuestionState {
+    answers_for_all: Vec::new(),
+    chosen_formats: Vec::new(),
+    new_names: Vec::new(),
+    quit: false,
+});
+
+struct QuestionState {
+    answers_for_all: Vec<(QuestionKind, bool)>,
+    /// Formats chosen for files that could be any of the listed ones, `None` if they were skipped
+    chosen_formats: Vec<(Vec<Extension>, Option<Extension>)>,
+    /// Paths given with `[r]ename`, taken even before they're written to
+    new_names: Vec<PathBuf>,
+    /// Set by `[q]uit`, every remaining question fails
+    quit: bool,
+}
+
+/// Answer to a question
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    None,
+    Rename,
+    Quit,
+}
+
 /// Check if QuestionPolicy flags were set, otherwise, ask user if they want to overwrite.
 pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) -> crate::Result<bool> {
     match question_policy {
@@ -47,11 +92,132 @@ pub fn user_wants_to_overwrite(path: &Path, question_policy: QuestionPolicy) ->
             let path = to_utf(strip_cur_dir(path));
             let path = Some(&*path);
             let placeholder = Some("FILE");
-            Confirmation::new("Do you want to overwrite 'FILE'?", placeholder).ask(path)
+            Confirmation::new("Do you want to overwrite 'FILE'?", placeholder).ask(path, QuestionKind::Overwrite)
         }
     }
 }
 
+/// Check if QuestionPolicy flags were set, otherwise, ask the user if they want to overwrite
+/// `path`, or to write to another name in the same directory.
+///
+/// Returns the path to write to, which is free, `None` if the user doesn't want to write it.
+pub fn ask_to_overwrite_or_rename(path: &Path, question_policy: QuestionPolicy) -> crate::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(Some(path.to_path_buf()));
+    }
+    let overwrite = match question_policy {
+        QuestionPolicy::AlwaysYes => true,
+        QuestionPolicy::AlwaysNo => false,
+        QuestionPolicy::Ask => {
+            let mut state = QUESTION_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.quit {
+                return Err(cancelled_error());
+            }
+            let answered = state
+                .answers_for_all
+                .iter()
+                .find(|(kind, _)| *kind == QuestionKind::Overwrite);
+            match answered {
+                Some(&(_, overwrite)) => overwrite,
+                None => {
+                    let display_path = to_utf(strip_cur_dir(path));
+                    let confirmation = Confirmation::new("Do you want to overwrite 'FILE'?", Some("FILE"));
+                    match confirmation.prompt(Some(&display_path), true)? {
+                        Answer::Yes => true,
+                        Answer::No => false,
+                        Answer::All => {
+                            state.answers_for_all.push((QuestionKind::Overwrite, true));
+                            true
+                        }
+                        Answer::None => {
+                            state.answers_for_all.push((QuestionKind::Overwrite, false));
+                            false
+                        }
+                        Answer::Rename => {
+                            let new_path = ask_new_name(path, &state.new_names)?;
+                            state.new_names.push(new_path.clone());
+                            return Ok(Some(new_path));
+                        }
+                        Answer::Quit => {
+                            state.quit = true;
+                            return Err(cancelled_error());
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    if !overwrite {
+        return Ok(None);
+    }
+    utils::remove_file_or_dir(path)?;
+    Ok(Some(path.to_path_buf()))
+}
+
+/// Asks for another name for `path`, in the same directory, an empty answer takes the first free
+/// one like `file (2).txt`. Names that exist or are in `taken` are refused.
+fn ask_new_name(path: &Path, taken: &[PathBuf]) -> crate::Result<PathBuf> {
+    let is_free = |candidate: &Path| !candidate.exists() && !taken.iter().any(|taken| taken == candidate);
+    let suggested = (2..)
+        .map(|number| numbered_path(path, number))
+        .find(|candidate| is_free(candidate))
+        .expect("some number is free");
+    let suggested_name = suggested
+        .file_name()
+        .expect("has a name")
+        .to_string_lossy()
+        .into_owned();
+
+    loop {
+        print!("New name for '{}' [{suggested_name}]: ", to_utf(strip_cur_dir(path)));
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(FinalError::with_title("Unexpected EOF when asking question.")
+                .detail(format!(
+                    "When asking for a new name for '{}'.",
+                    to_utf(strip_cur_dir(path))
+                ))
+                .hint("If using Ouch in scripting, consider using `--yes` and `--no`.")
+                .into());
+        }
+
+        let name = answer.trim_end_matches(['\n', '\r']);
+        if name.is_empty() {
+            return Ok(suggested);
+        }
+        // Only a name, the file stays next to the one it would have overwritten
+        let is_name = matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        );
+        if !is_name {
+            println!("'{name}' is not a file name.");
+            continue;
+        }
+        let candidate = path.with_file_name(name);
+        if !is_free(&candidate) {
+            println!("'{name}' is taken too.");
+            continue;
+        }
+        return Ok(candidate);
+    }
+}
+
+/// `archive.tar.gz` becomes `archive (2).tar.gz` for the number 2, the number goes before every
+/// extension.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let name = path.file_name().expect("has a name").to_string_lossy();
+    // The dot of hidden files doesn't start an extension
+    let (stem, extensions) = match name.get(1..).and_then(|rest| rest.find('.')) {
+        Some(dot) => name.split_at(dot + 1),
+        None => (&*name, ""),
+    };
+    path.with_file_name(format!("{stem} ({number}){extensions}"))
+}
+
 /// Create the file if it doesn't exist and if it does then ask to overwrite it.
 /// If the user doesn't want to overwrite then we return [`Ok(None)`]
 pub fn ask_to_create_file(path: &Path, question_policy: QuestionPolicy) -> Result<Option<fs::File>> {
@@ -86,9 +252,112 @@ pub fn user_wants_to_continue(
             let path = to_utf(strip_cur_dir(path));
             let path = Some(&*path);
             let placeholder = Some("FILE");
-            Confirmation::new(&format!("Do you want to {action} 'FILE'?"), placeholder).ask(path)
+            Confirmation::new(&format!("Do you want to {action} 'FILE'?"), placeholder)
+                .ask(path, QuestionKind::Continue(question_action))
+        }
+    }
+}
+
+/// Asks which of `candidates` the file at `path` is, `None` if it must be skipped.
+///
+/// `--yes` takes the most likely format and `--no` skips the file. The choice is remembered for
+/// the following files that could be the same formats.
+pub fn choose_format(
+    path: &Path,
+    candidates: &[Extension],
+    question_policy: QuestionPolicy,
+) -> crate::Result<Option<Extension>> {
+    let path = to_utf(strip_cur_dir(path));
+    let names: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+
+    match question_policy {
+        QuestionPolicy::AlwaysYes => {
+            info!(
+                accessible,
+                "'{path}' could be {}, decompressing it as {}.",
+                names.join(" or "),
+                names[0]
+            );
+            return Ok(candidates.first().cloned());
+        }
+        QuestionPolicy::AlwaysNo => return Ok(None),
+        QuestionPolicy::Ask => {}
+    }
+
+    let mut state = QUESTION_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+    if state.quit {
+        return Err(cancelled_error());
+    }
+    if let Some((_, chosen)) = state.chosen_formats.iter().find(|(formats, _)| formats == candidates) {
+        return Ok(chosen.clone());
+    }
+
+    let _heartbeat_paused = heartbeat::pause();
+    let options: Vec<String> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| format!("[{}] {name}", index + 1))
+        .collect();
+
+    // Ask the same question to end while no valid answers are given
+    let chosen = loop {
+        if is_running_in_accessible_mode() {
+            print!("Which format is '{path}'? {}, skip or quit: ", options.join(", "));
+        } else {
+            print!(
+                "'{path}' could be {}, which one is it? {}/[s]kip/[q]uit ",
+                names.join(" or "),
+                options.join("/")
+            );
+        }
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(FinalError::with_title("Unexpected EOF when asking question.")
+                .detail(format!("When asking which format '{path}' is."))
+                .hint("If using Ouch in scripting, consider using `--yes` and `--no`, or `--format`.")
+                .into());
+        }
+
+        answer.make_ascii_lowercase();
+        match answer.trim() {
+            "" => break candidates.first().cloned(),
+            "s" | "skip" => break None,
+            "q" | "quit" => {
+                state.quit = true;
+                return Err(cancelled_error());
+            }
+            number => {
+                let index = number.parse::<usize>().ok().and_then(|number| number.checked_sub(1));
+                if let Some(chosen) = index.and_then(|index| candidates.get(index)) {
+                    break Some(chosen.clone());
+                }
+            }
         }
+    };
+
+    state.chosen_formats.push((candidates.to_vec(), chosen.clone()));
+    Ok(chosen)
+}
+
+/// Asks for the password of the encrypted archive at `path`, without echoing it.
+pub fn ask_password(path: &Path) -> crate::Result<String> {
+    let path = to_utf(strip_cur_dir(path));
+
+    let state = QUESTION_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+    if state.quit {
+        return Err(cancelled_error());
     }
+
+    let _heartbeat_paused = heartbeat::pause();
+    // Read from the terminal, even when stdin is redirected
+    rpassword::prompt_password(format!("Password for '{path}': ")).map_err(|err| {
+        FinalError::with_title(format!("Could not ask for the password of '{path}'"))
+            .detail(err.to_string())
+            .hint("Give it with `--password` when there is no terminal to type it in")
+            .into()
+    })
 }
 
 /// Confirmation dialog for end user with [Y/n] question.
@@ -114,32 +383,57 @@ impl<'a> Confirmation<'a> {
     }
 
     /// Creates user message and receives a boolean input to be used on the program
-    pub fn ask(&self, substitute: Option<&'a str>) -> crate::Result<bool> {
+    ///
+    /// The user can also answer all the remaining questions of `kind` at once, or quit.
+    pub fn ask(&self, substitute: Option<&'a str>, kind: QuestionKind) -> crate::Result<bool> {
+        let mut state = QUESTION_STATE.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if state.quit {
+            return Err(cancelled_error());
+        }
+        if let Some(&(_, answer)) = state.answers_for_all.iter().find(|(answered, _)| *answered == kind) {
+            return Ok(answer);
+        }
+
+        match self.prompt(substitute, false)? {
+            Answer::Yes => Ok(true),
+            Answer::No => Ok(false),
+            Answer::All => {
+                state.answers_for_all.push((kind, true));
+                Ok(true)
+            }
+            Answer::None => {
+                state.answers_for_all.push((kind, false));
+                Ok(false)
+            }
+            Answer::Rename => unreachable!("renaming isn't offered"),
+            Answer::Quit => {
+                state.quit = true;
+                Err(cancelled_error())
+            }
+        }
+    }
+
+    /// Asks until a valid answer is given, which may be `[r]ename` if `renamable`
+    fn prompt(&self, substitute: Option<&'a str>, renamable: bool) -> crate::Result<Answer> {
         let message = match (self.placeholder, substitute) {
             (None, _) => Cow::Borrowed(self.prompt),
             (Some(_), None) => unreachable!("dev error, should be reported, we checked this won't happen"),
             (Some(placeholder), Some(subs)) => Cow::Owned(self.prompt.replace(placeholder, subs)),
         };
 
+        let _heartbeat_paused = heartbeat::pause();
+
         // Ask the same question to end while no valid answers are given
         loop {
+            let (green, red, reset) = (*colors::GREEN, *colors::RED, *colors::RESET);
             if is_running_in_accessible_mode() {
-                print!(
-                    "{} {}yes{}/{}no{}: ",
-                    message,
-                    *colors::GREEN,
-                    *colors::RESET,
-                    *colors::RED,
-                    *colors::RESET
-                );
+                let rename = if renamable { "rename/" } else { "" };
+                print!("{message} {green}yes{reset}/{red}no{reset}/{green}all{reset}/{red}none{reset}/{rename}quit: ");
             } else {
+                let rename = if renamable { "[r]ename/" } else { "" };
                 print!(
-                    "{} [{}Y{}/{}n{}] ",
-                    message,
-                    *colors::GREEN,
-                    *colors::RESET,
-                    *colors::RED,
-                    *colors::RESET
+                    "{message} [{green}Y{reset}]es/[{red}n{reset}]o/[{green}A{reset}]ll/n[{red}O{reset}]ne/{rename}[q]uit "
                 );
             }
             io::stdout().flush()?;
@@ -159,10 +453,37 @@ impl<'a> Confirmation<'a> {
 
             answer.make_ascii_lowercase();
             match answer.trim() {
-                "" | "y" | "yes" => return Ok(true),
-                "n" | "no" => return Ok(false),
+                "" | "y" | "yes" => return Ok(Answer::Yes),
+                "n" | "no" => return Ok(Answer::No),
+                "a" | "all" => return Ok(Answer::All),
+                "o" | "none" => return Ok(Answer::None),
+                "r" | "rename" if renamable => return Ok(Answer::Rename),
+                "q" | "quit" => return Ok(Answer::Quit),
                 _ => continue, // Try again
             }
         }
     }
 }
+
+fn cancelled_error() -> Error {
+    FinalError::with_title("Cancelled by the user").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(
+            numbered_path(Path::new("out/file.txt"), 2),
+            Path::new("out/file (2).txt")
+        );
+        assert_eq!(
+            numbered_path(Path::new("archive.tar.gz"), 3),
+            Path::new("archive (3).tar.gz")
+        );
+        assert_eq!(numbered_path(Path::new("dir"), 2), Path::new("dir (2)"));
+        assert_eq!(numbered_path(Path::new(".bashrc"), 2), Path::new(".bashrc (2)"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Where temporary files go, see `--temp-dir`.
+//!
+//! The default temporary directory is often a small tmpfs, so archives that can't be streamed
+//! are only buffered in a file when a temporary directory was chosen, by `--temp-dir` or
+//! `TMPDIR`, and in memory otherwise.
+//!
+//! Everything created here is registered, see [`super::temp_registry`].
+
+use std::{
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::OnceCell;
+use tempfile::{NamedTempFile, TempDir};
+
+use super::temp_registry::register;
+
+/// The temporary directory chosen by the user, if any
+static TEMP_DIR: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+pub fn set_temp_dir(dir: Option<PathBuf>) {
+    if TEMP_DIR.get().is_none() {
+        TEMP_DIR.set(dir).unwrap();
+    }
+}
+
+/// The temporary directory chosen by the user, if any.
+pub fn temp_dir() -> Option<&'static Path> {
+    TEMP_DIR.get().and_then(Option::as_deref)
+}
+
+/// Creates a temporary file in the chosen temporary directory, or in `default_dir`.
+pub fn temp_file_in(default_dir: &Path) -> io::Result<NamedTempFile> {
+    let file = NamedTempFile::new_in(temp_dir().unwrap_or(default_dir))?;
+    register(file.path());
+    Ok(file)
+}
+
+/// Creates a temporary directory in the chosen temporary directory, or in the system's.
+pub fn scratch_dir() -> io::Result<TempDir> {
+    staging_dir_in(&temp_dir().map_or_else(std::env::temp_dir, Path::to_path_buf))
+}
+
+/// Creates a temporary directory in `dir`, whatever the chosen temporary directory is.
+pub fn staging_dir_in(dir: &Path) -> io::Result<TempDir> {
+    let staging_dir = tempfile::tempdir_in(dir)?;
+    register(staging_dir.path());
+    Ok(staging_dir)
+}
+
+/// Buffer for an archive that can't be streamed, see the module documentation.
+pub enum SpillBuffer {
+    Memory(Cursor<Vec<u8>>),
+    File(NamedTempFile),
+}
+
+impl SpillBuffer {
+    pub fn new() -> io::Result<Self> {
+        Ok(match temp_dir() {
+            Some(dir) => Self::File(temp_file_in(dir)?),
+            None => Self::Memory(Cursor::new(vec![])),
+        })
+    }
+
+    /// Buffers everything `reader` has, ready to be read from the start.
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut buffer = Self::new()?;
+        io::copy(&mut reader, &mut buffer)?;
+        buffer.rewind()?;
+        Ok(buffer)
+    }
+
+    /// Whether archives will be buffered in memory, which users are warned about.
+    pub fn is_in_memory() -> bool {
+        temp_dir().is_none()
+    }
+}
+
+impl Read for SpillBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(cursor) => cursor.read(buf),
+            Self::File(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(cursor) => cursor.write(buf),
+            Self::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Memory(cursor) => cursor.flush(),
+            Self::File(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for SpillBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Memory(cursor) => cursor.seek(pos),
+            Self::File(file) => file.seek(pos),
+        }
+    }
+}
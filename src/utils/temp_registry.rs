@@ -0,0 +1,122 @@
+//! Registry of the temporary files and directories of running processes.
+//!
+//! Temporary files are removed when they're dropped, which an interrupted or crashed run never
+//! gets to do. Each process lists the ones it creates in a file named after its PID, in the state
+//! directory, and removes that file when it exits; a file whose process is gone lists orphans.
+
+use std::{
+    io::{self, Write},
+    path::{self, Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+use bstr::ByteSlice;
+use fs_err as fs;
+
+use crate::utils::{remove_file_or_dir, state_dir};
+
+/// The registry file of this process, opened on first use
+static REGISTRY: Mutex<Option<fs::File>> = Mutex::new(None);
+
+fn registry_dir() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("temp"))
+}
+
+fn open_registry(registry: &mut Option<fs::File>) {
+    if registry.is_some() {
+        return;
+    }
+    let Some(dir) = registry_dir() else {
+        return;
+    };
+
+    let open = || {
+        fs::create_dir_all(&dir)?;
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(process::id().to_string()))
+    };
+    *registry = open().ok();
+}
+
+/// Opens the registry file of this process ahead of time, `--sandbox` forbids creating it later.
+pub fn open_temp_registry() {
+    open_registry(&mut REGISTRY.lock().unwrap());
+}
+
+/// Records that `path` was created by this process, a best effort that never fails.
+pub fn register(path: &Path) {
+    let mut registry = REGISTRY.lock().unwrap();
+    open_registry(&mut registry);
+
+    let (Some(file), Ok(path)) = (registry.as_mut(), path::absolute(path)) else {
+        return;
+    };
+    if let Some(bytes) = <[u8]>::from_path(&path) {
+        let _ = file.write_all(&[bytes, b"\0"].concat());
+    }
+}
+
+/// Removes the registry file of this process, whose temporary files were already removed.
+pub fn remove_temp_registry() {
+    if REGISTRY.lock().unwrap().take().is_some() {
+        if let Some(dir) = registry_dir() {
+            let _ = fs::remove_file(dir.join(process::id().to_string()));
+        }
+    }
+}
+
+/// Removes the temporary files left by processes that are gone, returns how many were removed.
+pub fn remove_orphaned_temp_files() -> crate::Result<usize> {
+    let Some(dir) = registry_dir() else {
+        return Ok(0);
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse().ok()) else {
+            continue;
+        };
+        if pid == process::id() || is_running(pid) {
+            continue;
+        }
+
+        let registered = fs::read(entry.path())?;
+        for path in registered.split_str("\0").filter_map(|path| path.to_path().ok()) {
+            // Only what `tempfile` names, in case the registry was tampered with
+            let is_temporary = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(".tmp"));
+            if is_temporary && fs::symlink_metadata(path).is_ok() {
+                remove_file_or_dir(path)?;
+                removed += 1;
+            }
+        }
+        fs::remove_file(entry.path())?;
+    }
+    Ok(removed)
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Safety: signal 0 only checks whether the process exists
+    let code = unsafe { libc::kill(pid, 0) };
+    code == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a portable way to tell, every other process is taken as running.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,88 @@
+//! Splitting `--threads` between files processed in parallel and the threads of each codec.
+
+use std::{num::NonZeroUsize, thread};
+
+/// Speedup of a codec using `n` threads relative to a single one, a fourth of every additional
+/// thread is assumed lost to splitting and joining the data
+fn codec_speedup(threads: usize) -> f64 {
+    1.0 + (threads as f64 - 1.0) * 0.75
+}
+
+/// How many jobs run side by side, and how many threads each of their codecs may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadAllocation {
+    pub workers: usize,
+    pub codec_threads: usize,
+}
+
+impl ThreadAllocation {
+    /// Splits `threads` between jobs of `job_sizes` bytes, `0` means one thread per CPU core.
+    ///
+    /// Every split is estimated by the time it takes for the whole run, the largest job must
+    /// finish, and the others must fit in the remaining workers, so a single large job among
+    /// small ones gets all threads for its codec, while jobs of similar sizes run side by side.
+    /// `parallel_codec` tells if any of the codecs can use more than one thread.
+    pub fn new(threads: usize, job_sizes: &[u64], parallel_codec: bool) -> Self {
+        let threads = resolve_thread_count(threads);
+        let max_workers = job_sizes.len().clamp(1, threads);
+
+        if !parallel_codec {
+            return Self {
+                workers: max_workers,
+                codec_threads: 1,
+            };
+        }
+
+        let total = job_sizes.iter().sum::<u64>().max(1) as f64;
+        let largest = job_sizes.iter().copied().max().unwrap_or(0) as f64;
+
+        let estimated_time = |workers: usize| {
+            let speedup = codec_speedup(threads / workers);
+            f64::max(largest / speedup, total / (workers as f64 * speedup))
+        };
+
+        // On ties, prefer more workers, they don't suffer from the codecs' overhead
+        let workers = (1..=max_workers)
+            .rev()
+            .min_by(|&a, &b| estimated_time(a).total_cmp(&estimated_time(b)))
+            .unwrap_or(1);
+
+        Self {
+            workers,
+            codec_threads: threads / workers,
+        }
+    }
+}
+
+/// The number of threads to use, resolving `0` to the number of CPU cores.
+pub fn resolve_thread_count(threads: usize) -> usize {
+    if threads == 0 {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    } else {
+        threads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_allocation() {
+        let allocation = |job_sizes: &[u64]| ThreadAllocation::new(8, job_sizes, true);
+        let split = |workers, codec_threads| ThreadAllocation { workers, codec_threads };
+
+        // A single job uses all threads for its codec
+        assert_eq!(allocation(&[1000]), split(1, 8));
+        // One large job among small ones, the large one dominates
+        let mut sizes = vec![100_000];
+        sizes.extend([1_000; 20]);
+        assert_eq!(allocation(&sizes), split(1, 8));
+        // Many jobs of similar sizes run side by side
+        assert_eq!(allocation(&[1000; 20]), split(8, 1));
+        assert_eq!(allocation(&[1000; 4]), split(4, 2));
+
+        // Codecs that can't use more threads leave them all to the workers
+        assert_eq!(ThreadAllocation::new(8, &[100_000, 1_000], false), split(2, 1));
+    }
+}
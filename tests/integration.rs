@@ -1,7 +1,11 @@
 #[macro_use]
 mod utils;
 
-use std::{iter::once, path::PathBuf};
+use std::{
+    iter::once,
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use fs_err as fs;
 use parse_display::Display;
@@ -10,7 +14,7 @@ use rand::{rngs::SmallRng, Rng, SeedableRng};
 use tempfile::tempdir;
 use test_strategy::{proptest, Arbitrary};
 
-use crate::utils::{assert_same_directory, write_random_content};
+use crate::utils::{assert_same_directory, run_in, write_random_content};
 
 // tar and zip extensions
 #[derive(Arbitrary, Debug, Display)]
@@ -18,14 +22,17 @@ use crate::utils::{assert_same_directory, write_random_content};
 enum DirectoryExtension {
     #[display("7z")]
     SevenZ,
+    Cpio,
     Tar,
     Tbz,
     Tbz2,
     Tgz,
+    Tlz,
     Tlz4,
     Tlzma,
     Tsz,
     Txz,
+    Tzo,
     Tzst,
     Zip,
 }
@@ -34,11 +41,14 @@ enum DirectoryExtension {
 #[derive(Arbitrary, Debug, Display)]
 #[display(style = "lowercase")]
 enum FileExtension {
+    Br,
     Bz,
     Bz2,
     Gz,
+    Lz,
     Lz4,
     Lzma,
+    Lzo,
     Sz,
     Xz,
     Zst,
@@ -77,7 +87,7 @@ fn create_random_files(dir: impl Into<PathBuf>, depth: u8, rng: &mut SmallRng) {
 
     // create more random files in 0 to 2 new directories
     for _ in 0..rng.gen_range(0..=2u32) {
-        create_random_files(&tempfile::tempdir_in(dir).unwrap().into_path(), depth - 1, rng);
+        create_random_files(tempfile::tempdir_in(dir).unwrap().into_path(), depth - 1, rng);
     }
 }
 
@@ -151,6 +161,3239 @@ fn multiple_files(
     assert_same_directory(before, after, !matches!(ext, DirectoryExtension::Zip));
 }
 
+// `-C` stores inputs relative to the given directory, and creates the output directory when decompressing
+#[test]
+fn base_directory() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let project = &dir.join("project");
+    fs::create_dir_all(project.join("src/nested")).unwrap();
+    fs::create_dir_all(project.join("docs")).unwrap();
+    fs::write(project.join("src/nested/main.rs"), "fn main() {}").unwrap();
+    fs::write(project.join("docs/README"), "docs").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", "-C", project, "src/nested/main.rs", "docs", archive);
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", archive, "-C", after);
+        assert_eq!(
+            fs::read(after.join("archive/src/nested/main.rs")).unwrap(),
+            b"fn main() {}"
+        );
+        assert_eq!(fs::read(after.join("archive/docs/README")).unwrap(), b"docs");
+    }
+}
+
+// `--prefix` places every entry inside of a leading directory
+#[test]
+fn prefix() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir_all(before.join("src")).unwrap();
+    fs::write(before.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(before.join("README"), "readme").unwrap();
+
+    for format in ["tar.zst", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!(
+            "-A",
+            "c",
+            before.join("src"),
+            before.join("README"),
+            archive,
+            "--prefix",
+            "myproject-1.2.3/"
+        );
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", archive, "-d", after);
+        assert_eq!(
+            fs::read(after.join("myproject-1.2.3/src/main.rs")).unwrap(),
+            b"fn main() {}"
+        );
+        assert_eq!(fs::read(after.join("myproject-1.2.3/README")).unwrap(), b"readme");
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--prefix", "../escape", "--yes"])
+        .arg(before.join("README"))
+        .arg(dir.join("invalid.tar"))
+        .assert()
+        .failure();
+}
+
+// entries with absolute paths get their leading '/' removed, unless `--absolute-paths` is used
+#[test]
+fn absolute_paths() {
+    let dir = tempdir().unwrap();
+    let dir = &dir.path().canonicalize().unwrap();
+    let before = &dir.join("before");
+    // Long enough to not fit in a plain tar header
+    let long_name = "a".repeat(120);
+    fs::create_dir(before).unwrap();
+    fs::write(before.join(&long_name), "long").unwrap();
+    fs::write(before.join("short"), "short").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "-P", "c", before, archive);
+
+        let stripped = &dir.join(format!("stripped-{format}"));
+        ouch!("-A", "d", archive, "-d", stripped);
+        let relative_before = before.strip_prefix("/").unwrap();
+        assert_eq!(
+            fs::read(stripped.join(relative_before).join("short")).unwrap(),
+            b"short"
+        );
+        assert_eq!(
+            fs::read(stripped.join(relative_before).join(&long_name)).unwrap(),
+            b"long"
+        );
+
+        fs::remove_dir_all(before).unwrap();
+        ouch!("-A", "-P", "d", archive, "-d", dir.join(format!("kept-{format}")));
+        assert_eq!(fs::read(before.join("short")).unwrap(), b"short");
+        assert_eq!(fs::read(before.join(&long_name)).unwrap(), b"long");
+    }
+}
+
+// `SOURCE_DATE_EPOCH` clamps the modification times stored in archives
+#[test]
+fn source_date_epoch() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("file"), "content").unwrap();
+    let epoch = 1_000_000_000;
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        crate::utils::cargo_bin()
+            .env("SOURCE_DATE_EPOCH", epoch.to_string())
+            .args(["-A", "c", "--yes"])
+            .arg(before)
+            .arg(archive)
+            .assert()
+            .success();
+
+        let after = &dir.join(format!("after-{format}"));
+        ouch!("-A", "d", archive, "-d", after);
+        let modified = fs::metadata(after.join("before/file")).unwrap().modified().unwrap();
+        assert_eq!(modified, UNIX_EPOCH + Duration::from_secs(epoch));
+    }
+
+    crate::utils::cargo_bin()
+        .env("SOURCE_DATE_EPOCH", "yesterday")
+        .args(["-A", "c", "--yes"])
+        .arg(before)
+        .arg(dir.join("invalid.tar"))
+        .assert()
+        .failure();
+}
+
+// `--zip-align` aligns the data of every file in the zip archive
+#[test]
+fn zip_align() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    for (i, name) in ["a", "bb", "ccc", "dddd"].into_iter().enumerate() {
+        fs::write(before.join(name), "x".repeat(i * 7)).unwrap();
+    }
+    let archive = &dir.join("archive.zip");
+
+    ouch!("-A", "c", before, archive, "--zip-align", "4096");
+
+    let mut zip_archive = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+    for i in 0..zip_archive.len() {
+        let file = zip_archive.by_index(i).unwrap();
+        if file.is_file() {
+            assert_eq!(file.data_start() % 4096, 0, "{} is not aligned", file.name());
+        }
+    }
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", archive, "-d", after);
+    assert_same_directory(before, after.join("before"), false);
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--zip-align", "4", "--yes"])
+        .arg(before)
+        .arg(dir.join("archive.tar"))
+        .assert()
+        .failure();
+}
+
+// `--silent` prints nothing unless there's an error
+#[test]
+fn silent() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+
+    let output = ouch!("--silent", "c", before, archive);
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    // Decompressing twice would ask to overwrite, but `--yes` is also given, and warnings are silenced
+    for _ in 0..2 {
+        let output = ouch!("--silent", "d", archive, "-d", dir.join("after"));
+        assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    }
+
+    let assert = crate::utils::cargo_bin()
+        .args(["--silent", "d", "--yes"])
+        .arg(dir.join("missing.tar.gz"))
+        .assert()
+        .failure();
+    assert!(!assert.get_output().stderr.is_empty());
+}
+
+// answering `[A]ll` to an overwrite question applies to the following ones, and `[q]uit` stops
+#[test]
+fn answer_all_questions() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+
+    let mut archives = vec![];
+    for name in ["a", "b", "c"] {
+        fs::write(dir.join(name), "new").unwrap();
+        let archive = dir.join(format!("{name}.tar"));
+        ouch!("-A", "c", dir.join(name), &archive);
+        fs::write(out.join(name), "old").unwrap();
+        archives.push(archive);
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .args(&archives)
+        .arg("-d")
+        .arg(out)
+        .write_stdin("A\n")
+        .assert()
+        .success();
+    for name in ["a", "b", "c"] {
+        assert_eq!(fs::read(out.join(name)).unwrap(), b"new");
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .args(&archives)
+        .arg("-d")
+        .arg(out)
+        .write_stdin("q\n")
+        .assert()
+        .failure();
+}
+
+// `[r]ename` extracts to another name instead of overwriting, a free one if none is given
+#[test]
+fn rename_on_conflict() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+    fs::write(dir.join("a.txt"), "new").unwrap();
+    let archive = &dir.join("a.tar");
+    ouch!("-A", "c", dir.join("a.txt"), archive);
+    fs::write(out.join("a.txt"), "old").unwrap();
+
+    let extract = |answers: &str| {
+        crate::utils::cargo_bin()
+            .args(["-A", "d"])
+            .arg(archive)
+            .arg("-d")
+            .arg(out)
+            .write_stdin(answers)
+            .assert()
+            .success();
+    };
+    extract("r\n\n");
+    extract("rename\nb.txt\n");
+    // Names that are taken or aren't names are asked again
+    extract("r\na.txt\n../c.txt\nc.txt\n");
+
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+    assert_eq!(fs::read(out.join("a (2).txt")).unwrap(), b"new");
+    assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"new");
+    assert_eq!(fs::read(out.join("c.txt")).unwrap(), b"new");
+    assert!(!dir.join("c.txt").exists());
+}
+
+// `ouch undo` removes the paths created by the last extraction recorded with `--record`
+#[test]
+fn undo_recorded_extraction() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let state_dir = &dir.join("state");
+    let before = &dir.join("before");
+    fs::create_dir_all(before.join("nested")).unwrap();
+    fs::write(before.join("nested/file"), "content").unwrap();
+    let archive = &dir.join("archive.tar");
+    ouch!("-A", "c", before, archive);
+
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+    fs::write(out.join("unrelated"), "kept").unwrap();
+
+    crate::utils::cargo_bin()
+        .env("XDG_STATE_HOME", state_dir)
+        .args(["-A", "d", "--record", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(out)
+        .assert()
+        .success();
+    assert!(out.join("before/nested/file").exists());
+
+    crate::utils::cargo_bin()
+        .env("XDG_STATE_HOME", state_dir)
+        .args(["-A", "undo", "--yes"])
+        .assert()
+        .success();
+    assert!(!out.join("before").exists());
+    assert!(out.join("unrelated").exists());
+
+    // the record is consumed, there's nothing left to undo
+    crate::utils::cargo_bin()
+        .env("XDG_STATE_HOME", state_dir)
+        .args(["-A", "undo", "--yes"])
+        .assert()
+        .failure();
+}
+
+// `--manifest-out` lists every path created by the extraction
+#[test]
+fn manifest_of_created_paths() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir_all(before.join("nested")).unwrap();
+    fs::write(before.join("nested/file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", before, archive);
+
+    let out = &dir.join("out");
+    fs::create_dir(out).unwrap();
+    fs::write(out.join("unrelated"), "not created").unwrap();
+    let manifest = &dir.join("manifest.txt");
+    ouch!("-A", "d", archive, "-d", out, "--manifest-out", manifest);
+
+    let out = out.canonicalize().unwrap();
+    let expected: Vec<_> = ["before", "before/nested", "before/nested/file"]
+        .iter()
+        .map(|path| format!("{}\n", out.join(path).display()))
+        .collect();
+    assert_eq!(fs::read_to_string(manifest).unwrap(), expected.concat());
+
+    fs::remove_dir_all(out.join("before")).unwrap();
+    ouch!(
+        "-A",
+        "d",
+        archive,
+        "-d",
+        &out,
+        "--manifest-out",
+        manifest,
+        "--manifest-null"
+    );
+    assert_eq!(
+        fs::read_to_string(manifest).unwrap(),
+        expected.concat().replace('\n', "\0")
+    );
+}
+
+// `ouch compare` reports the differences between an archive and a directory
+#[test]
+fn compare_archive_with_directory() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let src = &dir.join("src");
+    fs::create_dir_all(src.join("nested")).unwrap();
+    fs::write(src.join("changed"), "original").unwrap();
+    fs::write(src.join("nested/removed"), "content").unwrap();
+    let archive = &dir.join("src.tar.zst");
+    ouch!("-A", "c", src, archive);
+
+    ouch!("-A", "compare", archive, src);
+
+    fs::write(src.join("changed"), "modified").unwrap();
+    fs::write(src.join("added"), "content").unwrap();
+    fs::remove_file(src.join("nested/removed")).unwrap();
+
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "compare"])
+        .arg(archive)
+        .arg(src)
+        .assert()
+        .failure();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    let src = src.display();
+    assert_eq!(
+        stdout,
+        format!(
+            "{src}/added: missing from the archive\n\
+             {src}/changed: content differs\n\
+             {src}/nested/removed: missing on disk\n"
+        )
+    );
+}
+
+// Asking a format for what it can't do names the limitation and suggests a format that can
+#[test]
+fn unsupported_capability_suggests_format() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("a.txt"), "content").unwrap();
+
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "c", "--index"])
+        .arg(dir.join("a.txt"))
+        .arg(dir.join("out.zip"))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(
+        stderr.contains("'--index' only applies to tar.zst archives"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("out.tar.zst' instead"), "{stderr}");
+
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "c", "--block-size", "1M"])
+        .arg(dir.join("a.txt"))
+        .arg(dir.join("a.txt.gz"))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("a.txt.xz' instead"), "{stderr}");
+}
+
+// `--format` overrides misleading or missing extensions, the known ones are dropped from the output
+#[test]
+fn decompress_with_format_flag() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("a.txt"), "first").unwrap();
+    fs::write(dir.join("b.txt"), "second").unwrap();
+
+    let archive = &dir.join("misleading.zip");
+    ouch!(
+        "-A",
+        "c",
+        "--format",
+        "tar.gz",
+        dir.join("a.txt"),
+        dir.join("b.txt"),
+        archive
+    );
+    let out = &dir.join("out");
+    ouch!("-A", "d", "--format", "tar.gz", archive, "-d", out);
+    assert_eq!(fs::read(out.join("misleading/a.txt")).unwrap(), b"first");
+    assert_eq!(fs::read(out.join("misleading/b.txt")).unwrap(), b"second");
+
+    // Without extensions to drop, it would be decompressed into itself
+    let compressed = &dir.join("data.bin");
+    ouch!("-A", "c", "--format", "gz", dir.join("a.txt"), compressed);
+    crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["-A", "d", "--yes", "--format", "gz", "data.bin"])
+        .assert()
+        .failure();
+    ouch!("-A", "d", "--format", "gz", compressed, "-d", out);
+    assert_eq!(fs::read(out.join("data.bin")).unwrap(), b"first");
+    assert_ne!(fs::read(compressed).unwrap(), b"first");
+}
+
+// `ouch test` reads archives without extracting them, `--recursive` tests the archives inside too
+#[test]
+fn test_nested_archives() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("a.txt"), "hello world").unwrap();
+    let inner = &dir.join("inner.zip");
+    ouch!("-A", "c", dir.join("a.txt"), inner);
+    let outer = &dir.join("outer.tar.gz");
+    ouch!("-A", "c", inner, outer);
+    ouch!("-A", "test", "--recursive", outer);
+
+    // The checksum at the end of the gzip stream no longer matches
+    let damaged = &dir.join("damaged.txt.gz");
+    ouch!("-A", "c", dir.join("a.txt"), damaged);
+    let mut bytes = fs::read(damaged).unwrap();
+    let crc = bytes.len() - 8;
+    bytes[crc] ^= 0xff;
+    fs::write(damaged, bytes).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "test"])
+        .arg(damaged)
+        .assert()
+        .failure();
+
+    let outer = &dir.join("outer.zip");
+    ouch!("-A", "c", inner, damaged, outer);
+    ouch!("-A", "test", outer);
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "test", "--recursive"])
+        .arg(outer)
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("'damaged.txt.gz'"), "{stderr}");
+}
+
+// `ouch test` goes on after a corrupted file, and names all of them at the end
+#[test]
+fn test_reports_each_file() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("a.txt"), "hello world").unwrap();
+    let good = &dir.join("good.tar.gz");
+    ouch!("-A", "c", dir.join("a.txt"), good);
+    let truncated = &dir.join("truncated.tar.gz");
+    fs::write(truncated, &fs::read(good).unwrap()[..40]).unwrap();
+    let copy = &dir.join("copy.tar.gz");
+    fs::copy(good, copy).unwrap();
+
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "test"])
+        .args([truncated, good, copy])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("good.tar.gz' is OK"), "{stderr}");
+    assert!(stderr.contains("copy.tar.gz' is OK"), "{stderr}");
+    assert!(stderr.contains("1 of 3 files failed the test"), "{stderr}");
+}
+
+// Compressed tarballs without extensions are detected as such from their contents, and listed or
+// unpacked rather than decompressed into a tar file
+#[test]
+fn detect_tarball_without_extension() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("a.txt"), "hello world").unwrap();
+    let archive = &dir.join("backup.tar.zst");
+    ouch!("-A", "c", dir.join("a.txt"), archive);
+    let renamed = &dir.join("backup");
+    fs::rename(archive, renamed).unwrap();
+
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "list", "--yes"])
+        .arg(renamed)
+        .assert()
+        .success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    assert!(stdout.contains("a.txt"), "{stdout}");
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", renamed, "-d", out);
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"hello world");
+}
+
+/// A script standing for `ssh`, which runs the command it's given in `remote`, the host
+#[cfg(unix)]
+fn fake_ssh(dir: &std::path::Path, remote: &std::path::Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let ssh = dir.join("fake-ssh");
+    fs::write(
+        &ssh,
+        format!("#!/bin/sh\nshift\ncd '{}' && exec sh -c \"$1\"\n", remote.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    ssh
+}
+
+// `[user@]host:path` streams the archive to `ssh`
+#[cfg(unix)]
+#[test]
+fn compress_over_ssh() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let remote = &dir.join("remote");
+    fs::create_dir(remote).unwrap();
+    let ssh = &fake_ssh(dir, remote);
+    fs::write(dir.join("a.txt"), "hello world").unwrap();
+
+    let compress = |extra: &[&str]| {
+        let mut command = crate::utils::cargo_bin();
+        command
+            .env("OUCH_SSH_COMMAND", ssh)
+            .current_dir(dir)
+            .args(["-A", "c"])
+            .args(extra)
+            .args(["a.txt", "me@host:backup.tar.zst"]);
+        command.assert()
+    };
+    compress(&[]).success();
+    ouch!("-A", "d", remote.join("backup.tar.zst"), "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/a.txt")).unwrap(), b"hello world");
+
+    // It isn't replaced without `--yes`, and nothing partial is left behind
+    compress(&[]).failure();
+    compress(&["--yes"]).success();
+    compress(&["--index"]).failure();
+    let mut files: Vec<_> = fs::read_dir(remote)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["backup.tar.zst"]);
+}
+
+// Archives are streamed from `[user@]host:path` too, zip archives entry by entry
+#[cfg(unix)]
+#[test]
+fn decompress_over_ssh() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let remote = &dir.join("remote");
+    fs::create_dir(remote).unwrap();
+    let ssh = &fake_ssh(dir, remote);
+    fs::write(dir.join("a.txt"), "hello world").unwrap();
+
+    for name in ["backup.tar.zst", "backup.zip"] {
+        ouch!("-A", "c", dir.join("a.txt"), remote.join(name));
+        let out = &dir.join("out").join(name);
+        crate::utils::cargo_bin()
+            .env("OUCH_SSH_COMMAND", ssh)
+            .args(["-A", "d", &format!("me@host:{name}"), "-d"])
+            .arg(out)
+            .assert()
+            .success();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"hello world");
+    }
+
+    crate::utils::cargo_bin()
+        .env("OUCH_SSH_COMMAND", ssh)
+        .args(["-A", "d", "me@host:missing.tar.zst", "-d"])
+        .arg(dir.join("missing"))
+        .assert()
+        .failure();
+}
+
+// `--help-man` and `--help-markdown` render the documentation of every subcommand and format
+#[test]
+fn help_man_and_markdown() {
+    let output = crate::utils::cargo_bin().arg("--help-man").assert().success();
+    let man = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    assert!(man.contains(".TH ouch 1"));
+    assert!(man.contains(".TH ouch-decompress 1"));
+    assert!(man.contains(".SH FORMATS"));
+
+    let output = crate::utils::cargo_bin().arg("--help-markdown").assert().success();
+    let markdown = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    assert!(markdown.starts_with("# ouch\n"));
+    assert!(markdown.contains("### ouch compress"));
+    assert!(markdown.contains("| gz | Gzip compression | 0 to 9 |"));
+}
+
+// `--upload` sends the archive to a URL once it's compressed and tested, and keeps it
+#[cfg(feature = "upload")]
+#[test]
+fn compress_and_upload() {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/artifacts/archive", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = vec![];
+        for status in ["201 Created", "500 Internal Server Error"] {
+            let mut reader = BufReader::new(listener.accept().unwrap().0);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let length = head
+                .lines()
+                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ")?.parse().ok())
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            requests.push((head, body));
+        }
+        requests
+    });
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    crate::utils::cargo_bin()
+        .env("OUCH_UPLOAD_AUTHORIZATION", "Bearer secret")
+        .args(["-A", "c", "--upload", &url])
+        .arg(dir.join("file"))
+        .arg(archive)
+        .assert()
+        .success();
+
+    let failed = &dir.join("failed.zip");
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--upload-method", "post", "--upload", &url])
+        .arg(dir.join("file"))
+        .arg(failed)
+        .assert()
+        .failure();
+    assert!(failed.exists());
+
+    let requests = server.join().unwrap();
+    let (head, body) = &requests[0];
+    assert!(head.starts_with("PUT /artifacts/archive HTTP/1.1\r\n"), "{head}");
+    assert!(
+        head.to_ascii_lowercase().contains("authorization: bearer secret"),
+        "{head}"
+    );
+    assert_eq!(body, &fs::read(archive).unwrap());
+    let (head, body) = &requests[1];
+    assert!(head.starts_with("POST "), "{head}");
+    assert_eq!(body, &fs::read(failed).unwrap());
+}
+
+// `--profile` applies the options of a profile from the configuration file
+#[test]
+fn compress_with_profile() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let config_dir = &dir.join("config");
+    fs::create_dir_all(config_dir.join("ouch")).unwrap();
+    fs::write(
+        config_dir.join("ouch/config.toml"),
+        "[profile.backup]\nformat = \"tar.zst\"\nlevel = 15\n",
+    )
+    .unwrap();
+
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("file"), "content").unwrap();
+    let archive = &dir.join("backup");
+
+    crate::utils::cargo_bin()
+        .env("XDG_CONFIG_HOME", config_dir)
+        .args(["-A", "c", "--yes", "--profile", "backup"])
+        .arg(before)
+        .arg(archive)
+        .assert()
+        .success();
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", "--format", "tar.zst", archive, "-d", after);
+    assert_eq!(fs::read(after.join("before/file")).unwrap(), b"content");
+
+    crate::utils::cargo_bin()
+        .env("XDG_CONFIG_HOME", config_dir)
+        .args(["-A", "c", "--yes", "--profile", "missing"])
+        .arg(before)
+        .arg(dir.join("other.tar"))
+        .assert()
+        .failure();
+}
+
+// `--pipeline` compresses with the pipeline's profile, then runs its steps on the archive
+#[cfg(unix)]
+#[test]
+fn compress_with_pipeline() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let config_dir = &dir.join("config");
+    fs::create_dir_all(config_dir.join("ouch")).unwrap();
+    fs::write(
+        config_dir.join("ouch/config.toml"),
+        r#"
+        [profile.backup]
+        format = "tar.gz"
+
+        [pipeline.release]
+        profile = "backup"
+        steps = ['cp "$OUCH_ARCHIVE" "$OUCH_ARCHIVE.copy"', 'wc -c < "$OUCH_ARCHIVE" > "$OUCH_ARCHIVE.size"']
+
+        [pipeline.broken]
+        steps = ["exit 3", 'touch "$OUCH_ARCHIVE.unreachable"']
+        "#,
+    )
+    .unwrap();
+
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("file"), "content").unwrap();
+    let archive = &dir.join("release");
+
+    crate::utils::cargo_bin()
+        .env("XDG_CONFIG_HOME", config_dir)
+        .args(["-A", "c", "--yes", "--pipeline", "release"])
+        .arg(before)
+        .arg(archive)
+        .assert()
+        .success();
+    assert_eq!(fs::read(archive).unwrap(), fs::read(dir.join("release.copy")).unwrap());
+    assert!(dir.join("release.size").exists());
+    ouch!("-A", "d", "--format", "tar.gz", archive, "-d", dir.join("after"));
+
+    let archive = &dir.join("broken.tar");
+    crate::utils::cargo_bin()
+        .env("XDG_CONFIG_HOME", config_dir)
+        .args(["-A", "c", "--yes", "--pipeline", "broken"])
+        .arg(before)
+        .arg(archive)
+        .assert()
+        .failure();
+    assert!(archive.exists());
+    assert!(!dir.join("broken.tar.unreachable").exists());
+}
+
+// The `[levels]` table of the configuration file gives the levels of formats without `--level`
+#[test]
+fn compress_with_configured_levels() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let config_dir = &dir.join("config");
+    fs::create_dir_all(config_dir.join("ouch")).unwrap();
+    fs::write(config_dir.join("ouch/config.toml"), "[levels]\nxz = 1\n").unwrap();
+
+    let input = &dir.join("input");
+    let content: String = (0..50_000).map(|i| format!("{} ", i * 7919 % 10007)).collect();
+    fs::write(input, content).unwrap();
+
+    let compress = |output: &str, args: &[&str]| {
+        crate::utils::cargo_bin()
+            .env("XDG_CONFIG_HOME", config_dir)
+            .args(["-A", "c", "--yes"])
+            .args(args)
+            .arg(input)
+            .arg(dir.join(output))
+            .assert()
+            .success();
+        fs::read(dir.join(output)).unwrap()
+    };
+    let configured = compress("configured.xz", &[]);
+    assert_eq!(configured, compress("level_1.xz", &["--level", "1"]));
+    assert_ne!(configured, compress("level_9.xz", &["--level", "9"]));
+
+    fs::write(config_dir.join("ouch/config.toml"), "[levels]\ntar = 1\n").unwrap();
+    crate::utils::cargo_bin()
+        .env("XDG_CONFIG_HOME", config_dir)
+        .args(["-A", "c", "--yes"])
+        .arg(input)
+        .arg(dir.join("unknown.xz"))
+        .assert()
+        .failure();
+    assert!(!dir.join("unknown.xz").exists());
+}
+
+// `--best` is `--slow`, and levels below the ones of a format are its lowest
+#[test]
+fn compress_level_bounds() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    let content: String = (0..50_000).map(|i| format!("{} ", i * 7919 % 10007)).collect();
+    fs::write(input, content).unwrap();
+
+    let compress = |output: &str, args: &[&str]| {
+        crate::utils::cargo_bin()
+            .args(["-A", "c", "--yes"])
+            .args(args)
+            .arg(input)
+            .arg(dir.join(output))
+            .assert()
+            .success();
+        fs::read(dir.join(output)).unwrap()
+    };
+    for extension in ["gz", "xz", "br"] {
+        assert_eq!(
+            compress(&format!("slow.{extension}"), &["--slow"]),
+            compress(&format!("best.{extension}"), &["--best"])
+        );
+        assert_eq!(
+            compress(&format!("lowest.{extension}"), &["--level", "0"]),
+            compress(&format!("negative.{extension}"), &["--level", "-3"])
+        );
+    }
+}
+
+// aliases of the configuration file are expanded when they're given instead of a subcommand
+#[test]
+fn run_configured_alias() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let config_dir = &dir.join("config");
+    fs::create_dir_all(config_dir.join("ouch")).unwrap();
+    fs::write(
+        config_dir.join("ouch/config.toml"),
+        "[alias]\nbackup = \"compress --yes\"\nunpack = [\"decompress\", \"--dir\"]\n",
+    )
+    .unwrap();
+    fs::write(dir.join("file"), "content").unwrap();
+    let archive = &dir.join("backup.tar.gz");
+
+    let ouch_with_config = || {
+        let mut command = crate::utils::cargo_bin();
+        command.env("XDG_CONFIG_HOME", config_dir).arg("-A");
+        command
+    };
+    ouch_with_config()
+        .arg("backup")
+        .arg(dir.join("file"))
+        .arg(archive)
+        .assert()
+        .success();
+    ouch_with_config()
+        .arg("unpack")
+        .arg(dir.join("out"))
+        .arg(archive)
+        .assert()
+        .success();
+    assert_eq!(fs::read(dir.join("out/file")).unwrap(), b"content");
+
+    ouch_with_config().arg("missing").arg(archive).assert().failure();
+}
+
+// decompressing a single-file format into an existing FIFO streams into it, without replacing it
+#[cfg(unix)]
+#[test]
+fn decompress_into_fifo() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "streamed content").unwrap();
+    let archive = &dir.join("data.zst");
+    ouch!("-A", "c", input, archive);
+
+    let output_dir = &dir.join("output");
+    fs::create_dir(output_dir).unwrap();
+    run_in(output_dir, "mkfifo", "data").unwrap();
+
+    let fifo = output_dir.join("data");
+    let reader = std::thread::spawn(move || fs::read(fifo).unwrap());
+    ouch!("-A", "d", archive, "-d", output_dir);
+
+    assert_eq!(reader.join().unwrap(), b"streamed content");
+    let metadata = fs::symlink_metadata(output_dir.join("data")).unwrap();
+    assert!(metadata.file_type().is_fifo());
+}
+
+// device nodes are skipped unless `--devices` is given, creating them requires root
+#[cfg(unix)]
+#[test]
+fn decompress_devices() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("devices.tar");
+    ouch!("-A", "c", "/dev/null", archive, "--special-files", "store");
+
+    ouch!("-A", "d", archive, "-d", dir.join("skipped"));
+    assert!(!dir.join("skipped/null").exists());
+
+    let is_root = run_in(dir, "id", "-u").is_ok_and(|output| output.stdout == b"0\n");
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes", "--devices"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("restored"))
+        .assert();
+    if is_root {
+        assert.success();
+        let metadata = fs::symlink_metadata(dir.join("restored/null")).unwrap();
+        assert!(metadata.file_type().is_char_device());
+    } else {
+        assert.failure();
+    }
+}
+
+// a device node can't be written outside of the output folder through a symlink extracted before it
+#[cfg(unix)]
+#[test]
+fn device_through_symlink_stays_inside() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let outside = &dir.join("outside");
+    fs::create_dir(outside).unwrap();
+    let archive = &dir.join("dev.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    builder.append_link(&mut header, "link", outside).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Char);
+    header.set_size(0);
+    header.set_mode(0o666);
+    header.set_device_major(1).unwrap();
+    header.set_device_minor(3).unwrap();
+    builder
+        .append_data(&mut header, "link/pwned_null", std::io::empty())
+        .unwrap();
+    builder.finish().unwrap();
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes", "--devices"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .assert()
+        .failure();
+    assert!(fs::symlink_metadata(outside.join("pwned_null")).is_err());
+}
+
+// `--sandbox` lets the extraction write inside of the output directory only
+#[cfg(target_os = "linux")]
+#[test]
+fn sandboxed_extraction() {
+    let dir = tempdir().unwrap();
+    let dir = &dir.path().canonicalize().unwrap();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "-P", "c", before, archive);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes", "--sandbox"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("sandboxed"))
+        .output()
+        .unwrap();
+    if String::from_utf8_lossy(&output.stderr).contains("Landlock is not supported") {
+        return;
+    }
+    assert!(output.status.success());
+    assert!(dir
+        .join("sandboxed")
+        .join(before.strip_prefix("/").unwrap())
+        .join("file")
+        .exists());
+
+    // Extracting to the absolute paths of the entries would write outside of the output directory
+    fs::remove_dir_all(before).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "-P", "d", "--yes", "--sandbox"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("escaped"))
+        .assert()
+        .failure();
+    assert!(!before.exists());
+
+    // Files on other hosts are read with `ssh`, which the sandbox can't run
+    let output = crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["-A", "d", "--yes", "--sandbox", "host:archive.tar.gz", "-d", "remote"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("files on other hosts"));
+    assert!(!dir.join("remote").exists());
+}
+
+// `--run-as` extracts as another user, while the owners stored in the archive are restored
+#[cfg(unix)]
+#[test]
+fn extract_as_another_user() {
+    use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let before = &dir.join("before");
+    fs::create_dir_all(before.join("sub")).unwrap();
+    fs::write(before.join("sub/file"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+
+    let is_root = run_in(dir, "id", "-u").is_ok_and(|output| output.stdout == b"0\n");
+    if !is_root {
+        ouch!("-A", "c", before, archive);
+        crate::utils::cargo_bin()
+            .args(["-A", "d", "--yes", "--run-as", "nobody"])
+            .arg(archive)
+            .assert()
+            .failure();
+        return;
+    }
+
+    for path in [before, &before.join("sub"), &before.join("sub/file")] {
+        chown(path, Some(1234), Some(4321)).unwrap();
+    }
+    ouch!("-A", "c", before, archive);
+    fs::set_permissions(archive, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", "--run-as", "nobody", archive, "-d", after);
+    for path in ["before", "before/sub", "before/sub/file"] {
+        let metadata = fs::symlink_metadata(after.join(path)).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 4321));
+    }
+
+    // To a relative path, with `--heartbeat`, which starts its thread after the helper
+    crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["-A", "d", "--yes", "--heartbeat", "1", "--run-as", "nobody"])
+        .arg(archive)
+        .args(["-d", "relative"])
+        .assert()
+        .success();
+    let metadata = fs::symlink_metadata(dir.join("relative/before/sub/file")).unwrap();
+    assert_eq!((metadata.uid(), metadata.gid()), (1234, 4321));
+}
+
+// extract entries whose names are longer than the filesystem allows
+#[test]
+fn truncate_long_names() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let long_name = format!("{}.txt", "a".repeat(300));
+
+    let archive = &dir.join("archive.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(7);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, format!("dir/{long_name}"), &b"content"[..])
+        .unwrap();
+    builder.into_inner().unwrap();
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("error"))
+        .assert()
+        .failure();
+
+    ouch!("-A", "d", "--long-names", "skip", archive, "-d", dir.join("skip"));
+    assert_eq!(fs::read_dir(dir.join("skip")).unwrap().count(), 0);
+
+    ouch!(
+        "-A",
+        "d",
+        "--long-names",
+        "truncate",
+        archive,
+        "-d",
+        dir.join("truncate")
+    );
+    let extracted: Vec<_> = fs::read_dir(dir.join("truncate/dir")).unwrap().collect();
+    assert_eq!(extracted.len(), 1);
+    let extracted = extracted[0].as_ref().unwrap();
+    assert!(extracted.file_name().len() <= 255);
+    assert!(extracted.file_name().to_str().unwrap().ends_with(".txt"));
+    assert_eq!(fs::read(extracted.path()).unwrap(), b"content");
+}
+
+// errors of damaged archives tell which entry was being extracted
+#[test]
+fn damaged_archive_error_context() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+
+    let mut data = vec![0; 100_000];
+    SmallRng::seed_from_u64(0).fill(&mut data[..]);
+    fs::write(dir.join("first"), "intact").unwrap();
+    fs::write(dir.join("second"), data).unwrap();
+    let archive = &dir.join("archive.zip");
+    ouch!("-A", "c", dir.join("first"), dir.join("second"), archive);
+
+    // Damage the middle of the compressed data of "second"
+    let mut bytes = fs::read(archive).unwrap();
+    bytes[50_000..51_000].fill(0);
+    fs::write(archive, bytes).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("While extracting 'second'"), "{stderr}");
+    assert!(stderr.contains("1 entries were extracted before the error"), "{stderr}");
+}
+
+// directories get their permissions and modification times after their contents are extracted
+#[cfg(unix)]
+#[test]
+fn restore_directory_attributes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use filetime_creation::{set_file_mtime, FileTime};
+
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let read_only = &dir.join("input/read-only");
+    fs::create_dir_all(read_only.join("sub")).unwrap();
+    fs::write(read_only.join("sub/file"), "content").unwrap();
+    let mtime = FileTime::from_unix_time(1_000_000_000, 0);
+    for path in [read_only, &read_only.join("sub")] {
+        set_file_mtime(path, mtime).unwrap();
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o555)).unwrap();
+    }
+
+    for format in ["tar", "zip", "zip.gz"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", dir.join("input"), archive);
+        let output = &dir.join(format!("output-{format}"));
+        ouch!("-A", "d", archive, "-d", output);
+
+        for path in ["input/read-only", "input/read-only/sub"] {
+            let metadata = fs::metadata(output.join(path)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o555, "{format}: {path}");
+            assert_eq!(
+                FileTime::from_last_modification_time(&metadata),
+                mtime,
+                "{format}: {path}"
+            );
+        }
+        assert_eq!(fs::read(output.join("input/read-only/sub/file")).unwrap(), b"content");
+    }
+
+    // Let the temporary directory be removed
+    for path in [read_only, &read_only.join("sub")] {
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+// list only the first or last entries of an archive
+#[test]
+fn list_head_and_tail() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for i in 0..5 {
+        fs::write(input.join(format!("file{i}")), "content").unwrap();
+    }
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", input, archive);
+
+    let list = |args: &[&str]| {
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list"])
+            .args(args)
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.lines().skip(1).map(str::to_owned).collect::<Vec<_>>()
+    };
+
+    let all = list(&[]);
+    assert_eq!(all.len(), 6);
+    assert_eq!(list(&["--head", "2"]), all[..2]);
+    assert_eq!(list(&["--tail", "2"]), all[4..]);
+    assert_eq!(list(&["--tail", "0"]), Vec::<String>::new());
+}
+
+// print only the entry count and sizes of archives
+#[test]
+fn list_summary() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    for i in 0..3 {
+        fs::write(input.join(format!("file{i}")), "content").unwrap();
+    }
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list", "--summary"])
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let compressed_size = fs::metadata(archive).unwrap().len();
+
+        let summary: Vec<&str> = stdout.lines().skip(1).collect();
+        assert_eq!(
+            summary[..4],
+            [
+                "Entries: 4",
+                "Files: 3",
+                "Directories: 1",
+                "Uncompressed size: 21 (21.00 B)"
+            ]
+        );
+        assert!(summary[4].starts_with(&format!("Compressed size: {compressed_size} (")));
+    }
+}
+
+// `--heartbeat` reports that ouch is still working while it's blocked without printing anything
+#[cfg(unix)]
+#[test]
+fn heartbeat_while_blocked() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "streamed content").unwrap();
+    let archive = &dir.join("data.zst");
+    ouch!("-A", "c", input, archive);
+
+    let output_dir = &dir.join("output");
+    fs::create_dir(output_dir).unwrap();
+    run_in(output_dir, "mkfifo", "data").unwrap();
+
+    // Nobody reads from the FIFO for a while, so the extraction waits
+    let fifo = output_dir.join("data");
+    let reader = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(2500));
+        fs::read(fifo).unwrap()
+    });
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--heartbeat", "1", "-d"])
+        .arg(output_dir)
+        .arg(archive)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(reader.join().unwrap(), b"streamed content");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Still working: "), "{stderr}");
+    assert!(stderr.contains(" processed."), "{stderr}");
+}
+
+// `--heartbeat` starts its thread after `--sandbox`, which only confines the threads started later
+#[cfg(target_os = "linux")]
+#[test]
+fn heartbeat_is_sandboxed() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "streamed content").unwrap();
+    let archive = &dir.join("data.zst");
+    ouch!("-A", "c", input, archive);
+
+    let output_dir = &dir.join("output");
+    fs::create_dir(output_dir).unwrap();
+    run_in(output_dir, "mkfifo", "data").unwrap();
+
+    // Blocked on the FIFO until it's read, with the heartbeat running
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("ouch"))
+        .args(["-A", "d", "--sandbox", "--heartbeat", "1", "-d"])
+        .arg(output_dir)
+        .arg(archive)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+    let tasks = PathBuf::from(format!("/proc/{}/task", child.id()));
+    let statuses: Vec<String> = fs::read_dir(tasks)
+        .unwrap()
+        .map(|task| fs::read_to_string(task.unwrap().path().join("status")).unwrap())
+        .collect();
+    assert_eq!(fs::read(output_dir.join("data")).unwrap(), b"streamed content");
+    let output = child.wait_with_output().unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Landlock is not supported") {
+        return;
+    }
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Still working: "), "{stderr}");
+    assert!(statuses.len() > 1);
+    for status in statuses {
+        assert!(status.lines().any(|line| line == "Seccomp:\t2"), "{status}");
+    }
+}
+
+// with `--temp-dir`, archives that can't be streamed are buffered in a file there instead of in memory
+#[test]
+fn buffer_archives_in_temp_dir() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "buffered content").unwrap();
+    let temp_dir = &dir.join("temp");
+    fs::create_dir(temp_dir).unwrap();
+    let archive = &dir.join("archive.zip.gz");
+
+    // Nothing is asked about running out of memory, which would fail without a terminal
+    let compress = crate::utils::cargo_bin()
+        .args(["c", "--temp-dir"])
+        .args([temp_dir, input, archive])
+        .output()
+        .unwrap();
+    let decompress = crate::utils::cargo_bin()
+        .args(["d", "--temp-dir"])
+        .args([temp_dir, archive])
+        .arg("-d")
+        .arg(dir.join("output"))
+        .output()
+        .unwrap();
+    for output in [compress, decompress] {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        assert!(!stderr.contains("in-memory"), "{stderr}");
+    }
+
+    assert_eq!(fs::read(dir.join("output/input")).unwrap(), b"buffered content");
+    assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
+}
+
+// `ouch clean-temp` removes the temporary files registered by processes that are gone
+#[cfg(unix)]
+#[test]
+fn clean_orphaned_temp_files() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let state_dir = &dir.join("state");
+    let registry_dir = &state_dir.join("ouch/temp");
+    fs::create_dir_all(registry_dir).unwrap();
+
+    let orphan = &dir.join(".tmpOrphan");
+    fs::create_dir(orphan).unwrap();
+    fs::write(orphan.join("partial"), "content").unwrap();
+    // Not named like a temporary file, so it's never removed
+    let unrelated = &dir.join("unrelated");
+    fs::write(unrelated, "kept").unwrap();
+
+    let mut exited = std::process::Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    let registered = format!("{}\0{}\0", orphan.display(), unrelated.display());
+    fs::write(registry_dir.join(exited.id().to_string()), registered).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .env("XDG_STATE_HOME", state_dir)
+        .args(["-A", "clean-temp"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Removed 1 temporary files"));
+    assert!(!orphan.exists());
+    assert!(unrelated.exists());
+    assert_eq!(fs::read_dir(registry_dir).unwrap().count(), 0);
+
+    // A run that isn't interrupted removes its own registry file
+    let archive = &dir.join("archive.tar");
+    ouch!("-A", "c", unrelated, archive);
+    crate::utils::cargo_bin()
+        .env("XDG_STATE_HOME", state_dir)
+        .args(["-A", "d", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .assert()
+        .success();
+    assert_eq!(fs::read_dir(registry_dir).unwrap().count(), 0);
+}
+
+// `--verbose` tells which formats were detected and how the archive is read
+#[test]
+fn verbose_format_chain() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "content").unwrap();
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", input, archive);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--verbose", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Detected tar ← zstd in"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(", streaming it."));
+}
+
+// files without extension that look like more than one format ask which one they are, once
+#[test]
+fn choose_ambiguous_format() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    // A tar archive starting with the signature of zip archives
+    let mut builder = tar::Builder::new(vec![]);
+    for name in ["PK\x03\x04 first", "second"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(7);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, &b"content"[..]).unwrap();
+    }
+    let polyglot = builder.into_inner().unwrap();
+    fs::write(dir.join("a"), &polyglot).unwrap();
+    fs::write(dir.join("b"), &polyglot).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .args([dir.join("a"), dir.join("b")])
+        .arg("-d")
+        .arg(dir.join("out"))
+        .write_stdin("2\n")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout.matches("Which format is").count(), 1, "{stdout}");
+    assert_eq!(fs::read(dir.join("out/a/second")).unwrap(), b"content");
+    assert_eq!(fs::read(dir.join("out/b/second")).unwrap(), b"content");
+}
+
+/// Overwrites `path` with `contents`, keeping its modification time, like a sidecar would see an
+/// archive replaced by another one of the same size within the same tick of the clock
+fn write_keeping_mtime(path: &PathBuf, contents: &[u8]) {
+    let mtime = fs::metadata(path).unwrap().modified().unwrap();
+    fs::write(path, contents).unwrap();
+    let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.set_modified(mtime).unwrap();
+}
+
+/// Bytes at the end of an archive that its sidecar has a checksum of
+const INDEX_TAIL_SIZE: usize = 64 * 1024;
+
+// `--index` writes a sidecar next to tar.zst archives, which `list` reads instead of the archive
+#[test]
+fn list_from_seek_index() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("file"), "indexed content").unwrap();
+    let mut other = vec![0; 3 * 1024 * 1024];
+    SmallRng::seed_from_u64(248).fill(&mut other[..]);
+    fs::write(input.join("nested/other"), other).unwrap();
+    let archive = &dir.join("archive.tar.zst");
+
+    ouch!("-A", "c", input, archive, "--index");
+    assert!(dir.join("archive.tar.zst.idx").exists());
+    let list = || crate::utils::cargo_bin().arg("l").arg(archive).output().unwrap();
+    let listed = list();
+    assert!(listed.status.success());
+
+    // The sidecar has everything, the start of the archive isn't even read
+    let mut contents = fs::read(archive).unwrap();
+    let len = contents.len();
+    contents[..len - INDEX_TAIL_SIZE].fill(0);
+    write_keeping_mtime(archive, &contents);
+    let listed_from_index = list();
+    assert!(listed_from_index.status.success());
+    assert_eq!(listed_from_index.stdout, listed.stdout);
+
+    // Unless it's stale, even with the same size and modification time
+    contents.fill(0);
+    write_keeping_mtime(archive, &contents);
+    assert!(!list().status.success());
+    fs::write(archive, vec![0; len + 1]).unwrap();
+    assert!(!list().status.success());
+
+    crate::utils::cargo_bin()
+        .args(["c", "--index"])
+        .arg(input)
+        .arg(dir.join("archive.zip"))
+        .assert()
+        .failure();
+}
+
+// `ouch index` writes the sidecar of an existing tar archive, compressed in any format
+#[test]
+fn index_existing_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("file"), "indexed content").unwrap();
+    let mut other = vec![0; 256 * 1024];
+    SmallRng::seed_from_u64(248).fill(&mut other[..]);
+    fs::write(input.join("other"), other).unwrap();
+    let archive = &dir.join("archive.tar.gz");
+
+    ouch!("-A", "c", input, archive);
+    let list = || crate::utils::cargo_bin().arg("l").arg(archive).output().unwrap();
+    let listed = list();
+    ouch!("index", archive);
+    assert!(dir.join("archive.tar.gz.idx").exists());
+
+    let mut contents = fs::read(archive).unwrap();
+    let len = contents.len();
+    contents[..len - INDEX_TAIL_SIZE].fill(0);
+    write_keeping_mtime(archive, &contents);
+    assert_eq!(list().stdout, listed.stdout);
+
+    let zip = &dir.join("archive.zip");
+    ouch!("-A", "c", input, zip);
+    crate::utils::cargo_bin().arg("index").arg(zip).assert().failure();
+}
+
+// Selective extraction of indexed archives only decodes the part of the archive holding the
+// selected entries
+#[test]
+fn extract_from_seek_index() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let big = &dir.join("big");
+    fs::create_dir(big).unwrap();
+    let mut data = vec![0; 3 * 1024 * 1024];
+    SmallRng::seed_from_u64(248).fill(&mut data[..]);
+    fs::write(big.join("data"), data).unwrap();
+    let small = &dir.join("small");
+    fs::write(small, "indexed content").unwrap();
+    let extract = |archive: &PathBuf, only: &str, output: &str| {
+        crate::utils::cargo_bin()
+            .args(["d", "--yes", "--only", only, "-d"])
+            .arg(dir.join(output))
+            .arg(archive)
+            .assert()
+    };
+
+    // "small" is in the last frame, the first one isn't decoded
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", big, small, archive, "--index");
+    let mut corrupted = fs::read(archive).unwrap();
+    corrupted[..64].fill(0);
+    write_keeping_mtime(archive, &corrupted);
+    extract(archive, "small", "zst").success();
+    assert_eq!(fs::read(dir.join("zst/small")).unwrap(), b"indexed content");
+    extract(archive, "big/data", "zst_big").failure();
+
+    // Nothing after "small" is decoded
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", small, big, archive);
+    ouch!("index", archive);
+    let mut corrupted = fs::read(archive).unwrap();
+    let len = corrupted.len();
+    corrupted[len / 2..len - INDEX_TAIL_SIZE].fill(0);
+    write_keeping_mtime(archive, &corrupted);
+    extract(archive, "small", "gz").success();
+    assert_eq!(fs::read(dir.join("gz/small")).unwrap(), b"indexed content");
+    extract(archive, "big/data", "gz_big").failure();
+}
+
+// `ouch split` cuts a file into numbered parts, which are joined back when decompressing the first one
+#[test]
+fn split_and_join_parts() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let mut content = vec![0; 400_000];
+    SmallRng::seed_from_u64(250).fill(&mut content[..]);
+    fs::write(input.join("file"), &content).unwrap();
+
+    for name in ["archive.tar.gz", "archive.zip"] {
+        let archive = &dir.join(name);
+        ouch!("-A", "c", input, archive);
+        ouch!("split", archive, "--size", "100K");
+        assert!(dir.join(format!("{name}.004")).exists());
+        fs::remove_file(archive).unwrap();
+
+        let output = &dir.join("output");
+        ouch!("-A", "d", dir.join(format!("{name}.001")), "-d", output);
+        assert_eq!(fs::read(output.join("input/file")).unwrap(), content);
+        fs::remove_dir_all(output).unwrap();
+    }
+}
+
+// `--emit-checksum` prints the checksum of the archive as it's written, like `sha256sum` would
+#[test]
+fn emit_checksum_of_archive() {
+    use sha2::{Digest, Sha256};
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "checksummed content").unwrap();
+
+    for name in ["archive.tar.gz", "archive.zip", "archive.7z"] {
+        let archive = &dir.join(name);
+        let output = crate::utils::cargo_bin()
+            .args(["c", "--emit-checksum", "sha256"])
+            .args([input, archive])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let digest = Sha256::digest(fs::read(archive).unwrap());
+        let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout, format!("{hex}  {}\n", archive.display()));
+    }
+}
+
+// nested zip archives are streamed, with the permissions of their central directory
+#[cfg(unix)]
+#[test]
+fn stream_nested_zip() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    let mut data = vec![0; 300_000];
+    SmallRng::seed_from_u64(7).fill(&mut data[..]);
+    fs::write(input.join("data.bin"), &data).unwrap();
+    fs::write(input.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(input.join("run.sh"), std::fs::Permissions::from_mode(0o750)).unwrap();
+
+    let archive = &dir.join("archive.zip.gz");
+    ouch!("-A", "c", input, archive);
+    let output = &dir.join("output");
+    let assert = crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes", "-d"])
+        .args([output, archive])
+        .assert()
+        .success();
+    // Nothing was buffered
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(!stderr.contains("in-memory"), "{stderr}");
+
+    assert_eq!(fs::read(output.join("input/data.bin")).unwrap(), data);
+    let mode = fs::metadata(output.join("input/run.sh")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o750);
+}
+
+// encrypted zip archives are listed without their password, and extracted with it
+#[test]
+fn encrypted_zip() {
+    let mut archive = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    archive.push("tests/data/testfile.encrypted.zip");
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+
+    let output = crate::utils::cargo_bin().args(["l"]).arg(&archive).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("testfile.txt"));
+
+    let output = crate::utils::cargo_bin()
+        .args(["d", "--yes", "--password", "wrong", "-d"])
+        .args([&dir.join("wrong"), &archive])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid password"));
+
+    ouch!("d", &archive, "--password", "secret", "-d", dir);
+    assert_eq!(fs::read_to_string(dir.join("testfile.txt")).unwrap(), "Testing 123\n");
+}
+
+// `--password` encrypts zip archives, with AES or ZipCrypto, and refuses to write the other formats in plain text
+#[test]
+fn compress_encrypted_zip() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("sub/file.txt"), "secret content").unwrap();
+
+    let archive = &dir.join("archive.zip");
+    ouch!("c", input, archive, "--password", "hunter2");
+    assert!(!fs::read(archive)
+        .unwrap()
+        .windows(b"secret content".len())
+        .any(|window| window == b"secret content"));
+
+    // Listed without the password
+    let output = crate::utils::cargo_bin().args(["l"]).arg(archive).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|line| line == "input/sub/"), "{stdout}");
+    assert!(stdout.lines().any(|line| line == "input/sub/file.txt"), "{stdout}");
+
+    let output_dir = &dir.join("output");
+    ouch!("d", archive, "--password", "hunter2", "-d", output_dir);
+    assert_eq!(
+        fs::read_to_string(output_dir.join("input/sub/file.txt")).unwrap(),
+        "secret content"
+    );
+
+    // The compression method and flags of the local header of "input/sub/file.txt"
+    let method_and_flags = |archive: &PathBuf| {
+        let data = fs::read(archive).unwrap();
+        let header = (0..data.len())
+            .map(|start| &data[start..])
+            .find(|header| header.starts_with(b"PK\x03\x04") && header[30..].starts_with(b"input/sub/file.txt"))
+            .unwrap();
+        (
+            u16::from_le_bytes([header[8], header[9]]),
+            u16::from_le_bytes([header[6], header[7]]),
+        )
+    };
+    // AES is stored with the method 99
+    assert_eq!(method_and_flags(archive).0, 99);
+
+    let zipcrypto_archive = &dir.join("zipcrypto.zip");
+    ouch!(
+        "c",
+        input,
+        zipcrypto_archive,
+        "--password",
+        "hunter2",
+        "--encryption",
+        "zipcrypto"
+    );
+    let (method, flags) = method_and_flags(zipcrypto_archive);
+    assert_ne!(method, 99);
+    assert_eq!(flags & 1, 1, "not encrypted");
+    let output_dir = &dir.join("zipcrypto_output");
+    ouch!("d", zipcrypto_archive, "--password", "hunter2", "-d", output_dir);
+    assert_eq!(
+        fs::read_to_string(output_dir.join("input/sub/file.txt")).unwrap(),
+        "secret content"
+    );
+
+    crate::utils::cargo_bin()
+        .args(["c", "--yes", "--password", "hunter2"])
+        .args([input, &dir.join("archive.tar.gz")])
+        .assert()
+        .failure();
+    assert!(!dir.join("archive.tar.gz").exists());
+
+    // Without a password there is nothing to encrypt
+    crate::utils::cargo_bin()
+        .args(["c", "--yes", "--encryption", "zipcrypto"])
+        .args([input, &dir.join("plain.zip")])
+        .assert()
+        .failure();
+}
+
+// file entries repeated in an archive, like the later versions of a file appended to a tar
+#[test]
+fn duplicate_entries() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let archive = &dir.join("archive.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    for (name, content) in [
+        ("dir/a.txt", &b"first"[..]),
+        ("dir/b.txt", b"other"),
+        ("dir/a.txt", b"second"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, content).unwrap();
+    }
+    builder.finish().unwrap();
+
+    for (policy, expected) in [
+        ("keep-last", &[("a.txt", "second")][..]),
+        ("keep-first", &[("a.txt", "first")]),
+        ("version-suffix", &[("a.txt", "first"), ("a (version 2).txt", "second")]),
+    ] {
+        let output = &dir.join(policy);
+        ouch!("d", archive, "--duplicate-entries", policy, "-d", output);
+        for (name, content) in expected {
+            assert_eq!(
+                fs::read_to_string(output.join("dir").join(name)).unwrap(),
+                *content,
+                "{policy}"
+            );
+        }
+        assert_eq!(
+            fs::read_dir(output.join("dir")).unwrap().count(),
+            expected.len() + 1,
+            "{policy}"
+        );
+    }
+}
+
+// extract and list the entries of some types only, like the symlinks of an untrusted archive
+#[cfg(unix)]
+#[test]
+fn entry_type_filters() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let archive = &dir.join("archive.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    builder.append_data(&mut header, "dir", std::io::empty()).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(4);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, "dir/file.txt", &b"data"[..]).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    builder.append_link(&mut header, "dir/link", "file.txt").unwrap();
+    builder.finish().unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["list", "--type", "l"])
+        .arg(archive)
+        .output()
+        .unwrap();
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert!(listed.contains("dir/link") && !listed.contains("file.txt"), "{listed}");
+
+    let output = &dir.join("output");
+    ouch!("d", archive, "--type", "f", "-d", output);
+    assert_eq!(fs::read_to_string(output.join("dir/file.txt")).unwrap(), "data");
+    assert!(fs::symlink_metadata(output.join("dir/link")).is_err());
+}
+
+// copy some entries of an archive into another one of another format
+#[test]
+fn copy_archive_entries() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let before = &dir.join("project");
+    fs::create_dir_all(before.join("assets/images")).unwrap();
+    fs::create_dir_all(before.join("src")).unwrap();
+    fs::write(before.join("assets/images/logo.png"), "logo").unwrap();
+    fs::write(before.join("assets/style.css"), "style").unwrap();
+    fs::write(before.join("src/main.rs"), "main").unwrap();
+    let source = &dir.join("project.tar.gz");
+    ouch!("c", before, source);
+
+    let copy = &dir.join("assets.zip");
+    ouch!("copy", source, copy, "--only", "project/assets/**");
+    let after = &dir.join("after");
+    ouch!("d", copy, "-d", after);
+    let assets = after.join("project/assets");
+    assert_eq!(fs::read_to_string(assets.join("images/logo.png")).unwrap(), "logo");
+    assert_eq!(fs::read_to_string(assets.join("style.css")).unwrap(), "style");
+    assert!(!after.join("project/src").exists());
+
+    // Converted back without filter, the entries are the same, with the same times and permissions
+    let back = &dir.join("back.tar.zst");
+    ouch!("convert", copy, back);
+    let after_back = &dir.join("after_back");
+    ouch!("d", back, "-d", after_back);
+    assert_same_directory(after.join("project"), after_back.join("project"), false);
+    let logo = fs::metadata(assets.join("images/logo.png")).unwrap();
+    let logo_back = fs::metadata(after_back.join("project/assets/images/logo.png")).unwrap();
+    assert_eq!(logo.modified().unwrap(), logo_back.modified().unwrap());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(logo.permissions().mode(), logo_back.permissions().mode());
+    }
+
+    // With --ignore-case, patterns match whatever casing was stored
+    let ignoring_case = &dir.join("ignoring_case.zip");
+    ouch!(
+        "copy",
+        source,
+        ignoring_case,
+        "--only",
+        "PROJECT/Assets/**",
+        "--ignore-case"
+    );
+    let after_ignoring_case = &dir.join("after_ignoring_case");
+    ouch!("d", ignoring_case, "-d", after_ignoring_case);
+    assert_same_directory(after.join("project"), after_ignoring_case.join("project"), false);
+}
+
+// show the first lines of small text files when listing
+#[test]
+fn list_preview() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("README.md"), "# Title\nfirst line\nsecond line\n").unwrap();
+    fs::write(input.join("binary.dat"), [0, 159, 146, 150]).unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list", "--preview", "2"])
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+
+        let readme = lines.iter().position(|line| line.ends_with("README.md")).unwrap();
+        assert_eq!(lines[readme + 1..readme + 3], ["  │ # Title", "  │ first line"]);
+        assert_eq!(lines.iter().filter(|line| line.starts_with("  │")).count(), 2);
+    }
+}
+
+// decompress .tar.Z archives of the Unix compress tool, which can't be created
+#[test]
+fn unpack_unix_compress() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let archive = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/testfile.tar.Z");
+
+    ouch!("-A", "d", &archive, "-d", dir);
+    let numbers: String = (1..=3000).map(|i| format!("{i}\n")).collect();
+    assert_eq!(
+        fs::read_to_string(dir.join("testfile/testfile.txt")).unwrap(),
+        "Testing 123\n"
+    );
+    assert_eq!(fs::read_to_string(dir.join("testfile/numbers.txt")).unwrap(), numbers);
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--yes"])
+        .arg(dir.join("testfile"))
+        .arg(dir.join("output.tar.Z"))
+        .assert()
+        .failure();
+    assert!(!dir.join("output.tar.Z").exists());
+}
+
+// show the types of files sniffed from their contents when listing
+#[test]
+fn list_detect_types() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("image"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+    fs::write(input.join("notes"), "some notes\n").unwrap();
+    fs::write(input.join("data"), [0, 159, 146, 150]).unwrap();
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", input, archive);
+
+        let output = crate::utils::cargo_bin()
+            .args(["-A", "list", "--detect-types"])
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines: Vec<&str> = stdout.lines().skip(1).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "input/",
+                "input/data  [data]",
+                "input/image  [PNG image]",
+                "input/notes  [text]"
+            ]
+        );
+    }
+}
+
+// `--selinux` stores contexts in tar archives only, files without one are stored as usual
+#[cfg(target_os = "linux")]
+#[test]
+fn selinux_contexts() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::write(dir.join("src/file.txt"), "content").unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "c", "--selinux"])
+        .arg(dir.join("src"))
+        .arg(dir.join("archive.zip"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only applies to tar archives"));
+
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", "--selinux", dir.join("src"), archive);
+    ouch!("-A", "d", "--selinux", archive, "-d", dir.join("out"));
+    assert_same_directory(dir.join("src"), dir.join("out/src"), false);
+}
+
+// files extracted from a quarantined archive are quarantined too, unless `--quarantine never`
+#[cfg(target_os = "macos")]
+#[test]
+fn quarantine_extracted_files() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::write(dir.join("src/file.txt"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", dir.join("src"), archive);
+
+    let value = "0083;65a1b2c3;Safari;";
+    let status = std::process::Command::new("xattr")
+        .args(["-w", "com.apple.quarantine", value])
+        .arg(archive)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let quarantine_of = |path: PathBuf| {
+        let output = std::process::Command::new("xattr")
+            .args(["-p", "com.apple.quarantine"])
+            .arg(path)
+            .output()
+            .unwrap();
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    ouch!("-A", "d", archive, "-d", dir.join("inherit"));
+    assert_eq!(quarantine_of(dir.join("inherit/src/file.txt")).as_deref(), Some(value));
+    assert_eq!(quarantine_of(dir.join("inherit/src")).as_deref(), Some(value));
+
+    ouch!("-A", "d", archive, "-d", dir.join("never"), "--quarantine", "never");
+    assert_eq!(quarantine_of(dir.join("never/src/file.txt")), None);
+}
+
+// only macOS has the quarantine attribute
+#[cfg(not(target_os = "macos"))]
+#[test]
+fn quarantine_always_requires_macos() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("file.txt"), "content").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+    ouch!("-A", "c", dir.join("file.txt"), archive);
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--quarantine", "always"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only supported on macOS"));
+    assert!(!dir.join("out/file.txt").exists());
+
+    ouch!("-A", "d", archive, "-d", dir.join("out"), "--quarantine", "never");
+    assert!(dir.join("out/file.txt").exists());
+}
+
+// a .deb package is extracted with its control files in a DEBIAN folder, or without them
+#[test]
+fn decompress_deb_package() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("data/usr/bin")).unwrap();
+    fs::write(dir.join("data/usr/bin/hello"), "#!/bin/sh\n").unwrap();
+    fs::create_dir(dir.join("control")).unwrap();
+    fs::write(dir.join("control/control"), "Package: hello\n").unwrap();
+    ouch!("-A", "c", dir.join("control/control"), dir.join("control.tar.gz"));
+    ouch!("-A", "c", dir.join("data/usr"), dir.join("data.tar.xz"));
+
+    // An ar archive, whose members are aligned to 2 bytes
+    let mut package = b"!<arch>\n".to_vec();
+    for (name, data) in [
+        ("debian-binary", b"2.0\n".to_vec()),
+        ("control.tar.gz", fs::read(dir.join("control.tar.gz")).unwrap()),
+        ("data.tar.xz", fs::read(dir.join("data.tar.xz")).unwrap()),
+    ] {
+        let header = format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", 0, 0, 0, 100644, data.len());
+        package.extend_from_slice(header.as_bytes());
+        package.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            package.push(b'\n');
+        }
+    }
+    let package_path = &dir.join("hello.deb");
+    fs::write(package_path, package).unwrap();
+
+    ouch!("-A", "d", package_path, "-d", dir.join("all"));
+    assert_eq!(fs::read(dir.join("all/hello/usr/bin/hello")).unwrap(), b"#!/bin/sh\n");
+    assert_eq!(
+        fs::read(dir.join("all/hello/DEBIAN/control")).unwrap(),
+        b"Package: hello\n"
+    );
+
+    ouch!("-A", "d", package_path, "-d", dir.join("data_only"), "--deb-data-only");
+    assert!(dir.join("data_only/usr/bin/hello").exists());
+    assert!(!dir.join("data_only/DEBIAN").exists());
+}
+
+// the members of a static library are extracted and listed, with the long names of GNU ar
+#[test]
+fn decompress_ar_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    let mut archive = b"!<arch>\n".to_vec();
+    for (name, data) in [
+        ("/", &b"\0\0\0\0"[..]),
+        ("//", b"a_very_long_object_name.o/\n\n"),
+        ("short.o/", b"odd"),
+        ("/0", b"long"),
+    ] {
+        let header = format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", 0, 0, 0, 100644, data.len());
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+    let archive_path = &dir.join("libhello.a");
+    fs::write(archive_path, archive).unwrap();
+
+    ouch!("-A", "d", archive_path, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/libhello/short.o")).unwrap(), b"odd");
+    assert_eq!(
+        fs::read(dir.join("out/libhello/a_very_long_object_name.o")).unwrap(),
+        b"long"
+    );
+
+    let output = crate::utils::cargo_bin()
+        .args(["list"])
+        .arg(archive_path)
+        .output()
+        .unwrap();
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        listed.contains("short.o") && listed.contains("a_very_long_object_name.o"),
+        "{listed}"
+    );
+}
+
+// the files of an ISO 9660 image are listed and extracted, without mounting it
+#[test]
+fn decompress_iso_image() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    // A directory record of the extent at `sector`
+    let record = |sector: u32, size: u32, is_dir: bool, name: &[u8]| {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&sector.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[18..25].copy_from_slice(&[120, 1, 2, 3, 4, 5, 0]);
+        record[25] = if is_dir { 2 } else { 0 };
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    };
+    let mut image = vec![0; 21 * 2048];
+    image[16 * 2048..][..6].copy_from_slice(b"\x01CD001");
+    image[16 * 2048 + 128..][..2].copy_from_slice(&2048u16.to_le_bytes());
+    image[16 * 2048 + 156..][..34].copy_from_slice(&record(18, 2048, true, &[0]));
+    image[17 * 2048..][..6].copy_from_slice(b"\xFFCD001");
+    let root = [
+        record(18, 2048, true, &[0]),
+        record(18, 2048, true, &[1]),
+        record(19, 2048, true, b"DOCS"),
+    ]
+    .concat();
+    image[18 * 2048..][..root.len()].copy_from_slice(&root);
+    let docs = [
+        record(19, 2048, true, &[0]),
+        record(18, 2048, true, &[1]),
+        record(20, 6, false, b"README.TXT;1"),
+    ]
+    .concat();
+    image[19 * 2048..][..docs.len()].copy_from_slice(&docs);
+    image[20 * 2048..][..6].copy_from_slice(b"hello\n");
+    let image_path = &dir.join("disc.iso");
+    fs::write(image_path, image).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["list"])
+        .arg(image_path)
+        .output()
+        .unwrap();
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert!(listed.contains("DOCS/README.TXT"), "{listed}");
+
+    ouch!("-A", "d", image_path, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/DOCS/README.TXT")).unwrap(), b"hello\n");
+}
+
+// the files of a cabinet, whose names separate directories with backslashes, are extracted
+#[cfg(feature = "cab")]
+#[test]
+fn decompress_cab_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    // An uncompressed cabinet of one folder of one data block, without checksums
+    let files: [(&str, &[u8]); 2] = [("drivers\\net.inf", b"[Version]\n"), ("readme.txt", b"hi")];
+    let data: Vec<u8> = files.iter().flat_map(|(_, data)| data.to_vec()).collect();
+    let mut entries = vec![];
+    let mut offset = 0;
+    for (name, file_data) in files {
+        entries.extend_from_slice(&(file_data.len() as u32).to_le_bytes());
+        entries.extend_from_slice(&(offset as u32).to_le_bytes());
+        // The folder, then 2020-01-02 03:04:06 and the attributes
+        for number in [0u16, 20514, 6275, 0] {
+            entries.extend_from_slice(&number.to_le_bytes());
+        }
+        entries.extend_from_slice(name.as_bytes());
+        entries.push(0);
+        offset += file_data.len();
+    }
+    let data_start = 36 + 8 + entries.len();
+    let size = data_start + 8 + data.len();
+
+    let mut cabinet = b"MSCF".to_vec();
+    for number in [0, size as u32, 0, 36 + 8, 0] {
+        cabinet.extend_from_slice(&number.to_le_bytes());
+    }
+    cabinet.extend_from_slice(&[3, 1]);
+    for number in [1u16, files.len() as u16, 0, 0, 0] {
+        cabinet.extend_from_slice(&number.to_le_bytes());
+    }
+    cabinet.extend_from_slice(&(data_start as u32).to_le_bytes());
+    cabinet.extend_from_slice(&[1, 0, 0, 0]);
+    cabinet.extend_from_slice(&entries);
+    cabinet.extend_from_slice(&[0; 4]);
+    cabinet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    cabinet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    cabinet.extend_from_slice(&data);
+    let cabinet_path = &dir.join("driver.cab");
+    fs::write(cabinet_path, cabinet).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["list"])
+        .arg(cabinet_path)
+        .output()
+        .unwrap();
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert!(listed.contains("drivers/net.inf"), "{listed}");
+
+    ouch!("-A", "d", cabinet_path, "-d", dir.join("out"));
+    assert_eq!(
+        fs::read(dir.join("out/driver/drivers/net.inf")).unwrap(),
+        b"[Version]\n"
+    );
+    assert_eq!(fs::read(dir.join("out/driver/readme.txt")).unwrap(), b"hi");
+}
+
+// the files of a SquashFS image are listed and extracted, without mounting it
+#[test]
+fn decompress_squashfs_image() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+
+    let numbers = |numbers: &[u32]| -> Vec<u8> { numbers.iter().flat_map(|number| number.to_le_bytes()).collect() };
+    // An uncompressed metadata block
+    let metadata = |data: &[u8]| [&(data.len() as u16 | 0x8000).to_le_bytes(), data].concat();
+    // The type, permissions, uid and gid indexes, modification time and number of an inode
+    let inode = |kind: u32, mode: u32, number: u32| numbers(&[kind | mode << 16, 0, 1_577_934_245, number]);
+    // A listing of one entry, whose inode is at `offset` in the inode table
+    let listing = |name: &str, offset: u16| {
+        let mut listing = numbers(&[0, 0, 1]);
+        listing.extend_from_slice(&offset.to_le_bytes());
+        listing.extend_from_slice(&[0; 4]);
+        listing.extend_from_slice(&(name.len() as u16 - 1).to_le_bytes());
+        listing.extend_from_slice(name.as_bytes());
+        listing
+    };
+
+    // "hello\n" in a fragment, "docs/readme.txt" at 0 in the inode table, "docs" at 32 and the root at 64
+    let mut image = vec![0; 96];
+    image.extend_from_slice(b"hello\n");
+    let inodes = [
+        inode(2, 0o644, 3),
+        numbers(&[0, 0, 0, 6]),
+        inode(1, 0o755, 2),
+        numbers(&[0, 2, 30 + 3, 1]),
+        inode(1, 0o755, 1),
+        numbers(&[0, 3, (24 + 3) | 30 << 16, 1]),
+    ]
+    .concat();
+    let inode_table = image.len() as u64;
+    image.extend(metadata(&inodes));
+    let directory_table = image.len() as u64;
+    image.extend(metadata(&[listing("readme.txt", 0), listing("docs", 32)].concat()));
+    let fragments = image.len() as u64;
+    image.extend(metadata(
+        &[96u64.to_le_bytes().as_slice(), &numbers(&[6 | 1 << 24, 0])].concat(),
+    ));
+    let fragment_table = image.len() as u64;
+    image.extend_from_slice(&fragments.to_le_bytes());
+    let ids = image.len() as u64;
+    image.extend(metadata(&numbers(&[0])));
+    let id_table = image.len() as u64;
+    image.extend_from_slice(&ids.to_le_bytes());
+
+    let bytes_used = image.len() as u64;
+    // The magic, 3 inodes, blocks of 4 KiB, a fragment, gzip, 1 id and version 4.0
+    image[..32].copy_from_slice(&numbers(&[0x7371_7368, 3, 0, 4096, 1, 1 | 12 << 16, 1 << 16, 4]));
+    let tables = [
+        64,
+        bytes_used,
+        id_table,
+        u64::MAX,
+        inode_table,
+        directory_table,
+        fragment_table,
+        u64::MAX,
+    ];
+    for (index, table) in tables.into_iter().enumerate() {
+        image[32 + index * 8..][..8].copy_from_slice(&table.to_le_bytes());
+    }
+    let image_path = &dir.join("firmware.squashfs");
+    fs::write(image_path, image).unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["list"])
+        .arg(image_path)
+        .output()
+        .unwrap();
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert!(listed.contains("docs/readme.txt"), "{listed}");
+
+    ouch!("-A", "d", image_path, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/docs/readme.txt")).unwrap(), b"hello\n");
+}
+
+// `--stats` reports the resources used by the run when it ends
+#[cfg(unix)]
+#[test]
+fn stats_report() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.tar.gz");
+    fs::write(dir.join("file"), "some data").unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "--stats", "c"])
+        .arg(dir.join("file"))
+        .arg(archive)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Elapsed time: "), "{stderr}");
+    assert!(stderr.contains("Peak memory: "), "{stderr}");
+}
+
+// `--seekable` writes zstd seekable files, whose seek table lets partial extractions of tar.zst
+// archives skip over the frames of the other entries
+#[test]
+fn seekable_zstd_partial_extraction() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("wanted"), "seekable content").unwrap();
+    let big: Vec<u8> = (0..4 * 1024 * 1024u64).map(|i| (i * i % 251) as u8).collect();
+    fs::write(input.join("big"), &big).unwrap();
+    let archive = &dir.join("archive.tar.zst");
+
+    ouch!("-A", "c", input, archive, "--seekable");
+    let mut compressed = fs::read(archive).unwrap();
+    assert_eq!(compressed[compressed.len() - 4..], 0x8F92EAB1u32.to_le_bytes());
+
+    // Other decoders read it as any zstd file
+    ouch!("-A", "d", archive, "-d", dir.join("full"));
+    assert_eq!(fs::read(dir.join("full/input/big")).unwrap(), big);
+
+    // Break the third frame, which only holds the contents of "big"
+    let frame_count = u32::from_le_bytes(compressed[compressed.len() - 9..][..4].try_into().unwrap()) as usize;
+    let table = compressed.len() - 9 - frame_count * 8;
+    let frame_size = |frame: usize| u32::from_le_bytes(compressed[table + frame * 8..][..4].try_into().unwrap());
+    let third_frame = (frame_size(0) + frame_size(1)) as usize;
+    compressed[third_frame..third_frame + 4].fill(0);
+    fs::write(archive, &compressed).unwrap();
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("broken"))
+        .assert()
+        .failure();
+
+    // Extracting "wanted" doesn't decode it
+    let only = &dir.join("only");
+    fs::write(only, "input/wanted\n").unwrap();
+    ouch!("-A", "d", archive, "--only-from", only, "-d", dir.join("part"));
+    assert_eq!(fs::read(dir.join("part/input/wanted")).unwrap(), b"seekable content");
+    assert!(!dir.join("part/input/big").exists());
+
+    crate::utils::cargo_bin()
+        .args(["c", "--seekable"])
+        .arg(input)
+        .arg(dir.join("archive.tar.gz"))
+        .assert()
+        .failure();
+}
+
+// `--resume` leaves out the entries listed in the state file of a run stopped by `--max-duration`,
+// and runs that finish within their budget don't write one
+#[test]
+fn compress_resume_from_state_file() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("first"), "archived earlier").unwrap();
+    fs::write(input.join("second"), "archived now").unwrap();
+
+    let whole = &dir.join("whole.tar.gz");
+    ouch!("-A", "c", input, whole, "--max-duration", "1h");
+    assert!(!dir.join("whole.tar.gz.resume").exists());
+
+    let state = &dir.join("earlier.tar.resume");
+    fs::write(state, "ouch-resume 1\0input\0input/first\0").unwrap();
+    let rest = &dir.join("rest.tar.gz");
+    ouch!("-A", "c", input, rest, "--resume", state);
+    ouch!("-A", "d", rest, "-d", dir.join("out"));
+    assert!(!dir.join("out/input/first").exists());
+    assert_eq!(fs::read(dir.join("out/input/second")).unwrap(), b"archived now");
+
+    fs::write(state, "something else").unwrap();
+    crate::utils::cargo_bin()
+        .args(["c", "--resume"])
+        .arg(state)
+        .arg(input)
+        .arg(dir.join("again.tar"))
+        .assert()
+        .failure();
+    crate::utils::cargo_bin()
+        .args(["c", "--max-duration", "1h"])
+        .arg(input)
+        .arg(dir.join("archive.zip"))
+        .assert()
+        .failure();
+}
+
+// `ouch detect` prints the formats of files from their names, and from their contents when these
+// disagree or the name has no extension
+#[test]
+fn detect_formats() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::write(input, "some content").unwrap();
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", input, archive);
+    fs::copy(archive, dir.join("misnamed.tar.gz")).unwrap();
+    fs::copy(archive, dir.join("unnamed")).unwrap();
+
+    let detect = |args: &[&str]| {
+        let output = crate::utils::cargo_bin()
+            .current_dir(dir)
+            .arg("detect")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        detect(&["archive.tar.zst", "misnamed.tar.gz", "unnamed", "input"]),
+        "archive.tar.zst: tar.zst\nmisnamed.tar.gz: tar.gz (contents look like zst)\nunnamed: zst\ninput: unknown\n"
+    );
+
+    let json = detect(&["--json", "misnamed.tar.gz"]);
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([{
+            "path": "misnamed.tar.gz",
+            "extension": "tar.gz",
+            "formats": ["tar", "gzip"],
+            "sniffed": "zst",
+        }])
+    );
+
+    crate::utils::cargo_bin()
+        .current_dir(dir)
+        .args(["detect", "missing"])
+        .assert()
+        .failure();
+}
+
+// gzip is compressed in blocks on several threads, but still as a single member that any gzip
+// decoder reads, like the output of pigz
+#[test]
+fn parallel_gzip_is_gunzip_compatible() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    let mut rng = SmallRng::seed_from_u64(267);
+    let content: Vec<u8> = (0..3 * 1024 * 1024).map(|_| rng.gen_range(b'a'..=b'f')).collect();
+    fs::write(input, &content).unwrap();
+    let compressed = &dir.join("input.gz");
+
+    ouch!("-A", "c", input, compressed, "--threads", "4");
+    let mut decoded = vec![];
+    std::io::Read::read_to_end(
+        &mut flate2::read::GzDecoder::new(fs::File::open(compressed).unwrap()),
+        &mut decoded,
+    )
+    .unwrap();
+    assert_eq!(decoded, content);
+}
+
+// xz is compressed in blocks of `--block-size` on several threads, which any xz decoder reads, and
+// decompressed on several threads too
+#[test]
+fn multithreaded_xz() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    let mut rng = SmallRng::seed_from_u64(268);
+    let content: Vec<u8> = (0..3 * 1024 * 1024).map(|_| rng.gen_range(b'a'..=b'f')).collect();
+    fs::write(input, &content).unwrap();
+    let compressed = &dir.join("input.xz");
+
+    ouch!("-A", "c", input, compressed, "--threads", "4", "--block-size", "256K");
+    let mut decoded = vec![];
+    std::io::Read::read_to_end(
+        &mut liblzma::read::XzDecoder::new(fs::File::open(compressed).unwrap()),
+        &mut decoded,
+    )
+    .unwrap();
+    assert_eq!(decoded, content);
+
+    ouch!("-A", "d", compressed, "-d", dir.join("out"), "--threads", "4");
+    assert_eq!(fs::read(dir.join("out/input")).unwrap(), content);
+
+    crate::utils::cargo_bin()
+        .args(["c", "--block-size", "1M"])
+        .arg(input)
+        .arg(dir.join("input.zst"))
+        .assert()
+        .failure();
+}
+
+// with --expand-archives, the entries of input archives are compressed instead of the archives
+#[test]
+fn compress_expand_archives() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join("from_zip"), "zipped").unwrap();
+    let inner_zip = &dir.join("inner.zip");
+    ouch!("-A", "c", input.join("from_zip"), inner_zip);
+    fs::write(input.join("from_tar"), "tarred").unwrap();
+    let inner_tar = &dir.join("inner.tar.gz");
+    ouch!("-A", "c", input.join("from_tar"), inner_tar);
+    fs::write(dir.join("plain"), "as is").unwrap();
+
+    for outer in ["outer.tar.zst", "outer.zip"] {
+        let outer = &dir.join(outer);
+        ouch!(
+            "-A",
+            "c",
+            inner_zip,
+            inner_tar,
+            dir.join("plain"),
+            outer,
+            "--expand-archives"
+        );
+        ouch!("-A", "d", outer, "-d", dir.join("out"));
+        let out = &dir.join("out/outer");
+        assert_eq!(fs::read(out.join("from_zip")).unwrap(), b"zipped");
+        assert_eq!(fs::read(out.join("from_tar")).unwrap(), b"tarred");
+        assert_eq!(fs::read(out.join("plain")).unwrap(), b"as is");
+        assert!(!out.join("inner.zip").exists());
+        assert!(!out.join("inner.tar.gz").exists());
+        fs::remove_dir_all(dir.join("out")).unwrap();
+    }
+
+    crate::utils::cargo_bin()
+        .args(["c", "--expand-archives"])
+        .arg(inner_zip)
+        .arg(dir.join("outer.7z"))
+        .assert()
+        .failure();
+}
+
+// --normalize converts entry names to a Unicode normalization form when compressing and extracting
+#[test]
+fn normalize_entry_names() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let composed = "caf\u{e9}";
+    let decomposed = "cafe\u{301}";
+    let input = &dir.join("input");
+    fs::create_dir(input).unwrap();
+    fs::write(input.join(decomposed), "content").unwrap();
+
+    for (extension, form, name) in [
+        ("tar", "nfc", composed),
+        ("zip", "nfc", composed),
+        ("tar.gz", "none", decomposed),
+    ] {
+        let archive = &dir.join(format!("archive.{extension}"));
+        ouch!("-A", "c", input, archive, "--normalize", form);
+        let listed = crate::utils::cargo_bin()
+            .args(["-A", "l"])
+            .arg(archive)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        assert!(String::from_utf8(listed).unwrap().contains(&format!("input/{name}\n")));
+    }
+
+    let out = &dir.join("out");
+    ouch!("-A", "d", dir.join("archive.tar"), "-d", out, "--normalize", "nfd");
+    assert_eq!(fs::read(out.join("input").join(decomposed)).unwrap(), b"content");
+    assert!(!out.join("input").join(composed).exists());
+}
+
+// --long finds repetitions further apart than the default zstd window, and its archives are
+// decompressed whatever their window
+#[test]
+fn zstd_long_distance_matching() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    let mut rng = SmallRng::seed_from_u64(270);
+    let mut content = vec![0; 4 * 1024 * 1024];
+    rng.fill(&mut content[..]);
+    content.extend_from_within(..);
+    fs::write(input, &content).unwrap();
+
+    let short = &dir.join("short.zst");
+    ouch!("-A", "c", input, short);
+    let long = &dir.join("long.zst");
+    ouch!("-A", "c", input, long, "--long=28");
+    assert!(fs::metadata(long).unwrap().len() < fs::metadata(short).unwrap().len() * 6 / 10);
+
+    // Windows above 2^27 bytes are rejected by default decoders
+    assert!(zstd::decode_all(fs::File::open(long).unwrap()).is_err());
+    ouch!("-A", "d", long, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/long")).unwrap(), content);
+
+    let archive = &dir.join("archive.tar.zst");
+    ouch!("-A", "c", input, archive, "--long");
+    ouch!("-A", "d", archive, "-d", dir.join("unpacked"));
+    assert_eq!(fs::read(dir.join("unpacked/input")).unwrap(), content);
+
+    crate::utils::cargo_bin()
+        .args(["c", "--long"])
+        .arg(input)
+        .arg(dir.join("input.xz"))
+        .assert()
+        .failure();
+}
+
+// `ouch train-dict` trains a zstd dictionary that small similar files compress better with, and that
+// they need to be decompressed
+#[test]
+fn zstd_dictionary() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let samples = &dir.join("samples");
+    fs::create_dir(samples).unwrap();
+    let mut rng = SmallRng::seed_from_u64(271);
+    for i in 0..500 {
+        let record = format!(
+            "{{\"id\": {i}, \"user\": \"user-{}\", \"status\": \"{}\", \"score\": {}}}\n",
+            rng.gen_range(0..1000),
+            ["active", "suspended", "pending"][rng.gen_range(0..3)],
+            rng.gen_range(0..100),
+        );
+        fs::write(samples.join(format!("record_{i}.json")), record).unwrap();
+    }
+    let input = &samples.join("record_0.json");
+
+    let dict = &dir.join("records.dict");
+    ouch!("-A", "train-dict", samples, "-o", dict, "--max-size", "4K");
+    assert!(fs::metadata(dict).unwrap().len() <= 4096);
+
+    let plain = &dir.join("plain.zst");
+    ouch!("-A", "c", input, plain);
+    let compressed = &dir.join("record.zst");
+    ouch!("-A", "c", input, compressed, "--dict", dict);
+    assert!(fs::metadata(compressed).unwrap().len() < fs::metadata(plain).unwrap().len());
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(compressed)
+        .arg("-d")
+        .arg(dir.join("without"))
+        .assert()
+        .failure();
+    ouch!("-A", "d", compressed, "-d", dir.join("out"), "--dict", dict);
+    assert_eq!(fs::read(dir.join("out/record")).unwrap(), fs::read(input).unwrap());
+
+    // A single sample isn't enough
+    crate::utils::cargo_bin()
+        .args(["-A", "train-dict"])
+        .arg(input)
+        .arg("-o")
+        .arg(dir.join("too_few.dict"))
+        .assert()
+        .failure();
+    assert!(!dir.join("too_few.dict").exists());
+}
+
+// metadata the filesystem can't store, like extended attributes in an unknown namespace, is
+// reported at the end of the extraction, or fails it with `--strict-metadata`
+#[cfg(target_os = "linux")]
+#[test]
+fn strict_metadata() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let archive = &dir.join("archive.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+
+    let record = b"28 SCHILY.xattr.ouch.test=1\n";
+    let mut header = tar::Header::new_ustar();
+    header.set_path("././@PaxHeader").unwrap();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_size(record.len() as u64);
+    header.set_cksum();
+    builder.append(&header, &record[..]).unwrap();
+    let mut header = tar::Header::new_ustar();
+    header.set_path("file.txt").unwrap();
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_size(7);
+    header.set_cksum();
+    builder.append(&header, &b"content"[..]).unwrap();
+    builder.into_inner().unwrap();
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::read(dir.join("out/file.txt")).unwrap(), b"content");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Could not restore the extended attributes of"),
+        "{stderr}"
+    );
+
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--strict-metadata"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("strict"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Extract without '--strict-metadata'"), "{stderr}");
+}
+
+// `--ignore-existing` resumes an extraction in place, only the files that differ in size, or in
+// modification time too with `mtime`, are extracted again
+#[test]
+fn ignore_existing() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("kept.txt"), "original").unwrap();
+    fs::write(input.join("sub/cut.txt"), "the whole content").unwrap();
+    let out = &dir.join("out");
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("input.{format}"));
+        ouch!("-A", "c", input, archive);
+        let _ = fs::remove_dir_all(out);
+        ouch!("-A", "d", archive, "-d", out);
+
+        // Same size, it's taken for the extracted file, unlike the one cut short by a crash
+        fs::write(out.join("input/kept.txt"), "ORIGINAL").unwrap();
+        fs::write(out.join("input/sub/cut.txt"), "the whole").unwrap();
+        ouch!("-A", "d", archive, "-d", out, "--ignore-existing");
+        assert_eq!(fs::read(out.join("input/kept.txt")).unwrap(), b"ORIGINAL");
+        assert_eq!(fs::read(out.join("input/sub/cut.txt")).unwrap(), b"the whole content");
+
+        // Written after the extraction, it isn't of the entry's time
+        let kept = std::fs::OpenOptions::new()
+            .write(true)
+            .open(out.join("input/kept.txt"))
+            .unwrap();
+        kept.set_modified(UNIX_EPOCH + Duration::from_secs(86_400 * 365))
+            .unwrap();
+        ouch!("-A", "d", archive, "-d", out, "--ignore-existing", "mtime");
+        assert_eq!(fs::read(out.join("input/kept.txt")).unwrap(), b"original");
+    }
+}
+
+// An extraction with `--ignore-existing` puts the files where a normal one moves them, in a
+// directory named after the archive when it has several roots, so that resuming it after it was
+// interrupted finishes the same tree
+#[test]
+fn resume_multi_root_extraction() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("docs")).unwrap();
+    fs::write(input.join("docs/guide.md"), "the guide").unwrap();
+    fs::write(input.join("readme.txt"), "the readme").unwrap();
+    fs::write(input.join("notes.txt"), "the notes").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("roots.{format}"));
+        crate::utils::cargo_bin()
+            .current_dir(input)
+            .args(["-A", "--yes", "c", "docs", "readme.txt", "notes.txt"])
+            .arg(archive)
+            .assert()
+            .success();
+
+        let expected = &dir.join(format!("expected-{format}"));
+        ouch!("-A", "d", archive, "-d", expected);
+        assert!(expected.join("roots/docs/guide.md").exists());
+
+        // Left by an extraction that was interrupted while writing "notes.txt"
+        let resumed = &dir.join(format!("resumed-{format}"));
+        ouch!("-A", "d", archive, "-d", resumed, "--ignore-existing");
+        assert_same_directory(expected, resumed, false);
+        fs::remove_file(resumed.join("roots/docs/guide.md")).unwrap();
+        fs::write(resumed.join("roots/notes.txt"), "the").unwrap();
+
+        ouch!("-A", "d", archive, "-d", resumed, "--ignore-existing");
+        assert_same_directory(expected, resumed, false);
+    }
+}
+
+// The patterns after "--" select the entries to extract, like the ones of `--only`, and the
+// patterns matching nothing are reported
+#[test]
+fn decompress_selected_entries() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    fs::create_dir_all(input.join("docs/api")).unwrap();
+    fs::create_dir_all(input.join("src")).unwrap();
+    fs::write(input.join("docs/guide.md"), "guide").unwrap();
+    fs::write(input.join("docs/api/index.md"), "index").unwrap();
+    fs::write(input.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(input.join("readme.txt"), "readme").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = dir.join(format!("input.{format}"));
+        ouch!("-A", "c", input, &archive);
+        let out = dir.join(format!("out-{format}"));
+
+        let assert = crate::utils::cargo_bin()
+            .args(["-A", "--yes", "d", "--ignore-case", "--only", "INPUT/README.TXT"])
+            .arg(&archive)
+            .arg("-d")
+            .arg(&out)
+            .args(["--", "input/docs/**", "missing/*"])
+            .assert()
+            .success();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+        assert!(stderr.contains("'missing/*' matched none of the entries"), "{stderr}");
+
+        let out = out.join("input");
+        assert_eq!(fs::read(out.join("docs/guide.md")).unwrap(), b"guide");
+        assert_eq!(fs::read(out.join("docs/api/index.md")).unwrap(), b"index");
+        assert_eq!(fs::read(out.join("readme.txt")).unwrap(), b"readme");
+        assert!(!out.join("src").exists());
+    }
+
+    // Without "--", a misspelled archive isn't taken for a pattern
+    crate::utils::cargo_bin()
+        .args(["-A", "--yes", "d"])
+        .arg(dir.join("input.zip"))
+        .arg(dir.join("inptu.zip"))
+        .arg("-d")
+        .arg(dir.join("typo"))
+        .assert()
+        .failure();
+}
+
+// `--exclude` leaves out the matching files and directories, and `--include` keeps only the
+// matching files, with every archive format
+#[test]
+fn compress_include_exclude() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("project");
+    fs::create_dir_all(input.join("src")).unwrap();
+    fs::create_dir_all(input.join("target/debug")).unwrap();
+    fs::write(input.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(input.join("src/main.o"), "object").unwrap();
+    fs::write(input.join("target/debug/build.rs"), "build").unwrap();
+    fs::write(input.join("readme.txt"), "readme").unwrap();
+
+    for format in ["tar.gz", "zip", "7z"] {
+        let archive = &dir.join(format!("excluded.{format}"));
+        ouch!("-A", "c", input, archive, "--exclude", "target/", "--exclude", "*.o");
+        let out = &dir.join(format!("excluded-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("project/src/main.rs").exists());
+        assert!(out.join("project/readme.txt").exists());
+        assert!(!out.join("project/src/main.o").exists());
+        assert!(!out.join("project/target").exists());
+
+        let archive = &dir.join(format!("included.{format}"));
+        ouch!("-A", "c", input, archive, "--include", "*.rs", "--exclude", "target");
+        let out = &dir.join(format!("included-{format}"));
+        ouch!("-A", "d", archive, "-d", out);
+        assert!(out.join("project/src/main.rs").exists());
+        assert!(!out.join("project/readme.txt").exists());
+        assert!(!out.join("project/src/main.o").exists());
+        assert!(!out.join("project/target").exists());
+    }
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c"])
+        .arg(input)
+        .arg(dir.join("invalid.tar"))
+        .args(["--exclude", "["])
+        .assert()
+        .failure();
+    assert!(!dir.join("invalid.tar").exists());
+}
+
+// the files of the cpio payload of a .rpm package are extracted, like with `rpm2cpio | cpio -id`
+#[test]
+fn decompress_rpm_package() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("root/usr/bin")).unwrap();
+    fs::write(dir.join("root/usr/bin/hello"), "#!/bin/sh\n").unwrap();
+    ouch!("-A", "c", dir.join("root/usr"), dir.join("payload.cpio.xz"));
+
+    // A header whose index points to the null-terminated strings of its data
+    let header = |strings: &[(u32, &str)]| {
+        let (mut index, mut data) = (vec![], vec![]);
+        for &(tag, value) in strings {
+            for number in [tag, 6, data.len() as u32, 1] {
+                index.extend_from_slice(&number.to_be_bytes());
+            }
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+        }
+        let mut header = vec![0x8E, 0xAD, 0xE8, 0x01, 0, 0, 0, 0];
+        header.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        header.extend(index);
+        header.extend(data);
+        header
+    };
+    let mut package = vec![0xED, 0xAB, 0xEE, 0xDB, 3, 0];
+    package.resize(96, 0);
+    // The signature is padded to 8 bytes
+    package.extend(header(&[(1000, "signature")]));
+    package.resize(package.len().next_multiple_of(8), 0);
+    package.extend(header(&[(1000, "hello"), (1124, "cpio"), (1125, "xz")]));
+    package.extend(fs::read(dir.join("payload.cpio.xz")).unwrap());
+    let package_path = &dir.join("hello.rpm");
+    fs::write(package_path, package).unwrap();
+
+    ouch!("-A", "d", package_path, "-d", dir.join("out"));
+    assert_eq!(fs::read(dir.join("out/usr/bin/hello")).unwrap(), b"#!/bin/sh\n");
+}
+
+// the paths printed by `list --null` are given back to `decompress --only-from`, which extracts
+// them with everything inside of them
+#[test]
+fn decompress_only_listed_paths() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("src/sub")).unwrap();
+    fs::write(dir.join("src/a.txt"), "a").unwrap();
+    fs::write(dir.join("src/b.txt"), "b").unwrap();
+    fs::write(dir.join("src/sub/c.txt"), "c").unwrap();
+
+    for format in ["tar.gz", "zip"] {
+        let archive = &dir.join(format!("archive.{format}"));
+        ouch!("-A", "c", dir.join("src"), archive);
+
+        let output = crate::utils::cargo_bin()
+            .args(["list", "--null"])
+            .arg(archive)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut paths: Vec<&[u8]> = output.stdout.split(|&byte| byte == 0).collect();
+        assert_eq!(paths.pop(), Some(&b""[..]));
+        paths.sort();
+        assert_eq!(
+            paths,
+            [&b"src"[..], b"src/a.txt", b"src/b.txt", b"src/sub", b"src/sub/c.txt"]
+        );
+
+        let out = &dir.join(format!("out_{format}"));
+        crate::utils::cargo_bin()
+            .args(["-A", "d", "--only-from", "-", "-d"])
+            .arg(out)
+            .arg(archive)
+            .write_stdin("src/a.txt\0src/sub\0")
+            .assert()
+            .success();
+        assert!(out.join("src/a.txt").exists());
+        assert!(out.join("src/sub/c.txt").exists());
+        assert!(!out.join("src/b.txt").exists());
+    }
+
+    // A list with a path per line, like one written by hand, whose missing paths are reported
+    let list = &dir.join("list.txt");
+    fs::write(list, "src/b.txt\r\n./src/missing.txt\n\n").unwrap();
+    let out = &dir.join("out_lines");
+    let output = crate::utils::cargo_bin()
+        .args(["-A", "d", "--only-from"])
+        .arg(list)
+        .arg("-d")
+        .arg(out)
+        .arg(dir.join("archive.tar.gz"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(out.join("src/b.txt").exists());
+    assert!(!out.join("src/a.txt").exists());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("'src/missing.txt' from '--only-from'"), "{stderr}");
+}
+
+// compress files read from a null-delimited list, whose names contain spaces and newlines
+#[cfg(unix)]
+#[test]
+fn files_from0() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before = &dir.join("before");
+    fs::create_dir(before).unwrap();
+    fs::write(before.join("with space"), "a").unwrap();
+    fs::write(before.join("with\nnewline"), "b").unwrap();
+    let archive = &dir.join("archive.tar.gz");
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--files-from0", "-", "--yes"])
+        .arg(archive)
+        .current_dir(before)
+        .write_stdin("with space\0with\nnewline\0")
+        .assert()
+        .success();
+
+    let after = &dir.join("after");
+    ouch!("-A", "d", archive, "-d", after);
+    assert_same_directory(before, after.join("archive"), false);
+}
+
+// The small files of tar archives are read in parallel batches, the archive is the same as when
+// they're read one at a time, entries in the same order
+#[test]
+fn tar_batches_match_serial_archive() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let input = &dir.join("input");
+    let mut rng = SmallRng::seed_from_u64(225);
+    // Several batches, with large files between the small ones
+    for i in 0..700 {
+        let subdir = input.join(format!("dir{}", i % 7));
+        fs::create_dir_all(&subdir).unwrap();
+        let size = if i % 97 == 0 {
+            200 * 1024
+        } else {
+            rng.gen_range(0..2048)
+        };
+        let mut content = vec![0; size];
+        rng.fill(&mut content[..]);
+        fs::write(subdir.join(format!("file{i}")), content).unwrap();
+    }
+
+    let compress = |threads: &str, archive: &str| {
+        crate::utils::cargo_bin()
+            .args(["-q", "c", "--yes"])
+            .arg(input)
+            .arg(dir.join(archive))
+            .env("RAYON_NUM_THREADS", threads)
+            .assert()
+            .success();
+        fs::read(dir.join(archive)).unwrap()
+    };
+    let serial = compress("1", "serial.tar");
+    let parallel = compress("8", "parallel.tar");
+    assert!(serial == parallel, "the archives differ");
+}
+
+// FIFOs are skipped by default, stored with `--special-files store` and rejected with `--special-files error`
+#[cfg(unix)]
+#[test]
+fn special_files_policy() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let before_dir = &dir.join("before").join("dir");
+    fs::create_dir_all(before_dir).unwrap();
+    fs::write(before_dir.join("file"), "content").unwrap();
+    run_in(before_dir, "mkfifo", "pipe").unwrap();
+
+    let skipped = &dir.join("skipped.tar");
+    ouch!("-A", "c", before_dir, skipped);
+    ouch!("-A", "d", skipped, "-d", dir.join("skipped"));
+    assert!(dir.join("skipped/dir/file").exists());
+    assert!(!dir.join("skipped/dir/pipe").exists());
+
+    let stored = &dir.join("stored.tar");
+    ouch!("-A", "c", before_dir, stored, "--special-files", "store");
+    ouch!("-A", "d", stored, "-d", dir.join("stored"));
+    let metadata = fs::symlink_metadata(dir.join("stored/dir/pipe")).unwrap();
+    assert!(metadata.file_type().is_fifo());
+
+    crate::utils::cargo_bin()
+        .args(["-A", "c", "--special-files", "error", "--yes"])
+        .arg(before_dir)
+        .arg(dir.join("rejected.tar"))
+        .assert()
+        .failure();
+}
+
+// a FIFO can't be written outside of the output folder through a symlink extracted before it
+#[cfg(unix)]
+#[test]
+fn fifo_through_symlink_stays_inside() {
+    let dir = tempdir().unwrap();
+    let dir = dir.path();
+    let outside = &dir.join("outside");
+    fs::create_dir(outside).unwrap();
+    let archive = &dir.join("fifo.tar");
+    let mut builder = tar::Builder::new(fs::File::create(archive).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    builder.append_link(&mut header, "link", outside).unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Fifo);
+    header.set_size(0);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "link/pwned_fifo", std::io::empty())
+        .unwrap();
+    builder.finish().unwrap();
+
+    crate::utils::cargo_bin()
+        .args(["-A", "d", "--yes"])
+        .arg(archive)
+        .arg("-d")
+        .arg(dir.join("out"))
+        .assert()
+        .failure();
+    assert!(fs::symlink_metadata(outside.join("pwned_fifo")).is_err());
+}
+
 #[cfg(feature = "unrar")]
 #[test]
 fn unpack_rar() -> Result<(), Box<dyn std::error::Error>> {
@@ -18,7 +18,7 @@ fn sanity_check_through_mime() {
 
     let formats = [
         "7z", "tar", "zip", "tar.gz", "tgz", "tbz", "tbz2", "txz", "tlzma", "tzst", "tar.bz", "tar.bz2", "tar.lzma",
-        "tar.xz", "tar.zst",
+        "tar.xz", "tar.zst", "tar.lz", "tlz",
     ];
 
     let expected_mimes = [
@@ -37,6 +37,8 @@ fn sanity_check_through_mime() {
         "application/x-xz",
         "application/x-xz",
         "application/zstd",
+        "application/x-lzip",
+        "application/x-lzip",
     ];
 
     assert_eq!(formats.len(), expected_mimes.len());